@@ -0,0 +1,87 @@
+#![allow(clippy::needless_return)]
+
+//! Generates a named test per test case of the sample machine's mock config and expectations
+//! file, so adding a case to the JSON files adds its test.
+//!
+//! `$OUT_DIR/sfn_local_test_cases.rs` holds a module per state machine with a `#[tokio::test]`
+//! per case. Each test calls `run_test_case(state_machine, case)` of the module that includes
+//! the file and asserts that the returned `CasesReport` passed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::path::Path;
+
+use serde_json::Value;
+
+const MOCK_CONFIG: &str = "src/sfn-local-mock.json";
+const EXPECTATIONS: &str = "src/sfn-local-expectations.json";
+const GENERATED: &str = "sfn_local_test_cases.rs";
+
+/// Words that cannot name a function or a module, even in raw identifiers.
+const RESERVED: &[&str] = &["crate", "self", "Self", "super"];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={}", MOCK_CONFIG);
+    println!("cargo:rerun-if-changed={}", EXPECTATIONS);
+
+    // Both files key the test cases the same way, as in `TestCases::new`.
+    let mut test_cases: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for path in [MOCK_CONFIG, EXPECTATIONS] {
+        let document: Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap())
+            .unwrap_or_else(|error| panic!("{} is not valid JSON: {}", path, error));
+        let state_machines = document["StateMachines"].as_object().into_iter().flatten();
+        for (state_machine, value) in state_machines {
+            let cases = value["TestCases"].as_object().into_iter().flatten();
+            test_cases
+                .entry(state_machine.clone())
+                .or_default()
+                .extend(cases.map(|(case, _)| case.clone()));
+        }
+    }
+
+    let mut generated = format!(
+        "// Generated by build.rs from {} and {}.\n",
+        MOCK_CONFIG, EXPECTATIONS
+    );
+    for (state_machine, cases) in &test_cases {
+        writeln!(generated, "\n#[allow(non_snake_case)]").unwrap();
+        writeln!(generated, "mod {} {{", identifier(state_machine)).unwrap();
+        let mut names = BTreeSet::new();
+        for case in cases {
+            let mut name = identifier(case);
+            // Names that only differ in characters an identifier cannot have.
+            while !names.insert(name.clone()) {
+                name.push('_');
+            }
+            writeln!(
+                generated,
+                "    #[tokio::test]\n    async fn {}() {{\n        \
+                 super::run_test_case({:?}, {:?}).await.assert_passed();\n    }}",
+                name, state_machine, case
+            )
+            .unwrap();
+        }
+        writeln!(generated, "}}").unwrap();
+    }
+
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    std::fs::write(Path::new(&out_dir).join(GENERATED), generated).unwrap();
+}
+
+/// A raw identifier for a test case or state machine name, with every character that an
+/// identifier cannot have replaced by `_`.
+fn identifier(name: &str) -> String {
+    let mut identifier: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if identifier.is_empty() || identifier.starts_with(|c: char| c.is_ascii_digit()) {
+        identifier.insert(0, '_');
+    }
+    if identifier == "_" || RESERVED.contains(&identifier.as_str()) {
+        identifier.push('_');
+    }
+
+    return format!("r#{}", identifier);
+}
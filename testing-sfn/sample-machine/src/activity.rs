@@ -0,0 +1,296 @@
+//! Activity tasks and a local activity worker.
+//!
+//! A Task state whose `Resource` is an activity ARN
+//! (`arn:aws:states:<region>:<account>:activity:<name>`) is not run by a handler: the executor
+//! queues it until a worker picks it up with [`Executor::get_activity_task`], then waits for
+//! the worker to report the task token through `SendTaskSuccess`, `SendTaskFailure` or
+//! `SendTaskHeartbeat`, exactly like a `.waitForTaskToken` integration. [`ActivityWorker`] is
+//! that worker loop around a closure.
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::sync::{oneshot, Notify};
+
+use crate::asl::StatesError;
+use crate::executor::{Executor, TokenError};
+
+/// How long `GetActivityTask` waits for a task before returning nothing, as in Step Functions.
+pub const ACTIVITY_POLL_SECONDS: u64 = 60;
+
+/// What `GetActivityTask` hands to a worker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActivityTask {
+    pub task_token: String,
+    /// The state input after `InputPath` and `Parameters`.
+    pub input: Value,
+}
+
+struct Scheduled {
+    task: ActivityTask,
+    /// Receives the name of the worker that picked the task up.
+    started: oneshot::Sender<String>,
+}
+
+/// The activity tasks waiting for a worker, by activity ARN.
+#[derive(Clone, Default)]
+pub(crate) struct Activities {
+    queues: Arc<Mutex<HashMap<String, VecDeque<Scheduled>>>>,
+    scheduled: Arc<Notify>,
+}
+
+impl Activities {
+    /// Queues a task; the returned receiver resolves once a worker picks it up. Dropping the
+    /// receiver, e.g. when the task times out first, withdraws the task.
+    pub(crate) fn schedule(
+        &self,
+        activity_arn: &str,
+        task: ActivityTask,
+    ) -> oneshot::Receiver<String> {
+        let (started, receiver) = oneshot::channel();
+        self.queues
+            .lock()
+            .unwrap()
+            .entry(activity_arn.to_string())
+            .or_default()
+            .push_back(Scheduled { task, started });
+        self.scheduled.notify_waiters();
+
+        return receiver;
+    }
+
+    /// Waits up to `wait` for a task of the activity.
+    pub(crate) async fn poll(
+        &self,
+        activity_arn: &str,
+        worker_name: &str,
+        wait: Duration,
+    ) -> Option<ActivityTask> {
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            // Created before looking at the queue, so a task scheduled in between still wakes us.
+            let scheduled = self.scheduled.notified();
+            if let Some(task) = self.take(activity_arn, worker_name) {
+                return Some(task);
+            }
+            if tokio::time::timeout_at(deadline, scheduled).await.is_err() {
+                return None;
+            }
+        }
+    }
+
+    fn take(&self, activity_arn: &str, worker_name: &str) -> Option<ActivityTask> {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.get_mut(activity_arn)?;
+        while let Some(scheduled) = queue.pop_front() {
+            if scheduled.started.send(worker_name.to_string()).is_ok() {
+                return Some(scheduled.task);
+            }
+        }
+
+        return None;
+    }
+}
+
+/// Polls an activity and runs a closure on each of its tasks, reporting the closure's result
+/// as `SendTaskSuccess` or `SendTaskFailure`.
+#[derive(Clone)]
+pub struct ActivityWorker {
+    executor: Executor,
+    activity_arn: String,
+    name: String,
+    heartbeat: Option<Duration>,
+    poll: Duration,
+}
+
+impl ActivityWorker {
+    pub fn new(executor: &Executor, activity_arn: impl Into<String>) -> Self {
+        return Self {
+            executor: executor.clone(),
+            activity_arn: activity_arn.into(),
+            name: "local-worker".to_string(),
+            heartbeat: None,
+            poll: Duration::from_secs(ACTIVITY_POLL_SECONDS),
+        };
+    }
+
+    /// The `workerName` recorded in `ActivityStarted` events.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        return self;
+    }
+
+    /// Sends `SendTaskHeartbeat` at this interval while the closure runs, for activities with
+    /// `HeartbeatSeconds`.
+    pub fn heartbeat(mut self, every: Duration) -> Self {
+        self.heartbeat = Some(every);
+        return self;
+    }
+
+    /// How long each `GetActivityTask` call waits for a task.
+    pub fn poll_timeout(mut self, poll: Duration) -> Self {
+        self.poll = poll;
+        return self;
+    }
+
+    /// Polls once and works on the task, if there is one. Returns whether there was a task.
+    /// `TaskTimedOut` means Step Functions gave up on the task while the closure ran.
+    pub async fn work_once<F, Fut>(&self, f: &F) -> Result<bool, TokenError>
+    where
+        F: Fn(ActivityTask) -> Fut,
+        Fut: Future<Output = Result<Value, StatesError>>,
+    {
+        let task = match self
+            .executor
+            .get_activity_task(&self.activity_arn, &self.name, self.poll)
+            .await
+        {
+            Some(task) => task,
+            None => return Ok(false),
+        };
+        let token = task.task_token.clone();
+        let mut work = Box::pin(f(task));
+        let result = match self.heartbeat {
+            Some(every) => loop {
+                tokio::select! {
+                    result = &mut work => break result,
+                    _ = tokio::time::sleep(every) => self.executor.send_task_heartbeat(&token)?,
+                }
+            },
+            None => work.await,
+        };
+
+        match result {
+            Ok(output) => self.executor.send_task_success(&token, output)?,
+            Err(error) => self.executor.send_task_failure(&token, error)?,
+        }
+
+        return Ok(true);
+    }
+
+    /// Works on tasks until the returned future is dropped. Tasks that time out while the
+    /// closure runs are given up, as a Step Functions worker would.
+    pub async fn run<F, Fut>(&self, f: F)
+    where
+        F: Fn(ActivityTask) -> Fut,
+        Fut: Future<Output = Result<Value, StatesError>>,
+    {
+        loop {
+            let _ = self.work_once(&f).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::asl::Definition;
+    use crate::history::ExecutionStatus;
+
+    const ACTIVITY: &str = "arn:aws:states:us-east-1:123456789012:activity:get-html";
+
+    fn definition() -> Definition {
+        return Definition::from_value(json!({
+            "StartAt": "GetHtml",
+            "States": {
+                "GetHtml": {
+                    "Type": "Task",
+                    "Resource": ACTIVITY,
+                    "Parameters": { "url.$": "$.url" },
+                    "HeartbeatSeconds": 1,
+                    "Retry": [{ "ErrorEquals": ["Html.Unreachable"], "IntervalSeconds": 0, "MaxAttempts": 1 }],
+                    "End": true
+                }
+            }
+        }))
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn workers_complete_activity_tasks() {
+        let executor = Executor::new();
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let seen = attempts.clone();
+        let worker = ActivityWorker::new(&executor, ACTIVITY)
+            .name("html-worker")
+            .heartbeat(Duration::from_millis(100));
+        tokio::spawn(async move {
+            worker
+                .run(|task: ActivityTask| {
+                    let seen = seen.clone();
+                    async move {
+                        let attempt = {
+                            let mut seen = seen.lock().unwrap();
+                            seen.push(task.input.clone());
+                            seen.len()
+                        };
+                        if attempt == 1 {
+                            return Err(StatesError::new("Html.Unreachable", "connection reset"));
+                        }
+                        tokio::time::sleep(Duration::from_millis(1500)).await;
+                        return Ok(json!({ "size": 20480 }));
+                    }
+                })
+                .await;
+        });
+
+        let execution = executor
+            .execute(&definition(), json!({ "url": "https://example.com" }))
+            .await;
+
+        assert_eq!(execution.status, ExecutionStatus::Succeeded);
+        assert_eq!(execution.output, Some(json!({ "size": 20480 })));
+        assert_eq!(attempts.lock().unwrap().len(), 2);
+        let events: Vec<_> = execution
+            .events
+            .iter()
+            .map(|event| event.event_type.as_str())
+            .filter(|event_type| event_type.starts_with("Activity"))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                "ActivityScheduled",
+                "ActivityStarted",
+                "ActivityFailed",
+                "ActivityScheduled",
+                "ActivityStarted",
+                "ActivitySucceeded"
+            ]
+        );
+        let started = execution
+            .events
+            .iter()
+            .find(|event| event.event_type == "ActivityStarted")
+            .unwrap();
+        assert_eq!(started.details.worker_name.as_deref(), Some("html-worker"));
+    }
+
+    #[tokio::test]
+    async fn activities_time_out_without_heartbeats() {
+        let executor = Executor::new();
+        let handle = executor.start(crate::executor::ExecutionRequest::new(
+            definition(),
+            json!({ "url": "https://example.com" }),
+        ));
+
+        let task = executor
+            .get_activity_task(ACTIVITY, "test", Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(task.input, json!({ "url": "https://example.com" }));
+
+        let execution = handle.wait().await;
+        assert_eq!(execution.status, ExecutionStatus::Failed);
+        assert_eq!(execution.error.as_deref(), Some("States.HeartbeatTimeout"));
+        assert_eq!(
+            executor.send_task_success(&task.task_token, json!({})),
+            Err(TokenError::TaskTimedOut)
+        );
+    }
+}
@@ -0,0 +1,521 @@
+//! Amazon States Language definitions and the pieces of the language that do not depend on how
+//! a state machine is executed: paths, intrinsic functions, Choice rules and input/output
+//! processing.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+pub mod choice;
+pub mod intrinsics;
+pub mod jsonata;
+pub mod path;
+pub mod processing;
+pub mod query;
+
+pub const STATES_ALL: &str = "States.ALL";
+pub const STATES_TIMEOUT: &str = "States.Timeout";
+pub const STATES_HEARTBEAT_TIMEOUT: &str = "States.HeartbeatTimeout";
+pub const STATES_TASK_FAILED: &str = "States.TaskFailed";
+pub const STATES_RUNTIME: &str = "States.Runtime";
+pub const STATES_NO_CHOICE_MATCHED: &str = "States.NoChoiceMatched";
+pub const STATES_INTRINSIC_FAILURE: &str = "States.IntrinsicFailure";
+pub const STATES_RESULT_PATH_MATCH_FAILURE: &str = "States.ResultPathMatchFailure";
+pub const STATES_QUERY_EVALUATION_ERROR: &str = "States.QueryEvaluationError";
+
+/// An error raised while running a state, in the `{"Error": ..., "Cause": ...}` shape that
+/// Step Functions hands to `Catch` clauses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatesError {
+    #[serde(rename = "Error")]
+    pub error: String,
+    #[serde(rename = "Cause", default)]
+    pub cause: String,
+}
+
+impl StatesError {
+    pub fn new(error: impl Into<String>, cause: impl Into<String>) -> Self {
+        return Self {
+            error: error.into(),
+            cause: cause.into(),
+        };
+    }
+
+    pub fn runtime(cause: impl Into<String>) -> Self {
+        return Self::new(STATES_RUNTIME, cause);
+    }
+
+    pub fn to_value(&self) -> Value {
+        return json!({ "Error": self.error, "Cause": self.cause });
+    }
+}
+
+impl fmt::Display for StatesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}: {}", self.error, self.cause);
+    }
+}
+
+impl std::error::Error for StatesError {}
+
+/// A state machine definition, or one of its `Parallel` branches or `Map` processors.
+///
+/// The document is kept as JSON so that it round-trips exactly; the accessors only look at the
+/// fields the executor and the test harness need.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Definition {
+    value: Value,
+}
+
+impl Definition {
+    pub fn from_json(definition: &str) -> Result<Self, StatesError> {
+        let value = serde_json::from_str(definition)
+            .map_err(|error| StatesError::new("InvalidDefinition", error.to_string()))?;
+
+        return Self::from_value(value);
+    }
+
+    pub fn from_value(value: Value) -> Result<Self, StatesError> {
+        let definition = Self { value };
+        let problems = definition.validate();
+        if !problems.is_empty() {
+            return Err(StatesError::new("InvalidDefinition", problems.join("; ")));
+        }
+
+        return Ok(definition);
+    }
+
+    pub fn start_at(&self) -> &str {
+        return self.value["StartAt"].as_str().unwrap_or_default();
+    }
+
+    /// The `QueryLanguage` of the definition, which its states use unless they set their own.
+    pub fn query_language(&self) -> QueryLanguage {
+        return self
+            .value
+            .get("QueryLanguage")
+            .and_then(|language| serde_json::from_value(language.clone()).ok())
+            .unwrap_or_default();
+    }
+
+    pub fn timeout_seconds(&self) -> Option<u64> {
+        return self.value.get("TimeoutSeconds").and_then(Value::as_u64);
+    }
+
+    pub fn states(&self) -> impl Iterator<Item = State<'_>> {
+        return self
+            .value
+            .get("States")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flat_map(|states| states.iter())
+            .map(|(name, value)| State { name, value });
+    }
+
+    pub fn state(&self, name: &str) -> Option<State<'_>> {
+        let (name, value) = self.value.get("States")?.as_object()?.get_key_value(name)?;
+
+        return Some(State { name, value });
+    }
+
+    pub fn as_value(&self) -> &Value {
+        return &self.value;
+    }
+
+    pub fn into_value(self) -> Value {
+        return self.value;
+    }
+
+    pub fn to_json(&self) -> String {
+        return self.value.to_string();
+    }
+
+    /// The names of every Task state, including those of `Parallel` branches and `Map`
+    /// processors.
+    pub fn task_states(&self) -> Vec<String> {
+        let mut tasks = Vec::new();
+        for state in self.states() {
+            if state.kind() == Some(StateKind::Task) {
+                tasks.push(state.name.to_string());
+            }
+            for branch in state.branches() {
+                tasks.extend(branch.task_states());
+            }
+        }
+
+        return tasks;
+    }
+
+    /// Structural checks that every executor relies on. This is not a full ASL validator.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let states = match self.value.get("States").and_then(Value::as_object) {
+            Some(states) => states,
+            None => return vec!["missing the States object".to_string()],
+        };
+
+        match self.value.get("StartAt").and_then(Value::as_str) {
+            Some(start_at) if states.contains_key(start_at) => {}
+            Some(start_at) => problems.push(format!("StartAt '{}' is not a state", start_at)),
+            None => problems.push("missing StartAt".to_string()),
+        }
+        if let Some(problem) = seconds_problem("TimeoutSeconds", self.value.get("TimeoutSeconds")) {
+            problems.push(format!("the definition {}", problem));
+        }
+
+        let assigned: BTreeSet<String> = self
+            .states()
+            .flat_map(|state| state.assigned_variables())
+            .collect();
+        for state in self.states() {
+            if state.kind().is_none() {
+                problems.push(format!("state '{}' has an unknown Type", state.name));
+                continue;
+            }
+
+            if let Some(problem) = seconds_problem("TimeoutSeconds", state.field("TimeoutSeconds"))
+            {
+                problems.push(format!("state '{}' {}", state.name, problem));
+            }
+            if let Some(problem) =
+                seconds_problem("HeartbeatSeconds", state.field("HeartbeatSeconds"))
+            {
+                problems.push(format!("state '{}' {}", state.name, problem));
+            }
+
+            for target in state.transitions() {
+                if !states.contains_key(target) {
+                    problems.push(format!(
+                        "state '{}' transitions to unknown state '{}'",
+                        state.name, target
+                    ));
+                }
+            }
+
+            for branch in state.branches() {
+                for name in branch.nested_assigned_variables() {
+                    if assigned.contains(&name) {
+                        problems.push(format!(
+                            "state '{}' assigns the outer variable '{}' inside a branch",
+                            state.name, name
+                        ));
+                    }
+                }
+                problems.extend(
+                    branch
+                        .validate()
+                        .into_iter()
+                        .map(|problem| format!("in '{}': {}", state.name, problem)),
+                );
+            }
+        }
+
+        return problems;
+    }
+
+    /// The variables assigned by every state, including those of nested branches.
+    fn nested_assigned_variables(&self) -> BTreeSet<String> {
+        let mut assigned = BTreeSet::new();
+        for state in self.states() {
+            assigned.extend(state.assigned_variables());
+            for branch in state.branches() {
+                assigned.extend(branch.nested_assigned_variables());
+            }
+        }
+
+        return assigned;
+    }
+}
+
+/// `TimeoutSeconds` and `HeartbeatSeconds` must be positive integers. JSONata expressions are
+/// checked when they are evaluated.
+fn seconds_problem(field: &str, value: Option<&Value>) -> Option<String> {
+    return match value {
+        None | Some(Value::String(_)) => None,
+        Some(seconds) if seconds.as_u64().unwrap_or(0) > 0 => None,
+        Some(seconds) => Some(format!("has {} {}, not a positive integer", field, seconds)),
+    };
+}
+
+/// The language of a state's expressions: JSONPath paths with `Parameters` and friends, or
+/// `{% ... %}` JSONata expressions with `Arguments` and `Output`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueryLanguage {
+    #[default]
+    #[serde(rename = "JSONPath")]
+    JsonPath,
+    #[serde(rename = "JSONata")]
+    Jsonata,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StateKind {
+    Task,
+    Pass,
+    Choice,
+    Wait,
+    Succeed,
+    Fail,
+    Parallel,
+    Map,
+}
+
+/// A borrowed view of a single state in a [`Definition`].
+#[derive(Debug, Clone, Copy)]
+pub struct State<'a> {
+    pub name: &'a str,
+    pub value: &'a Value,
+}
+
+impl<'a> State<'a> {
+    pub fn kind(&self) -> Option<StateKind> {
+        return serde_json::from_value(self.value.get("Type")?.clone()).ok();
+    }
+
+    pub fn field(&self, key: &str) -> Option<&'a Value> {
+        return self.value.get(key);
+    }
+
+    pub fn str_field(&self, key: &str) -> Option<&'a str> {
+        return self.value.get(key).and_then(Value::as_str);
+    }
+
+    pub fn next(&self) -> Option<&'a str> {
+        return self.str_field("Next");
+    }
+
+    /// The state's own `QueryLanguage`, or `default` (the definition's).
+    pub fn query_language(&self, default: QueryLanguage) -> QueryLanguage {
+        return self
+            .value
+            .get("QueryLanguage")
+            .and_then(|language| serde_json::from_value(language.clone()).ok())
+            .unwrap_or(default);
+    }
+
+    /// The names of the variables the `Assign` fields of the state, its Choice rules and its
+    /// Catch clauses set.
+    pub fn assigned_variables(&self) -> Vec<String> {
+        let mut holders = vec![self.value];
+        for list in ["Choices", "Catch"] {
+            holders.extend(
+                self.value
+                    .get(list)
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten(),
+            );
+        }
+
+        return holders
+            .into_iter()
+            .filter_map(|holder| holder.get("Assign").and_then(Value::as_object))
+            .flat_map(|assign| assign.keys())
+            .map(|key| key.strip_suffix(".$").unwrap_or(key).to_string())
+            .collect();
+    }
+
+    pub fn is_end(&self) -> bool {
+        return self
+            .value
+            .get("End")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+    }
+
+    pub fn retriers(&self) -> Result<Vec<Retrier>, StatesError> {
+        return self.list_field("Retry");
+    }
+
+    pub fn catchers(&self) -> Result<Vec<Catcher>, StatesError> {
+        return self.list_field("Catch");
+    }
+
+    /// `Parallel` branches, or the `ItemProcessor`/`Iterator` of a `Map` state.
+    pub fn branches(&self) -> Vec<Definition> {
+        let mut branches = Vec::new();
+        if let Some(values) = self.value.get("Branches").and_then(Value::as_array) {
+            branches.extend(values.iter().cloned().map(|value| Definition { value }));
+        }
+        if let Some(value) = self
+            .value
+            .get("ItemProcessor")
+            .or_else(|| self.value.get("Iterator"))
+        {
+            branches.push(Definition {
+                value: value.clone(),
+            });
+        }
+
+        return branches;
+    }
+
+    /// Every state name this state can transition to, including Choice rules and Catch clauses.
+    pub fn transitions(&self) -> Vec<&'a str> {
+        let mut targets: Vec<&'a str> = self.next().into_iter().collect();
+        if let Some(choices) = self.value.get("Choices").and_then(Value::as_array) {
+            targets.extend(choices.iter().filter_map(|rule| rule["Next"].as_str()));
+        }
+        if let Some(default) = self.str_field("Default") {
+            targets.push(default);
+        }
+        if let Some(catchers) = self.value.get("Catch").and_then(Value::as_array) {
+            targets.extend(
+                catchers
+                    .iter()
+                    .filter_map(|catcher| catcher["Next"].as_str()),
+            );
+        }
+
+        return targets;
+    }
+
+    fn list_field<T: for<'de> Deserialize<'de>>(&self, key: &str) -> Result<Vec<T>, StatesError> {
+        return match self.value.get(key) {
+            None | Some(Value::Null) => Ok(Vec::new()),
+            Some(value) => serde_json::from_value(value.clone()).map_err(|error| {
+                StatesError::runtime(format!(
+                    "Invalid {} field in state '{}': {}",
+                    key, self.name, error
+                ))
+            }),
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Retrier {
+    pub error_equals: Vec<String>,
+    #[serde(default = "Retrier::default_interval_seconds")]
+    pub interval_seconds: u64,
+    #[serde(default = "Retrier::default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "Retrier::default_backoff_rate")]
+    pub backoff_rate: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_delay_seconds: Option<u64>,
+}
+
+impl Retrier {
+    fn default_interval_seconds() -> u64 {
+        return 1;
+    }
+
+    fn default_max_attempts() -> u32 {
+        return 3;
+    }
+
+    fn default_backoff_rate() -> f64 {
+        return 2.0;
+    }
+
+    /// The delay before the given retry attempt, where the first retry is attempt `0`.
+    pub fn delay_seconds(&self, attempt: u32) -> f64 {
+        let delay = self.interval_seconds as f64 * self.backoff_rate.powi(attempt as i32);
+
+        return match self.max_delay_seconds {
+            Some(max) => delay.min(max as f64),
+            None => delay,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Catcher {
+    pub error_equals: Vec<String>,
+    pub next: String,
+    #[serde(default = "Catcher::default_result_path")]
+    pub result_path: Option<String>,
+}
+
+impl Catcher {
+    fn default_result_path() -> Option<String> {
+        return Some("$".to_string());
+    }
+}
+
+/// Whether an `ErrorEquals` list matches an error name, following the Step Functions rules for
+/// the `States.ALL` and `States.TaskFailed` wildcards.
+pub fn error_matches(error_equals: &[String], error: &str) -> bool {
+    return error_equals
+        .iter()
+        .any(|candidate| match candidate.as_str() {
+            STATES_ALL => error != STATES_RUNTIME,
+            STATES_TASK_FAILED => error != STATES_RUNTIME && error != STATES_TIMEOUT,
+            candidate => candidate == error,
+        });
+}
+
+/// Renders a JSON document the way Step Functions stores it in histories and execution outputs.
+pub fn to_document(value: &Value) -> String {
+    return value.to_string();
+}
+
+/// The inverse of [`to_document`]; Step Functions treats a missing document as `{}`.
+pub fn from_document(document: Option<&str>) -> Result<Value, StatesError> {
+    return match document {
+        None => Ok(Value::Object(Map::new())),
+        Some(document) if document.trim().is_empty() => Ok(Value::Object(Map::new())),
+        Some(document) => serde_json::from_str(document)
+            .map_err(|error| StatesError::new("InvalidExecutionInput", error.to_string())),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn rejects_transitions_to_unknown_states() {
+        let result = Definition::from_value(json!({
+            "StartAt": "A",
+            "States": { "A": { "Type": "Pass", "Next": "B" } }
+        }));
+
+        assert_eq!(
+            result.unwrap_err().cause,
+            "state 'A' transitions to unknown state 'B'"
+        );
+    }
+
+    #[test]
+    fn rejects_timeouts_and_heartbeats_that_are_not_positive() {
+        let result = Definition::from_value(json!({
+            "StartAt": "A",
+            "TimeoutSeconds": 0,
+            "States": {
+                "A": {
+                    "Type": "Task",
+                    "Resource": "arn",
+                    "TimeoutSeconds": -1,
+                    "HeartbeatSeconds": 0.5,
+                    "End": true
+                }
+            }
+        }));
+
+        assert_eq!(
+            result.unwrap_err().cause,
+            "the definition has TimeoutSeconds 0, not a positive integer; \
+             state 'A' has TimeoutSeconds -1, not a positive integer; \
+             state 'A' has HeartbeatSeconds 0.5, not a positive integer"
+        );
+    }
+
+    #[test]
+    fn wildcards_do_not_match_runtime_errors() {
+        let all = vec![STATES_ALL.to_string()];
+        let task_failed = vec![STATES_TASK_FAILED.to_string()];
+
+        assert!(error_matches(&all, "Lambda.ResourceNotReadyException"));
+        assert!(!error_matches(&all, STATES_RUNTIME));
+        assert!(!error_matches(&task_failed, STATES_TIMEOUT));
+    }
+}
@@ -0,0 +1,413 @@
+//! Choice rule evaluation: comparison operators, their `...Path` variants, type tests and the
+//! `And`/`Or`/`Not` combinators.
+
+use serde_json::Value;
+
+use super::{path, State, StatesError, STATES_NO_CHOICE_MATCHED};
+
+/// Every data-test operator a Choice rule can use, without the `Path` suffix.
+pub const COMPARISON_OPERATORS: &[&str] = &[
+    "StringEquals",
+    "StringLessThan",
+    "StringGreaterThan",
+    "StringLessThanEquals",
+    "StringGreaterThanEquals",
+    "StringMatches",
+    "NumericEquals",
+    "NumericLessThan",
+    "NumericGreaterThan",
+    "NumericLessThanEquals",
+    "NumericGreaterThanEquals",
+    "BooleanEquals",
+    "TimestampEquals",
+    "TimestampLessThan",
+    "TimestampGreaterThan",
+    "TimestampLessThanEquals",
+    "TimestampGreaterThanEquals",
+];
+
+pub const TYPE_TESTS: &[&str] = &[
+    "IsNull",
+    "IsPresent",
+    "IsNumeric",
+    "IsString",
+    "IsBoolean",
+    "IsTimestamp",
+];
+
+/// Picks the next state of a Choice state for the given (already filtered) input.
+pub fn next_state(state: &State, input: &Value, context: &Value) -> Result<String, StatesError> {
+    let choices = state
+        .field("Choices")
+        .and_then(Value::as_array)
+        .ok_or_else(|| {
+            StatesError::runtime(format!("Choice state '{}' has no Choices", state.name))
+        })?;
+
+    for rule in choices {
+        if evaluate(rule, input, context)? {
+            return rule["Next"].as_str().map(str::to_string).ok_or_else(|| {
+                StatesError::runtime(format!("A Choice rule in '{}' has no Next", state.name))
+            });
+        }
+    }
+
+    return state
+        .str_field("Default")
+        .map(str::to_string)
+        .ok_or_else(|| {
+            StatesError::new(
+                STATES_NO_CHOICE_MATCHED,
+                format!("No Matches! for state '{}' and input {}", state.name, input),
+            )
+        });
+}
+
+/// Evaluates a single (possibly nested) Choice rule.
+pub fn evaluate(rule: &Value, input: &Value, context: &Value) -> Result<bool, StatesError> {
+    if let Some(rules) = rule.get("And").and_then(Value::as_array) {
+        for rule in rules {
+            if !evaluate(rule, input, context)? {
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    }
+    if let Some(rules) = rule.get("Or").and_then(Value::as_array) {
+        for rule in rules {
+            if evaluate(rule, input, context)? {
+                return Ok(true);
+            }
+        }
+        return Ok(false);
+    }
+    if let Some(rule) = rule.get("Not") {
+        return Ok(!evaluate(rule, input, context)?);
+    }
+
+    let variable = rule
+        .get("Variable")
+        .and_then(Value::as_str)
+        .ok_or_else(|| StatesError::runtime(format!("Choice rule {} has no Variable", rule)))?;
+    let value = path::Path::parse(variable)?.select(input, context);
+
+    for test in TYPE_TESTS {
+        if let Some(expected) = rule.get(*test) {
+            let expected = expected.as_bool().unwrap_or(false);
+            let actual = match (*test, &value) {
+                ("IsPresent", value) => value.is_some(),
+                (_, None) => false,
+                ("IsNull", Some(value)) => value.is_null(),
+                ("IsNumeric", Some(value)) => value.is_number(),
+                ("IsString", Some(value)) => value.is_string(),
+                ("IsBoolean", Some(value)) => value.is_boolean(),
+                ("IsTimestamp", Some(value)) => value.as_str().and_then(parse_timestamp).is_some(),
+                _ => unreachable!(),
+            };
+            return Ok(actual == expected);
+        }
+    }
+
+    let value = value.ok_or_else(|| {
+        StatesError::runtime(format!(
+            "Invalid path '{}': The choice state's condition path references an invalid value.",
+            variable
+        ))
+    })?;
+
+    for operator in COMPARISON_OPERATORS {
+        let path_operator = format!("{}Path", operator);
+        let expected = match (rule.get(*operator), rule.get(&path_operator)) {
+            (Some(expected), _) => expected.clone(),
+            (None, Some(Value::String(expression))) => {
+                path::select(expression, input, context, &path_operator)?
+            }
+            _ => continue,
+        };
+        return Ok(compare(operator, &value, &expected));
+    }
+
+    return Err(StatesError::runtime(format!(
+        "Choice rule {} has no supported comparison operator",
+        rule
+    )));
+}
+
+/// Renders a Choice rule as a short condition, e.g. `$.size > 10240` or
+/// `IsPresent($.accepted) && !($.name matches "test-*")`.
+pub fn describe(rule: &Value) -> String {
+    let describe_all = |rules: &Vec<Value>, separator: &str| {
+        return rules
+            .iter()
+            .map(|rule| {
+                let description = describe(rule);
+                if rule.get("And").is_some() || rule.get("Or").is_some() {
+                    format!("({})", description)
+                } else {
+                    description
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(separator);
+    };
+    if let Some(rules) = rule.get("And").and_then(Value::as_array) {
+        return describe_all(rules, " && ");
+    }
+    if let Some(rules) = rule.get("Or").and_then(Value::as_array) {
+        return describe_all(rules, " || ");
+    }
+    if let Some(rule) = rule.get("Not") {
+        return format!("!({})", describe(rule));
+    }
+
+    let variable = rule["Variable"].as_str().unwrap_or("?");
+    for test in TYPE_TESTS {
+        if let Some(expected) = rule.get(*test) {
+            let negation = if expected.as_bool().unwrap_or(false) {
+                ""
+            } else {
+                "!"
+            };
+            return format!("{}{}({})", negation, test, variable);
+        }
+    }
+    for operator in COMPARISON_OPERATORS {
+        let path_operator = format!("{}Path", operator);
+        let expected = match (rule.get(*operator), rule.get(&path_operator)) {
+            (Some(expected), _) => expected.to_string(),
+            (None, Some(Value::String(expression))) => expression.clone(),
+            _ => continue,
+        };
+        let comparison = ["String", "Numeric", "Boolean", "Timestamp"]
+            .iter()
+            .find_map(|prefix| operator.strip_prefix(prefix))
+            .unwrap_or(operator);
+        let symbol = match comparison {
+            "Equals" => "==",
+            "LessThan" => "<",
+            "GreaterThan" => ">",
+            "LessThanEquals" => "<=",
+            "GreaterThanEquals" => ">=",
+            _ => "matches",
+        };
+        return format!("{} {} {}", variable, symbol, expected);
+    }
+
+    return rule.to_string();
+}
+
+/// Applies a comparison operator. Mismatched types never match, as in Step Functions.
+pub fn compare(operator: &str, value: &Value, expected: &Value) -> bool {
+    use std::cmp::Ordering;
+
+    let ordering = if operator.starts_with("String") {
+        match (value.as_str(), expected.as_str()) {
+            (Some(value), Some(pattern)) if operator == "StringMatches" => {
+                return string_matches(value, pattern)
+            }
+            (Some(value), Some(expected)) => value.cmp(expected),
+            _ => return false,
+        }
+    } else if operator.starts_with("Numeric") {
+        match (value.as_f64(), expected.as_f64()) {
+            (Some(value), Some(expected)) => match value.partial_cmp(&expected) {
+                Some(ordering) => ordering,
+                None => return false,
+            },
+            _ => return false,
+        }
+    } else if operator.starts_with("Boolean") {
+        return value.is_boolean() && value == expected;
+    } else if operator.starts_with("Timestamp") {
+        match (
+            value.as_str().and_then(parse_timestamp),
+            expected.as_str().and_then(parse_timestamp),
+        ) {
+            (Some(value), Some(expected)) => match value.partial_cmp(&expected) {
+                Some(ordering) => ordering,
+                None => return false,
+            },
+            _ => return false,
+        }
+    } else {
+        return false;
+    };
+
+    if operator.ends_with("LessThanEquals") {
+        return ordering != Ordering::Greater;
+    }
+    if operator.ends_with("GreaterThanEquals") {
+        return ordering != Ordering::Less;
+    }
+    if operator.ends_with("LessThan") {
+        return ordering == Ordering::Less;
+    }
+    if operator.ends_with("GreaterThan") {
+        return ordering == Ordering::Greater;
+    }
+
+    return ordering == Ordering::Equal;
+}
+
+/// `StringMatches` patterns: `*` matches any run of characters and `\*` a literal asterisk.
+fn string_matches(value: &str, pattern: &str) -> bool {
+    let mut parts: Vec<String> = vec![String::new()];
+    let mut characters = pattern.chars();
+    while let Some(character) = characters.next() {
+        match character {
+            '\\' => {
+                if let Some(escaped) = characters.next() {
+                    parts.last_mut().unwrap().push(escaped);
+                }
+            }
+            '*' => parts.push(String::new()),
+            _ => parts.last_mut().unwrap().push(character),
+        }
+    }
+
+    let (first, rest) = parts.split_first().unwrap();
+    if !value.starts_with(first.as_str()) {
+        return false;
+    }
+    let mut remaining = &value[first.len()..];
+    if rest.is_empty() {
+        return remaining.is_empty();
+    }
+    let (last, middle) = rest.split_last().unwrap();
+    for part in middle {
+        match remaining.find(part.as_str()) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    return remaining.len() >= last.len() && remaining.ends_with(last.as_str());
+}
+
+/// Parses an RFC 3339 timestamp (`2016-03-14T01:59:00Z`, optionally with fractional seconds or
+/// an offset) into seconds since the Unix epoch.
+pub fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let bytes = timestamp.as_bytes();
+    if bytes.len() < 20 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[10] != b'T' {
+        return None;
+    }
+    if bytes[13] != b':' || bytes[16] != b':' {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| timestamp.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    if second > 60 {
+        return None;
+    }
+
+    let mut rest = &timestamp[19..];
+    let mut fraction = 0.0;
+    if let Some(after_dot) = rest.strip_prefix('.') {
+        let digits = after_dot
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(after_dot.len());
+        if digits == 0 {
+            return None;
+        }
+        fraction = format!("0.{}", &after_dot[..digits]).parse().ok()?;
+        rest = &after_dot[digits..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            if rest.len() != 6 || rest.as_bytes()[3] != b':' {
+                return None;
+            }
+            sign * (rest[1..3].parse::<i64>().ok()? * 3600 + rest[4..6].parse::<i64>().ok()? * 60)
+        }
+    };
+
+    let days = days_from_civil(year, month, day);
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - offset;
+
+    return Some(seconds as f64 + fraction);
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date (Howard Hinnant's algorithm).
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    return era * 146_097 + day_of_era - 719_468;
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn evaluates_the_sample_machine_threshold() {
+        let rule = json!({ "Variable": "$.size", "NumericGreaterThan": 10240, "Next": "IsBig" });
+        let context = json!({});
+
+        assert!(evaluate(&rule, &json!({ "size": 10241 }), &context).unwrap());
+        assert!(!evaluate(&rule, &json!({ "size": 10240 }), &context).unwrap());
+        assert!(!evaluate(&rule, &json!({ "size": "big" }), &context).unwrap());
+        assert!(evaluate(&rule, &json!({}), &context).is_err());
+    }
+
+    #[test]
+    fn evaluates_combinators_type_tests_and_paths() {
+        let rule = json!({
+            "And": [
+                { "Variable": "$.accepted", "IsPresent": true },
+                { "Not": { "Variable": "$.name", "StringMatches": "test-*" } },
+                { "Variable": "$.created", "TimestampLessThanPath": "$.deadline" }
+            ]
+        });
+        let input = json!({
+            "accepted": false,
+            "name": "order-1",
+            "created": "2023-01-01T10:00:00Z",
+            "deadline": "2023-01-01T11:00:00.5+00:30"
+        });
+
+        assert!(evaluate(&rule, &input, &json!({})).unwrap());
+    }
+
+    #[test]
+    fn describes_rules_as_conditions() {
+        let rule = json!({
+            "And": [
+                { "Variable": "$.accepted", "IsPresent": true },
+                { "Not": { "Variable": "$.name", "StringMatches": "test-*" } },
+                { "Or": [
+                    { "Variable": "$.size", "NumericGreaterThan": 10240 },
+                    { "Variable": "$.created", "TimestampLessThanPath": "$.deadline" }
+                ] }
+            ]
+        });
+
+        assert_eq!(
+            describe(&rule),
+            "IsPresent($.accepted) && !($.name matches \"test-*\") && ($.size > 10240 || $.created < $.deadline)"
+        );
+    }
+
+    #[test]
+    fn matches_star_patterns() {
+        assert!(string_matches("log-2023.txt", "log-*.txt"));
+        assert!(string_matches("a*b", "a\\*b"));
+        assert!(!string_matches("ab", "a\\*b"));
+        assert!(string_matches("anything", "*"));
+    }
+}
@@ -0,0 +1,475 @@
+//! Intrinsic functions (`States.Format`, `States.StringToJson`, ...) used in payload templates.
+
+use serde_json::{Map, Value};
+
+use super::{path, StatesError, STATES_INTRINSIC_FAILURE};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Argument {
+    Literal(Value),
+    Path(String),
+    Call(String, Vec<Argument>),
+}
+
+/// Whether a `.$` value is an intrinsic function call rather than a path.
+pub fn is_intrinsic(expression: &str) -> bool {
+    return expression.trim_start().starts_with("States.");
+}
+
+pub fn evaluate(expression: &str, input: &Value, context: &Value) -> Result<Value, StatesError> {
+    let mut parser = Parser {
+        source: expression,
+        position: 0,
+    };
+    let call = parser.argument()?;
+    parser.skip_whitespace();
+    if parser.position != expression.len() || !matches!(call, Argument::Call(..)) {
+        return Err(failure(format!(
+            "Invalid intrinsic function expression '{}'",
+            expression
+        )));
+    }
+
+    return resolve(&call, input, context, false);
+}
+
+fn failure(cause: impl Into<String>) -> StatesError {
+    return StatesError::new(STATES_INTRINSIC_FAILURE, cause);
+}
+
+/// String literals keep their `\{`/`\}` escapes only when they are the `States.Format`
+/// template, so escaped braces are not mistaken for placeholders.
+fn resolve(
+    argument: &Argument,
+    input: &Value,
+    context: &Value,
+    keep_escapes: bool,
+) -> Result<Value, StatesError> {
+    return match argument {
+        Argument::Literal(Value::String(string)) if !keep_escapes => Ok(Value::String(
+            string.replace("\\{", "{").replace("\\}", "}"),
+        )),
+        Argument::Literal(value) => Ok(value.clone()),
+        Argument::Path(expression) => path::select(expression, input, context, "Parameters"),
+        Argument::Call(name, arguments) => {
+            let values = arguments
+                .iter()
+                .enumerate()
+                .map(|(index, argument)| {
+                    resolve(
+                        argument,
+                        input,
+                        context,
+                        name == "States.Format" && index == 0,
+                    )
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            call(name, values)
+        }
+    };
+}
+
+fn call(name: &str, arguments: Vec<Value>) -> Result<Value, StatesError> {
+    let arity = |expected: usize| {
+        if arguments.len() == expected {
+            Ok(())
+        } else {
+            Err(failure(format!(
+                "{} expects {} arguments but received {}",
+                name,
+                expected,
+                arguments.len()
+            )))
+        }
+    };
+
+    return match name {
+        "States.Format" => {
+            let template = string_argument(name, arguments.first())?;
+            format(template, &arguments[1..])
+        }
+        "States.StringToJson" => {
+            arity(1)?;
+            serde_json::from_str(string_argument(name, arguments.first())?)
+                .map_err(|error| failure(format!("{}: {}", name, error)))
+        }
+        "States.JsonToString" => {
+            arity(1)?;
+            Ok(Value::String(arguments[0].to_string()))
+        }
+        "States.Array" => Ok(Value::Array(arguments)),
+        "States.ArrayPartition" => {
+            arity(2)?;
+            let array = array_argument(name, &arguments[0])?;
+            let size = integer_argument(name, &arguments[1])?;
+            if size <= 0 {
+                return Err(failure(format!("{} expects a positive chunk size", name)));
+            }
+            Ok(Value::Array(
+                array
+                    .chunks(size as usize)
+                    .map(|chunk| Value::Array(chunk.to_vec()))
+                    .collect(),
+            ))
+        }
+        "States.ArrayContains" => {
+            arity(2)?;
+            let array = array_argument(name, &arguments[0])?;
+            Ok(Value::Bool(array.contains(&arguments[1])))
+        }
+        "States.ArrayRange" => {
+            arity(3)?;
+            let start = integer_argument(name, &arguments[0])?;
+            let end = integer_argument(name, &arguments[1])?;
+            let step = integer_argument(name, &arguments[2])?;
+            if step == 0 {
+                return Err(failure(format!("{} expects a non-zero step", name)));
+            }
+            let mut values = Vec::new();
+            let mut current = start;
+            while (step > 0 && current <= end) || (step < 0 && current >= end) {
+                values.push(Value::from(current));
+                current += step;
+            }
+            Ok(Value::Array(values))
+        }
+        "States.ArrayGetItem" => {
+            arity(2)?;
+            let array = array_argument(name, &arguments[0])?;
+            let index = integer_argument(name, &arguments[1])?;
+            array
+                .get(index.max(0) as usize)
+                .filter(|_| index >= 0)
+                .cloned()
+                .ok_or_else(|| failure(format!("{}: index {} is out of bounds", name, index)))
+        }
+        "States.ArrayLength" => {
+            arity(1)?;
+            Ok(Value::from(array_argument(name, &arguments[0])?.len()))
+        }
+        "States.ArrayUnique" => {
+            arity(1)?;
+            let mut unique: Vec<Value> = Vec::new();
+            for value in array_argument(name, &arguments[0])? {
+                if !unique.contains(value) {
+                    unique.push(value.clone());
+                }
+            }
+            Ok(Value::Array(unique))
+        }
+        "States.JsonMerge" => {
+            arity(3)?;
+            if arguments[2] != Value::Bool(false) {
+                return Err(failure(format!("{} only supports shallow merges", name)));
+            }
+            match (&arguments[0], &arguments[1]) {
+                (Value::Object(left), Value::Object(right)) => {
+                    let mut merged: Map<String, Value> = left.clone();
+                    merged.extend(right.clone());
+                    Ok(Value::Object(merged))
+                }
+                _ => Err(failure(format!("{} expects two objects", name))),
+            }
+        }
+        "States.MathAdd" => {
+            arity(2)?;
+            let left = integer_argument(name, &arguments[0])?;
+            let right = integer_argument(name, &arguments[1])?;
+            Ok(Value::from(left + right))
+        }
+        "States.MathRandom" => {
+            if arguments.len() < 2 {
+                return Err(failure(format!("{} expects a start and an end", name)));
+            }
+            let start = integer_argument(name, &arguments[0])?;
+            let end = integer_argument(name, &arguments[1])?;
+            if end <= start {
+                return Err(failure(format!("{} expects start < end", name)));
+            }
+            let random = uuid::Uuid::new_v4().as_u128() % (end - start) as u128;
+            Ok(Value::from(start + random as i64))
+        }
+        "States.StringSplit" => {
+            arity(2)?;
+            let value = string_argument(name, arguments.first())?;
+            let delimiters = string_argument(name, arguments.get(1))?;
+            Ok(Value::Array(
+                value
+                    .split(|character| delimiters.contains(character))
+                    .filter(|part| !part.is_empty())
+                    .map(|part| Value::String(part.to_string()))
+                    .collect(),
+            ))
+        }
+        "States.Base64Encode" => {
+            arity(1)?;
+            Ok(Value::String(base64_encode(
+                string_argument(name, arguments.first())?.as_bytes(),
+            )))
+        }
+        "States.Base64Decode" => {
+            arity(1)?;
+            let bytes = base64_decode(string_argument(name, arguments.first())?)
+                .ok_or_else(|| failure(format!("{}: invalid base64 input", name)))?;
+            String::from_utf8(bytes)
+                .map(Value::String)
+                .map_err(|error| failure(format!("{}: {}", name, error)))
+        }
+        "States.UUID" => {
+            arity(0)?;
+            Ok(Value::String(uuid::Uuid::new_v4().to_string()))
+        }
+        _ => Err(failure(format!(
+            "The intrinsic function {} is not supported",
+            name
+        ))),
+    };
+}
+
+fn format(template: &str, arguments: &[Value]) -> Result<Value, StatesError> {
+    let mut output = String::new();
+    let mut arguments = arguments.iter();
+    let mut characters = template.chars().peekable();
+    while let Some(character) = characters.next() {
+        match character {
+            '\\' if matches!(characters.peek(), Some('{') | Some('}')) => {
+                output.extend(characters.next());
+            }
+            '{' if characters.peek() == Some(&'}') => {
+                characters.next();
+                let argument = arguments
+                    .next()
+                    .ok_or_else(|| failure("States.Format has more placeholders than arguments"))?;
+                match argument {
+                    Value::String(string) => output.push_str(string),
+                    other => output.push_str(&other.to_string()),
+                }
+            }
+            _ => output.push(character),
+        }
+    }
+    if arguments.next().is_some() {
+        return Err(failure(
+            "States.Format has more arguments than placeholders",
+        ));
+    }
+
+    return Ok(Value::String(output));
+}
+
+fn string_argument<'a>(name: &str, value: Option<&'a Value>) -> Result<&'a str, StatesError> {
+    return value
+        .and_then(Value::as_str)
+        .ok_or_else(|| failure(format!("{} expects a string argument", name)));
+}
+
+fn array_argument<'a>(name: &str, value: &'a Value) -> Result<&'a Vec<Value>, StatesError> {
+    return value
+        .as_array()
+        .ok_or_else(|| failure(format!("{} expects an array argument", name)));
+}
+
+fn integer_argument(name: &str, value: &Value) -> Result<i64, StatesError> {
+    return value
+        .as_i64()
+        .or_else(|| {
+            value
+                .as_f64()
+                .filter(|number| number.fract() == 0.0)
+                .map(|number| number as i64)
+        })
+        .ok_or_else(|| failure(format!("{} expects an integer argument", name)));
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    for chunk in bytes.chunks(3) {
+        let buffer = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let combined = (buffer[0] as u32) << 16 | (buffer[1] as u32) << 8 | buffer[2] as u32;
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (combined >> (18 - index * 6)) & 0x3f;
+                output.push(BASE64_ALPHABET[sextet as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+
+    return output;
+}
+
+fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for character in encoded.trim_end_matches('=').bytes() {
+        let sextet = BASE64_ALPHABET.iter().position(|&c| c == character)? as u32;
+        buffer = buffer << 6 | sextet;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    return Some(bytes);
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        return &self.source[self.position..];
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn error(&self, reason: &str) -> StatesError {
+        return failure(format!(
+            "Invalid intrinsic function expression '{}': {} at position {}",
+            self.source, reason, self.position
+        ));
+    }
+
+    fn argument(&mut self) -> Result<Argument, StatesError> {
+        self.skip_whitespace();
+        let rest = self.rest();
+
+        if rest.starts_with('\'') {
+            return self.string();
+        }
+        if rest.starts_with('$') {
+            let end = self.token_end();
+            let expression = rest[..end].to_string();
+            self.position += end;
+            return Ok(Argument::Path(expression));
+        }
+        if rest.starts_with("States.") {
+            let open = rest.find('(').ok_or_else(|| self.error("expected '('"))?;
+            let name = rest[..open].trim().to_string();
+            self.position += open + 1;
+            let mut arguments = Vec::new();
+            loop {
+                self.skip_whitespace();
+                if self.rest().starts_with(')') {
+                    self.position += 1;
+                    break;
+                }
+                if !arguments.is_empty() {
+                    if !self.rest().starts_with(',') {
+                        return Err(self.error("expected ',' or ')'"));
+                    }
+                    self.position += 1;
+                }
+                arguments.push(self.argument()?);
+            }
+            return Ok(Argument::Call(name, arguments));
+        }
+
+        let end = self.token_end();
+        let literal = rest[..end].trim();
+        self.position += end;
+
+        return serde_json::from_str(literal)
+            .map(Argument::Literal)
+            .map_err(|_| self.error("expected a string, number, boolean, null, path or call"));
+    }
+
+    /// The end of a bare token, i.e. the next top-level `,` or `)`.
+    fn token_end(&self) -> usize {
+        let rest = self.rest();
+        let mut depth = 0;
+        for (index, character) in rest.char_indices() {
+            match character {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                ',' | ')' if depth == 0 => return index,
+                _ => {}
+            }
+        }
+
+        return rest.len();
+    }
+
+    fn string(&mut self) -> Result<Argument, StatesError> {
+        let mut value = String::new();
+        let mut characters = self.rest().char_indices().skip(1);
+        while let Some((index, character)) = characters.next() {
+            match character {
+                '\\' => match characters.next() {
+                    Some((_, escaped @ ('{' | '}'))) => {
+                        value.push('\\');
+                        value.push(escaped);
+                    }
+                    Some((_, escaped)) => value.push(escaped),
+                    None => break,
+                },
+                '\'' => {
+                    self.position += index + 1;
+                    return Ok(Argument::Literal(Value::String(value)));
+                }
+                _ => value.push(character),
+            }
+        }
+
+        return Err(self.error("unterminated string"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn evaluates_nested_calls_with_paths_and_escapes() {
+        let input = json!({ "orderId": "42", "items": [1, 2, 3, 4, 5] });
+        let context = json!({});
+
+        assert_eq!(
+            evaluate(
+                "States.Format('Order {} has {} items \\{\\}', $.orderId, States.ArrayLength($.items))",
+                &input,
+                &context
+            )
+            .unwrap(),
+            json!("Order 42 has 5 items {}")
+        );
+        assert_eq!(
+            evaluate("States.ArrayPartition($.items, 2)", &input, &context).unwrap(),
+            json!([[1, 2], [3, 4], [5]])
+        );
+        assert_eq!(
+            evaluate(
+                "States.Base64Decode(States.Base64Encode('rust'))",
+                &input,
+                &context
+            )
+            .unwrap(),
+            json!("rust")
+        );
+    }
+
+    #[test]
+    fn reports_unknown_functions_as_intrinsic_failures() {
+        let error = evaluate("States.Nope()", &json!({}), &json!({})).unwrap_err();
+
+        assert_eq!(error.error, STATES_INTRINSIC_FAILURE);
+    }
+}
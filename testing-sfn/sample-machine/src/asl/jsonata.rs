@@ -0,0 +1,1355 @@
+//! A JSONata evaluator for the `{% ... %}` expressions of `QueryLanguage: JSONata` states.
+//!
+//! The supported subset is what state machines tend to use: field paths with predicates and
+//! wildcards, arithmetic, comparison, boolean and string operators, conditionals, array (with
+//! ranges) and object constructors, variable bindings in blocks, lambdas, the `~>` operator and
+//! most of the function library, plus the functions Step Functions adds (`$partition`, `$range`,
+//! `$random`, `$uuid` and `$parse`). Regular expressions, group-by, descendant (`**`) and
+//! parent (`%`) operators, date formatting and `$hash` are not supported.
+//!
+//! Every failure, including syntax errors, is a `States.QueryEvaluationError`.
+
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use serde_json::{Map, Number, Value};
+
+use super::{StatesError, STATES_QUERY_EVALUATION_ERROR};
+use crate::history::{now, to_rfc3339};
+
+/// Evaluates `expression` with `$` bound to `input` and every entry of `variables` bound to
+/// `$<name>`. Returns `None` when the result is undefined.
+pub fn evaluate(
+    expression: &str,
+    input: &Value,
+    variables: &Map<String, Value>,
+) -> Result<Option<Value>, StatesError> {
+    let node = parse(expression)?;
+    let mut environment = Environment::new();
+    environment.insert("$".to_string(), Item::Value(input.clone()));
+    for (name, value) in variables {
+        environment.insert(name.clone(), Item::Value(value.clone()));
+    }
+
+    return match eval(&node, &Item::Value(input.clone()), &mut environment)? {
+        Item::Undefined => Ok(None),
+        Item::Value(value) => Ok(Some(value)),
+        Item::Function(_) => Err(failure(format!(
+            "The expression '{}' evaluates to a function",
+            expression.trim()
+        ))),
+    };
+}
+
+fn failure(cause: impl Into<String>) -> StatesError {
+    return StatesError::new(STATES_QUERY_EVALUATION_ERROR, cause);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    String(String),
+    Name(String),
+    Variable(String),
+    Operator(&'static str),
+    End,
+}
+
+const OPERATORS: &[&str] = &[
+    "..", ":=", "!=", "<=", ">=", "~>", ".", "[", "]", "{", "}", "(", ")", ",", ":", ";", "?", "+",
+    "-", "*", "/", "%", "&", "=", "<", ">",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, StatesError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_';
+
+    while position < chars.len() {
+        let c = chars[position];
+        let rest: String = chars[position..chars.len().min(position + 2)]
+            .iter()
+            .collect();
+        if c.is_whitespace() {
+            position += 1;
+        } else if rest == "/*" {
+            let end = (position + 2..chars.len().saturating_sub(1))
+                .find(|index| chars[*index] == '*' && chars[*index + 1] == '/')
+                .ok_or_else(|| failure("Unterminated comment"))?;
+            position = end + 2;
+        } else if c.is_ascii_digit() {
+            let start = position;
+            while position < chars.len() && chars[position].is_ascii_digit() {
+                position += 1;
+            }
+            if position + 1 < chars.len()
+                && chars[position] == '.'
+                && chars[position + 1].is_ascii_digit()
+            {
+                position += 1;
+                while position < chars.len() && chars[position].is_ascii_digit() {
+                    position += 1;
+                }
+            }
+            if position < chars.len() && matches!(chars[position], 'e' | 'E') {
+                position += 1;
+                if position < chars.len() && matches!(chars[position], '+' | '-') {
+                    position += 1;
+                }
+                while position < chars.len() && chars[position].is_ascii_digit() {
+                    position += 1;
+                }
+            }
+            let text: String = chars[start..position].iter().collect();
+            let number = text
+                .parse()
+                .map_err(|_| failure(format!("Invalid number '{}'", text)))?;
+            tokens.push(Token::Number(number));
+        } else if c == '"' || c == '\'' {
+            let (string, end) = string_literal(&chars, position)?;
+            tokens.push(Token::String(string));
+            position = end;
+        } else if c == '`' {
+            let end = (position + 1..chars.len())
+                .find(|index| chars[*index] == '`')
+                .ok_or_else(|| failure("Unterminated quoted name"))?;
+            tokens.push(Token::Name(chars[position + 1..end].iter().collect()));
+            position = end + 1;
+        } else if c == '$' {
+            let start = position + 1;
+            if chars.get(start) == Some(&'$') {
+                tokens.push(Token::Variable("$".to_string()));
+                position = start + 1;
+                continue;
+            }
+            position = start;
+            while position < chars.len() && is_name_char(chars[position]) {
+                position += 1;
+            }
+            tokens.push(Token::Variable(chars[start..position].iter().collect()));
+        } else if is_name_char(c) {
+            let start = position;
+            while position < chars.len() && is_name_char(chars[position]) {
+                position += 1;
+            }
+            tokens.push(Token::Name(chars[start..position].iter().collect()));
+        } else {
+            let operator = OPERATORS
+                .iter()
+                .find(|operator| rest.starts_with(**operator))
+                .ok_or_else(|| failure(format!("Unexpected character '{}'", c)))?;
+            tokens.push(Token::Operator(operator));
+            position += operator.len();
+        }
+    }
+    tokens.push(Token::End);
+
+    return Ok(tokens);
+}
+
+fn string_literal(chars: &[char], start: usize) -> Result<(String, usize), StatesError> {
+    let quote = chars[start];
+    let mut string = String::new();
+    let mut position = start + 1;
+    loop {
+        let c = *chars
+            .get(position)
+            .ok_or_else(|| failure("Unterminated string literal"))?;
+        position += 1;
+        if c == quote {
+            return Ok((string, position));
+        }
+        if c != '\\' {
+            string.push(c);
+            continue;
+        }
+        let escaped = *chars
+            .get(position)
+            .ok_or_else(|| failure("Unterminated string literal"))?;
+        position += 1;
+        string.push(match escaped {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'u' => {
+                let hex: String = chars
+                    .get(position..position + 4)
+                    .unwrap_or(&[])
+                    .iter()
+                    .collect();
+                position += 4;
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| failure(format!("Invalid unicode escape '\\u{}'", hex)))?
+            }
+            other => other,
+        });
+    }
+}
+
+#[derive(Debug)]
+struct Lambda {
+    parameters: Vec<String>,
+    body: Node,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Literal(Value),
+    Field(String),
+    Wildcard,
+    /// `$` is the context value, `$$` the root input, anything else a bound variable.
+    Variable(String),
+    Path(Box<Node>, Box<Node>),
+    Filter(Box<Node>, Box<Node>),
+    Negate(Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+    /// `a..b`, only inside array constructors.
+    Range(Box<Node>, Box<Node>),
+    Array(Vec<Node>),
+    Object(Vec<(Node, Node)>),
+    Condition(Box<Node>, Box<Node>, Option<Box<Node>>),
+    Block(Vec<Node>),
+    Bind(String, Box<Node>),
+    Call(Box<Node>, Vec<Node>),
+    Lambda(Rc<Lambda>),
+    Apply(Box<Node>, Box<Node>),
+}
+
+fn parse(expression: &str) -> Result<Node, StatesError> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        position: 0,
+    };
+    let node = parser.expression(0)?;
+    if parser.peek() != &Token::End {
+        return Err(failure(format!(
+            "Unexpected {:?} in '{}'",
+            parser.peek(),
+            expression.trim()
+        )));
+    }
+
+    return Ok(node);
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        return &self.tokens[self.position];
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token != Token::End {
+            self.position += 1;
+        }
+
+        return token;
+    }
+
+    fn is_operator(&self, operator: &str) -> bool {
+        return matches!(self.peek(), Token::Operator(found) if *found == operator);
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), StatesError> {
+        return match self.next() {
+            Token::Operator(found) if found == operator => Ok(()),
+            other => Err(failure(format!(
+                "Expected '{}' but found {:?}",
+                operator, other
+            ))),
+        };
+    }
+
+    /// The binding power of the infix operator at the current position, if there is one.
+    fn infix(&self) -> Option<(&'static str, u8)> {
+        let operator = match self.peek() {
+            Token::Operator(operator) => *operator,
+            Token::Name(name) if name == "and" => "and",
+            Token::Name(name) if name == "or" => "or",
+            Token::Name(name) if name == "in" => "in",
+            _ => return None,
+        };
+        let power = match operator {
+            "[" | "(" => 80,
+            "." => 75,
+            "*" | "/" | "%" => 60,
+            "+" | "-" | "&" => 50,
+            "=" | "!=" | "<" | "<=" | ">" | ">=" | "in" | "~>" => 40,
+            "and" => 30,
+            "or" => 25,
+            "?" => 20,
+            ":=" => 10,
+            _ => return None,
+        };
+
+        return Some((operator, power));
+    }
+
+    fn expression(&mut self, min_power: u8) -> Result<Node, StatesError> {
+        let mut left = self.prefix()?;
+        while let Some((operator, power)) = self.infix() {
+            if power <= min_power {
+                break;
+            }
+            self.next();
+            left = match operator {
+                "." => Node::Path(Box::new(left), Box::new(self.expression(power)?)),
+                "[" => {
+                    if self.is_operator("]") {
+                        self.next();
+                        continue;
+                    }
+                    let predicate = self.expression(0)?;
+                    self.expect("]")?;
+                    Node::Filter(Box::new(left), Box::new(predicate))
+                }
+                "(" => Node::Call(Box::new(left), self.list(")")?),
+                "?" => {
+                    let then = self.expression(0)?;
+                    let otherwise = if self.is_operator(":") {
+                        self.next();
+                        Some(Box::new(self.expression(0)?))
+                    } else {
+                        None
+                    };
+                    Node::Condition(Box::new(left), Box::new(then), otherwise)
+                }
+                ":=" => match left {
+                    Node::Variable(name) if name != "$" && !name.is_empty() => {
+                        Node::Bind(name, Box::new(self.expression(power - 1)?))
+                    }
+                    _ => return Err(failure("The left side of ':=' must be a variable name")),
+                },
+                "~>" => Node::Apply(Box::new(left), Box::new(self.expression(power)?)),
+                _ => Node::Binary(operator, Box::new(left), Box::new(self.expression(power)?)),
+            };
+        }
+
+        return Ok(left);
+    }
+
+    fn prefix(&mut self) -> Result<Node, StatesError> {
+        return match self.next() {
+            Token::Number(number) => Ok(Node::Literal(number_value(number)?)),
+            Token::String(string) => Ok(Node::Literal(Value::String(string))),
+            Token::Variable(name) => Ok(Node::Variable(name)),
+            Token::Name(name) => match name.as_str() {
+                "true" => Ok(Node::Literal(Value::Bool(true))),
+                "false" => Ok(Node::Literal(Value::Bool(false))),
+                "null" => Ok(Node::Literal(Value::Null)),
+                "function" if self.is_operator("(") => self.lambda(),
+                _ => Ok(Node::Field(name)),
+            },
+            Token::Operator("-") => Ok(Node::Negate(Box::new(self.expression(70)?))),
+            Token::Operator("*") => Ok(Node::Wildcard),
+            Token::Operator("(") => {
+                let mut nodes = Vec::new();
+                while !self.is_operator(")") {
+                    nodes.push(self.expression(0)?);
+                    if !self.is_operator(")") {
+                        self.expect(";")?;
+                    }
+                }
+                self.next();
+                Ok(Node::Block(nodes))
+            }
+            Token::Operator("[") => {
+                let mut items = Vec::new();
+                while !self.is_operator("]") {
+                    let item = self.expression(0)?;
+                    if self.is_operator("..") {
+                        self.next();
+                        let end = self.expression(0)?;
+                        items.push(Node::Range(Box::new(item), Box::new(end)));
+                    } else {
+                        items.push(item);
+                    }
+                    if !self.is_operator("]") {
+                        self.expect(",")?;
+                    }
+                }
+                self.next();
+                Ok(Node::Array(items))
+            }
+            Token::Operator("{") => {
+                let mut entries = Vec::new();
+                while !self.is_operator("}") {
+                    let key = self.expression(0)?;
+                    self.expect(":")?;
+                    entries.push((key, self.expression(0)?));
+                    if !self.is_operator("}") {
+                        self.expect(",")?;
+                    }
+                }
+                self.next();
+                Ok(Node::Object(entries))
+            }
+            other => Err(failure(format!("Unexpected {:?}", other))),
+        };
+    }
+
+    /// `function($a, $b) { body }`, after the `function` keyword.
+    fn lambda(&mut self) -> Result<Node, StatesError> {
+        self.expect("(")?;
+        let mut parameters = Vec::new();
+        while !self.is_operator(")") {
+            match self.next() {
+                Token::Variable(name) if !name.is_empty() => parameters.push(name),
+                other => return Err(failure(format!("Invalid function parameter {:?}", other))),
+            }
+            if !self.is_operator(")") {
+                self.expect(",")?;
+            }
+        }
+        self.next();
+        self.expect("{")?;
+        let mut body = Vec::new();
+        while !self.is_operator("}") {
+            body.push(self.expression(0)?);
+            if !self.is_operator("}") {
+                self.expect(";")?;
+            }
+        }
+        self.next();
+
+        return Ok(Node::Lambda(Rc::new(Lambda {
+            parameters,
+            body: Node::Block(body),
+        })));
+    }
+
+    /// Comma separated expressions up to `close`, which is consumed.
+    fn list(&mut self, close: &str) -> Result<Vec<Node>, StatesError> {
+        let mut nodes = Vec::new();
+        while !self.is_operator(close) {
+            nodes.push(self.expression(0)?);
+            if !self.is_operator(close) {
+                self.expect(",")?;
+            }
+        }
+        self.next();
+
+        return Ok(nodes);
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Function {
+    Lambda(Rc<Lambda>, Environment),
+    Builtin(String),
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Undefined,
+    Value(Value),
+    Function(Function),
+}
+
+impl Item {
+    fn value(&self) -> Option<&Value> {
+        return match self {
+            Item::Value(value) => Some(value),
+            _ => None,
+        };
+    }
+
+    fn from_values(mut values: Vec<Value>) -> Self {
+        return match values.len() {
+            0 => Item::Undefined,
+            1 => Item::Value(values.remove(0)),
+            _ => Item::Value(Value::Array(values)),
+        };
+    }
+}
+
+type Environment = BTreeMap<String, Item>;
+
+fn eval(node: &Node, context: &Item, environment: &mut Environment) -> Result<Item, StatesError> {
+    return match node {
+        Node::Literal(value) => Ok(Item::Value(value.clone())),
+        Node::Field(name) => Ok(map_values(context, |value| match value {
+            Value::Object(object) => object.get(name).cloned(),
+            _ => None,
+        })),
+        Node::Wildcard => Ok(map_values(context, |value| match value {
+            Value::Object(object) => Some(Value::Array(object.values().cloned().collect())),
+            _ => None,
+        })),
+        Node::Variable(name) if name.is_empty() => Ok(context.clone()),
+        Node::Variable(name) => Ok(match environment.get(name) {
+            Some(item) => item.clone(),
+            None if BUILTINS.contains(&name.as_str()) => {
+                Item::Function(Function::Builtin(name.clone()))
+            }
+            None => Item::Undefined,
+        }),
+        Node::Path(left, right) => {
+            let left = eval(left, context, environment)?;
+            match left {
+                Item::Value(Value::Array(items)) => {
+                    let mut values = Vec::new();
+                    for item in items {
+                        match eval(right, &Item::Value(item), environment)? {
+                            Item::Value(Value::Array(nested))
+                                if !matches!(**right, Node::Array(_)) =>
+                            {
+                                values.extend(nested)
+                            }
+                            Item::Value(value) => values.push(value),
+                            _ => {}
+                        }
+                    }
+                    Ok(Item::from_values(values))
+                }
+                Item::Value(_) => eval(right, &left, environment),
+                _ => Ok(Item::Undefined),
+            }
+        }
+        Node::Filter(left, predicate) => {
+            let items = match eval(left, context, environment)? {
+                Item::Value(Value::Array(items)) => items,
+                Item::Value(value) => vec![value],
+                _ => return Ok(Item::Undefined),
+            };
+            let length = items.len() as f64;
+            let mut values = Vec::new();
+            for (index, item) in items.into_iter().enumerate() {
+                let keep = match eval(predicate, &Item::Value(item.clone()), environment)? {
+                    Item::Value(Value::Number(number)) => {
+                        let wanted = number.as_f64().unwrap_or(f64::NAN).floor();
+                        let wanted = if wanted < 0.0 {
+                            length + wanted
+                        } else {
+                            wanted
+                        };
+                        wanted == index as f64
+                    }
+                    result => truthy(&result),
+                };
+                if keep {
+                    values.push(item);
+                }
+            }
+            Ok(Item::from_values(values))
+        }
+        Node::Negate(operand) => match eval(operand, context, environment)? {
+            Item::Undefined => Ok(Item::Undefined),
+            Item::Value(Value::Number(number)) => {
+                Ok(Item::Value(number_value(-number.as_f64().unwrap_or(0.0))?))
+            }
+            _ => Err(failure("The operand of '-' must be a number")),
+        },
+        Node::Binary(operator, left, right) => binary(operator, left, right, context, environment),
+        Node::Range(..) => Err(failure("A range is only allowed in an array constructor")),
+        Node::Array(nodes) => {
+            let mut values = Vec::new();
+            for node in nodes {
+                if let Node::Range(start, end) = node {
+                    let start = eval(start, context, environment)?;
+                    let end = eval(end, context, environment)?;
+                    let (start, end) = match (integer(&start), integer(&end)) {
+                        (Some(start), Some(end)) => (start, end),
+                        _ => return Err(failure("The bounds of a range must be integers")),
+                    };
+                    values.extend((start..=end).map(Value::from));
+                    continue;
+                }
+                match eval(node, context, environment)? {
+                    Item::Value(value) => values.push(value),
+                    Item::Function(_) => return Err(failure("An array cannot hold a function")),
+                    Item::Undefined => {}
+                }
+            }
+            Ok(Item::Value(Value::Array(values)))
+        }
+        Node::Object(entries) => {
+            let mut object = Map::new();
+            for (key, value) in entries {
+                let key = match eval(key, context, environment)? {
+                    Item::Value(Value::String(key)) => key,
+                    _ => return Err(failure("Object keys must evaluate to strings")),
+                };
+                match eval(value, context, environment)? {
+                    Item::Value(value) => {
+                        object.insert(key, value);
+                    }
+                    Item::Function(_) => return Err(failure("An object cannot hold a function")),
+                    Item::Undefined => {}
+                }
+            }
+            Ok(Item::Value(Value::Object(object)))
+        }
+        Node::Condition(condition, then, otherwise) => {
+            if truthy(&eval(condition, context, environment)?) {
+                eval(then, context, environment)
+            } else {
+                match otherwise {
+                    Some(otherwise) => eval(otherwise, context, environment),
+                    None => Ok(Item::Undefined),
+                }
+            }
+        }
+        Node::Block(nodes) => {
+            let mut scope = environment.clone();
+            let mut result = Item::Undefined;
+            for node in nodes {
+                result = eval(node, context, &mut scope)?;
+            }
+            Ok(result)
+        }
+        Node::Bind(name, value) => {
+            let value = eval(value, context, environment)?;
+            environment.insert(name.clone(), value.clone());
+            Ok(value)
+        }
+        Node::Lambda(lambda) => Ok(Item::Function(Function::Lambda(
+            lambda.clone(),
+            environment.clone(),
+        ))),
+        Node::Call(function, arguments) => {
+            let function = eval(function, context, environment)?;
+            let arguments = arguments
+                .iter()
+                .map(|argument| eval(argument, context, environment))
+                .collect::<Result<Vec<_>, _>>()?;
+            apply(&function, arguments)
+        }
+        Node::Apply(left, right) => {
+            let value = eval(left, context, environment)?;
+            match &**right {
+                Node::Call(function, arguments) => {
+                    let function = eval(function, context, environment)?;
+                    let mut values = vec![value];
+                    for argument in arguments {
+                        values.push(eval(argument, context, environment)?);
+                    }
+                    apply(&function, values)
+                }
+                right => {
+                    let function = eval(right, context, environment)?;
+                    apply(&function, vec![value])
+                }
+            }
+        }
+    };
+}
+
+/// Applies `f` to the context value, or to each of its elements when it is an array, and
+/// flattens the results into a sequence.
+fn map_values(context: &Item, f: impl Fn(&Value) -> Option<Value>) -> Item {
+    let items = match context {
+        Item::Value(Value::Array(items)) => items.iter().collect(),
+        Item::Value(value) => vec![value],
+        _ => return Item::Undefined,
+    };
+    let mut values = Vec::new();
+    for item in items {
+        match f(item) {
+            Some(Value::Array(nested)) if matches!(context, Item::Value(Value::Array(_))) => {
+                values.extend(nested)
+            }
+            Some(value) => values.push(value),
+            None => {}
+        }
+    }
+    return Item::from_values(values);
+}
+
+fn binary(
+    operator: &str,
+    left: &Node,
+    right: &Node,
+    context: &Item,
+    environment: &mut Environment,
+) -> Result<Item, StatesError> {
+    match operator {
+        "and" => {
+            let result = truthy(&eval(left, context, environment)?)
+                && truthy(&eval(right, context, environment)?);
+            return Ok(Item::Value(Value::Bool(result)));
+        }
+        "or" => {
+            let result = truthy(&eval(left, context, environment)?)
+                || truthy(&eval(right, context, environment)?);
+            return Ok(Item::Value(Value::Bool(result)));
+        }
+        _ => {}
+    }
+
+    let left = eval(left, context, environment)?;
+    let right = eval(right, context, environment)?;
+    return match operator {
+        "+" | "-" | "*" | "/" | "%" => {
+            let (left, right) = match (left.value(), right.value()) {
+                (Some(Value::Number(left)), Some(Value::Number(right))) => {
+                    (left.as_f64().unwrap_or(0.0), right.as_f64().unwrap_or(0.0))
+                }
+                (None, _) | (_, None) => return Ok(Item::Undefined),
+                _ => {
+                    return Err(failure(format!(
+                        "Both sides of the '{}' operator must evaluate to numbers",
+                        operator
+                    )))
+                }
+            };
+            let result = match operator {
+                "+" => left + right,
+                "-" => left - right,
+                "*" => left * right,
+                "/" => left / right,
+                _ => left % right,
+            };
+            Ok(Item::Value(number_value(result)?))
+        }
+        "&" => {
+            let left = to_string(&left)?.unwrap_or_default();
+            let right = to_string(&right)?.unwrap_or_default();
+            Ok(Item::Value(Value::String(left + &right)))
+        }
+        "=" | "!=" => {
+            let equal = match (left.value(), right.value()) {
+                (Some(left), Some(right)) => deep_equal(left, right),
+                _ => return Ok(Item::Value(Value::Bool(false))),
+            };
+            Ok(Item::Value(Value::Bool(equal == (operator == "="))))
+        }
+        "<" | "<=" | ">" | ">=" => {
+            let ordering = match (left.value(), right.value()) {
+                (Some(Value::Number(left)), Some(Value::Number(right))) => left
+                    .as_f64()
+                    .unwrap_or(0.0)
+                    .partial_cmp(&right.as_f64().unwrap_or(0.0)),
+                (Some(Value::String(left)), Some(Value::String(right))) => Some(left.cmp(right)),
+                (None, _) | (_, None) => return Ok(Item::Undefined),
+                _ => {
+                    return Err(failure(format!(
+                        "Both sides of the '{}' operator must be numbers or strings",
+                        operator
+                    )))
+                }
+            };
+            let result = match (operator, ordering) {
+                (_, None) => false,
+                ("<", Some(ordering)) => ordering.is_lt(),
+                ("<=", Some(ordering)) => ordering.is_le(),
+                (">", Some(ordering)) => ordering.is_gt(),
+                (_, Some(ordering)) => ordering.is_ge(),
+            };
+            Ok(Item::Value(Value::Bool(result)))
+        }
+        "in" => {
+            let found = match (left.value(), right.value()) {
+                (Some(left), Some(Value::Array(values))) => {
+                    values.iter().any(|value| deep_equal(left, value))
+                }
+                (Some(left), Some(right)) => deep_equal(left, right),
+                _ => false,
+            };
+            Ok(Item::Value(Value::Bool(found)))
+        }
+        _ => Err(failure(format!("Unknown operator '{}'", operator))),
+    };
+}
+
+fn apply(function: &Item, arguments: Vec<Item>) -> Result<Item, StatesError> {
+    return match function {
+        Item::Function(Function::Lambda(lambda, captured)) => {
+            let mut scope = captured.clone();
+            let mut arguments = arguments.into_iter();
+            for parameter in &lambda.parameters {
+                scope.insert(
+                    parameter.clone(),
+                    arguments.next().unwrap_or(Item::Undefined),
+                );
+            }
+            eval(&lambda.body, &Item::Undefined, &mut scope)
+        }
+        Item::Function(Function::Builtin(name)) => builtin(name, arguments),
+        _ => Err(failure("Attempted to invoke a non-function")),
+    };
+}
+
+fn truthy(item: &Item) -> bool {
+    return match item {
+        Item::Undefined | Item::Function(_) => false,
+        Item::Value(value) => truthy_value(value),
+    };
+}
+
+fn truthy_value(value: &Value) -> bool {
+    return match value {
+        Value::Null => false,
+        Value::Bool(boolean) => *boolean,
+        Value::Number(number) => number.as_f64().unwrap_or(0.0) != 0.0,
+        Value::String(string) => !string.is_empty(),
+        Value::Array(values) => values.iter().any(truthy_value),
+        Value::Object(object) => !object.is_empty(),
+    };
+}
+
+fn deep_equal(left: &Value, right: &Value) -> bool {
+    return match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.as_f64() == right.as_f64(),
+        (Value::Array(left), Value::Array(right)) => {
+            left.len() == right.len()
+                && left
+                    .iter()
+                    .zip(right)
+                    .all(|(left, right)| deep_equal(left, right))
+        }
+        (Value::Object(left), Value::Object(right)) => {
+            left.len() == right.len()
+                && left.iter().all(|(key, value)| {
+                    right.get(key).is_some_and(|other| deep_equal(value, other))
+                })
+        }
+        (left, right) => left == right,
+    };
+}
+
+/// Integral numbers become JSON integers, like JavaScript prints them.
+fn number_value(number: f64) -> Result<Value, StatesError> {
+    if !number.is_finite() {
+        return Err(failure("The result of a numeric operation is out of range"));
+    }
+    if number.fract() == 0.0 && number.abs() < 9.0e15 {
+        return Ok(Value::from(number as i64));
+    }
+
+    return Ok(Value::Number(
+        Number::from_f64(number).expect("finite numbers are valid JSON"),
+    ));
+}
+
+fn integer(item: &Item) -> Option<i64> {
+    let number = item.value()?.as_f64()?;
+
+    return (number.fract() == 0.0).then_some(number as i64);
+}
+
+fn number_to_string(number: &Number) -> String {
+    return match number.as_f64() {
+        Some(float) if float.fract() == 0.0 && float.abs() < 9.0e15 => (float as i64).to_string(),
+        _ => number.to_string(),
+    };
+}
+
+fn to_string(item: &Item) -> Result<Option<String>, StatesError> {
+    return match item {
+        Item::Undefined => Ok(None),
+        Item::Value(Value::String(string)) => Ok(Some(string.clone())),
+        Item::Value(Value::Number(number)) => Ok(Some(number_to_string(number))),
+        Item::Value(value) => Ok(Some(value.to_string())),
+        Item::Function(_) => Err(failure("A function cannot be converted to a string")),
+    };
+}
+
+const BUILTINS: &[&str] = &[
+    "string",
+    "length",
+    "substring",
+    "substringBefore",
+    "substringAfter",
+    "uppercase",
+    "lowercase",
+    "trim",
+    "contains",
+    "split",
+    "join",
+    "replace",
+    "number",
+    "abs",
+    "floor",
+    "ceil",
+    "round",
+    "power",
+    "sqrt",
+    "sum",
+    "max",
+    "min",
+    "average",
+    "count",
+    "boolean",
+    "not",
+    "exists",
+    "append",
+    "reverse",
+    "sort",
+    "distinct",
+    "keys",
+    "lookup",
+    "merge",
+    "type",
+    "map",
+    "filter",
+    "reduce",
+    "now",
+    "millis",
+    "uuid",
+    "random",
+    "partition",
+    "range",
+    "parse",
+    "hash",
+];
+
+fn builtin(name: &str, arguments: Vec<Item>) -> Result<Item, StatesError> {
+    let argument = |index: usize| arguments.get(index).cloned().unwrap_or(Item::Undefined);
+    let string = |index: usize| -> Result<Option<String>, StatesError> {
+        return match argument(index) {
+            Item::Undefined => Ok(None),
+            Item::Value(Value::String(string)) => Ok(Some(string)),
+            _ => Err(failure(format!(
+                "Argument {} of ${} must be a string",
+                index + 1,
+                name
+            ))),
+        };
+    };
+    let number = |index: usize| -> Result<Option<f64>, StatesError> {
+        return match argument(index) {
+            Item::Undefined => Ok(None),
+            Item::Value(Value::Number(number)) => Ok(number.as_f64()),
+            _ => Err(failure(format!(
+                "Argument {} of ${} must be a number",
+                index + 1,
+                name
+            ))),
+        };
+    };
+    // Functions over arrays treat a single value as an array of one.
+    let array = |index: usize| -> Vec<Value> {
+        return match argument(index) {
+            Item::Value(Value::Array(values)) => values,
+            Item::Value(value) => vec![value],
+            _ => Vec::new(),
+        };
+    };
+    let numbers = |index: usize| -> Result<Vec<f64>, StatesError> {
+        return array(index)
+            .iter()
+            .map(|value| {
+                value
+                    .as_f64()
+                    .ok_or_else(|| failure(format!("${} expects an array of numbers", name)))
+            })
+            .collect();
+    };
+    let value = |result: Value| -> Result<Item, StatesError> { Ok(Item::Value(result)) };
+    let defined = arguments
+        .first()
+        .is_some_and(|first| !matches!(first, Item::Undefined));
+
+    return match name {
+        "string" => Ok(to_string(&argument(0))?.map_or(Item::Undefined, |s| Item::Value(s.into()))),
+        "length" => match string(0)? {
+            Some(string) => value(Value::from(string.chars().count())),
+            None => Ok(Item::Undefined),
+        },
+        "substring" => {
+            let chars: Vec<char> = match string(0)? {
+                Some(string) => string.chars().collect(),
+                None => return Ok(Item::Undefined),
+            };
+            let length = chars.len() as i64;
+            let start = number(1)?.unwrap_or(0.0) as i64;
+            let start = if start < 0 {
+                (length + start).max(0)
+            } else {
+                start.min(length)
+            };
+            let end = match number(2)? {
+                Some(count) => (start + count.max(0.0) as i64).min(length),
+                None => length,
+            };
+            value(Value::String(
+                chars[start as usize..end as usize].iter().collect(),
+            ))
+        }
+        "substringBefore" | "substringAfter" => {
+            let (string, separator) = match (string(0)?, string(1)?) {
+                (Some(string), Some(separator)) => (string, separator),
+                (string, _) => return Ok(string.map_or(Item::Undefined, |s| Item::Value(s.into()))),
+            };
+            let result = match (string.find(&separator), name) {
+                (None, _) => string.clone(),
+                (Some(index), "substringBefore") => string[..index].to_string(),
+                (Some(index), _) => string[index + separator.len()..].to_string(),
+            };
+            value(Value::String(result))
+        }
+        "uppercase" | "lowercase" | "trim" => match string(0)? {
+            Some(string) => value(Value::String(match name {
+                "uppercase" => string.to_uppercase(),
+                "lowercase" => string.to_lowercase(),
+                _ => string.split_whitespace().collect::<Vec<_>>().join(" "),
+            })),
+            None => Ok(Item::Undefined),
+        },
+        "contains" => match (string(0)?, string(1)?) {
+            (Some(string), Some(pattern)) => value(Value::Bool(string.contains(&pattern))),
+            _ => Ok(Item::Undefined),
+        },
+        "split" => {
+            let (string, separator) = match (string(0)?, string(1)?) {
+                (Some(string), Some(separator)) => (string, separator),
+                _ => return Ok(Item::Undefined),
+            };
+            let limit = number(2)?.map_or(usize::MAX, |limit| limit.max(0.0) as usize);
+            let parts: Vec<Value> = if separator.is_empty() {
+                string
+                    .chars()
+                    .map(|c| Value::String(c.to_string()))
+                    .collect()
+            } else {
+                string.split(separator.as_str()).map(Value::from).collect()
+            };
+            value(Value::Array(parts.into_iter().take(limit).collect()))
+        }
+        "join" if !defined => Ok(Item::Undefined),
+        "join" => {
+            let separator = string(1)?.unwrap_or_default();
+            let parts = array(0)
+                .into_iter()
+                .map(|part| match part {
+                    Value::String(part) => Ok(part),
+                    _ => Err(failure("$join expects an array of strings")),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            value(Value::String(parts.join(&separator)))
+        }
+        "replace" => {
+            let (string, pattern, replacement) = match (string(0)?, string(1)?, string(2)?) {
+                (Some(string), Some(pattern), Some(replacement)) => (string, pattern, replacement),
+                _ => return Ok(Item::Undefined),
+            };
+            if pattern.is_empty() {
+                return Err(failure("The pattern of $replace cannot be empty"));
+            }
+            let result = match number(3)? {
+                Some(limit) => string.replacen(&pattern, &replacement, limit.max(0.0) as usize),
+                None => string.replace(&pattern, &replacement),
+            };
+            value(Value::String(result))
+        }
+        "number" => match argument(0) {
+            Item::Undefined => Ok(Item::Undefined),
+            Item::Value(Value::Number(number)) => value(Value::Number(number)),
+            Item::Value(Value::Bool(boolean)) => value(Value::from(boolean as i64)),
+            Item::Value(Value::String(string)) => {
+                let parsed: f64 = string
+                    .trim()
+                    .parse()
+                    .map_err(|_| failure(format!("Unable to cast '{}' to a number", string)))?;
+                value(number_value(parsed)?)
+            }
+            _ => Err(failure(
+                "Unable to cast the argument of $number to a number",
+            )),
+        },
+        "abs" | "floor" | "ceil" | "sqrt" => match number(0)? {
+            Some(number) => value(number_value(match name {
+                "abs" => number.abs(),
+                "floor" => number.floor(),
+                "ceil" => number.ceil(),
+                _ if number < 0.0 => return Err(failure("$sqrt of a negative number")),
+                _ => number.sqrt(),
+            })?),
+            None => Ok(Item::Undefined),
+        },
+        "round" => match number(0)? {
+            Some(rounded_number) => {
+                // JSONata rounds half to even.
+                let scale = 10f64.powi(number(1)?.unwrap_or(0.0) as i32);
+                let scaled = rounded_number * scale;
+                let rounded = scaled.round();
+                let rounded = if (scaled - scaled.trunc()).abs() == 0.5 && rounded % 2.0 != 0.0 {
+                    rounded - scaled.signum()
+                } else {
+                    rounded
+                };
+                value(number_value(rounded / scale)?)
+            }
+            None => Ok(Item::Undefined),
+        },
+        "power" => match (number(0)?, number(1)?) {
+            (Some(base), Some(exponent)) => value(number_value(base.powf(exponent))?),
+            _ => Ok(Item::Undefined),
+        },
+        "sum" => value(number_value(numbers(0)?.iter().sum())?),
+        "max" | "min" | "average" => {
+            let numbers = numbers(0)?;
+            if numbers.is_empty() {
+                return Ok(Item::Undefined);
+            }
+            let result = match name {
+                "max" => numbers.iter().cloned().fold(f64::MIN, f64::max),
+                "min" => numbers.iter().cloned().fold(f64::MAX, f64::min),
+                _ => numbers.iter().sum::<f64>() / numbers.len() as f64,
+            };
+            value(number_value(result)?)
+        }
+        "count" => value(Value::from(array(0).len())),
+        "boolean" => match argument(0) {
+            Item::Undefined => Ok(Item::Undefined),
+            item => value(Value::Bool(truthy(&item))),
+        },
+        "not" => match argument(0) {
+            Item::Undefined => Ok(Item::Undefined),
+            item => value(Value::Bool(!truthy(&item))),
+        },
+        "exists" => value(Value::Bool(defined)),
+        "append" => {
+            let mut values = array(0);
+            values.extend(array(1));
+            match arguments
+                .iter()
+                .all(|argument| matches!(argument, Item::Undefined))
+            {
+                true => Ok(Item::Undefined),
+                false => value(Value::Array(values)),
+            }
+        }
+        "reverse" | "sort" | "distinct" | "merge" if !defined => Ok(Item::Undefined),
+        "reverse" => {
+            let mut values = array(0);
+            values.reverse();
+            value(Value::Array(values))
+        }
+        "sort" => {
+            let mut values = array(0);
+            let comparator = argument(1);
+            let mut error = None;
+            // A comparator returns true when its first argument sorts after the second.
+            let mut after = |left: &Value, right: &Value| -> bool {
+                let result = match &comparator {
+                    Item::Undefined => match (left, right) {
+                        (Value::Number(left), Value::Number(right)) => {
+                            Ok(left.as_f64() > right.as_f64())
+                        }
+                        (Value::String(left), Value::String(right)) => Ok(left > right),
+                        _ => Err(failure("$sort expects numbers or strings")),
+                    },
+                    function => {
+                        let arguments = vec![Item::Value(left.clone()), Item::Value(right.clone())];
+                        apply(function, arguments).map(|result| truthy(&result))
+                    }
+                };
+                return result.unwrap_or_else(|failed| {
+                    error.get_or_insert(failed);
+                    false
+                });
+            };
+            values.sort_by(|left, right| {
+                if after(left, right) {
+                    std::cmp::Ordering::Greater
+                } else if after(right, left) {
+                    std::cmp::Ordering::Less
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            });
+            if let Some(error) = error {
+                return Err(error);
+            }
+            value(Value::Array(values))
+        }
+        "distinct" => {
+            let mut values: Vec<Value> = Vec::new();
+            for candidate in array(0) {
+                if !values.iter().any(|value| deep_equal(value, &candidate)) {
+                    values.push(candidate);
+                }
+            }
+            value(Value::Array(values))
+        }
+        "keys" => {
+            let mut keys: Vec<Value> = Vec::new();
+            for object in array(0) {
+                for key in object.as_object().into_iter().flat_map(Map::keys) {
+                    if !keys.iter().any(|existing| existing == key) {
+                        keys.push(Value::String(key.clone()));
+                    }
+                }
+            }
+            Ok(Item::from_values(keys))
+        }
+        "lookup" => {
+            let key = string(1)?.unwrap_or_default();
+            let values = array(0)
+                .iter()
+                .filter_map(|object| object.get(&key).cloned())
+                .collect();
+            Ok(Item::from_values(values))
+        }
+        "merge" => {
+            let mut merged = Map::new();
+            for object in array(0) {
+                match object {
+                    Value::Object(object) => merged.extend(object),
+                    _ => return Err(failure("$merge expects an array of objects")),
+                }
+            }
+            value(Value::Object(merged))
+        }
+        "type" => Ok(match argument(0) {
+            Item::Undefined => Item::Undefined,
+            Item::Function(_) => Item::Value("function".into()),
+            Item::Value(result) => Item::Value(
+                match result {
+                    Value::Null => "null",
+                    Value::Bool(_) => "boolean",
+                    Value::Number(_) => "number",
+                    Value::String(_) => "string",
+                    Value::Array(_) => "array",
+                    Value::Object(_) => "object",
+                }
+                .into(),
+            ),
+        }),
+        "map" | "filter" => {
+            let function = argument(1);
+            let mut values = Vec::new();
+            for (index, item) in array(0).into_iter().enumerate() {
+                let arguments = vec![
+                    Item::Value(item.clone()),
+                    Item::Value(Value::from(index)),
+                    argument(0),
+                ];
+                let result = apply(&function, arguments)?;
+                match (name, result) {
+                    ("map", Item::Value(result)) => values.push(result),
+                    ("filter", result) if truthy(&result) => values.push(item),
+                    _ => {}
+                }
+            }
+            Ok(Item::from_values(values))
+        }
+        "reduce" => {
+            let function = argument(1);
+            let mut items = array(0).into_iter();
+            let mut accumulator = match argument(2) {
+                Item::Undefined => match items.next() {
+                    Some(first) => Item::Value(first),
+                    None => return Ok(Item::Undefined),
+                },
+                initial => initial,
+            };
+            for item in items {
+                accumulator = apply(&function, vec![accumulator, Item::Value(item)])?;
+            }
+            Ok(accumulator)
+        }
+        "now" => value(Value::String(to_rfc3339(now()))),
+        "millis" => value(Value::from((now() * 1000.0) as i64)),
+        "uuid" => value(Value::String(uuid::Uuid::new_v4().to_string())),
+        "random" => {
+            let bits = uuid::Uuid::new_v4().as_u128() & ((1 << 53) - 1);
+            value(number_value(bits as f64 / (1u64 << 53) as f64)?)
+        }
+        "partition" => {
+            let size = number(1)?.unwrap_or(0.0);
+            if size < 1.0 || size.fract() != 0.0 {
+                return Err(failure("$partition expects a positive integer size"));
+            }
+            let chunks = array(0)
+                .chunks(size as usize)
+                .map(|chunk| Value::Array(chunk.to_vec()))
+                .collect();
+            value(Value::Array(chunks))
+        }
+        "range" => {
+            let (start, end, step) = match (number(0)?, number(1)?, number(2)?) {
+                (Some(start), Some(end), Some(step)) if step != 0.0 => (start, end, step),
+                _ => {
+                    return Err(failure(
+                        "$range expects a start, an end and a non-zero step",
+                    ))
+                }
+            };
+            let mut values = Vec::new();
+            let mut current = start;
+            while (step > 0.0 && current <= end) || (step < 0.0 && current >= end) {
+                values.push(number_value(current)?);
+                current += step;
+            }
+            value(Value::Array(values))
+        }
+        "parse" => match string(0)? {
+            Some(string) => serde_json::from_str(&string)
+                .map(Item::Value)
+                .map_err(|error| failure(format!("$parse: {}", error))),
+            None => Ok(Item::Undefined),
+        },
+        _ => Err(failure(format!(
+            "The function ${} is not supported by the local executor",
+            name
+        ))),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn eval_str(expression: &str, input: Value) -> Result<Option<Value>, StatesError> {
+        let variables = json!({ "threshold": 10240, "states": { "input": input.clone() } });
+
+        return evaluate(expression, &input, variables.as_object().unwrap());
+    }
+
+    #[test]
+    fn evaluates_paths_operators_and_functions() {
+        let input = json!({
+            "url": "https://example.com",
+            "size": 20480,
+            "pages": [{ "size": 1, "tag": "a" }, { "size": 2, "tag": "b" }, { "size": 3, "tag": "a" }]
+        });
+        let cases = [
+            ("$states.input.size > $threshold", json!(true)),
+            ("size / 1024 & ' KiB'", json!("20 KiB")),
+            ("pages[tag = 'a'].size", json!([1, 3])),
+            ("pages[-1].tag", json!("a")),
+            ("$sum(pages.size) + $count(pages)", json!(9)),
+            ("$map([1..3], function($v) { $v * 2 })", json!([2, 4, 6])),
+            (
+                "{ 'big': size > 10240 ? 'yes' : 'no', 'host': $substringAfter(url, '//') }",
+                json!({ "big": "yes", "host": "example.com" }),
+            ),
+            ("( $half := size / 2; $half ~> $string() )", json!("10240")),
+            ("$partition([1, 2, 3], 2)", json!([[1, 2], [3]])),
+            ("$round(2.5) + $round(3.5)", json!(6)),
+            ("'b' in pages.tag and $exists(url)", json!(true)),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(
+                eval_str(expression, input.clone()).unwrap(),
+                Some(expected),
+                "{}",
+                expression
+            );
+        }
+        assert_eq!(eval_str("missing.field", input.clone()).unwrap(), None);
+    }
+
+    #[test]
+    fn fails_with_query_evaluation_errors() {
+        for expression in ["size + 'a'", "$nope()", "(1 + ", "$hash('a', 'SHA-256')"] {
+            let error = eval_str(expression, json!({ "size": 1 })).unwrap_err();
+            assert_eq!(error.error, STATES_QUERY_EVALUATION_ERROR, "{}", expression);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! The JSONPath subset that Amazon States Language allows: `$`/`$$` roots, dotted and bracketed
+//! field names, array indexes and the `[*]` wildcard.
+
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+use super::StatesError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Field(String),
+    Index(i64),
+    Wildcard,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    /// `true` for paths rooted at the context object (`$$`).
+    pub context: bool,
+    pub segments: Vec<Segment>,
+}
+
+impl Path {
+    pub fn parse(expression: &str) -> Result<Self, StatesError> {
+        let invalid = |reason: &str| {
+            StatesError::runtime(format!("Invalid JSONPath '{}': {}", expression, reason))
+        };
+
+        let (context, mut rest) = if let Some(rest) = expression.strip_prefix("$$") {
+            (true, rest)
+        } else if let Some(rest) = expression.strip_prefix('$') {
+            (false, rest)
+        } else {
+            return Err(invalid("paths must start with '$'"));
+        };
+
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after_dot) = rest.strip_prefix('.') {
+                let end = after_dot.find(['.', '[']).unwrap_or(after_dot.len());
+                let name = &after_dot[..end];
+                if name.is_empty() {
+                    return Err(invalid("empty field name"));
+                }
+                segments.push(if name == "*" {
+                    Segment::Wildcard
+                } else {
+                    Segment::Field(name.to_string())
+                });
+                rest = &after_dot[end..];
+            } else if let Some(after_bracket) = rest.strip_prefix('[') {
+                let end = after_bracket
+                    .find(']')
+                    .ok_or_else(|| invalid("unterminated '['"))?;
+                let inner = after_bracket[..end].trim();
+                segments.push(if inner == "*" {
+                    Segment::Wildcard
+                } else if let Some(name) = unquote(inner) {
+                    Segment::Field(name.to_string())
+                } else {
+                    let index = inner
+                        .parse::<i64>()
+                        .map_err(|_| invalid("only indexes, quoted names and '*' are supported"))?;
+                    Segment::Index(index)
+                });
+                rest = &after_bracket[end + 1..];
+            } else {
+                return Err(invalid("expected '.' or '['"));
+            }
+        }
+
+        return Ok(Self { context, segments });
+    }
+
+    pub fn is_root(&self) -> bool {
+        return self.segments.is_empty();
+    }
+
+    pub fn has_wildcard(&self) -> bool {
+        return self.segments.contains(&Segment::Wildcard);
+    }
+
+    /// Resolves the path against the input (or the context object for `$$` paths).
+    ///
+    /// Returns `None` when a field does not exist. Paths with a wildcard always resolve to an
+    /// array of every match.
+    pub fn select(&self, input: &Value, context: &Value) -> Option<Value> {
+        let root = if self.context { context } else { input };
+        let mut current = vec![root];
+        for segment in &self.segments {
+            let mut next = Vec::new();
+            for value in current {
+                match (segment, value) {
+                    (Segment::Field(name), Value::Object(object)) => {
+                        next.extend(object.get(name));
+                    }
+                    (Segment::Index(index), Value::Array(array)) => {
+                        let index = if *index < 0 {
+                            array.len() as i64 + index
+                        } else {
+                            *index
+                        };
+                        if index >= 0 {
+                            next.extend(array.get(index as usize));
+                        }
+                    }
+                    (Segment::Wildcard, Value::Array(array)) => next.extend(array.iter()),
+                    (Segment::Wildcard, Value::Object(object)) => next.extend(object.values()),
+                    _ => {}
+                }
+            }
+            if next.is_empty() && !self.has_wildcard() {
+                return None;
+            }
+            current = next;
+        }
+
+        if self.has_wildcard() {
+            return Some(Value::Array(current.into_iter().cloned().collect()));
+        }
+
+        return current.first().map(|value| (*value).clone());
+    }
+
+    /// Writes `value` at this path inside `target`, creating intermediate objects the way
+    /// `ResultPath` does.
+    pub fn set(&self, target: &mut Value, value: Value) -> Result<(), StatesError> {
+        if self.context || self.has_wildcard() {
+            return Err(StatesError::runtime(format!(
+                "'{}' is not a valid reference path",
+                self
+            )));
+        }
+
+        let mut current = target;
+        for segment in &self.segments {
+            current = match segment {
+                Segment::Field(name) => {
+                    if current.is_null() {
+                        *current = Value::Object(Map::new());
+                    }
+                    match current {
+                        Value::Object(object) => object.entry(name.clone()).or_insert(Value::Null),
+                        _ => {
+                            return Err(StatesError::new(
+                                super::STATES_RESULT_PATH_MATCH_FAILURE,
+                                format!(
+                                    "Unable to apply ReferencePath {} to input {}",
+                                    self, current
+                                ),
+                            ))
+                        }
+                    }
+                }
+                Segment::Index(index) => {
+                    let in_bounds = matches!(
+                        current,
+                        Value::Array(array) if *index >= 0 && (*index as usize) < array.len()
+                    );
+                    if !in_bounds {
+                        return Err(StatesError::new(
+                            super::STATES_RESULT_PATH_MATCH_FAILURE,
+                            format!(
+                                "Unable to apply ReferencePath {} to input {}",
+                                self, current
+                            ),
+                        ));
+                    }
+                    &mut current[*index as usize]
+                }
+                Segment::Wildcard => unreachable!("wildcards are rejected above"),
+            };
+        }
+        *current = value;
+
+        return Ok(());
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", if self.context { "$$" } else { "$" })?;
+        for segment in &self.segments {
+            match segment {
+                Segment::Field(name) if is_plain_name(name) => write!(f, ".{}", name)?,
+                Segment::Field(name) => write!(f, "['{}']", name)?,
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+                Segment::Wildcard => write!(f, "[*]")?,
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+/// Selects `expression` from the input or context, failing the way Step Functions does when the
+/// path does not exist. `field` names the ASL field for the error message.
+pub fn select(
+    expression: &str,
+    input: &Value,
+    context: &Value,
+    field: &str,
+) -> Result<Value, StatesError> {
+    let path = Path::parse(expression)?;
+
+    return path.select(input, context).ok_or_else(|| {
+        StatesError::runtime(format!(
+            "The JSONPath '{}' specified for the field '{}' could not be found in the input '{}'",
+            expression, field, input
+        ))
+    });
+}
+
+fn unquote(inner: &str) -> Option<&str> {
+    return inner
+        .strip_prefix('\'')
+        .and_then(|name| name.strip_suffix('\''))
+        .or_else(|| {
+            inner
+                .strip_prefix('"')
+                .and_then(|name| name.strip_suffix('"'))
+        });
+}
+
+fn is_plain_name(name: &str) -> bool {
+    return !name.is_empty()
+        && name
+            .chars()
+            .all(|character| character.is_alphanumeric() || character == '_' || character == '-');
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn selects_fields_indexes_and_wildcards() {
+        let input = json!({ "orders": [{ "id": "a" }, { "id": "b" }], "size": 10 });
+        let context = json!({ "Task": { "Token": "token" } });
+
+        let select = |expression: &str| Path::parse(expression).unwrap().select(&input, &context);
+
+        assert_eq!(select("$.size"), Some(json!(10)));
+        assert_eq!(select("$.orders[1].id"), Some(json!("b")));
+        assert_eq!(select("$['orders'][-1]['id']"), Some(json!("b")));
+        assert_eq!(select("$.orders[*].id"), Some(json!(["a", "b"])));
+        assert_eq!(select("$$.Task.Token"), Some(json!("token")));
+        assert_eq!(select("$.missing"), None);
+    }
+
+    #[test]
+    fn sets_values_creating_intermediate_objects() {
+        let mut target = json!({ "url": "https://www.rust-lang.org/" });
+        Path::parse("$.result.size")
+            .unwrap()
+            .set(&mut target, json!(1024))
+            .unwrap();
+
+        assert_eq!(
+            target,
+            json!({ "url": "https://www.rust-lang.org/", "result": { "size": 1024 } })
+        );
+        assert!(Path::parse("$.url.size")
+            .unwrap()
+            .set(&mut target, json!(1))
+            .is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Input and output processing: `InputPath`, `Parameters`, `ResultSelector`, `ResultPath` and
+//! `OutputPath`, applied in the order Step Functions applies them.
+
+use serde_json::{Map, Value};
+
+use super::path::{self, Path};
+use super::{intrinsics, State, StatesError};
+
+/// Applies `InputPath`. A `null` path discards the input and passes `{}` on.
+pub fn input_path(state: &State, input: &Value, context: &Value) -> Result<Value, StatesError> {
+    return filter_path(state, "InputPath", input, context);
+}
+
+/// Applies `OutputPath`. A `null` path discards the output and passes `{}` on.
+pub fn output_path(state: &State, output: &Value, context: &Value) -> Result<Value, StatesError> {
+    return filter_path(state, "OutputPath", output, context);
+}
+
+/// Applies `Parameters` (or `ItemSelector` for Map items) to the effective input.
+pub fn parameters(state: &State, input: &Value, context: &Value) -> Result<Value, StatesError> {
+    return match state.field("Parameters") {
+        Some(template) => apply_template(template, input, context),
+        None => Ok(input.clone()),
+    };
+}
+
+/// Applies `ResultSelector` to a task, Parallel or Map result.
+pub fn result_selector(
+    state: &State,
+    result: &Value,
+    context: &Value,
+) -> Result<Value, StatesError> {
+    return match state.field("ResultSelector") {
+        Some(template) => apply_template(template, result, context),
+        None => Ok(result.clone()),
+    };
+}
+
+/// Combines the raw state input and the result according to `ResultPath`.
+pub fn result_path(state: &State, input: &Value, result: Value) -> Result<Value, StatesError> {
+    return match state.field("ResultPath") {
+        None => Ok(result),
+        Some(Value::Null) => Ok(input.clone()),
+        Some(Value::String(expression)) => merge_result(expression, input, result),
+        Some(other) => Err(StatesError::runtime(format!(
+            "Invalid ResultPath {} in state '{}'",
+            other, state.name
+        ))),
+    };
+}
+
+/// Places `result` inside `input` at the reference path `expression`.
+pub fn merge_result(expression: &str, input: &Value, result: Value) -> Result<Value, StatesError> {
+    let path = Path::parse(expression)?;
+    if path.is_root() {
+        return Ok(result);
+    }
+    let mut output = input.clone();
+    path.set(&mut output, result)?;
+
+    return Ok(output);
+}
+
+/// Evaluates a payload template: every key ending in `.$` is replaced by the value of its path
+/// or intrinsic function, everything else is copied as-is.
+pub fn apply_template(
+    template: &Value,
+    input: &Value,
+    context: &Value,
+) -> Result<Value, StatesError> {
+    return match template {
+        Value::Object(object) => {
+            let mut output = Map::new();
+            for (key, value) in object {
+                match key.strip_suffix(".$") {
+                    Some(key) => {
+                        let expression = value.as_str().ok_or_else(|| {
+                            StatesError::runtime(format!(
+                                "The value for the field '{}.$' must be a valid JSONPath or a valid intrinsic function call",
+                                key
+                            ))
+                        })?;
+                        let resolved = if intrinsics::is_intrinsic(expression) {
+                            intrinsics::evaluate(expression, input, context)?
+                        } else {
+                            path::select(expression, input, context, &format!("{}.$", key))?
+                        };
+                        output.insert(key.to_string(), resolved);
+                    }
+                    None => {
+                        output.insert(key.clone(), apply_template(value, input, context)?);
+                    }
+                }
+            }
+            Ok(Value::Object(output))
+        }
+        Value::Array(values) => values
+            .iter()
+            .map(|value| apply_template(value, input, context))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+        other => Ok(other.clone()),
+    };
+}
+
+fn filter_path(
+    state: &State,
+    field: &str,
+    value: &Value,
+    context: &Value,
+) -> Result<Value, StatesError> {
+    return match state.field(field) {
+        None => Ok(value.clone()),
+        Some(Value::Null) => Ok(Value::Object(Map::new())),
+        Some(Value::String(expression)) => path::select(expression, value, context, field),
+        Some(other) => Err(StatesError::runtime(format!(
+            "Invalid {} {} in state '{}'",
+            field, other, state.name
+        ))),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn applies_every_stage_in_order() {
+        let value = json!({
+            "Type": "Task",
+            "InputPath": "$.order",
+            "Parameters": { "orderId.$": "$.id", "token.$": "$$.Task.Token", "static": [1, { "a.$": "$.id" }] },
+            "ResultSelector": { "status.$": "$.Status" },
+            "ResultPath": "$.result",
+            "OutputPath": "$.result"
+        });
+        let state = State {
+            name: "Add order",
+            value: &value,
+        };
+        let input = json!({ "order": { "id": "42" } });
+        let context = json!({ "Task": { "Token": "t" } });
+
+        let effective = input_path(&state, &input, &context).unwrap();
+        let parameters = parameters(&state, &effective, &context).unwrap();
+        assert_eq!(
+            parameters,
+            json!({ "orderId": "42", "token": "t", "static": [1, { "a": "42" }] })
+        );
+
+        let selected = result_selector(&state, &json!({ "Status": "OK" }), &context).unwrap();
+        let merged = result_path(&state, &input, selected).unwrap();
+        assert_eq!(
+            merged,
+            json!({ "order": { "id": "42" }, "result": { "status": "OK" } })
+        );
+        assert_eq!(
+            output_path(&state, &merged, &context).unwrap(),
+            json!({ "status": "OK" })
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! JSONata input and output processing, and the `Assign` field of both query languages.
+//!
+//! `QueryLanguage: JSONata` states have no `InputPath`, `Parameters`, `ResultSelector`,
+//! `ResultPath` or `OutputPath`. `Arguments` builds what a Task or Parallel branch receives,
+//! `Output` builds what the state passes on, and `Assign` sets workflow variables. All three
+//! are templates whose `{% ... %}` strings are JSONata expressions. Expressions see the
+//! variables in scope and `$states`: `input`, `context`, and `result` or `errorOutput` once
+//! there is one.
+//!
+//! `Assign` and `Output` both see the variables as they were when the state was entered: the
+//! assignments of a state do not see each other, and its `Output` does not see them either.
+
+use serde_json::{json, Map, Value};
+
+use super::{jsonata, processing, State, StatesError, STATES_QUERY_EVALUATION_ERROR};
+
+/// What the expressions of a state can read.
+#[derive(Debug, Clone, Copy)]
+pub struct Bindings<'a> {
+    pub variables: &'a Map<String, Value>,
+    pub input: &'a Value,
+    pub context: &'a Value,
+    pub result: Option<&'a Value>,
+    pub error_output: Option<&'a Value>,
+}
+
+impl<'a> Bindings<'a> {
+    pub fn new(variables: &'a Map<String, Value>, input: &'a Value, context: &'a Value) -> Self {
+        return Self {
+            variables,
+            input,
+            context,
+            result: None,
+            error_output: None,
+        };
+    }
+
+    pub fn result(mut self, result: &'a Value) -> Self {
+        self.result = Some(result);
+        return self;
+    }
+
+    pub fn error_output(mut self, error_output: &'a Value) -> Self {
+        self.error_output = Some(error_output);
+        return self;
+    }
+
+    /// The variables with the reserved `$states` added.
+    fn scope(&self) -> Map<String, Value> {
+        let mut states = json!({ "input": self.input, "context": self.context });
+        if let Some(result) = self.result {
+            states["result"] = result.clone();
+        }
+        if let Some(error_output) = self.error_output {
+            states["errorOutput"] = error_output.clone();
+        }
+        let mut scope = self.variables.clone();
+        scope.insert("states".to_string(), states);
+
+        return scope;
+    }
+}
+
+/// Whether a string is a `{% ... %}` JSONata expression.
+pub fn is_expression(value: &str) -> bool {
+    let value = value.trim();
+
+    return value.starts_with("{%") && value.ends_with("%}") && value.len() >= 4;
+}
+
+/// Evaluates a template: every `{% ... %}` string is replaced by the value of its expression,
+/// objects and arrays are walked, and anything else is copied as-is.
+pub fn evaluate(template: &Value, bindings: &Bindings) -> Result<Value, StatesError> {
+    return evaluate_in(template, bindings.input, &bindings.scope());
+}
+
+fn evaluate_in(
+    template: &Value,
+    input: &Value,
+    scope: &Map<String, Value>,
+) -> Result<Value, StatesError> {
+    return match template {
+        Value::String(string) if is_expression(string) => {
+            let trimmed = string.trim();
+            let expression = &trimmed[2..trimmed.len() - 2];
+            jsonata::evaluate(expression, input, scope)?.ok_or_else(|| {
+                StatesError::new(
+                    STATES_QUERY_EVALUATION_ERROR,
+                    format!(
+                        "The JSONata expression '{}' returned nothing (undefined)",
+                        expression.trim()
+                    ),
+                )
+            })
+        }
+        Value::Object(object) => {
+            let mut output = Map::new();
+            for (key, value) in object {
+                output.insert(key.clone(), evaluate_in(value, input, scope)?);
+            }
+            Ok(Value::Object(output))
+        }
+        Value::Array(values) => values
+            .iter()
+            .map(|value| evaluate_in(value, input, scope))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Array),
+        other => Ok(other.clone()),
+    };
+}
+
+/// Applies `Arguments` to the state input.
+pub fn arguments(state: &State, bindings: &Bindings) -> Result<Value, StatesError> {
+    return match state.field("Arguments") {
+        Some(template) => evaluate(template, bindings),
+        None => Ok(bindings.input.clone()),
+    };
+}
+
+/// Applies the `Output` of a state, Choice rule or Catch clause, which replaces `default`.
+pub fn output(holder: &Value, bindings: &Bindings, default: Value) -> Result<Value, StatesError> {
+    return match holder.get("Output") {
+        Some(template) => evaluate(template, bindings),
+        None => Ok(default),
+    };
+}
+
+/// Evaluates the `Assign` of a JSONata state, Choice rule or Catch clause.
+pub fn assign(holder: &Value, bindings: &Bindings) -> Result<Map<String, Value>, StatesError> {
+    let assign = match holder.get("Assign") {
+        Some(Value::Object(assign)) => assign,
+        Some(_) => return Err(StatesError::runtime("Assign must be an object")),
+        None => return Ok(Map::new()),
+    };
+    let scope = bindings.scope();
+    let mut assigned = Map::new();
+    for (name, template) in assign {
+        assigned.insert(name.clone(), evaluate_in(template, bindings.input, &scope)?);
+    }
+
+    return Ok(assigned);
+}
+
+/// Evaluates the `Assign` of a JSONPath state, Choice rule or Catch clause: keys ending in `.$`
+/// take the value of their path in `value` (the state's result) or of their intrinsic function.
+pub fn assign_json_path(
+    holder: &Value,
+    value: &Value,
+    context: &Value,
+) -> Result<Map<String, Value>, StatesError> {
+    return match holder.get("Assign") {
+        Some(template) => match processing::apply_template(template, value, context)? {
+            Value::Object(assigned) => Ok(assigned),
+            _ => Err(StatesError::runtime("Assign must be an object")),
+        },
+        None => Ok(Map::new()),
+    };
+}
+
+/// Picks the next state of a JSONata Choice state from the `Condition` of its rules. Returns the
+/// matching rule too, as its `Assign` and `Output` apply; `Default` has no rule.
+pub fn choose<'a>(
+    state: &State<'a>,
+    bindings: &Bindings,
+) -> Result<(String, Option<&'a Value>), StatesError> {
+    let choices = state
+        .field("Choices")
+        .and_then(Value::as_array)
+        .ok_or_else(|| {
+            StatesError::runtime(format!("Choice state '{}' has no Choices", state.name))
+        })?;
+
+    for rule in choices {
+        let condition = rule.get("Condition").ok_or_else(|| {
+            StatesError::runtime(format!(
+                "A Choice rule in '{}' has no Condition",
+                state.name
+            ))
+        })?;
+        let matched = match evaluate(condition, bindings)? {
+            Value::Bool(matched) => matched,
+            other => {
+                return Err(StatesError::new(
+                    STATES_QUERY_EVALUATION_ERROR,
+                    format!(
+                        "The Condition {} evaluated to {}, not a boolean",
+                        condition, other
+                    ),
+                ))
+            }
+        };
+        if matched {
+            let next = rule["Next"].as_str().ok_or_else(|| {
+                StatesError::runtime(format!("A Choice rule in '{}' has no Next", state.name))
+            })?;
+            return Ok((next.to_string(), Some(rule)));
+        }
+    }
+
+    return state
+        .str_field("Default")
+        .map(|default| (default.to_string(), None))
+        .ok_or_else(|| {
+            StatesError::new(
+                super::STATES_NO_CHOICE_MATCHED,
+                format!(
+                    "No Matches! for state '{}' and input {}",
+                    state.name, bindings.input
+                ),
+            )
+        });
+}
+
+/// How long a JSONata Wait state waits; `Seconds` and `Timestamp` may be expressions.
+pub fn wait_seconds(state: &State, bindings: &Bindings) -> Result<f64, StatesError> {
+    if let Some(seconds) = state.field("Seconds") {
+        return evaluate(seconds, bindings)?
+            .as_f64()
+            .ok_or_else(|| query_error("Seconds must evaluate to a number"));
+    }
+    let timestamp = match state.field("Timestamp") {
+        Some(timestamp) => evaluate(timestamp, bindings)?,
+        None => {
+            return Err(StatesError::runtime(format!(
+                "Wait state '{}' has no Seconds or Timestamp",
+                state.name
+            )))
+        }
+    };
+    let target = timestamp
+        .as_str()
+        .and_then(super::choice::parse_timestamp)
+        .ok_or_else(|| query_error(format!("{} is not a valid timestamp", timestamp)))?;
+
+    return Ok((target - crate::history::now()).max(0.0));
+}
+
+/// The error of a JSONata Fail state; `Error` and `Cause` may be expressions.
+pub fn fail_error(state: &State, bindings: &Bindings) -> Result<StatesError, StatesError> {
+    let field = |name: &str| -> Result<String, StatesError> {
+        return match state.field(name) {
+            Some(template) => match evaluate(template, bindings)? {
+                Value::String(value) => Ok(value),
+                other => Ok(other.to_string()),
+            },
+            None => Ok(String::new()),
+        };
+    };
+
+    return Ok(StatesError::new(field("Error")?, field("Cause")?));
+}
+
+fn query_error(cause: impl Into<String>) -> StatesError {
+    return StatesError::new(STATES_QUERY_EVALUATION_ERROR, cause);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluates_templates_with_states_and_variables() {
+        let variables = json!({ "threshold": 10240 });
+        let variables = variables.as_object().unwrap();
+        let input = json!({ "url": "https://example.com" });
+        let context = json!({ "Execution": { "Name": "e1" } });
+        let result = json!({ "size": 20480 });
+        let bindings = Bindings::new(variables, &input, &context).result(&result);
+        let state = json!({
+            "Type": "Task",
+            "Output": {
+                "url": "{% $states.input.url %}",
+                "big": "{% $states.result.size > $threshold %}",
+                "execution": "{% $states.context.Execution.Name %}",
+                "static": [1, "{ not an expression }"]
+            },
+            "Assign": { "threshold": "{% $threshold * 2 %}", "last": "{% $threshold %}" }
+        });
+
+        assert_eq!(
+            output(&state, &bindings, Value::Null).unwrap(),
+            json!({ "url": "https://example.com", "big": true, "execution": "e1", "static": [1, "{ not an expression }"] })
+        );
+        assert_eq!(
+            Value::Object(assign(&state, &bindings).unwrap()),
+            json!({ "threshold": 20480, "last": 10240 })
+        );
+
+        let undefined = evaluate(&json!("{% $states.result.missing %}"), &bindings).unwrap_err();
+        assert_eq!(undefined.error, STATES_QUERY_EVALUATION_ERROR);
+    }
+}
@@ -0,0 +1,603 @@
+//! A Rust builder for state machine definitions, typed by the data their tasks exchange.
+//!
+//! [`task::<Input, Output>(resource)`](task) ties a Task state to the Rust types its resource is
+//! invoked with and returns. The JSONPaths reading them are not checked by the compiler:
+//! [`StateMachine::check`] checks them at runtime against the `JsonSchema` of the types with a
+//! [`Contract`](crate::contract::Contract), so renaming or removing a field of `Output` fails the
+//! check of every definition reading it, e.g. the `$.size` of a
+//! `choice().when(numeric_gt("$.size", 10240), "IsBig")`.
+//!
+//! A built state machine is emitted as a [`Definition`], as ASL JSON or as the YAML of a
+//! Serverless Framework `stepFunctions.stateMachines` entry, states in the order they were added.
+//! [`get_att`] resources become `!GetAtt` tags in the YAML and the placeholder ARNs
+//! [`serverless`](crate::serverless) resolves them to in the definition.
+
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use serde_yaml::value::{Tag, TaggedValue};
+use serde_yaml::Mapping;
+
+use crate::asl::{Definition, StatesError};
+use crate::contract::{schema_of, Contract, ContractReport};
+
+/// What a Task state runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resource {
+    Arn(String),
+    /// A CloudFormation `!GetAtt`, e.g. `get-html.Arn`.
+    GetAtt(String),
+}
+
+impl From<&str> for Resource {
+    fn from(arn: &str) -> Self {
+        return Resource::Arn(arn.to_string());
+    }
+}
+
+impl From<String> for Resource {
+    fn from(arn: String) -> Self {
+        return Resource::Arn(arn);
+    }
+}
+
+/// The resource `!GetAtt <attribute>`, e.g. `get_att("get-html.Arn")` for a Lambda function of
+/// the same Serverless service.
+pub fn get_att(attribute: impl Into<String>) -> Resource {
+    return Resource::GetAtt(attribute.into());
+}
+
+/// A state that can be added to a [`StateMachine`].
+pub trait StateBuilder {
+    fn build(self) -> Mapping;
+
+    /// The schema of what the state's resource returns, for Task states.
+    fn output_schema(&self) -> Option<Value> {
+        return None;
+    }
+}
+
+/// A state machine definition under construction.
+#[derive(Debug, Clone)]
+pub struct StateMachine {
+    start_at: String,
+    comment: Option<String>,
+    timeout_seconds: Option<u64>,
+    states: Mapping,
+    input: Option<Value>,
+    task_outputs: BTreeMap<String, Value>,
+}
+
+impl StateMachine {
+    pub fn new(start_at: impl Into<String>) -> Self {
+        return Self {
+            start_at: start_at.into(),
+            comment: None,
+            timeout_seconds: None,
+            states: Mapping::new(),
+            input: None,
+            task_outputs: BTreeMap::new(),
+        };
+    }
+
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        return self;
+    }
+
+    pub fn timeout_seconds(mut self, seconds: u64) -> Self {
+        self.timeout_seconds = Some(seconds);
+        return self;
+    }
+
+    /// The type of the execution input, for [`check`](Self::check).
+    pub fn input<I: JsonSchema>(mut self) -> Self {
+        self.input = Some(schema_of::<I>());
+        return self;
+    }
+
+    /// Adds a state; a state added twice under the same name replaces the first one.
+    pub fn state(mut self, name: impl Into<String>, state: impl StateBuilder) -> Self {
+        let name = name.into();
+        match state.output_schema() {
+            Some(schema) => self.task_outputs.insert(name.clone(), schema),
+            None => self.task_outputs.remove(&name),
+        };
+        self.states.insert(yaml(&name), state.build().into());
+        return self;
+    }
+
+    /// The definition as YAML values, with `!GetAtt` tags.
+    fn document(&self) -> serde_yaml::Value {
+        let mut document = Mapping::new();
+        if let Some(comment) = &self.comment {
+            document.insert(yaml("Comment"), yaml(comment));
+        }
+        document.insert(yaml("StartAt"), yaml(&self.start_at));
+        if let Some(seconds) = self.timeout_seconds {
+            document.insert(yaml("TimeoutSeconds"), yaml(seconds));
+        }
+        document.insert(yaml("States"), self.states.clone().into());
+
+        return document.into();
+    }
+
+    /// The validated definition, with `!GetAtt` resources resolved like
+    /// [`ServerlessStateMachine`](crate::serverless::ServerlessStateMachine) does.
+    pub fn definition(&self) -> Result<Definition, StatesError> {
+        let value = crate::serverless::to_json(&self.document(), "", &mut BTreeMap::new());
+
+        return Definition::from_value(value);
+    }
+
+    /// The ASL JSON of the definition.
+    pub fn to_json(&self) -> Result<String, StatesError> {
+        return Ok(self.definition()?.to_json());
+    }
+
+    /// The definition as YAML, keeping `!GetAtt` tags.
+    pub fn to_yaml(&self) -> Result<String, crate::Error> {
+        self.definition()?;
+
+        return Ok(serde_yaml::to_string(&self.document())?);
+    }
+
+    /// A `state_machines/*.yml` file of the Serverless Framework configuration, like
+    /// `state_machines/simple.yml`.
+    pub fn to_serverless_yaml(&self, name: &str, id: &str) -> Result<String, crate::Error> {
+        self.definition()?;
+        let mut file = Mapping::new();
+        file.insert(yaml("name"), yaml(name));
+        file.insert(yaml("id"), yaml(id));
+        file.insert(yaml("definition"), self.document());
+
+        return Ok(serde_yaml::to_string(&file)?);
+    }
+
+    /// Checks the JSONPaths of the definition against the input type and the output types of
+    /// its typed tasks.
+    pub fn check(&self) -> Result<ContractReport, StatesError> {
+        let definition = self.definition()?;
+        let mut contract = Contract::new(&definition);
+        if let Some(input) = &self.input {
+            contract = contract.input(input.clone());
+        }
+        for (state, schema) in &self.task_outputs {
+            contract = contract.task_output(state, schema.clone());
+        }
+
+        return Ok(contract.check());
+    }
+}
+
+/// A Task state whose resource takes `I` and returns `O`.
+pub struct Task<I, O> {
+    fields: Mapping,
+    retriers: Vec<serde_yaml::Value>,
+    catchers: Vec<serde_yaml::Value>,
+    types: PhantomData<fn(I) -> O>,
+}
+
+/// A Task state; `I` and `O` are the payload and the result of the resource, e.g. the `Input`
+/// and `Output` of a Lambda handler.
+pub fn task<I, O>(resource: impl Into<Resource>) -> Task<I, O>
+where
+    I: Serialize + JsonSchema,
+    O: DeserializeOwned + JsonSchema,
+{
+    let resource = match resource.into() {
+        Resource::Arn(arn) => yaml(arn),
+        Resource::GetAtt(attribute) => serde_yaml::Value::Tagged(Box::new(TaggedValue {
+            tag: Tag::new("GetAtt"),
+            value: yaml(attribute),
+        })),
+    };
+    let mut fields = state_type("Task");
+    fields.insert(yaml("Resource"), resource);
+
+    return Task {
+        fields,
+        retriers: Vec::new(),
+        catchers: Vec::new(),
+        types: PhantomData,
+    };
+}
+
+impl<I, O> Task<I, O> {
+    pub fn next(mut self, state: impl Into<String>) -> Self {
+        self.fields.insert(yaml("Next"), yaml(state.into()));
+        return self;
+    }
+
+    pub fn end(mut self) -> Self {
+        self.fields.insert(yaml("End"), yaml(true));
+        return self;
+    }
+
+    pub fn parameters(mut self, parameters: Value) -> Self {
+        self.fields.insert(yaml("Parameters"), yaml(parameters));
+        return self;
+    }
+
+    pub fn result_selector(mut self, selector: Value) -> Self {
+        self.fields.insert(yaml("ResultSelector"), yaml(selector));
+        return self;
+    }
+
+    pub fn result_path(mut self, path: impl Into<String>) -> Self {
+        self.fields.insert(yaml("ResultPath"), yaml(path.into()));
+        return self;
+    }
+
+    pub fn output_path(mut self, path: impl Into<String>) -> Self {
+        self.fields.insert(yaml("OutputPath"), yaml(path.into()));
+        return self;
+    }
+
+    pub fn timeout_seconds(mut self, seconds: u64) -> Self {
+        self.fields.insert(yaml("TimeoutSeconds"), yaml(seconds));
+        return self;
+    }
+
+    pub fn heartbeat_seconds(mut self, seconds: u64) -> Self {
+        self.fields.insert(yaml("HeartbeatSeconds"), yaml(seconds));
+        return self;
+    }
+
+    /// Adds a retrier, tried in the order they were added.
+    pub fn retry(mut self, retry: Retry) -> Self {
+        self.retriers.push(retry.fields.into());
+        return self;
+    }
+
+    /// Adds a catcher, tried in the order they were added.
+    pub fn catch(mut self, catch: Catch) -> Self {
+        self.catchers.push(catch.fields.into());
+        return self;
+    }
+}
+
+impl<I, O: JsonSchema> StateBuilder for Task<I, O> {
+    fn build(mut self) -> Mapping {
+        if !self.retriers.is_empty() {
+            self.fields
+                .insert(yaml("Retry"), serde_yaml::Value::Sequence(self.retriers));
+        }
+        if !self.catchers.is_empty() {
+            self.fields
+                .insert(yaml("Catch"), serde_yaml::Value::Sequence(self.catchers));
+        }
+
+        return self.fields;
+    }
+
+    fn output_schema(&self) -> Option<Value> {
+        return Some(schema_of::<O>());
+    }
+}
+
+/// A `Retry` entry of a Task state.
+#[derive(Debug, Clone)]
+pub struct Retry {
+    fields: Mapping,
+}
+
+/// Retries the errors in `error_equals`, e.g. `["States.ALL"]`.
+pub fn retry(error_equals: &[&str]) -> Retry {
+    let mut fields = Mapping::new();
+    fields.insert(yaml("ErrorEquals"), yaml(error_equals));
+
+    return Retry { fields };
+}
+
+impl Retry {
+    pub fn interval_seconds(mut self, seconds: u64) -> Self {
+        self.fields.insert(yaml("IntervalSeconds"), yaml(seconds));
+        return self;
+    }
+
+    pub fn max_attempts(mut self, attempts: u64) -> Self {
+        self.fields.insert(yaml("MaxAttempts"), yaml(attempts));
+        return self;
+    }
+
+    pub fn backoff_rate(mut self, rate: f64) -> Self {
+        self.fields.insert(yaml("BackoffRate"), yaml(rate));
+        return self;
+    }
+
+    pub fn max_delay_seconds(mut self, seconds: u64) -> Self {
+        self.fields.insert(yaml("MaxDelaySeconds"), yaml(seconds));
+        return self;
+    }
+}
+
+/// A `Catch` entry of a Task state.
+#[derive(Debug, Clone)]
+pub struct Catch {
+    fields: Mapping,
+}
+
+/// Catches the errors in `error_equals` and goes on at `next`.
+pub fn catch(error_equals: &[&str], next: impl Into<String>) -> Catch {
+    let mut fields = Mapping::new();
+    fields.insert(yaml("ErrorEquals"), yaml(error_equals));
+    fields.insert(yaml("Next"), yaml(next.into()));
+
+    return Catch { fields };
+}
+
+impl Catch {
+    /// Where the error object goes in the state input; by default it replaces it.
+    pub fn result_path(mut self, path: impl Into<String>) -> Self {
+        self.fields.insert(yaml("ResultPath"), yaml(path.into()));
+        return self;
+    }
+}
+
+/// A Choice rule, built with [`numeric_gt`], [`string_eq`], [`and`] and the like.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    fields: Mapping,
+}
+
+fn comparison(variable: &str, operator: &str, value: serde_yaml::Value) -> Rule {
+    let mut fields = Mapping::new();
+    fields.insert(yaml("Variable"), yaml(variable));
+    fields.insert(yaml(operator), value);
+
+    return Rule { fields };
+}
+
+pub fn numeric_eq(variable: &str, value: impl Into<Value>) -> Rule {
+    return comparison(variable, "NumericEquals", yaml(value.into()));
+}
+
+pub fn numeric_gt(variable: &str, value: impl Into<Value>) -> Rule {
+    return comparison(variable, "NumericGreaterThan", yaml(value.into()));
+}
+
+pub fn numeric_gte(variable: &str, value: impl Into<Value>) -> Rule {
+    return comparison(variable, "NumericGreaterThanEquals", yaml(value.into()));
+}
+
+pub fn numeric_lt(variable: &str, value: impl Into<Value>) -> Rule {
+    return comparison(variable, "NumericLessThan", yaml(value.into()));
+}
+
+pub fn numeric_lte(variable: &str, value: impl Into<Value>) -> Rule {
+    return comparison(variable, "NumericLessThanEquals", yaml(value.into()));
+}
+
+pub fn string_eq(variable: &str, value: &str) -> Rule {
+    return comparison(variable, "StringEquals", yaml(value));
+}
+
+pub fn boolean_eq(variable: &str, value: bool) -> Rule {
+    return comparison(variable, "BooleanEquals", yaml(value));
+}
+
+pub fn is_present(variable: &str, present: bool) -> Rule {
+    return comparison(variable, "IsPresent", yaml(present));
+}
+
+fn combination(operator: &str, rules: Vec<serde_yaml::Value>) -> Rule {
+    let mut fields = Mapping::new();
+    fields.insert(yaml(operator), serde_yaml::Value::Sequence(rules));
+
+    return Rule { fields };
+}
+
+pub fn and(rules: impl IntoIterator<Item = Rule>) -> Rule {
+    return combination(
+        "And",
+        rules.into_iter().map(|rule| rule.fields.into()).collect(),
+    );
+}
+
+pub fn or(rules: impl IntoIterator<Item = Rule>) -> Rule {
+    return combination(
+        "Or",
+        rules.into_iter().map(|rule| rule.fields.into()).collect(),
+    );
+}
+
+pub fn not(rule: Rule) -> Rule {
+    let mut fields = Mapping::new();
+    fields.insert(yaml("Not"), rule.fields.into());
+
+    return Rule { fields };
+}
+
+/// A Choice state.
+#[derive(Debug, Clone)]
+pub struct Choice {
+    choices: Vec<serde_yaml::Value>,
+    default: Option<String>,
+}
+
+pub fn choice() -> Choice {
+    return Choice {
+        choices: Vec::new(),
+        default: None,
+    };
+}
+
+impl Choice {
+    /// Goes to `next` when `rule` matches and no earlier rule did.
+    pub fn when(mut self, rule: Rule, next: impl Into<String>) -> Self {
+        let mut fields = rule.fields;
+        fields.insert(yaml("Next"), yaml(next.into()));
+        self.choices.push(fields.into());
+        return self;
+    }
+
+    /// The `Default` state, when no rule matches.
+    pub fn otherwise(mut self, next: impl Into<String>) -> Self {
+        self.default = Some(next.into());
+        return self;
+    }
+}
+
+impl StateBuilder for Choice {
+    fn build(self) -> Mapping {
+        let mut fields = state_type("Choice");
+        fields.insert(yaml("Choices"), serde_yaml::Value::Sequence(self.choices));
+        if let Some(default) = self.default {
+            fields.insert(yaml("Default"), yaml(default));
+        }
+
+        return fields;
+    }
+}
+
+/// A Pass state.
+#[derive(Debug, Clone)]
+pub struct Pass {
+    fields: Mapping,
+}
+
+pub fn pass() -> Pass {
+    return Pass {
+        fields: state_type("Pass"),
+    };
+}
+
+impl Pass {
+    pub fn result(mut self, result: impl Into<Value>) -> Self {
+        self.fields.insert(yaml("Result"), yaml(result.into()));
+        return self;
+    }
+
+    pub fn parameters(mut self, parameters: Value) -> Self {
+        self.fields.insert(yaml("Parameters"), yaml(parameters));
+        return self;
+    }
+
+    pub fn result_path(mut self, path: impl Into<String>) -> Self {
+        self.fields.insert(yaml("ResultPath"), yaml(path.into()));
+        return self;
+    }
+
+    pub fn next(mut self, state: impl Into<String>) -> Self {
+        self.fields.insert(yaml("Next"), yaml(state.into()));
+        return self;
+    }
+
+    pub fn end(mut self) -> Self {
+        self.fields.insert(yaml("End"), yaml(true));
+        return self;
+    }
+}
+
+impl StateBuilder for Pass {
+    fn build(self) -> Mapping {
+        return self.fields;
+    }
+}
+
+/// A Fail state.
+#[derive(Debug, Clone)]
+pub struct Fail {
+    fields: Mapping,
+}
+
+pub fn fail() -> Fail {
+    return Fail {
+        fields: state_type("Fail"),
+    };
+}
+
+impl Fail {
+    pub fn error(mut self, error: impl Into<String>) -> Self {
+        self.fields.insert(yaml("Error"), yaml(error.into()));
+        return self;
+    }
+
+    pub fn cause(mut self, cause: impl Into<String>) -> Self {
+        self.fields.insert(yaml("Cause"), yaml(cause.into()));
+        return self;
+    }
+}
+
+impl StateBuilder for Fail {
+    fn build(self) -> Mapping {
+        return self.fields;
+    }
+}
+
+fn state_type(state_type: &str) -> Mapping {
+    let mut fields = Mapping::new();
+    fields.insert(yaml("Type"), yaml(state_type));
+
+    return fields;
+}
+
+fn yaml(value: impl Serialize) -> serde_yaml::Value {
+    return serde_yaml::to_value(value).unwrap_or(serde_yaml::Value::Null);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::{Input, Output};
+    use crate::serverless::ServerlessStateMachine;
+
+    fn simple(variable: &str) -> StateMachine {
+        return StateMachine::new("GetHtml")
+            .input::<Input>()
+            .state(
+                "GetHtml",
+                task::<Input, Output>(get_att("get-html.Arn"))
+                    .next("IsHtmlBig?")
+                    .catch(catch(&["States.ALL"], "Dunno")),
+            )
+            .state(
+                "IsHtmlBig?",
+                choice()
+                    .when(numeric_gt(variable, 10240), "IsBig")
+                    .otherwise("IsNotBig"),
+            )
+            .state("IsBig", pass().result(true).end())
+            .state("IsNotBig", pass().result(false).end())
+            .state("Dunno", fail());
+    }
+
+    #[test]
+    fn builds_the_simple_state_machine() {
+        let configured =
+            ServerlessStateMachine::from_yaml(include_str!("../state_machines/simple.yml"))
+                .unwrap();
+        let built = simple("$.size");
+
+        assert_eq!(built.definition().unwrap(), configured.definition);
+        let generated = ServerlessStateMachine::from_yaml(
+            &built
+                .to_serverless_yaml("tsa-chp04ln04-is-big-html", "SimpleExampleStateMachine")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(generated, configured);
+        built.check().unwrap().assert_ok();
+    }
+
+    #[test]
+    fn checks_paths_against_the_task_output_type() {
+        let report = simple("$.length").check().unwrap();
+
+        assert_eq!(report.violations.len(), 1);
+        assert_eq!(report.violations[0].state, "IsHtmlBig?");
+        assert_eq!(report.violations[0].path, "$.length");
+
+        let error = StateMachine::new("Missing")
+            .state("Dunno", fail())
+            .to_json()
+            .unwrap_err();
+        assert_eq!(error.error, "InvalidDefinition");
+        assert!(error.cause.contains("StartAt 'Missing' is not a state"));
+    }
+}
@@ -0,0 +1,607 @@
+//! Answering `.waitForTaskToken` tasks from the SNS or SQS messages that carry their tokens.
+//!
+//! The order workflow publishes `{"orderId": ..., "taskToken": ...}` to SNS and waits. A
+//! [`CallbackWorker`] reads those messages from an SNS event, or from an SQS event with raw or
+//! SNS-wrapped bodies, asks a [`Decide`] what to do with each one and reports the decision with
+//! `SendTaskSuccess`, `SendTaskFailure` or `SendTaskHeartbeat`. Failed calls are retried, except
+//! for tokens Step Functions no longer waits for (`TaskTimedOut`, `TaskDoesNotExist`), which are
+//! reported as [`Outcome::Expired`] rather than as errors, so the message is not redelivered.
+//! Every record of an event is handled; the [`EventReport`] lists the failed SQS messages as
+//! `batchItemFailures`, so only those are delivered again.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use crate::asl::{to_document, StatesError};
+
+/// The message field holding the task token, as `"taskToken.$": "$$.Task.Token"` names it.
+pub const TASK_TOKEN_KEY: &str = "taskToken";
+pub const TASK_TIMED_OUT: &str = "TaskTimedOut";
+pub const TASK_DOES_NOT_EXIST: &str = "TaskDoesNotExist";
+/// Errors that the same call would get again.
+const NOT_RETRYABLE: &[&str] = &[
+    TASK_TIMED_OUT,
+    TASK_DOES_NOT_EXIST,
+    "InvalidToken",
+    "InvalidOutput",
+    "ValidationException",
+];
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A message carrying a task token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskTokenMessage {
+    pub task_token: String,
+    /// The whole message, e.g. `{"orderId": ..., "taskToken": ...}`.
+    pub message: Value,
+    /// The string message attributes, e.g. `currentStatus`.
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl TaskTokenMessage {
+    pub fn new(message: Value, attributes: BTreeMap<String, String>) -> Result<Self, crate::Error> {
+        let task_token = message[TASK_TOKEN_KEY]
+            .as_str()
+            .ok_or_else(|| format!("The message has no {}: {}", TASK_TOKEN_KEY, message))?;
+
+        return Ok(Self {
+            task_token: task_token.to_string(),
+            message,
+            attributes,
+        });
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        return self.attributes.get(name).map(String::as_str);
+    }
+
+    /// An SNS notification, as in the `Sns` field of an SNS record or the body of an SQS
+    /// message without raw message delivery.
+    fn from_notification(notification: &Value) -> Result<Self, crate::Error> {
+        let message = serde_json::from_str(notification["Message"].as_str().unwrap_or_default())?;
+
+        return Self::new(
+            message,
+            attributes(&notification["MessageAttributes"], "Value"),
+        );
+    }
+
+    fn from_record(record: &Value) -> Result<Self, crate::Error> {
+        if let Some(notification) = record.get("Sns") {
+            return Self::from_notification(notification);
+        }
+
+        let body = record["body"]
+            .as_str()
+            .ok_or("The record is neither an SNS nor an SQS record")?;
+        let body: Value = serde_json::from_str(body)?;
+        if body["Type"] == "Notification" {
+            return Self::from_notification(&body);
+        }
+
+        return Self::new(
+            body,
+            attributes(&record["messageAttributes"], "stringValue"),
+        );
+    }
+}
+
+fn attributes(attributes: &Value, value_key: &str) -> BTreeMap<String, String> {
+    return attributes
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(name, attribute)| {
+            Some((name.clone(), attribute[value_key].as_str()?.to_string()))
+        })
+        .collect();
+}
+
+/// The records of an SNS or SQS Lambda event.
+fn records(event: &Value) -> Result<&Vec<Value>, crate::Error> {
+    return Ok(event["Records"]
+        .as_array()
+        .ok_or("The event has no Records")?);
+}
+
+/// The task token messages of an SNS or SQS Lambda event.
+pub fn messages(event: &Value) -> Result<Vec<TaskTokenMessage>, crate::Error> {
+    return records(event)?
+        .iter()
+        .map(TaskTokenMessage::from_record)
+        .collect();
+}
+
+/// What to do with a task token.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// `SendTaskSuccess` with this output.
+    Accept(Value),
+    /// `SendTaskFailure` with this error.
+    Reject(StatesError),
+    /// `SendTaskHeartbeat`, leaving the task waiting for a later decision.
+    Heartbeat,
+    /// Leaves the task alone, e.g. for messages another consumer answers.
+    Ignore,
+}
+
+/// Decides what to do with the task token of a message.
+#[async_trait]
+pub trait Decide: Send + Sync {
+    async fn decide(&self, message: &TaskTokenMessage) -> Decision;
+}
+
+pub struct DecideFn<F> {
+    f: F,
+}
+
+/// Wraps an async closure into a [`Decide`], like [`handler_fn`](crate::executor::handler_fn).
+pub fn decide_fn<F, Fut>(f: F) -> DecideFn<F>
+where
+    F: Fn(TaskTokenMessage) -> Fut + Send + Sync,
+    Fut: Future<Output = Decision> + Send,
+{
+    return DecideFn { f };
+}
+
+#[async_trait]
+impl<F, Fut> Decide for DecideFn<F>
+where
+    F: Fn(TaskTokenMessage) -> Fut + Send + Sync,
+    Fut: Future<Output = Decision> + Send,
+{
+    async fn decide(&self, message: &TaskTokenMessage) -> Decision {
+        return (self.f)(message.clone()).await;
+    }
+}
+
+/// What became of a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Accepted,
+    Rejected,
+    Heartbeat,
+    Ignored,
+    /// Step Functions no longer waits for the token; the error code says why.
+    Expired(String),
+    /// The message could not be read or its decision could not be reported.
+    Failed(String),
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &str {
+        return match self {
+            Outcome::Accepted => "ACCEPTED",
+            Outcome::Rejected => "REJECTED",
+            Outcome::Heartbeat => "HEARTBEAT",
+            Outcome::Ignored => "IGNORED",
+            Outcome::Expired(_) => "EXPIRED",
+            Outcome::Failed(_) => "FAILED",
+        };
+    }
+}
+
+/// What became of one record of an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordReport {
+    /// The `messageId` of an SQS record; SNS records have none.
+    pub message_id: Option<String>,
+    pub outcome: Outcome,
+}
+
+/// What became of every record of an event, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventReport {
+    pub records: Vec<RecordReport>,
+}
+
+impl EventReport {
+    pub fn outcomes(&self) -> Vec<Outcome> {
+        return self
+            .records
+            .iter()
+            .map(|record| record.outcome.clone())
+            .collect();
+    }
+
+    pub fn failed(&self) -> Vec<&RecordReport> {
+        return self
+            .records
+            .iter()
+            .filter(|record| matches!(record.outcome, Outcome::Failed(_)))
+            .collect();
+    }
+
+    /// The Lambda response: the outcomes and, for event sources with `ReportBatchItemFailures`,
+    /// the failed SQS messages as `batchItemFailures`. SNS has no partial batch response, so a
+    /// failed SNS record fails the invocation instead, and SNS retries it.
+    pub fn response(&self) -> Result<Value, crate::Error> {
+        let mut batch_item_failures = Vec::new();
+        for record in self.failed() {
+            match (&record.message_id, &record.outcome) {
+                (Some(message_id), _) => {
+                    batch_item_failures.push(json!({ "itemIdentifier": message_id }))
+                }
+                (None, Outcome::Failed(error)) => return Err(error.clone().into()),
+                (None, _) => {}
+            }
+        }
+        let outcomes: Vec<&str> = self
+            .records
+            .iter()
+            .map(|record| record.outcome.as_str())
+            .collect();
+
+        return Ok(json!({ "outcomes": outcomes, "batchItemFailures": batch_item_failures }));
+    }
+}
+
+/// A failed `SendTask*` call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendError {
+    /// The API error code, e.g. `TaskTimedOut`; `None` for network errors.
+    pub code: Option<String>,
+    pub message: String,
+}
+
+impl SendError {
+    fn retryable(&self) -> bool {
+        return !self
+            .code
+            .as_deref()
+            .is_some_and(|code| NOT_RETRYABLE.contains(&code));
+    }
+
+    fn expired(&self) -> bool {
+        return matches!(
+            self.code.as_deref(),
+            Some(TASK_TIMED_OUT | TASK_DOES_NOT_EXIST)
+        );
+    }
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match &self.code {
+            Some(code) => write!(f, "{}: {}", code, self.message),
+            None => write!(f, "{}", self.message),
+        };
+    }
+}
+
+impl std::error::Error for SendError {}
+
+/// Reports the decisions on task token messages to Step Functions or an SFN-compatible endpoint.
+#[derive(Clone)]
+pub struct CallbackWorker {
+    client: aws_sdk_sfn::Client,
+    decide: Arc<dyn Decide>,
+    max_attempts: u32,
+    retry_interval: Duration,
+}
+
+impl CallbackWorker {
+    pub fn new(client: aws_sdk_sfn::Client, decide: impl Decide + 'static) -> Self {
+        return Self {
+            client,
+            decide: Arc::new(decide),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+        };
+    }
+
+    /// How often a `SendTask*` call is tried before the message fails.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        return self;
+    }
+
+    /// The wait before the first retry; it doubles with every further retry.
+    pub fn retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        return self;
+    }
+
+    /// Handles every record of an SNS or SQS event, including the ones after a record that
+    /// cannot be read or whose decision cannot be reported. Fails only without `Records`.
+    pub async fn handle_event(&self, event: &Value) -> Result<EventReport, crate::Error> {
+        let mut report = EventReport::default();
+        for record in records(event)? {
+            let outcome = match TaskTokenMessage::from_record(record) {
+                Ok(message) => match self.handle_message(&message).await {
+                    Ok(outcome) => outcome,
+                    Err(error) => Outcome::Failed(error.to_string()),
+                },
+                Err(error) => Outcome::Failed(error.to_string()),
+            };
+            report.records.push(RecordReport {
+                message_id: record["messageId"].as_str().map(str::to_string),
+                outcome,
+            });
+        }
+
+        return Ok(report);
+    }
+
+    pub async fn handle_message(&self, message: &TaskTokenMessage) -> Result<Outcome, SendError> {
+        let decision = self.decide.decide(message).await;
+        let outcome = match &decision {
+            Decision::Accept(_) => Outcome::Accepted,
+            Decision::Reject(_) => Outcome::Rejected,
+            Decision::Heartbeat => Outcome::Heartbeat,
+            Decision::Ignore => return Ok(Outcome::Ignored),
+        };
+
+        let mut interval = self.retry_interval;
+        let mut attempt = 1;
+        loop {
+            let error = match self.send(&message.task_token, &decision).await {
+                Ok(()) => return Ok(outcome),
+                Err(error) => error,
+            };
+            if error.expired() {
+                return Ok(Outcome::Expired(error.code.unwrap_or_default()));
+            }
+            if !error.retryable() || attempt >= self.max_attempts {
+                return Err(error);
+            }
+            tokio::time::sleep(interval).await;
+            interval *= 2;
+            attempt += 1;
+        }
+    }
+
+    async fn send(&self, task_token: &str, decision: &Decision) -> Result<(), SendError> {
+        return match decision {
+            Decision::Accept(output) => self
+                .client
+                .send_task_success()
+                .task_token(task_token)
+                .output(to_document(output))
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(|error| {
+                    let error = error.into_service_error();
+                    send_error(error.code(), error.to_string())
+                }),
+            Decision::Reject(error) => self
+                .client
+                .send_task_failure()
+                .task_token(task_token)
+                .error(&error.error)
+                .cause(&error.cause)
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(|error| {
+                    let error = error.into_service_error();
+                    send_error(error.code(), error.to_string())
+                }),
+            Decision::Heartbeat => self
+                .client
+                .send_task_heartbeat()
+                .task_token(task_token)
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(|error| {
+                    let error = error.into_service_error();
+                    send_error(error.code(), error.to_string())
+                }),
+            Decision::Ignore => Ok(()),
+        };
+    }
+}
+
+fn send_error(code: Option<&str>, message: String) -> SendError {
+    return SendError {
+        code: code.map(str::to_string),
+        message,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::asl::Definition;
+    use crate::executor::{handler_fn, ExecutionRequest, Executor, TaskRequest};
+    use crate::history::ExecutionStatus;
+    use crate::server::{self, StepFunctions};
+
+    fn definition(timeout_seconds: u64) -> Definition {
+        return Definition::from_value(json!({
+            "StartAt": "Notify restaurant about the new order",
+            "States": {
+                "Notify restaurant about the new order": {
+                    "Type": "Task",
+                    "Resource": "arn:aws:states:::sns:publish.waitForTaskToken",
+                    "Parameters": {
+                        "TopicArn": "arn:aws:sns:us-east-1:123456789012:OrdersTopic",
+                        "Message": { "orderId.$": "$.orderId", "taskToken.$": "$$.Task.Token" },
+                        "MessageAttributes": {
+                            "currentStatus": { "DataType": "String", "StringValue": "ORDERED" }
+                        }
+                    },
+                    "TimeoutSeconds": timeout_seconds,
+                    "End": true
+                }
+            }
+        }))
+        .unwrap();
+    }
+
+    /// An executor publishing to a channel, served over HTTP, and a client of that endpoint.
+    fn endpoint() -> (
+        Executor,
+        mpsc::UnboundedReceiver<Value>,
+        aws_sdk_sfn::Client,
+    ) {
+        let (published, notifications) = mpsc::unbounded_channel();
+        let executor = Executor::new().with_handler(
+            "arn:aws:states:::sns:publish.waitForTaskToken",
+            handler_fn(move |request: TaskRequest| {
+                let published = published.clone();
+                async move {
+                    let parameters = &request.input;
+                    let notification = json!({
+                        "Type": "Notification",
+                        "Message": to_document(&parameters["Message"]),
+                        "MessageAttributes": {
+                            "currentStatus": {
+                                "Type": "String",
+                                "Value": parameters["MessageAttributes"]["currentStatus"]["StringValue"]
+                            }
+                        }
+                    });
+                    published.send(notification).unwrap();
+                    return Ok(json!({ "MessageId": "1" }));
+                }
+            }),
+        );
+        let address = server::spawn(
+            "127.0.0.1:0".parse().unwrap(),
+            StepFunctions::new(executor.clone()),
+        )
+        .unwrap();
+        let client = crate::remote::local_client(&format!("http://{}", address)).unwrap();
+
+        return (executor, notifications, client);
+    }
+
+    #[tokio::test]
+    async fn answers_sns_and_sqs_messages() {
+        let (executor, mut notifications, client) = endpoint();
+        let worker = CallbackWorker::new(
+            client,
+            decide_fn(|message: TaskTokenMessage| async move {
+                return match message.attribute("currentStatus") {
+                    Some("ORDERED") => Decision::Accept(json!({
+                        "orderId": message.message["orderId"],
+                        "accepted": true
+                    })),
+                    _ => Decision::Ignore,
+                };
+            }),
+        );
+
+        let handle = executor.start(ExecutionRequest::new(
+            definition(300),
+            json!({ "orderId": "1" }),
+        ));
+        let sns_event = json!({ "Records": [{ "Sns": notifications.recv().await.unwrap() }] });
+        assert_eq!(
+            worker.handle_event(&sns_event).await.unwrap().outcomes(),
+            [Outcome::Accepted]
+        );
+        assert_eq!(
+            handle.wait().await.output,
+            Some(json!({ "orderId": "1", "accepted": true }))
+        );
+
+        let handle = executor.start(ExecutionRequest::new(
+            definition(300),
+            json!({ "orderId": "2" }),
+        ));
+        let notification = notifications.recv().await.unwrap();
+        let raw_sqs_event = json!({ "Records": [{
+            "body": notification["Message"],
+            "messageAttributes": { "currentStatus": { "stringValue": "ACCEPTED", "dataType": "String" } }
+        }] });
+        assert_eq!(
+            worker
+                .handle_event(&raw_sqs_event)
+                .await
+                .unwrap()
+                .outcomes(),
+            [Outcome::Ignored]
+        );
+        let wrapped_sqs_event = json!({ "Records": [{ "body": to_document(&notification) }] });
+        assert_eq!(
+            worker
+                .handle_event(&wrapped_sqs_event)
+                .await
+                .unwrap()
+                .outcomes(),
+            [Outcome::Accepted]
+        );
+        assert_eq!(handle.wait().await.status, ExecutionStatus::Succeeded);
+    }
+
+    #[tokio::test]
+    async fn expired_tokens_are_not_errors() {
+        let (executor, mut notifications, client) = endpoint();
+        let worker = CallbackWorker::new(
+            client,
+            decide_fn(|_: TaskTokenMessage| async {
+                return Decision::Reject(StatesError::new("Order.Rejected", "closed"));
+            }),
+        );
+
+        let handle = executor.start(ExecutionRequest::new(
+            definition(1),
+            json!({ "orderId": "1" }),
+        ));
+        let notification = notifications.recv().await.unwrap();
+        assert_eq!(handle.wait().await.error.as_deref(), Some("States.Timeout"));
+
+        let event = json!({ "Records": [{ "Sns": notification }] });
+        let report = worker.handle_event(&event).await.unwrap();
+        assert_eq!(
+            report.outcomes(),
+            [Outcome::Expired(TASK_TIMED_OUT.to_string())]
+        );
+        assert_eq!(report.response().unwrap()["batchItemFailures"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn reports_failed_sqs_messages_as_batch_item_failures() {
+        let (executor, mut notifications, client) = endpoint();
+        let worker = CallbackWorker::new(
+            client,
+            decide_fn(|message: TaskTokenMessage| async move {
+                return Decision::Accept(json!({ "orderId": message.message["orderId"] }));
+            }),
+        );
+        let handle = executor.start(ExecutionRequest::new(
+            definition(300),
+            json!({ "orderId": "2" }),
+        ));
+        let notification = notifications.recv().await.unwrap();
+
+        let event = json!({ "Records": [
+            { "messageId": "m-1", "body": "{\"orderId\": \"1\"}" },
+            { "messageId": "m-2", "body": to_document(&notification) }
+        ] });
+        let report = worker.handle_event(&event).await.unwrap();
+
+        assert_eq!(report.failed().len(), 1);
+        assert!(
+            matches!(&report.records[0].outcome, Outcome::Failed(error) if error.contains("no taskToken"))
+        );
+        assert_eq!(report.records[1].outcome, Outcome::Accepted);
+        assert_eq!(
+            report.response().unwrap(),
+            json!({
+                "outcomes": ["FAILED", "ACCEPTED"],
+                "batchItemFailures": [{ "itemIdentifier": "m-1" }]
+            })
+        );
+        assert_eq!(handle.wait().await.status, ExecutionStatus::Succeeded);
+
+        let sns_event = json!({ "Records": [{ "Sns": { "Message": "{}" } }] });
+        let report = worker.handle_event(&sns_event).await.unwrap();
+        assert!(report.response().is_err());
+    }
+}
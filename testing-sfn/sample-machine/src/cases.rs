@@ -0,0 +1,505 @@
+//! Table-driven state machine tests.
+//!
+//! The test cases of a state machine are the test cases of the mock config plus those of a
+//! companion expectations file (`sfn-local-expectations.json`), which uses the same layout:
+//!
+//! ```json
+//! { "StateMachines": { "SimpleExample": {
+//!     "Input": { "url": "https://www.rust-lang.org/" },
+//!     "TestCases": { "IsBigPath": { "Status": "SUCCEEDED", "Output": true } }
+//! } } }
+//! ```
+//!
+//! A case may set its own `Input` and expect a `Status` (`SUCCEEDED` by default), an `Output`,
+//! an `Error`, a `Cause` and the `VisitedStates`. Cases that only exist in the expectations file
+//! run without mocks. Every case runs as its own named execution, several at a time, and the
+//! [`CasesReport`] prints a pass/fail line per case.
+//!
+//! The crate's `build.rs` turns the cases of `sfn-local-mock.json` and
+//! `sfn-local-expectations.json` into one named `#[tokio::test]` each, so the test harness
+//! filters, runs and reports them one by one. A module includes the generated tests and provides
+//! the `run_test_case` they call:
+//!
+//! ```ignore
+//! async fn run_test_case(state_machine: &str, case: &str) -> CasesReport {
+//!     let cases = TestCases::new(state_machine, Some(&mock_config()), Some(&expectations()));
+//!     return cases.only(case).run_local(&executor(), &definition()).await;
+//! }
+//!
+//! include!(concat!(env!("OUT_DIR"), "/sfn_local_test_cases.rs"));
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::path::Path;
+use std::time::Duration;
+
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::asl::Definition;
+use crate::executor::{
+    state_machine_arn, ExecutionRequest, Executor, DEFAULT_ACCOUNT_ID, DEFAULT_REGION,
+};
+use crate::fixture::StateMachineFixture;
+use crate::history::{Execution, ExecutionStatus};
+use crate::mock::MockConfig;
+use crate::remote;
+use crate::trace::ExecutionTrace;
+
+const DEFAULT_CONCURRENCY: usize = 8;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Expectations {
+    #[serde(default)]
+    pub state_machines: BTreeMap<String, StateMachineExpectations>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct StateMachineExpectations {
+    /// The input of every test case that does not set its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<Value>,
+    #[serde(default)]
+    pub test_cases: BTreeMap<String, Expectation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Expectation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<Value>,
+    #[serde(default = "succeeded")]
+    pub status: ExecutionStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cause: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visited_states: Option<Vec<String>>,
+}
+
+impl Default for Expectation {
+    fn default() -> Self {
+        return Self {
+            input: None,
+            status: ExecutionStatus::Succeeded,
+            output: None,
+            error: None,
+            cause: None,
+            visited_states: None,
+        };
+    }
+}
+
+fn succeeded() -> ExecutionStatus {
+    return ExecutionStatus::Succeeded;
+}
+
+impl Expectations {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let contents = std::fs::read_to_string(path)?;
+
+        return Ok(serde_json::from_str(&contents)?);
+    }
+}
+
+impl Expectation {
+    /// Every way the execution differs from the expectation.
+    pub fn mismatches(&self, execution: &Execution) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        if execution.status != self.status {
+            mismatches.push(format!(
+                "expected status {}, got {}",
+                self.status.as_str(),
+                execution.status.as_str()
+            ));
+        }
+        if let Some(output) = &self.output {
+            if execution.output.as_ref() != Some(output) {
+                mismatches.push(format!(
+                    "expected output {}, got {}",
+                    output,
+                    execution.output.clone().unwrap_or(Value::Null)
+                ));
+            }
+        }
+        if self.error.is_some() && execution.error != self.error {
+            mismatches.push(format!(
+                "expected error {}, got {}",
+                self.error.as_deref().unwrap_or_default(),
+                execution.error.as_deref().unwrap_or("none")
+            ));
+        }
+        if self.cause.is_some() && execution.cause != self.cause {
+            mismatches.push(format!(
+                "expected cause {}, got {}",
+                self.cause.as_deref().unwrap_or_default(),
+                execution.cause.as_deref().unwrap_or("none")
+            ));
+        }
+        if let Some(visited_states) = &self.visited_states {
+            let visited = execution.visited_states();
+            if &visited != visited_states {
+                mismatches.push(format!(
+                    "expected visited states {:?}, got {:?}",
+                    visited_states, visited
+                ));
+            }
+        }
+
+        return mismatches;
+    }
+}
+
+/// One row of the table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestCase {
+    pub name: String,
+    /// Whether the mock config has the test case, i.e. whether it runs as `arn#TestCase`.
+    pub mocked: bool,
+    pub input: Value,
+    pub expectation: Expectation,
+}
+
+/// The test cases of one state machine.
+#[derive(Debug, Clone)]
+pub struct TestCases {
+    state_machine: String,
+    cases: Vec<TestCase>,
+    concurrency: usize,
+    timeout: Duration,
+}
+
+impl TestCases {
+    pub fn new(
+        state_machine: impl Into<String>,
+        mock_config: Option<&MockConfig>,
+        expectations: Option<&Expectations>,
+    ) -> Self {
+        let state_machine = state_machine.into();
+        let mocked = mock_config
+            .and_then(|config| config.state_machine(&state_machine))
+            .map(|mocked| mocked.test_cases.keys().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let expected =
+            expectations.and_then(|expectations| expectations.state_machines.get(&state_machine));
+        let default_input = expected
+            .and_then(|expected| expected.input.clone())
+            .unwrap_or_else(|| json!({}));
+
+        let mut cases: BTreeMap<String, TestCase> = BTreeMap::new();
+        for name in mocked {
+            cases.insert(
+                name.clone(),
+                TestCase {
+                    name,
+                    mocked: true,
+                    input: default_input.clone(),
+                    expectation: Expectation::default(),
+                },
+            );
+        }
+        let expected_cases = expected.map(|expected| &expected.test_cases);
+        for (name, expectation) in expected_cases.into_iter().flatten() {
+            let case = cases.entry(name.clone()).or_insert_with(|| TestCase {
+                name: name.clone(),
+                mocked: false,
+                input: default_input.clone(),
+                expectation: Expectation::default(),
+            });
+            if let Some(input) = &expectation.input {
+                case.input = input.clone();
+            }
+            case.expectation = expectation.clone();
+        }
+
+        return Self {
+            state_machine,
+            cases: cases.into_values().collect(),
+            concurrency: DEFAULT_CONCURRENCY,
+            timeout: DEFAULT_TIMEOUT,
+        };
+    }
+
+    /// How many cases run at once. Use 1 for backends that cannot run executions in parallel.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        return self;
+    }
+
+    /// How long a remote case may run before it is reported as still running.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        return self;
+    }
+
+    /// Keeps only the cases whose name contains `filter`, like the test harness filter.
+    pub fn filter(mut self, filter: &str) -> Self {
+        self.cases.retain(|case| case.name.contains(filter));
+        return self;
+    }
+
+    /// Keeps only the case named `name`.
+    #[track_caller]
+    pub fn only(mut self, name: &str) -> Self {
+        self.cases.retain(|case| case.name == name);
+        if self.cases.is_empty() {
+            panic!("{} has no test case named {}", self.state_machine, name);
+        }
+        return self;
+    }
+
+    pub fn state_machine(&self) -> &str {
+        return &self.state_machine;
+    }
+
+    pub fn cases(&self) -> &[TestCase] {
+        return &self.cases;
+    }
+
+    /// Runs every case on the native executor, which must have the mock config.
+    pub async fn run_local(&self, executor: &Executor, definition: &Definition) -> CasesReport {
+        let arn = state_machine_arn(DEFAULT_REGION, DEFAULT_ACCOUNT_ID, &self.state_machine);
+
+        return self
+            .run(|case| {
+                let mut request = ExecutionRequest::new(definition.clone(), case.input.clone())
+                    .state_machine_arn(arn.clone())
+                    .name(case.name.clone());
+                if case.mocked {
+                    request = request.test_case(case.name.clone());
+                }
+                async move { Ok(executor.start(request).wait().await) }
+            })
+            .await;
+    }
+
+    /// Runs every case on the machine of a fixture, through its endpoint.
+    pub async fn run_fixture(&self, fixture: &StateMachineFixture) -> CasesReport {
+        return self
+            .run(|case| async move {
+                let test_case = case.mocked.then_some(case.name.as_str());
+                let execution_arn = fixture.start_execution(test_case, &case.input).await?;
+
+                return remote::wait_for_execution(fixture.client(), &execution_arn, self.timeout)
+                    .await;
+            })
+            .await;
+    }
+
+    async fn run<'a, F, Fut>(&'a self, execute: F) -> CasesReport
+    where
+        F: Fn(&'a TestCase) -> Fut,
+        Fut: Future<Output = Result<Execution, crate::Error>>,
+    {
+        let results: Vec<CaseResult> = futures::stream::iter(&self.cases)
+            .map(|case| {
+                let execution = execute(case);
+                async move {
+                    return match execution.await {
+                        Ok(execution) => CaseResult {
+                            name: case.name.clone(),
+                            mismatches: case.expectation.mismatches(&execution),
+                            execution: Some(execution),
+                        },
+                        Err(error) => CaseResult {
+                            name: case.name.clone(),
+                            mismatches: vec![format!("failed to run: {}", error)],
+                            execution: None,
+                        },
+                    };
+                }
+            })
+            .buffered(self.concurrency)
+            .collect()
+            .await;
+
+        return CasesReport {
+            state_machine: self.state_machine.clone(),
+            results,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseResult {
+    pub name: String,
+    /// `None` when the execution could not be started or fetched.
+    pub execution: Option<Execution>,
+    pub mismatches: Vec<String>,
+}
+
+impl CaseResult {
+    pub fn passed(&self) -> bool {
+        return self.mismatches.is_empty();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CasesReport {
+    pub state_machine: String,
+    pub results: Vec<CaseResult>,
+}
+
+impl CasesReport {
+    pub fn failed(&self) -> Vec<&CaseResult> {
+        return self
+            .results
+            .iter()
+            .filter(|result| !result.passed())
+            .collect();
+    }
+
+    /// Panics with the report if any case failed. The traces of the failed executions are
+    /// written to `SFN_TRACE_DIR` when it is set.
+    #[track_caller]
+    pub fn assert_passed(&self) {
+        let failed = self.failed();
+        if !failed.is_empty() {
+            for result in &failed {
+                if let Some(execution) = &result.execution {
+                    ExecutionTrace::from_execution(execution).dump_if_configured();
+                }
+            }
+            panic!(
+                "{} of {} test cases of {} failed\n\n{}",
+                failed.len(),
+                self.results.len(),
+                self.state_machine,
+                self
+            );
+        }
+    }
+}
+
+impl fmt::Display for CasesReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "running {} test cases of {}",
+            self.results.len(),
+            self.state_machine
+        )?;
+        for result in &self.results {
+            let outcome = if result.passed() { "ok" } else { "FAILED" };
+            writeln!(
+                f,
+                "test {}::{} ... {}",
+                self.state_machine, result.name, outcome
+            )?;
+        }
+
+        let failed = self.failed();
+        if !failed.is_empty() {
+            writeln!(f, "\nfailures:")?;
+        }
+        for result in &failed {
+            writeln!(f, "\n---- {}::{} ----", self.state_machine, result.name)?;
+            for mismatch in &result.mismatches {
+                writeln!(f, "{}", mismatch)?;
+            }
+            if let Some(execution) = &result.execution {
+                writeln!(f, "{}", ExecutionTrace::from_execution(execution))?;
+            }
+        }
+
+        let outcome = if failed.is_empty() { "ok" } else { "FAILED" };
+        return write!(
+            f,
+            "\ntest result: {}. {} passed; {} failed",
+            outcome,
+            self.results.len() - failed.len(),
+            failed.len()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::simple_definition;
+
+    mod sample_machine {
+        use super::*;
+
+        fn mock_config() -> MockConfig {
+            return serde_json::from_str(include_str!("sfn-local-mock.json")).unwrap();
+        }
+
+        fn expectations() -> Expectations {
+            return serde_json::from_str(include_str!("sfn-local-expectations.json")).unwrap();
+        }
+
+        async fn run_test_case(state_machine: &str, case: &str) -> CasesReport {
+            let cases = TestCases::new(state_machine, Some(&mock_config()), Some(&expectations()));
+            let executor = Executor::new().with_mock_config(mock_config());
+
+            return cases
+                .only(case)
+                .run_local(&executor, &simple_definition())
+                .await;
+        }
+
+        include!(concat!(env!("OUT_DIR"), "/sfn_local_test_cases.rs"));
+    }
+
+    #[tokio::test]
+    async fn runs_the_sample_machine_cases_from_the_expectations_file() {
+        let config: MockConfig = serde_json::from_str(include_str!("sfn-local-mock.json")).unwrap();
+        let mut expectations: Expectations =
+            serde_json::from_str(include_str!("sfn-local-expectations.json")).unwrap();
+        let executor = Executor::new().with_mock_config(config.clone());
+
+        let cases = TestCases::new("SimpleExample", Some(&config), Some(&expectations));
+        assert_eq!(
+            cases
+                .cases()
+                .iter()
+                .map(|case| case.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["GetHtmlError", "IsBigPath", "IsNotBigPath"]
+        );
+        let report = cases.run_local(&executor, &simple_definition()).await;
+        assert!(report.failed().is_empty(), "{}", report);
+
+        let simple_example = expectations.state_machines.get_mut("SimpleExample").unwrap();
+        simple_example
+            .test_cases
+            .get_mut("IsNotBigPath")
+            .unwrap()
+            .output = Some(json!(true));
+        simple_example.test_cases.insert(
+            "Unmocked".to_string(),
+            Expectation {
+                status: ExecutionStatus::Failed,
+                visited_states: Some(vec!["GetHtml".to_string(), "Dunno".to_string()]),
+                ..Default::default()
+            },
+        );
+        let report = TestCases::new("SimpleExample", Some(&config), Some(&expectations))
+            .concurrency(1)
+            .run_local(&executor, &simple_definition())
+            .await;
+
+        let failed = report.failed();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].name, "IsNotBigPath");
+        assert_eq!(failed[0].mismatches, vec!["expected output true, got false"]);
+        let rendered = report.to_string();
+        assert!(rendered.contains("test SimpleExample::IsNotBigPath ... FAILED"));
+        assert!(rendered.contains("test SimpleExample::Unmocked ... ok"));
+        assert!(rendered.ends_with("test result: FAILED. 3 passed; 1 failed"));
+        let panic = std::panic::catch_unwind(|| report.assert_passed()).unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert!(message.starts_with("1 of 4 test cases of SimpleExample failed"));
+        assert!(message.contains("expected output true, got false"));
+    }
+}
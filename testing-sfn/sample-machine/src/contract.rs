@@ -0,0 +1,716 @@
+//! Contract checks between the data a state machine is given and the JSONPaths it reads.
+//!
+//! The schemas of the execution input and of each Task's result (e.g. the `Output` of a Lambda
+//! handler, see [`schema_of`]) flow through the definition the way the data would: `InputPath`,
+//! `Parameters`, `ResultSelector`, `ResultPath` and `OutputPath` reshape them, and `Catch`
+//! clauses add the error object. Every JSONPath reference on the way, such as a Choice
+//! `Variable`, a `Parameters` key ending in `.$` or a `SecondsPath`, is resolved against the
+//! schema of its state. Paths that cannot exist, and Choice comparisons with a value of the wrong
+//! type, are reported as [`Violation`]s.
+//!
+//! Object schemas with `properties` are treated as closed, like serialized Rust structs, unless
+//! they allow `additionalProperties`. An empty schema (`{}`) stands for data nothing is known
+//! about and is never reported.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::fmt;
+
+use serde_json::{json, Map, Value};
+
+use crate::asl::path::{Path, Segment};
+use crate::asl::{choice, intrinsics, Catcher, Definition, State, StateKind};
+
+/// How many (state, input schema) pairs are checked before giving up on a looping definition.
+const MAX_VISITS: usize = 1000;
+/// How deeply `$ref`s of recursive types are inlined.
+const MAX_REF_DEPTH: usize = 8;
+
+/// The JSON schema of a Rust type, e.g. the `Output` of a Lambda handler.
+pub fn schema_of<T: schemars::JsonSchema>() -> Value {
+    return serde_json::to_value(schemars::schema_for!(T)).unwrap_or_else(|_| json!({}));
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Violation {
+    pub state: String,
+    /// The field holding the path, e.g. `Choices[0].Variable` or `Parameters.url.$`.
+    pub field: String,
+    pub path: String,
+    pub problem: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(
+            f,
+            "{}: {} '{}' {}",
+            self.state, self.field, self.path, self.problem
+        );
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContractReport {
+    pub violations: Vec<Violation>,
+}
+
+impl ContractReport {
+    pub fn is_ok(&self) -> bool {
+        return self.violations.is_empty();
+    }
+
+    /// Panics with every violation.
+    #[track_caller]
+    pub fn assert_ok(&self) {
+        if !self.is_ok() {
+            panic!("the definition does not match its data contracts\n{}", self);
+        }
+    }
+}
+
+impl fmt::Display for ContractReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.violations.is_empty() {
+            return write!(f, "No contract violations");
+        }
+        for (index, violation) in self.violations.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "  {}", violation)?;
+        }
+
+        return Ok(());
+    }
+}
+
+pub struct Contract<'a> {
+    definition: &'a Definition,
+    input: Value,
+    task_outputs: BTreeMap<String, Value>,
+}
+
+impl<'a> Contract<'a> {
+    /// A contract with nothing known about the input or the task results.
+    pub fn new(definition: &'a Definition) -> Self {
+        return Self {
+            definition,
+            input: json!({}),
+            task_outputs: BTreeMap::new(),
+        };
+    }
+
+    /// The schema of the execution input.
+    pub fn input(mut self, schema: Value) -> Self {
+        self.input = inline_refs(&schema, &schema, 0);
+        return self;
+    }
+
+    /// The schema of what a Task state's resource returns.
+    pub fn task_output(mut self, state: impl Into<String>, schema: Value) -> Self {
+        self.task_outputs
+            .insert(state.into(), inline_refs(&schema, &schema, 0));
+        return self;
+    }
+
+    pub fn check(&self) -> ContractReport {
+        let mut checker = Checker {
+            contract: self,
+            violations: BTreeSet::new(),
+        };
+        checker.definition(self.definition, self.input.clone());
+
+        return ContractReport {
+            violations: checker.violations.into_iter().collect(),
+        };
+    }
+}
+
+struct Checker<'a> {
+    contract: &'a Contract<'a>,
+    violations: BTreeSet<Violation>,
+}
+
+impl Checker<'_> {
+    /// Follows every transition from `StartAt` with the schema of each state's input.
+    fn definition(&mut self, definition: &Definition, input: Value) {
+        let mut queue = VecDeque::from([(definition.start_at().to_string(), input)]);
+        let mut seen = HashSet::new();
+        while let Some((name, input)) = queue.pop_front() {
+            if seen.len() >= MAX_VISITS || !seen.insert((name.clone(), input.to_string())) {
+                continue;
+            }
+            if let Some(state) = definition.state(&name) {
+                queue.extend(self.state(state, input));
+            }
+        }
+    }
+
+    /// Checks a state and returns its transitions with the schema of their input.
+    fn state(&mut self, state: State<'_>, input: Value) -> Vec<(String, Value)> {
+        let kind = match state.kind() {
+            Some(kind) => kind,
+            None => return Vec::new(),
+        };
+        let effective = self.filter(state, "InputPath", &input);
+        let mut next = Vec::new();
+
+        match kind {
+            StateKind::Task | StateKind::Parallel | StateKind::Map => {
+                let parameters = if kind == StateKind::Map {
+                    if let Some(path) = state.str_field("ItemsPath") {
+                        self.reference(state, "ItemsPath", path, &effective, Some("array"));
+                    }
+                    "ItemSelector"
+                } else {
+                    "Parameters"
+                };
+                self.template(state, parameters, &effective);
+                let result = match kind {
+                    StateKind::Task => self
+                        .contract
+                        .task_outputs
+                        .get(state.name)
+                        .cloned()
+                        .unwrap_or_else(|| json!({})),
+                    _ => json!({}),
+                };
+                for branch in state.branches() {
+                    self.definition(&branch, json!({}));
+                }
+                let result = self.template(state, "ResultSelector", &result);
+                let output = self.result_path(state, &input, result);
+                let output = self.filter(state, "OutputPath", &output);
+                next.extend(state.next().map(|name| (name.to_string(), output)));
+
+                let error = json!({
+                    "type": "object",
+                    "properties": { "Error": { "type": "string" }, "Cause": { "type": "string" } },
+                    "required": ["Error", "Cause"]
+                });
+                for catcher in state.catchers().unwrap_or_default() {
+                    let Catcher {
+                        next: name,
+                        result_path,
+                        ..
+                    } = catcher;
+                    let output = match result_path {
+                        Some(result_path) => set(&input, &result_path, error.clone()),
+                        None => input.clone(),
+                    };
+                    next.push((name, output));
+                }
+            }
+            StateKind::Pass => {
+                let result = match state.field("Result") {
+                    Some(result) => literal(result),
+                    None if state.field("Parameters").is_some() => {
+                        self.template(state, "Parameters", &effective)
+                    }
+                    None => effective.clone(),
+                };
+                let output = self.result_path(state, &input, result);
+                let output = self.filter(state, "OutputPath", &output);
+                next.extend(state.next().map(|name| (name.to_string(), output)));
+            }
+            StateKind::Choice => {
+                let rules = state.field("Choices").and_then(Value::as_array);
+                for (index, rule) in rules.into_iter().flatten().enumerate() {
+                    self.rule(state, &format!("Choices[{}]", index), rule, &effective);
+                }
+                let output = self.filter(state, "OutputPath", &effective);
+                for name in state.transitions() {
+                    next.push((name.to_string(), output.clone()));
+                }
+            }
+            StateKind::Wait => {
+                if let Some(path) = state.str_field("SecondsPath") {
+                    self.reference(state, "SecondsPath", path, &effective, Some("number"));
+                }
+                if let Some(path) = state.str_field("TimestampPath") {
+                    self.reference(state, "TimestampPath", path, &effective, Some("string"));
+                }
+                let output = self.filter(state, "OutputPath", &effective);
+                next.extend(state.next().map(|name| (name.to_string(), output)));
+            }
+            StateKind::Fail => {
+                for field in ["ErrorPath", "CausePath"] {
+                    if let Some(path) = state.str_field(field) {
+                        self.reference(state, field, path, &effective, Some("string"));
+                    }
+                }
+            }
+            StateKind::Succeed => {}
+        }
+
+        return next;
+    }
+
+    fn rule(&mut self, state: State<'_>, field: &str, rule: &Value, input: &Value) {
+        for combinator in ["And", "Or"] {
+            let rules = rule.get(combinator).and_then(Value::as_array);
+            for (index, nested) in rules.into_iter().flatten().enumerate() {
+                self.rule(state, &format!("{}.{}[{}]", field, combinator, index), nested, input);
+            }
+        }
+        if let Some(nested) = rule.get("Not") {
+            self.rule(state, &format!("{}.Not", field), nested, input);
+        }
+
+        let variable = match rule["Variable"].as_str() {
+            Some(variable) => variable,
+            None => return,
+        };
+        // Type tests are how a definition guards against missing or differently typed data.
+        if choice::TYPE_TESTS.iter().any(|test| rule.get(*test).is_some()) {
+            return;
+        }
+        for operator in choice::COMPARISON_OPERATORS {
+            let expected_type = match *operator {
+                operator if operator.starts_with("Numeric") => "number",
+                operator if operator.starts_with("Boolean") => "boolean",
+                _ => "string",
+            };
+            let path_operator = format!("{}Path", operator);
+            if rule.get(*operator).is_none() && rule.get(&path_operator).is_none() {
+                continue;
+            }
+            let variable_field = format!("{}.Variable", field);
+            let found = self.reference(state, &variable_field, variable, input, None);
+            if let Some(schema) = found {
+                if !has_type(&schema, expected_type) {
+                    self.violations.insert(Violation {
+                        state: state.name.to_string(),
+                        field: variable_field,
+                        path: variable.to_string(),
+                        problem: format!(
+                            "is {} but {} needs {}",
+                            describe_type(&schema),
+                            operator,
+                            article(expected_type)
+                        ),
+                    });
+                }
+            }
+            if let Some(Value::String(other)) = rule.get(&path_operator) {
+                let other_field = format!("{}.{}", field, path_operator);
+                self.reference(state, &other_field, other, input, Some(expected_type));
+            }
+            return;
+        }
+    }
+
+    /// Resolves a path, reporting it if it cannot exist or is not of `expected_type`. Returns
+    /// the schema of the value when it is known.
+    fn reference(
+        &mut self,
+        state: State<'_>,
+        field: &str,
+        expression: &str,
+        schema: &Value,
+        expected_type: Option<&str>,
+    ) -> Option<Value> {
+        let violation = |problem: String| Violation {
+            state: state.name.to_string(),
+            field: field.to_string(),
+            path: expression.to_string(),
+            problem,
+        };
+        let path = match Path::parse(expression) {
+            Ok(path) => path,
+            Err(error) => {
+                self.violations.insert(violation(error.cause));
+                return None;
+            }
+        };
+        if path.context {
+            return None;
+        }
+
+        return match resolve(schema, &path.segments) {
+            Resolution::Unknown => None,
+            Resolution::Missing(problem) => {
+                self.violations.insert(violation(format!("cannot exist: {}", problem)));
+                None
+            }
+            Resolution::Found(found) => {
+                if let Some(expected_type) = expected_type {
+                    if !has_type(&found, expected_type) {
+                        self.violations.insert(violation(format!(
+                            "is {} but must be {}",
+                            describe_type(&found),
+                            article(expected_type)
+                        )));
+                    }
+                }
+                Some(found)
+            }
+        };
+    }
+
+    /// Applies `InputPath` or `OutputPath`. A `null` path passes `{}` on.
+    fn filter(&mut self, state: State<'_>, field: &str, schema: &Value) -> Value {
+        return match state.field(field) {
+            None => schema.clone(),
+            Some(Value::Null) => json!({ "type": "object", "properties": {} }),
+            Some(Value::String(path)) => self
+                .reference(state, field, path, schema, None)
+                .unwrap_or_else(|| json!({})),
+            Some(_) => json!({}),
+        };
+    }
+
+    fn result_path(&mut self, state: State<'_>, input: &Value, result: Value) -> Value {
+        return match state.field("ResultPath") {
+            None => result,
+            Some(Value::Null) => input.clone(),
+            Some(Value::String(path)) => set(input, path, result),
+            Some(_) => json!({}),
+        };
+    }
+
+    /// The schema of a payload template (`Parameters`, `ResultSelector`), checking the path of
+    /// every key that ends in `.$`.
+    fn template(&mut self, state: State<'_>, field: &str, input: &Value) -> Value {
+        let template = match state.field(field) {
+            Some(template) => template,
+            None => return input.clone(),
+        };
+
+        return self.template_value(state, field, template, input);
+    }
+
+    fn template_value(
+        &mut self,
+        state: State<'_>,
+        field: &str,
+        template: &Value,
+        input: &Value,
+    ) -> Value {
+        let object = match template {
+            Value::Object(object) => object,
+            template => return literal(template),
+        };
+        let mut properties = Map::new();
+        for (key, value) in object {
+            let (name, schema) = match (key.strip_suffix(".$"), value.as_str()) {
+                (Some(name), Some(expression)) if !intrinsics::is_intrinsic(expression) => {
+                    let field = format!("{}.{}", field, key);
+                    let schema = self.reference(state, &field, expression, input, None);
+                    (name.to_string(), schema.unwrap_or_else(|| json!({})))
+                }
+                (Some(name), _) => (name.to_string(), json!({})),
+                (None, _) => {
+                    let field = format!("{}.{}", field, key);
+                    (key.clone(), self.template_value(state, &field, value, input))
+                }
+            };
+            properties.insert(name, schema);
+        }
+        let required: Vec<&String> = properties.keys().collect();
+
+        return json!({ "type": "object", "properties": properties, "required": required });
+    }
+}
+
+enum Resolution {
+    /// Nothing is known about the value.
+    Unknown,
+    /// The value cannot exist, and why.
+    Missing(String),
+    Found(Value),
+}
+
+fn resolve(schema: &Value, segments: &[Segment]) -> Resolution {
+    let segment = match segments.first() {
+        Some(segment) => segment,
+        None => return Resolution::Found(schema.clone()),
+    };
+
+    if let Some(alternatives) = schema
+        .get("anyOf")
+        .or_else(|| schema.get("oneOf"))
+        .and_then(Value::as_array)
+    {
+        let mut problems = Vec::new();
+        for alternative in alternatives {
+            match resolve(alternative, segments) {
+                Resolution::Missing(problem) => problems.push(problem),
+                resolution => return resolution,
+            }
+        }
+        return Resolution::Missing(problems.join("; "));
+    }
+
+    let types = types(schema);
+    match segment {
+        Segment::Field(name) => {
+            if let Some(types) = &types {
+                if !types.contains("object") {
+                    return Resolution::Missing(format!(
+                        "'{}' is read from {}",
+                        name,
+                        describe_type(schema)
+                    ));
+                }
+            }
+            if let Some(property) = schema["properties"].get(name) {
+                return resolve(property, &segments[1..]);
+            }
+            return match schema.get("additionalProperties") {
+                Some(additional @ Value::Object(_)) => resolve(additional, &segments[1..]),
+                Some(Value::Bool(false)) => Resolution::Missing(missing_field(schema, name)),
+                Some(_) => Resolution::Unknown,
+                None if schema.get("properties").is_some() => {
+                    Resolution::Missing(missing_field(schema, name))
+                }
+                None => Resolution::Unknown,
+            };
+        }
+        Segment::Index(_) | Segment::Wildcard => {
+            if let Some(types) = &types {
+                if !types.contains("array") {
+                    return Resolution::Missing(format!(
+                        "an index is read from {}",
+                        describe_type(schema)
+                    ));
+                }
+            }
+            let items = match schema.get("items") {
+                Some(items) => resolve(items, &segments[1..]),
+                None => Resolution::Unknown,
+            };
+            return match (segment, items) {
+                (Segment::Wildcard, Resolution::Found(items)) => {
+                    Resolution::Found(json!({ "type": "array", "items": items }))
+                }
+                (_, items) => items,
+            };
+        }
+    }
+}
+
+fn missing_field(schema: &Value, name: &str) -> String {
+    let fields: Vec<&String> = schema["properties"]
+        .as_object()
+        .map(|properties| properties.keys().collect())
+        .unwrap_or_default();
+
+    return format!("no field '{}' among {:?}", name, fields);
+}
+
+/// The JSON types a schema allows, or `None` when it does not say.
+fn types(schema: &Value) -> Option<BTreeSet<String>> {
+    let types: BTreeSet<String> = match &schema["type"] {
+        Value::String(type_name) => [type_name.clone()].into_iter().collect(),
+        Value::Array(type_names) => type_names
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => {
+            let alternatives = schema
+                .get("anyOf")
+                .or_else(|| schema.get("oneOf"))
+                .and_then(Value::as_array)?;
+            let mut types = BTreeSet::new();
+            for alternative in alternatives {
+                types.extend(self::types(alternative)?);
+            }
+            types
+        }
+    };
+
+    return Some(types);
+}
+
+fn has_type(schema: &Value, expected_type: &str) -> bool {
+    // An integer is a number for the Numeric operators.
+    return types(schema).into_iter().all(|types| {
+        types.contains(expected_type) || (expected_type == "number" && types.contains("integer"))
+    });
+}
+
+fn describe_type(schema: &Value) -> String {
+    return match types(schema) {
+        Some(types) => {
+            let types: Vec<String> = types.iter().map(|type_name| article(type_name)).collect();
+            types.join(" or ")
+        }
+        None => "anything".to_string(),
+    };
+}
+
+fn article(type_name: &str) -> String {
+    let article = if type_name.starts_with(['a', 'e', 'i', 'o', 'u']) {
+        "an"
+    } else {
+        "a"
+    };
+
+    return format!("{} {}", article, type_name);
+}
+
+/// The schema of a literal value, e.g. a Pass state's `Result`.
+fn literal(value: &Value) -> Value {
+    return match value {
+        Value::Null => json!({ "type": "null" }),
+        Value::Bool(_) => json!({ "type": "boolean" }),
+        Value::Number(number) if number.is_f64() => json!({ "type": "number" }),
+        Value::Number(_) => json!({ "type": "integer" }),
+        Value::String(_) => json!({ "type": "string" }),
+        Value::Array(values) => json!({
+            "type": "array",
+            "items": values.first().map_or_else(|| json!({}), literal)
+        }),
+        Value::Object(object) => {
+            let properties: Map<String, Value> = object
+                .iter()
+                .map(|(key, value)| (key.clone(), literal(value)))
+                .collect();
+            let required: Vec<&String> = object.keys().collect();
+            json!({ "type": "object", "properties": properties, "required": required })
+        }
+    };
+}
+
+/// The schema of `input` with `value` placed at a `ResultPath`. A path into an input nothing is
+/// known about yields an open object.
+fn set(input: &Value, expression: &str, value: Value) -> Value {
+    let path = match Path::parse(expression) {
+        Ok(path) => path,
+        Err(_) => return json!({}),
+    };
+
+    return set_segments(input, &path.segments, value);
+}
+
+fn set_segments(schema: &Value, segments: &[Segment], value: Value) -> Value {
+    let name = match segments.first() {
+        None => return value,
+        Some(Segment::Field(name)) => name,
+        Some(_) => return json!({}),
+    };
+
+    let mut object = match types(schema) {
+        Some(types) if types.contains("object") => schema.clone(),
+        _ => json!({ "type": "object", "properties": {}, "additionalProperties": true }),
+    };
+    let nested = set_segments(&object["properties"][name], &segments[1..], value);
+    object["properties"][name] = nested;
+    if let Some(required) = object["required"].as_array_mut() {
+        if !required.iter().any(|field| field == name) {
+            required.push(json!(name));
+        }
+    }
+
+    return object;
+}
+
+/// Replaces `{"$ref": "#/definitions/X"}` by the definition it points to, as schemars emits
+/// references for nested types.
+fn inline_refs(schema: &Value, root: &Value, depth: usize) -> Value {
+    return match schema {
+        Value::Object(object) => {
+            if let Some(reference) = object.get("$ref").and_then(Value::as_str) {
+                let target = reference
+                    .strip_prefix('#')
+                    .and_then(|pointer| root.pointer(pointer));
+                return match target {
+                    Some(target) if depth < MAX_REF_DEPTH => inline_refs(target, root, depth + 1),
+                    _ => json!({}),
+                };
+            }
+            Value::Object(
+                object
+                    .iter()
+                    .filter(|(key, _)| *key != "definitions" && *key != "$defs")
+                    .map(|(key, value)| (key.clone(), inline_refs(value, root, depth)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| inline_refs(value, root, depth))
+                .collect(),
+        ),
+        value => value.clone(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::simple_value;
+
+    fn simple_definition() -> Definition {
+        let mut definition = simple_value();
+        definition["States"]["GetHtml"]["Parameters"] = json!({ "url.$": "$.url" });
+        return Definition::from_value(definition).unwrap();
+    }
+
+    fn output_schema(size: Value) -> Value {
+        return json!({
+            "title": "Output",
+            "type": "object",
+            "required": ["size", "url"],
+            "properties": { "size": size, "url": { "type": "string" } }
+        });
+    }
+
+    #[test]
+    fn checks_choice_variables_against_the_task_output() {
+        let definition = simple_definition();
+        let contract = |output: Value| {
+            return Contract::new(&definition)
+                .task_output("GetHtml", output)
+                .check();
+        };
+
+        assert!(contract(output_schema(json!({ "type": "integer", "minimum": 0 }))).is_ok());
+
+        let renamed = json!({
+            "type": "object",
+            "properties": { "length": { "type": "integer" }, "url": { "type": "string" } }
+        });
+        assert_eq!(
+            contract(renamed).to_string(),
+            "  IsHtmlBig?: Choices[0].Variable '$.size' cannot exist: no field 'size' among [\"length\", \"url\"]"
+        );
+
+        let report = contract(output_schema(json!({ "type": "string" })));
+        assert_eq!(
+            report.violations[0].problem,
+            "is a string but NumericGreaterThan needs a number"
+        );
+        assert!(std::panic::catch_unwind(|| report.assert_ok()).is_err());
+    }
+
+    #[test]
+    fn checks_parameters_against_the_input_and_follows_result_paths() {
+        let mut definition = simple_definition().into_value();
+        definition["States"]["GetHtml"]["Parameters"] = json!({ "url.$": "$.address" });
+        definition["States"]["GetHtml"]["ResultPath"] = json!("$.page");
+        let definition = Definition::from_value(definition).unwrap();
+        let input = json!({
+            "type": "object",
+            "properties": { "url": { "$ref": "#/definitions/Url" } },
+            "definitions": { "Url": { "type": "string" } }
+        });
+
+        let report = Contract::new(&definition)
+            .input(input)
+            .task_output("GetHtml", output_schema(json!({ "type": "integer" })))
+            .check();
+
+        let violations: Vec<String> = report.violations.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            violations,
+            vec![
+                "GetHtml: Parameters.url.$ '$.address' cannot exist: no field 'address' among [\"url\"]",
+                "IsHtmlBig?: Choices[0].Variable '$.size' cannot exist: no field 'size' among [\"page\", \"url\"]",
+            ]
+        );
+    }
+}
@@ -0,0 +1,389 @@
+//! State transition, task invocation, duration and payload accounting from execution
+//! histories, priced as Standard and Express workflows.
+//!
+//! Standard workflows are billed per state transition: every state entered counts, and so does
+//! every retried task attempt. Express workflows are billed per request plus GB-seconds of
+//! duration, rounded up to the billing increment, at an assumed memory size. Durations are
+//! measured from the history, so local executions that skip waits only account for the time
+//! their tasks took. Any payload that gets close to the 256 KB state payload limit is reported.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cases::CasesReport;
+use crate::history::Execution;
+
+/// The largest input or output a state may pass on, in bytes.
+pub const MAX_PAYLOAD_BYTES: usize = 262_144;
+/// Payloads of at least this share of [`MAX_PAYLOAD_BYTES`] are reported.
+pub const PAYLOAD_WARNING_RATIO: f64 = 0.8;
+
+/// Prices in USD, `us-east-1` by default, without free tiers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct PriceTable {
+    pub standard_per_transition: f64,
+    pub express_per_request: f64,
+    pub express_per_gb_second: f64,
+    /// The memory an Express execution is assumed to use.
+    pub express_memory_mb: u32,
+    pub express_increment_ms: u64,
+}
+
+impl Default for PriceTable {
+    fn default() -> Self {
+        return Self {
+            standard_per_transition: 0.000025,
+            express_per_request: 0.000001,
+            express_per_gb_second: 0.00001667,
+            express_memory_mb: 64,
+            express_increment_ms: 100,
+        };
+    }
+}
+
+impl PriceTable {
+    /// Reads a JSON price table; missing prices keep their default.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let contents = std::fs::read_to_string(path)?;
+
+        return Ok(serde_json::from_str(&contents)?);
+    }
+}
+
+/// An event whose payload is close to the limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadWarning {
+    pub event_id: i64,
+    pub event_type: String,
+    /// The state the event belongs to, or the last state entered before it.
+    pub state: Option<String>,
+    pub bytes: usize,
+}
+
+impl fmt::Display for PayloadWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(
+            f,
+            "{} ({} #{}) is {} bytes, {:.0}% of the 256 KB payload limit",
+            self.state.as_deref().unwrap_or("execution"),
+            self.event_type,
+            self.event_id,
+            self.bytes,
+            self.bytes as f64 * 100.0 / MAX_PAYLOAD_BYTES as f64
+        );
+    }
+}
+
+/// The accounting of one execution.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecutionCost {
+    /// The test case or execution name.
+    pub label: String,
+    pub transitions: u64,
+    pub task_invocations: u64,
+    pub duration_ms: u64,
+    pub billed_duration_ms: u64,
+    pub largest_payload_bytes: usize,
+    pub warnings: Vec<PayloadWarning>,
+    pub standard_cost: f64,
+    pub express_cost: f64,
+}
+
+impl ExecutionCost {
+    pub fn new(label: impl Into<String>, execution: &Execution, prices: &PriceTable) -> Self {
+        let mut entered = 0;
+        let mut tasks_entered = 0;
+        let mut task_invocations = 0u64;
+        let mut largest_payload_bytes = 0;
+        let mut warnings = Vec::new();
+        let mut state = None;
+        for event in &execution.events {
+            let event_type = event.event_type.as_str();
+            if event_type.ends_with("StateEntered") {
+                entered += 1;
+                tasks_entered += u64::from(event_type == "TaskStateEntered");
+            }
+            if event_type.ends_with("Scheduled") {
+                task_invocations += 1;
+            }
+            if event.details.name.is_some() {
+                state = event.details.name.clone();
+            }
+
+            let details = &event.details;
+            let payloads = [&details.input, &details.output, &details.parameters];
+            let bytes = payloads
+                .iter()
+                .filter_map(|payload| payload.as_ref().map(String::len))
+                .max()
+                .unwrap_or_default();
+            largest_payload_bytes = largest_payload_bytes.max(bytes);
+            if bytes as f64 >= MAX_PAYLOAD_BYTES as f64 * PAYLOAD_WARNING_RATIO {
+                warnings.push(PayloadWarning {
+                    event_id: event.id,
+                    event_type: event.event_type.clone(),
+                    state: state.clone(),
+                    bytes,
+                });
+            }
+        }
+        // Every attempt after the first of a Task state is a retry.
+        let retries = task_invocations.saturating_sub(tasks_entered);
+        let transitions = entered + retries;
+
+        let stop_date = execution
+            .stop_date
+            .or_else(|| execution.events.last().map(|event| event.timestamp))
+            .unwrap_or(execution.start_date);
+        let duration_ms = ((stop_date - execution.start_date).max(0.0) * 1000.0).round() as u64;
+        let increment = prices.express_increment_ms.max(1);
+        let billed_duration_ms = duration_ms.div_ceil(increment).max(1) * increment;
+        let gb_seconds =
+            billed_duration_ms as f64 / 1000.0 * f64::from(prices.express_memory_mb) / 1024.0;
+
+        return Self {
+            label: label.into(),
+            transitions,
+            task_invocations,
+            duration_ms,
+            billed_duration_ms,
+            largest_payload_bytes,
+            warnings,
+            standard_cost: transitions as f64 * prices.standard_per_transition,
+            express_cost: prices.express_per_request + gb_seconds * prices.express_per_gb_second,
+        };
+    }
+}
+
+/// The accounting of several executions, e.g. the test cases of a state machine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostReport {
+    pub prices: PriceTable,
+    pub executions: Vec<ExecutionCost>,
+}
+
+impl CostReport {
+    pub fn new(prices: PriceTable) -> Self {
+        return Self {
+            prices,
+            executions: Vec::new(),
+        };
+    }
+
+    /// One line per test case that produced an execution.
+    pub fn from_cases(report: &CasesReport, prices: PriceTable) -> Self {
+        let mut cost = Self::new(prices);
+        for result in &report.results {
+            if let Some(execution) = &result.execution {
+                cost = cost.execution(&result.name, execution);
+            }
+        }
+
+        return cost;
+    }
+
+    pub fn execution(mut self, label: impl Into<String>, execution: &Execution) -> Self {
+        let cost = ExecutionCost::new(label, execution, &self.prices);
+        self.executions.push(cost);
+        return self;
+    }
+
+    pub fn standard_cost(&self) -> f64 {
+        return self.executions.iter().map(|cost| cost.standard_cost).sum();
+    }
+
+    pub fn express_cost(&self) -> f64 {
+        return self.executions.iter().map(|cost| cost.express_cost).sum();
+    }
+
+    pub fn warnings(&self) -> Vec<(&str, &PayloadWarning)> {
+        return self
+            .executions
+            .iter()
+            .flat_map(|cost| {
+                cost.warnings
+                    .iter()
+                    .map(move |warning| (cost.label.as_str(), warning))
+            })
+            .collect();
+    }
+}
+
+impl fmt::Display for CostReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .executions
+            .iter()
+            .map(|cost| cost.label.len())
+            .chain(["execution".len()])
+            .max()
+            .unwrap_or_default();
+        writeln!(
+            f,
+            "{:<width$}  {:>11}  {:>5}  {:>8}  {:>8}  {:>9}  {:>12}  {:>12}",
+            "execution",
+            "transitions",
+            "tasks",
+            "duration",
+            "billed",
+            "payload",
+            "standard",
+            "express"
+        )?;
+        for cost in &self.executions {
+            let payload = format!("{:.1} KB", cost.largest_payload_bytes as f64 / 1024.0);
+            let standard = format!("${:.7}", cost.standard_cost);
+            let express = format!("${:.7}", cost.express_cost);
+            writeln!(
+                f,
+                "{:<width$}  {:>11}  {:>5}  {:>6}ms  {:>6}ms  {:>9}  {:>12}  {:>12}",
+                cost.label,
+                cost.transitions,
+                cost.task_invocations,
+                cost.duration_ms,
+                cost.billed_duration_ms,
+                payload,
+                standard,
+                express
+            )?;
+        }
+
+        let count = self.executions.len().max(1) as f64;
+        write!(
+            f,
+            "\nper 1M executions like these: Standard ${:.2}, Express ${:.2} (at {} MB)",
+            self.standard_cost() / count * 1_000_000.0,
+            self.express_cost() / count * 1_000_000.0,
+            self.prices.express_memory_mb
+        )?;
+        let warnings = self.warnings();
+        if !warnings.is_empty() {
+            write!(f, "\n\npayload warnings:")?;
+        }
+        for (label, warning) in warnings {
+            write!(f, "\n  {}: {}", label, warning)?;
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::asl::Definition;
+    use crate::executor::Executor;
+    use crate::history::{EventDetails, ExecutionStatus, HistoryEvent};
+
+    fn event(
+        id: i64,
+        event_type: &str,
+        name: Option<&str>,
+        output: Option<String>,
+    ) -> HistoryEvent {
+        return HistoryEvent {
+            id,
+            previous_event_id: id - 1,
+            timestamp: 0.0,
+            event_type: event_type.to_string(),
+            details: EventDetails {
+                name: name.map(str::to_string),
+                output,
+                ..Default::default()
+            },
+        };
+    }
+
+    #[test]
+    fn counts_transitions_retries_and_large_payloads() {
+        let html = format!(r#"{{"html":"{}"}}"#, "x".repeat(240_000));
+        let events = vec![
+            event(1, "ExecutionStarted", None, None),
+            event(2, "TaskStateEntered", Some("GetHtml"), None),
+            event(3, "LambdaFunctionScheduled", None, None),
+            event(4, "LambdaFunctionFailed", None, None),
+            event(5, "LambdaFunctionScheduled", None, None),
+            event(6, "LambdaFunctionSucceeded", None, Some(html.clone())),
+            event(
+                7,
+                "TaskStateExited",
+                Some("GetHtml"),
+                Some("{}".to_string()),
+            ),
+            event(8, "PassStateEntered", Some("IsBig"), None),
+            event(
+                9,
+                "PassStateExited",
+                Some("IsBig"),
+                Some("true".to_string()),
+            ),
+            event(10, "ExecutionSucceeded", None, Some("true".to_string())),
+        ];
+        let execution = Execution {
+            execution_arn: "arn:aws:states:us-east-1:123456789012:execution:Local:e1".to_string(),
+            state_machine_arn: "arn:aws:states:us-east-1:123456789012:stateMachine:Local"
+                .to_string(),
+            name: "e1".to_string(),
+            status: ExecutionStatus::Succeeded,
+            start_date: 10.0,
+            stop_date: Some(10.25),
+            input: json!({}),
+            output: Some(json!(true)),
+            error: None,
+            cause: None,
+            events,
+            redrive_count: 0,
+            redrive_date: None,
+        };
+
+        let cost = ExecutionCost::new("IsBigPath", &execution, &PriceTable::default());
+
+        assert_eq!(cost.transitions, 3);
+        assert_eq!(cost.task_invocations, 2);
+        assert_eq!((cost.duration_ms, cost.billed_duration_ms), (250, 300));
+        assert_eq!(cost.largest_payload_bytes, html.len());
+        assert!((cost.standard_cost - 0.000075).abs() < 1e-12);
+        assert!((cost.express_cost - (0.000001 + 0.3 * 0.0625 * 0.00001667)).abs() < 1e-12);
+        assert_eq!(
+            cost.warnings,
+            [PayloadWarning {
+                event_id: 6,
+                event_type: "LambdaFunctionSucceeded".to_string(),
+                state: Some("GetHtml".to_string()),
+                bytes: html.len(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn prices_local_executions_with_a_configured_table() {
+        let definition = Definition::from_value(json!({
+            "StartAt": "First",
+            "States": {
+                "First": { "Type": "Pass", "Next": "Second" },
+                "Second": { "Type": "Pass", "End": true }
+            }
+        }))
+        .unwrap();
+        let execution = Executor::new().execute(&definition, json!({})).await;
+        let prices: PriceTable =
+            serde_json::from_value(json!({ "StandardPerTransition": 0.5, "ExpressMemoryMb": 128 }))
+                .unwrap();
+
+        let report = CostReport::new(prices)
+            .execution("one", &execution)
+            .execution("two", &execution);
+
+        assert_eq!(report.standard_cost(), 2.0);
+        assert_eq!(report.prices.express_per_request, 0.000001);
+        assert!(report.warnings().is_empty());
+        assert!(report
+            .to_string()
+            .contains("per 1M executions like these: Standard $1000000.00"));
+    }
+}
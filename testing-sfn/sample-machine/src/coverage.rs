@@ -0,0 +1,905 @@
+//! Generated test cases that cover every branch of a definition.
+//!
+//! The generator walks the top-level states from `StartAt`, takes every `Choice` rule and
+//! `Default`, every `Catch` clause and every `Retry` policy, and derives the mocked task results
+//! that drive each path: a task result that sits on both sides of a numeric threshold (`10240`
+//! and `10241` for `NumericGreaterThan: 10240`), a `Throw` for an error of each `ErrorEquals`,
+//! or a failed attempt followed by a result for a retrier. Each planned path is checked on the
+//! native [`Executor`], and a small set of paths, picked greedily, that covers every reachable
+//! branch becomes a mock config plus an expectations file for [`crate::cases::TestCases`].
+//!
+//! Choice data is written into the result of the last task before the Choice state (or into the
+//! execution input), so `ResultSelector`, `OutputPath` or `Pass` states that reshape it can leave
+//! branches uncovered; those are reported, not guessed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path as FilePath;
+
+use serde_json::{json, Map, Value};
+
+use crate::asl::path::Path;
+use crate::asl::{
+    choice, error_matches, Catcher, Definition, Retrier, State, StateKind, STATES_ALL,
+    STATES_TASK_FAILED, STATES_TIMEOUT,
+};
+use crate::cases::{Expectation, Expectations, StateMachineExpectations};
+use crate::executor::{
+    state_machine_arn, ExecutionRequest, Executor, DEFAULT_ACCOUNT_ID, DEFAULT_REGION,
+};
+use crate::history::{Execution, ExecutionStatus};
+use crate::mock::{MockConfig, MockedError, MockedResponse, MockedResult, MockedStateMachine};
+
+/// How many paths are planned before the walk stops, for definitions with many branches.
+const MAX_PLANS: usize = 256;
+/// How often a path may enter the same state, so that loops are taken but not unrolled.
+const MAX_VISITS: usize = 2;
+/// Tasks inside `Parallel` branches and `Map` processors return `{}` for this many invocations.
+const NESTED_INVOCATIONS: usize = 100;
+const SAMPLE_TIMESTAMP: &str = "2000-01-01T00:00:00Z";
+
+/// A way through a state that a test case can take.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Branch {
+    /// The Choice rule at `index` matches.
+    Rule {
+        state: String,
+        index: usize,
+        next: String,
+    },
+    /// No Choice rule matches.
+    Default { state: String, next: String },
+    /// The error is caught by the Catch clause at `index`.
+    Catch {
+        state: String,
+        index: usize,
+        next: String,
+    },
+    /// The retrier at `index` retries a failed attempt, which then succeeds.
+    Retry { state: String, index: usize },
+}
+
+impl Branch {
+    fn label(&self) -> String {
+        let label = match self {
+            Branch::Rule { next, .. } | Branch::Default { next, .. } => next.clone(),
+            Branch::Catch { state, index, .. } => format!("{}Catch{}", state, index),
+            Branch::Retry { state, index } => format!("{}Retry{}", state, index),
+        };
+
+        return identifier(&label);
+    }
+}
+
+impl fmt::Display for Branch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Branch::Rule { state, index, next } => {
+                write!(f, "{} Choices[{}] -> {}", state, index, next)
+            }
+            Branch::Default { state, next } => write!(f, "{} Default -> {}", state, next),
+            Branch::Catch { state, index, next } => {
+                write!(f, "{} Catch[{}] -> {}", state, index, next)
+            }
+            Branch::Retry { state, index } => write!(f, "{} Retry[{}]", state, index),
+        };
+    }
+}
+
+/// Every branch of the top-level states of a definition.
+pub fn branches(definition: &Definition) -> Vec<Branch> {
+    let mut branches = Vec::new();
+    for state in definition.states() {
+        let name = state.name.to_string();
+        let rules = state.field("Choices").and_then(Value::as_array);
+        for (index, rule) in rules.into_iter().flatten().enumerate() {
+            branches.push(Branch::Rule {
+                state: name.clone(),
+                index,
+                next: rule["Next"].as_str().unwrap_or_default().to_string(),
+            });
+        }
+        if let Some(next) = state.str_field("Default") {
+            branches.push(Branch::Default {
+                state: name.clone(),
+                next: next.to_string(),
+            });
+        }
+        for (index, retrier) in state.retriers().unwrap_or_default().iter().enumerate() {
+            if retrier.max_attempts > 0 {
+                branches.push(Branch::Retry {
+                    state: name.clone(),
+                    index,
+                });
+            }
+        }
+        for (index, catcher) in state.catchers().unwrap_or_default().into_iter().enumerate() {
+            branches.push(Branch::Catch {
+                state: name.clone(),
+                index,
+                next: catcher.next,
+            });
+        }
+    }
+
+    return branches;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedCase {
+    pub name: String,
+    pub branches: Vec<Branch>,
+}
+
+/// The generated test cases of a state machine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coverage {
+    pub state_machine: String,
+    pub branches: Vec<Branch>,
+    pub cases: Vec<GeneratedCase>,
+    pub mock_config: MockConfig,
+    pub expectations: Expectations,
+}
+
+impl Coverage {
+    pub fn covered(&self) -> BTreeSet<&Branch> {
+        return self
+            .cases
+            .iter()
+            .flat_map(|case| case.branches.iter())
+            .collect();
+    }
+
+    /// The branches no generated case takes, because no mocked result could drive them.
+    pub fn uncovered(&self) -> Vec<&Branch> {
+        let covered = self.covered();
+
+        return self
+            .branches
+            .iter()
+            .filter(|branch| !covered.contains(branch))
+            .collect();
+    }
+
+    pub fn write(
+        &self,
+        mock_config_path: impl AsRef<FilePath>,
+        expectations_path: impl AsRef<FilePath>,
+    ) -> Result<(), crate::Error> {
+        std::fs::write(
+            mock_config_path,
+            serde_json::to_string_pretty(&self.mock_config)? + "\n",
+        )?;
+        std::fs::write(
+            expectations_path,
+            serde_json::to_string_pretty(&self.expectations)? + "\n",
+        )?;
+
+        return Ok(());
+    }
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} test cases cover {} of {} branches",
+            self.state_machine,
+            self.cases.len(),
+            self.branches.len() - self.uncovered().len(),
+            self.branches.len()
+        )?;
+        for case in &self.cases {
+            let branches: Vec<String> = case.branches.iter().map(ToString::to_string).collect();
+            write!(f, "\n  {}: {}", case.name, branches.join(", "))?;
+        }
+        for branch in self.uncovered() {
+            write!(f, "\n  not covered: {}", branch)?;
+        }
+
+        return Ok(());
+    }
+}
+
+/// Plans a path for every branch, runs the paths on the native executor and keeps the fewest
+/// that cover every branch they can reach.
+pub async fn generate(definition: &Definition, state_machine: &str) -> Coverage {
+    let mut plans = Vec::new();
+    let start = Plan {
+        input: json!({}),
+        ..Plan::default()
+    };
+    Planner { definition }.explore(definition.start_at(), start, &mut plans);
+
+    let names: Vec<String> = (0..plans.len()).map(|index| format!("Plan{}", index)).collect();
+    let config = mock_config(state_machine, plans.iter().zip(&names));
+    let executor = Executor::new().skip_waits(true).with_mock_config(config);
+    let arn = state_machine_arn(DEFAULT_REGION, DEFAULT_ACCOUNT_ID, state_machine);
+    let executions = futures::future::join_all(plans.iter().zip(&names).map(|(plan, name)| {
+        let request = ExecutionRequest::new(definition.clone(), plan.input.clone())
+            .state_machine_arn(arn.clone())
+            .test_case(name.clone());
+        async { executor.start(request).wait().await }
+    }))
+    .await;
+    let verified: Vec<(Plan, Execution)> = plans
+        .into_iter()
+        .zip(executions)
+        .filter(|(plan, execution)| execution.visited_states() == plan.visited)
+        .collect();
+
+    let selected = select(verified);
+    let mut taken = BTreeSet::new();
+    let mut cases = Vec::new();
+    for (plan, _) in &selected {
+        let mut name = plan.name();
+        let mut suffix = 2;
+        while taken.contains(&name) {
+            name = format!("{}{}", plan.name(), suffix);
+            suffix += 1;
+        }
+        taken.insert(name.clone());
+        cases.push(GeneratedCase {
+            name,
+            branches: plan.branches.clone(),
+        });
+    }
+
+    let mut test_cases = BTreeMap::new();
+    for ((plan, execution), case) in selected.iter().zip(&cases) {
+        test_cases.insert(case.name.clone(), expectation(plan, execution));
+    }
+    let expectations = Expectations {
+        state_machines: [(
+            state_machine.to_string(),
+            StateMachineExpectations {
+                input: None,
+                test_cases,
+            },
+        )]
+        .into_iter()
+        .collect(),
+    };
+    let case_names: Vec<String> = cases.iter().map(|case| case.name.clone()).collect();
+    let plans = selected.iter().map(|(plan, _)| plan);
+
+    return Coverage {
+        state_machine: state_machine.to_string(),
+        branches: branches(definition),
+        cases,
+        mock_config: mock_config(state_machine, plans.zip(&case_names)),
+        expectations,
+    };
+}
+
+/// A planned path: the branches it takes and the mocked task results and input that drive it.
+#[derive(Debug, Clone, Default)]
+struct Plan {
+    branches: Vec<Branch>,
+    visited: Vec<String>,
+    input: Value,
+    /// The result of each invocation of each task on the path.
+    results: BTreeMap<String, Vec<MockedResult>>,
+    /// Tasks of `Parallel` branches and `Map` processors.
+    nested: BTreeSet<String>,
+    /// Where the data a Choice state looks at comes from.
+    data: Data,
+}
+
+#[derive(Debug, Clone, Default)]
+enum Data {
+    #[default]
+    Input,
+    /// The result of a task invocation, placed into the state input at `result_path`.
+    Result {
+        state: String,
+        invocation: usize,
+        result_path: Option<String>,
+    },
+}
+
+impl Plan {
+    fn name(&self) -> String {
+        if self.branches.is_empty() {
+            return "HappyPath".to_string();
+        }
+        let labels: Vec<String> = self.branches.iter().map(Branch::label).collect();
+
+        return labels.join("_");
+    }
+
+    fn push_result(&mut self, state: &str, result: MockedResult) -> usize {
+        let results = self.results.entry(state.to_string()).or_default();
+        results.push(result);
+
+        return results.len() - 1;
+    }
+
+    /// Writes the values a Choice rule needs into the data the Choice state will see.
+    fn constrain(&mut self, constraints: &BTreeMap<String, Option<Value>>) {
+        for (variable, value) in constraints {
+            let value = match value {
+                Some(value) => value.clone(),
+                None => continue,
+            };
+            let target = match &self.data {
+                Data::Result {
+                    state,
+                    invocation,
+                    result_path,
+                } => {
+                    let relative = result_path
+                        .as_deref()
+                        .and_then(|result_path| relative_path(variable, result_path));
+                    match (relative, self.results.get_mut(state)) {
+                        (Some(relative), Some(results)) => {
+                            if let Some(MockedResult::Return(result)) = results.get_mut(*invocation)
+                            {
+                                set(result, &relative, value);
+                            }
+                            continue;
+                        }
+                        _ => &mut self.input,
+                    }
+                }
+                Data::Input => &mut self.input,
+            };
+            set(target, variable, value);
+        }
+    }
+}
+
+struct Planner<'a> {
+    definition: &'a Definition,
+}
+
+impl Planner<'_> {
+    fn explore(&self, name: &str, mut plan: Plan, plans: &mut Vec<Plan>) {
+        if plans.len() >= MAX_PLANS {
+            return;
+        }
+        let state = match self.definition.state(name) {
+            Some(state) => state,
+            None => return,
+        };
+        if plan.visited.iter().filter(|visited| *visited == name).count() >= MAX_VISITS {
+            return;
+        }
+        plan.visited.push(name.to_string());
+
+        match state.kind() {
+            Some(StateKind::Task) => self.task(state, plan, plans),
+            Some(StateKind::Choice) => self.choice(state, plan, plans),
+            Some(StateKind::Parallel | StateKind::Map) => {
+                for branch in state.branches() {
+                    plan.nested.extend(branch.task_states());
+                }
+                self.follow(state.next(), plan, plans);
+            }
+            Some(StateKind::Succeed | StateKind::Fail) | None => plans.push(plan),
+            Some(StateKind::Pass | StateKind::Wait) => self.follow(state.next(), plan, plans),
+        }
+    }
+
+    fn follow(&self, next: Option<&str>, plan: Plan, plans: &mut Vec<Plan>) {
+        match next {
+            Some(next) => self.explore(next, plan, plans),
+            None => plans.push(plan),
+        }
+    }
+
+    fn task(&self, state: State<'_>, plan: Plan, plans: &mut Vec<Plan>) {
+        let retriers = state.retriers().unwrap_or_default();
+        let catchers = state.catchers().unwrap_or_default();
+        let result_path = match state.field("ResultPath") {
+            Some(Value::Null) => None,
+            Some(Value::String(path)) => Some(path.clone()),
+            _ => Some("$".to_string()),
+        };
+
+        let retries = (0..retriers.len()).map(Some);
+        for retry in std::iter::once(None).chain(retries) {
+            let mut plan = plan.clone();
+            if let Some(index) = retry {
+                let branch = Branch::Retry {
+                    state: state.name.to_string(),
+                    index,
+                };
+                match unmatched_error(&retriers, index) {
+                    Some(error) if retriers[index].max_attempts > 0 => {
+                        plan.push_result(state.name, throw(&error, &branch));
+                    }
+                    _ => continue,
+                }
+                plan.branches.push(branch);
+            }
+            let invocation = plan.push_result(state.name, MockedResult::Return(json!({})));
+            plan.data = Data::Result {
+                state: state.name.to_string(),
+                invocation,
+                result_path: result_path.clone(),
+            };
+            self.follow(state.next(), plan, plans);
+        }
+
+        for (index, catcher) in catchers.iter().enumerate() {
+            let error = match unmatched_error(&catchers, index) {
+                Some(error) => error,
+                None => continue,
+            };
+            let branch = Branch::Catch {
+                state: state.name.to_string(),
+                index,
+                next: catcher.next.clone(),
+            };
+            let attempts = retriers
+                .iter()
+                .find(|retrier| error_matches(&retrier.error_equals, &error))
+                .map_or(1, |retrier| retrier.max_attempts as usize + 1);
+            let mut plan = plan.clone();
+            for _ in 0..attempts {
+                plan.push_result(state.name, throw(&error, &branch));
+            }
+            plan.branches.push(branch);
+            self.explore(&catcher.next, plan, plans);
+        }
+    }
+
+    fn choice(&self, state: State<'_>, plan: Plan, plans: &mut Vec<Plan>) {
+        let rules: Vec<Value> = state
+            .field("Choices")
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+
+        let mut outcomes: Vec<(&Value, bool)> = rules.iter().map(|rule| (rule, false)).collect();
+        for index in 0..rules.len() {
+            outcomes[index].1 = true;
+            if let Some(constraints) = satisfy_all(&outcomes[..=index]) {
+                let mut plan = plan.clone();
+                plan.constrain(&constraints);
+                let next = rules[index]["Next"].as_str().unwrap_or_default();
+                plan.branches.push(Branch::Rule {
+                    state: state.name.to_string(),
+                    index,
+                    next: next.to_string(),
+                });
+                self.explore(next, plan, plans);
+            }
+            outcomes[index].1 = false;
+        }
+
+        if let Some(next) = state.str_field("Default") {
+            if let Some(constraints) = satisfy_all(&outcomes) {
+                let mut plan = plan;
+                plan.constrain(&constraints);
+                plan.branches.push(Branch::Default {
+                    state: state.name.to_string(),
+                    next: next.to_string(),
+                });
+                self.explore(next, plan, plans);
+            }
+        }
+    }
+}
+
+/// The values (or absences) of the variables that give each rule its wanted outcome, if the
+/// synthesized values do.
+fn satisfy_all(outcomes: &[(&Value, bool)]) -> Option<BTreeMap<String, Option<Value>>> {
+    let mut constraints = BTreeMap::new();
+    for (rule, outcome) in outcomes {
+        satisfy(rule, *outcome, &mut constraints);
+    }
+
+    let document = document(&constraints);
+    let satisfied = outcomes.iter().all(|(rule, outcome)| {
+        choice::evaluate(rule, &document, &json!({})).ok() == Some(*outcome)
+    });
+
+    return satisfied.then_some(constraints);
+}
+
+fn satisfy(rule: &Value, outcome: bool, constraints: &mut BTreeMap<String, Option<Value>>) {
+    if let Some(rules) = rule.get("And").and_then(Value::as_array) {
+        match outcome {
+            true => rules.iter().for_each(|rule| satisfy(rule, true, constraints)),
+            false => rules
+                .iter()
+                .take(1)
+                .for_each(|rule| satisfy(rule, false, constraints)),
+        }
+        return;
+    }
+    if let Some(rules) = rule.get("Or").and_then(Value::as_array) {
+        match outcome {
+            true => rules
+                .iter()
+                .take(1)
+                .for_each(|rule| satisfy(rule, true, constraints)),
+            false => rules.iter().for_each(|rule| satisfy(rule, false, constraints)),
+        }
+        return;
+    }
+    if let Some(rule) = rule.get("Not") {
+        return satisfy(rule, !outcome, constraints);
+    }
+
+    let variable = match rule["Variable"].as_str() {
+        Some(variable) => variable.to_string(),
+        None => return,
+    };
+    let mut candidates: Vec<Option<Value>> = match constraints.get(&variable) {
+        Some(Some(current)) => vec![Some(current.clone())],
+        _ => Vec::new(),
+    };
+    candidates.extend(leaf_candidates(rule, constraints));
+    for candidate in candidates {
+        let mut attempt = constraints.clone();
+        attempt.insert(variable.clone(), candidate);
+        if choice::evaluate(rule, &document(&attempt), &json!({})).ok() == Some(outcome) {
+            *constraints = attempt;
+            return;
+        }
+    }
+}
+
+/// Values to try for the variable of a leaf rule, boundary values first. A `...Path` operator
+/// also fixes the value of the other path.
+fn leaf_candidates(
+    rule: &Value,
+    constraints: &mut BTreeMap<String, Option<Value>>,
+) -> Vec<Option<Value>> {
+    if choice::TYPE_TESTS.iter().any(|test| rule.get(*test).is_some()) {
+        return vec![
+            None,
+            Some(Value::Null),
+            Some(json!(0)),
+            Some(json!("x")),
+            Some(json!(true)),
+            Some(json!(SAMPLE_TIMESTAMP)),
+        ];
+    }
+
+    for operator in choice::COMPARISON_OPERATORS {
+        let path_operator = format!("{}Path", operator);
+        let expected = match (rule.get(*operator), rule.get(&path_operator)) {
+            (Some(expected), _) => expected.clone(),
+            (None, Some(Value::String(other))) => {
+                let base = match *operator {
+                    operator if operator.starts_with("Numeric") => json!(0),
+                    operator if operator.starts_with("Boolean") => json!(true),
+                    operator if operator.starts_with("Timestamp") => json!(SAMPLE_TIMESTAMP),
+                    _ => json!("m"),
+                };
+                constraints
+                    .entry(other.clone())
+                    .or_insert_with(|| Some(base))
+                    .clone()
+                    .unwrap_or(Value::Null)
+            }
+            _ => continue,
+        };
+
+        let candidates = match &expected {
+            Value::Number(number) => {
+                let number = number.as_f64().unwrap_or_default();
+                [number, number + 1.0, number - 1.0]
+                    .into_iter()
+                    .map(|candidate| {
+                        if candidate.fract() == 0.0 && candidate.abs() < i64::MAX as f64 {
+                            json!(candidate as i64)
+                        } else {
+                            json!(candidate)
+                        }
+                    })
+                    .collect()
+            }
+            Value::Bool(boolean) => vec![json!(*boolean), json!(!*boolean)],
+            Value::String(string) if operator.starts_with("Timestamp") => vec![
+                json!(string),
+                json!("1970-01-01T00:00:00Z"),
+                json!("9999-12-31T23:59:59Z"),
+            ],
+            Value::String(string) if *operator == "StringMatches" => {
+                let matching = unescape_pattern(string);
+                vec![
+                    json!(matching),
+                    json!(""),
+                    json!("~"),
+                    json!(format!("{}~", matching)),
+                ]
+            }
+            Value::String(string) => vec![
+                json!(string),
+                json!(format!("{}~", string)),
+                json!(""),
+                json!("~"),
+            ],
+            _ => Vec::new(),
+        };
+
+        return candidates.into_iter().map(Some).collect();
+    }
+
+    return Vec::new();
+}
+
+/// A string that a `StringMatches` pattern matches: the pattern without its `*` wildcards.
+fn unescape_pattern(pattern: &str) -> String {
+    let mut matching = String::new();
+    let mut chars = pattern.chars();
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' => matching.extend(chars.next()),
+            '*' => {}
+            char => matching.push(char),
+        }
+    }
+
+    return matching;
+}
+
+fn document(constraints: &BTreeMap<String, Option<Value>>) -> Value {
+    let mut document = Value::Object(Map::new());
+    for (variable, value) in constraints {
+        if let Some(value) = value {
+            set(&mut document, variable, value.clone());
+        }
+    }
+
+    return document;
+}
+
+/// Writes a value at a reference path, ignoring paths that cannot be written: the plan then
+/// fails to verify and its branches are reported as not covered.
+fn set(target: &mut Value, path: &str, value: Value) {
+    if let Ok(path) = Path::parse(path) {
+        path.set(target, value).ok();
+    }
+}
+
+/// The path of a Choice variable inside a task result that is placed at `result_path`.
+fn relative_path(variable: &str, result_path: &str) -> Option<String> {
+    if result_path == "$" {
+        return Some(variable.to_string());
+    }
+    let rest = variable.strip_prefix(result_path)?;
+    if rest.is_empty() || rest.starts_with('.') || rest.starts_with('[') {
+        return Some(format!("${}", rest));
+    }
+
+    return None;
+}
+
+/// An error that the `ErrorEquals` at `index` matches and none of the earlier ones do.
+fn unmatched_error<T: ErrorEquals>(handlers: &[T], index: usize) -> Option<String> {
+    return handlers[index]
+        .error_equals()
+        .iter()
+        .flat_map(|candidate| match candidate.as_str() {
+            STATES_ALL | STATES_TASK_FAILED => {
+                vec![STATES_TASK_FAILED.to_string(), STATES_TIMEOUT.to_string()]
+            }
+            candidate => vec![candidate.to_string()],
+        })
+        .find(|error| {
+            error_matches(handlers[index].error_equals(), error)
+                && !handlers[..index]
+                    .iter()
+                    .any(|handler| error_matches(handler.error_equals(), error))
+        });
+}
+
+trait ErrorEquals {
+    fn error_equals(&self) -> &[String];
+}
+
+impl ErrorEquals for Retrier {
+    fn error_equals(&self) -> &[String] {
+        return &self.error_equals;
+    }
+}
+
+impl ErrorEquals for Catcher {
+    fn error_equals(&self) -> &[String] {
+        return &self.error_equals;
+    }
+}
+
+fn throw(error: &str, branch: &Branch) -> MockedResult {
+    return MockedResult::Throw(MockedError {
+        error: error.to_string(),
+        cause: format!("Generated to cover {}", branch),
+    });
+}
+
+/// Greedily picks the plans that add the most uncovered branches, shorter paths first on ties.
+fn select(mut verified: Vec<(Plan, Execution)>) -> Vec<(Plan, Execution)> {
+    let mut covered = BTreeSet::new();
+    let mut selected = Vec::new();
+    loop {
+        let gain = |plan: &Plan| {
+            plan.branches
+                .iter()
+                .filter(|branch| !covered.contains(*branch))
+                .collect::<BTreeSet<_>>()
+                .len()
+        };
+        let best = verified
+            .iter()
+            .enumerate()
+            .filter(|(_, (plan, _))| gain(plan) > 0)
+            .max_by_key(|(index, (plan, _))| {
+                (
+                    gain(plan),
+                    std::cmp::Reverse(plan.visited.len()),
+                    std::cmp::Reverse(*index),
+                )
+            })
+            .map(|(index, _)| index);
+        let index = match best {
+            Some(index) => index,
+            None => break,
+        };
+        let (plan, execution) = verified.remove(index);
+        covered.extend(plan.branches.iter().cloned());
+        selected.push((plan, execution));
+    }
+    if selected.is_empty() && !verified.is_empty() {
+        selected.push(verified.remove(0));
+    }
+
+    return selected;
+}
+
+fn mock_config<'a>(
+    state_machine: &str,
+    plans: impl Iterator<Item = (&'a Plan, &'a String)>,
+) -> MockConfig {
+    let mut config = MockConfig::default();
+    let mut test_cases = BTreeMap::new();
+    for (plan, case) in plans {
+        let mut states = BTreeMap::new();
+        for (state, results) in &plan.results {
+            let response = format!("{}{}", case, identifier(state));
+            states.insert(state.clone(), response.clone());
+            config
+                .mocked_responses
+                .insert(response, mocked_response(results));
+        }
+        let unmocked: Vec<&String> = plan
+            .nested
+            .iter()
+            .filter(|state| !states.contains_key(*state))
+            .collect();
+        for state in unmocked {
+            let response = format!("{}{}", case, identifier(state));
+            states.insert(state.clone(), response.clone());
+            config.mocked_responses.insert(
+                response,
+                [(
+                    format!("0-{}", NESTED_INVOCATIONS - 1),
+                    MockedResult::Return(json!({})),
+                )]
+                .into_iter()
+                .collect(),
+            );
+        }
+        test_cases.insert(case.clone(), states);
+    }
+    config
+        .state_machines
+        .insert(state_machine.to_string(), MockedStateMachine { test_cases });
+
+    return config;
+}
+
+/// Invocation results keyed by index, with runs of the same result merged into ranges.
+fn mocked_response(results: &[MockedResult]) -> MockedResponse {
+    let mut response = MockedResponse::new();
+    let mut start = 0;
+    for end in 0..results.len() {
+        if end + 1 < results.len() && results[end + 1] == results[start] {
+            continue;
+        }
+        let key = if start == end {
+            start.to_string()
+        } else {
+            format!("{}-{}", start, end)
+        };
+        response.insert(key, results[start].clone());
+        start = end + 1;
+    }
+
+    return response;
+}
+
+fn expectation(plan: &Plan, execution: &Execution) -> Expectation {
+    let input = match &plan.input {
+        Value::Object(object) if object.is_empty() => None,
+        Value::Null => None,
+        input => Some(input.clone()),
+    };
+    let succeeded = execution.status == ExecutionStatus::Succeeded;
+
+    return Expectation {
+        input,
+        status: execution.status,
+        output: if succeeded {
+            execution.output.clone()
+        } else {
+            None
+        },
+        error: execution.error.clone(),
+        cause: execution.cause.clone(),
+        visited_states: Some(execution.visited_states()),
+    };
+}
+
+/// A state name reduced to the characters test case and mocked response names keep.
+fn identifier(name: &str) -> String {
+    return name.chars().filter(char::is_ascii_alphanumeric).collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cases::TestCases;
+    use crate::test_fixtures::simple_value;
+
+    fn simple_definition() -> Definition {
+        let mut definition = simple_value();
+        definition["States"]["GetHtml"]["Retry"] =
+            json!([{ "ErrorEquals": ["Lambda.TooManyRequestsException"], "MaxAttempts": 2 }]);
+        return Definition::from_value(definition).unwrap();
+    }
+
+    #[tokio::test]
+    async fn covers_every_branch_with_boundary_values() {
+        let coverage = generate(&simple_definition(), "SimpleExample").await;
+
+        assert!(coverage.uncovered().is_empty(), "{}", coverage);
+        let names: Vec<&str> = coverage.cases.iter().map(|case| case.name.as_str()).collect();
+        assert_eq!(names, vec!["GetHtmlRetry0_IsBig", "GetHtmlCatch0", "IsNotBig"]);
+        let responses = &coverage.mock_config.mocked_responses;
+        assert_eq!(
+            responses["GetHtmlRetry0_IsBigGetHtml"]["1"],
+            MockedResult::Return(json!({ "size": 10241 }))
+        );
+        assert_eq!(
+            responses["IsNotBigGetHtml"]["0"],
+            MockedResult::Return(json!({ "size": 10240 }))
+        );
+        match &responses["GetHtmlCatch0GetHtml"]["0"] {
+            MockedResult::Throw(error) => assert_eq!(error.error, STATES_TASK_FAILED),
+            result => panic!("expected a Throw, got {:?}", result),
+        }
+
+        let cases = TestCases::new(
+            "SimpleExample",
+            Some(&coverage.mock_config),
+            Some(&coverage.expectations),
+        );
+        let executor = Executor::new()
+            .skip_waits(true)
+            .with_mock_config(coverage.mock_config.clone());
+        cases
+            .run_local(&executor, &simple_definition())
+            .await
+            .assert_passed();
+    }
+
+    #[test]
+    fn synthesizes_values_for_combined_rules() {
+        let rule = json!({ "And": [
+            { "Variable": "$.size", "NumericGreaterThanEquals": 10 },
+            { "Variable": "$.size", "NumericLessThan": 20 },
+            { "Not": { "Variable": "$.type", "StringMatches": "text/*" } },
+            { "Variable": "$.retried", "IsPresent": false }
+        ] });
+
+        let constraints = satisfy_all(&[(&rule, true)]).unwrap();
+        assert_eq!(document(&constraints), json!({ "size": 10, "type": "" }));
+        assert!(satisfy_all(&[(&rule, false)]).is_some());
+    }
+}
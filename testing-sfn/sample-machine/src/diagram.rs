@@ -0,0 +1,570 @@
+//! Mermaid and Graphviz (DOT) diagrams of state machine definitions.
+//!
+//! Choice rules label their edges, `Catch` clauses are dashed edges labelled with the errors they
+//! catch, and `Parallel` branches and `Map` processors are nested subgraphs. Given an execution,
+//! the visited states and the transitions taken are highlighted and the failing states marked.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use serde_json::Value;
+
+use crate::asl::{choice, Definition, State, StateKind};
+use crate::history::Execution;
+use crate::trace::{ExecutionTrace, StepOutcome};
+
+const START: &str = "start";
+const END: &str = "finish";
+
+const VISITED_FILL: &str = "#d4edda";
+const VISITED_STROKE: &str = "#28a745";
+const FAILED_FILL: &str = "#f8d7da";
+const FAILED_STROKE: &str = "#dc3545";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    Next,
+    Choice,
+    Default,
+    Catch,
+    /// From a `Parallel` or `Map` state to the start of one of its branches.
+    Branch,
+    Start,
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub id: String,
+    pub name: String,
+    pub kind: Option<StateKind>,
+    pub visited: bool,
+    pub failed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub label: Option<String>,
+    pub kind: EdgeKind,
+    pub taken: bool,
+}
+
+/// The states of a definition or of one `Parallel` branch or `Map` processor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    pub id: String,
+    pub label: String,
+    pub nodes: Vec<Node>,
+    pub clusters: Vec<Cluster>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagram {
+    pub root: Cluster,
+    pub edges: Vec<Edge>,
+}
+
+impl Diagram {
+    pub fn new(definition: &Definition) -> Self {
+        let mut builder = Builder::default();
+        let root = builder.cluster(definition, "root", "State machine");
+        if let Some(start) = find(&root, definition.start_at()) {
+            builder.edges.insert(
+                0,
+                Edge {
+                    from: START.to_string(),
+                    to: start.id.clone(),
+                    label: None,
+                    kind: EdgeKind::Start,
+                    taken: false,
+                },
+            );
+        }
+        for state in definition.states() {
+            let ends = state.is_end() || state.kind() == Some(StateKind::Succeed);
+            if let (true, Some(node)) = (ends, find(&root, state.name)) {
+                builder.edges.push(Edge {
+                    from: node.id.clone(),
+                    to: END.to_string(),
+                    label: None,
+                    kind: EdgeKind::End,
+                    taken: false,
+                });
+            }
+        }
+
+        return Self {
+            root,
+            edges: builder.edges,
+        };
+    }
+
+    /// Highlights the states an execution visited and the transitions it took, and marks the
+    /// states that failed it.
+    pub fn with_execution(mut self, execution: &Execution) -> Self {
+        let trace = ExecutionTrace::from_execution(execution);
+        let visited: HashSet<&str> = trace.steps.iter().map(|step| step.name.as_str()).collect();
+        let failed: HashSet<&str> = trace
+            .failed_steps()
+            .iter()
+            .map(|step| step.name.as_str())
+            .collect();
+        let succeeded: HashSet<&str> = trace
+            .steps
+            .iter()
+            .filter(|step| step.outcome == StepOutcome::Succeeded)
+            .map(|step| step.name.as_str())
+            .collect();
+
+        // A transition is the next state entered after a state exited.
+        let mut transitions: HashSet<(String, String)> = HashSet::new();
+        let mut exited: Option<String> = None;
+        for event in &execution.events {
+            let name = match &event.details.name {
+                Some(name) => name,
+                None => continue,
+            };
+            if event.event_type.ends_with("StateExited") {
+                exited = Some(name.clone());
+            } else if event.event_type.ends_with("StateEntered") {
+                if let Some(from) = exited.take() {
+                    transitions.insert((from, name.clone()));
+                }
+            }
+        }
+
+        let mut names = Vec::new();
+        mark(&mut self.root, &visited, &failed, &mut names);
+        let name_of = |id: &str| {
+            return names
+                .iter()
+                .find(|(node_id, _)| node_id == id)
+                .map(|(_, name)| name.as_str());
+        };
+        for edge in &mut self.edges {
+            let from = name_of(&edge.from);
+            let to = name_of(&edge.to);
+            edge.taken = match edge.kind {
+                EdgeKind::Start => to.is_some_and(|to| visited.contains(to)),
+                EdgeKind::End => from.is_some_and(|from| succeeded.contains(from)),
+                EdgeKind::Branch => {
+                    from.is_some_and(|from| visited.contains(from))
+                        && to.is_some_and(|to| visited.contains(to))
+                }
+                _ => match (from, to) {
+                    (Some(from), Some(to)) => {
+                        transitions.contains(&(from.to_string(), to.to_string()))
+                    }
+                    _ => false,
+                },
+            };
+        }
+
+        return self;
+    }
+
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::from("flowchart TD\n");
+        writeln!(out, "    {}((\"Start\"))", START).unwrap();
+        writeln!(out, "    {}((\"End\"))", END).unwrap();
+        mermaid_cluster(&mut out, &self.root, 1);
+
+        for edge in &self.edges {
+            let arrow = match edge.kind {
+                EdgeKind::Catch | EdgeKind::Branch => "-.->",
+                _ => "-->",
+            };
+            match &edge.label {
+                Some(label) => writeln!(
+                    out,
+                    "    {} {}|\"{}\"| {}",
+                    edge.from,
+                    arrow,
+                    mermaid_escape(label),
+                    edge.to
+                ),
+                None => writeln!(out, "    {} {} {}", edge.from, arrow, edge.to),
+            }
+            .unwrap();
+        }
+
+        let (visited, failed) = highlighted(&self.root);
+        if !visited.is_empty() || !failed.is_empty() {
+            writeln!(
+                out,
+                "    classDef visited fill:{},stroke:{}",
+                VISITED_FILL, VISITED_STROKE
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "    classDef failed fill:{},stroke:{}",
+                FAILED_FILL, FAILED_STROKE
+            )
+            .unwrap();
+        }
+        if !visited.is_empty() {
+            writeln!(out, "    class {} visited", visited.join(",")).unwrap();
+        }
+        if !failed.is_empty() {
+            writeln!(out, "    class {} failed", failed.join(",")).unwrap();
+        }
+        let taken: Vec<String> = self
+            .edges
+            .iter()
+            .enumerate()
+            .filter(|(_, edge)| edge.taken)
+            .map(|(index, _)| index.to_string())
+            .collect();
+        if !taken.is_empty() {
+            writeln!(
+                out,
+                "    linkStyle {} stroke:{},stroke-width:3px",
+                taken.join(","),
+                VISITED_STROKE
+            )
+            .unwrap();
+        }
+
+        return out;
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph StateMachine {\n");
+        out.push_str("    node [shape=box, style=rounded];\n");
+        writeln!(out, "    {} [shape=circle, label=\"Start\"];", START).unwrap();
+        writeln!(out, "    {} [shape=doublecircle, label=\"End\"];", END).unwrap();
+        dot_cluster(&mut out, &self.root, 1);
+
+        for edge in &self.edges {
+            let mut attributes = Vec::new();
+            if let Some(label) = &edge.label {
+                attributes.push(format!("label=\"{}\"", dot_escape(label)));
+            }
+            if matches!(edge.kind, EdgeKind::Catch | EdgeKind::Branch) {
+                attributes.push("style=dashed".to_string());
+            }
+            if edge.taken {
+                attributes.push(format!("color=\"{}\", penwidth=3", VISITED_STROKE));
+            }
+            let attributes = if attributes.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", attributes.join(", "))
+            };
+            writeln!(out, "    {} -> {}{};", edge.from, edge.to, attributes).unwrap();
+        }
+        out.push_str("}\n");
+
+        return out;
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    next_id: usize,
+    edges: Vec<Edge>,
+}
+
+impl Builder {
+    fn id(&mut self) -> String {
+        self.next_id += 1;
+        return format!("s{}", self.next_id);
+    }
+
+    fn cluster(&mut self, definition: &Definition, id: &str, label: &str) -> Cluster {
+        let mut cluster = Cluster {
+            id: id.to_string(),
+            label: label.to_string(),
+            nodes: Vec::new(),
+            clusters: Vec::new(),
+        };
+        for state in definition.states() {
+            cluster.nodes.push(Node {
+                id: self.id(),
+                name: state.name.to_string(),
+                kind: state.kind(),
+                visited: false,
+                failed: false,
+            });
+        }
+
+        for state in definition.states() {
+            let from = node_id(&cluster, state.name).unwrap_or_default();
+            for (to, label, kind) in transitions(&state) {
+                // Transitions to unknown states are left out; validation reports them.
+                if let Some(to) = node_id(&cluster, to) {
+                    self.edges.push(Edge {
+                        from: from.clone(),
+                        to,
+                        label,
+                        kind,
+                        taken: false,
+                    });
+                }
+            }
+
+            for (index, branch) in state.branches().iter().enumerate() {
+                let label = match state.kind() {
+                    Some(StateKind::Map) => format!("{} (Map)", state.name),
+                    _ => format!("{} branch {}", state.name, index + 1),
+                };
+                let id = format!("{}_{}", from, index + 1);
+                let nested = self.cluster(branch, &id, &label);
+                if let Some(start) = node_id(&nested, branch.start_at()) {
+                    self.edges.push(Edge {
+                        from: from.clone(),
+                        to: start,
+                        label: None,
+                        kind: EdgeKind::Branch,
+                        taken: false,
+                    });
+                }
+                cluster.clusters.push(nested);
+            }
+        }
+
+        return cluster;
+    }
+}
+
+/// The transitions of a state with their labels: the Choice conditions, `Default` and the
+/// errors of `Catch` clauses.
+fn transitions<'a>(state: &State<'a>) -> Vec<(&'a str, Option<String>, EdgeKind)> {
+    let mut transitions = Vec::new();
+    if let Some(next) = state.next() {
+        transitions.push((next, None, EdgeKind::Next));
+    }
+    if let Some(choices) = state.field("Choices").and_then(Value::as_array) {
+        for rule in choices {
+            if let Some(next) = rule["Next"].as_str() {
+                transitions.push((next, Some(choice::describe(rule)), EdgeKind::Choice));
+            }
+        }
+    }
+    if let Some(default) = state.str_field("Default") {
+        transitions.push((default, Some("Default".to_string()), EdgeKind::Default));
+    }
+    if let Some(catchers) = state.field("Catch").and_then(Value::as_array) {
+        for catcher in catchers {
+            if let Some(next) = catcher["Next"].as_str() {
+                let errors: Vec<&str> = catcher["ErrorEquals"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .collect();
+                let label = format!("Catch {}", errors.join(", "));
+                transitions.push((next, Some(label), EdgeKind::Catch));
+            }
+        }
+    }
+
+    return transitions;
+}
+
+/// The node of a state in `cluster` itself, not in its nested clusters.
+fn node_id(cluster: &Cluster, name: &str) -> Option<String> {
+    return cluster
+        .nodes
+        .iter()
+        .find(|node| node.name == name)
+        .map(|node| node.id.clone());
+}
+
+fn find<'a>(cluster: &'a Cluster, name: &str) -> Option<&'a Node> {
+    return cluster.nodes.iter().find(|node| node.name == name);
+}
+
+/// States of nested clusters are matched by name, so a name used in several branches is marked
+/// in all of them.
+fn mark(
+    cluster: &mut Cluster,
+    visited: &HashSet<&str>,
+    failed: &HashSet<&str>,
+    names: &mut Vec<(String, String)>,
+) {
+    for node in &mut cluster.nodes {
+        node.visited = visited.contains(node.name.as_str());
+        node.failed = failed.contains(node.name.as_str());
+        names.push((node.id.clone(), node.name.clone()));
+    }
+    for nested in &mut cluster.clusters {
+        mark(nested, visited, failed, names);
+    }
+}
+
+/// The ids of the visited (and not failed) nodes and of the failed nodes.
+fn highlighted(cluster: &Cluster) -> (Vec<String>, Vec<String>) {
+    let mut visited = Vec::new();
+    let mut failed = Vec::new();
+    for node in &cluster.nodes {
+        if node.failed {
+            failed.push(node.id.clone());
+        } else if node.visited {
+            visited.push(node.id.clone());
+        }
+    }
+    for nested in &cluster.clusters {
+        let (nested_visited, nested_failed) = highlighted(nested);
+        visited.extend(nested_visited);
+        failed.extend(nested_failed);
+    }
+
+    return (visited, failed);
+}
+
+fn mermaid_cluster(out: &mut String, cluster: &Cluster, depth: usize) {
+    let indent = "    ".repeat(depth);
+    for node in &cluster.nodes {
+        let label = mermaid_escape(&node.name);
+        let shape = match node.kind {
+            Some(StateKind::Choice) => format!("{{\"{}\"}}", label),
+            Some(StateKind::Succeed) | Some(StateKind::Fail) => format!("([\"{}\"])", label),
+            Some(StateKind::Pass) | Some(StateKind::Wait) => format!("(\"{}\")", label),
+            _ => format!("[\"{}\"]", label),
+        };
+        writeln!(out, "{}{}{}", indent, node.id, shape).unwrap();
+    }
+    for nested in &cluster.clusters {
+        writeln!(
+            out,
+            "{}subgraph {}[\"{}\"]",
+            indent,
+            nested.id,
+            mermaid_escape(&nested.label)
+        )
+        .unwrap();
+        mermaid_cluster(out, nested, depth + 1);
+        writeln!(out, "{}end", indent).unwrap();
+    }
+}
+
+fn dot_cluster(out: &mut String, cluster: &Cluster, depth: usize) {
+    let indent = "    ".repeat(depth);
+    for node in &cluster.nodes {
+        let mut attributes = vec![format!("label=\"{}\"", dot_escape(&node.name))];
+        let mut styles = vec!["rounded"];
+        match node.kind {
+            Some(StateKind::Choice) => attributes.push("shape=diamond".to_string()),
+            Some(StateKind::Succeed) | Some(StateKind::Fail) => styles.push("bold"),
+            _ => {}
+        }
+        let fill = if node.failed {
+            Some((FAILED_FILL, FAILED_STROKE))
+        } else if node.visited {
+            Some((VISITED_FILL, VISITED_STROKE))
+        } else {
+            None
+        };
+        if let Some((fill, stroke)) = fill {
+            styles.push("filled");
+            attributes.push(format!("fillcolor=\"{}\", color=\"{}\"", fill, stroke));
+        }
+        if styles.len() > 1 {
+            attributes.push(format!("style=\"{}\"", styles.join(",")));
+        }
+        writeln!(out, "{}{} [{}];", indent, node.id, attributes.join(", ")).unwrap();
+    }
+    for nested in &cluster.clusters {
+        writeln!(out, "{}subgraph cluster_{} {{", indent, nested.id).unwrap();
+        writeln!(out, "{}    label=\"{}\";", indent, dot_escape(&nested.label)).unwrap();
+        dot_cluster(out, nested, depth + 1);
+        writeln!(out, "{}}}", indent).unwrap();
+    }
+}
+
+fn mermaid_escape(text: &str) -> String {
+    return text.replace('"', "#quot;");
+}
+
+fn dot_escape(text: &str) -> String {
+    return text.replace('\\', "\\\\").replace('"', "\\\"");
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::executor::{ExecutionRequest, Executor};
+    use crate::mock::MockConfig;
+    use crate::test_fixtures::simple_definition;
+
+    #[test]
+    fn renders_choice_conditions_catches_and_branches() {
+        let diagram = Diagram::new(&simple_definition());
+        let mermaid = diagram.to_mermaid();
+        let dot = diagram.to_dot();
+
+        assert!(mermaid.starts_with("flowchart TD\n"));
+        assert!(mermaid.contains("|\"$.size > 10240\"|"));
+        assert!(mermaid.contains("-.->|\"Catch States.ALL\"|"));
+        assert!(mermaid.contains("{\"IsHtmlBig?\"}"));
+        assert!(dot.contains("label=\"$.size > 10240\""));
+        assert!(dot.contains("label=\"Catch States.ALL\", style=dashed"));
+
+        let order = Definition::from_json(include_str!(
+            "../../complex-machine/state-machine-definition.asl.json"
+        ))
+        .unwrap();
+        let nested = Diagram::new(&Definition::from_value(json!({
+            "StartAt": "Both",
+            "States": {
+                "Both": {
+                    "Type": "Parallel",
+                    "Branches": [order.into_value(), simple_definition().into_value()],
+                    "End": true
+                }
+            }
+        }))
+        .unwrap());
+        assert_eq!(nested.root.clusters.len(), 2);
+        assert!(nested.to_mermaid().contains("subgraph s1_2[\"Both branch 2\"]"));
+        assert!(nested.to_dot().contains("subgraph cluster_s1_1 {"));
+    }
+
+    #[tokio::test]
+    async fn highlights_the_executed_path_and_the_failing_state() {
+        let config: MockConfig = serde_json::from_str(include_str!("sfn-local-mock.json")).unwrap();
+        let executor = Executor::new().with_mock_config(config);
+        let execution = executor
+            .start(
+                ExecutionRequest::new(simple_definition(), json!({}))
+                    .state_machine_arn(crate::executor::state_machine_arn(
+                        crate::executor::DEFAULT_REGION,
+                        crate::executor::DEFAULT_ACCOUNT_ID,
+                        "SimpleExample",
+                    ))
+                    .test_case("GetHtmlError"),
+            )
+            .wait()
+            .await;
+        let diagram = Diagram::new(&simple_definition()).with_execution(&execution);
+
+        let node = |name: &str| find(&diagram.root, name).unwrap();
+        assert!(node("GetHtml").visited && !node("GetHtml").failed);
+        assert!(node("Dunno").failed);
+        assert!(!node("IsHtmlBig?").visited);
+        let taken: Vec<(&str, EdgeKind)> = diagram
+            .edges
+            .iter()
+            .filter(|edge| edge.taken)
+            .map(|edge| (edge.to.as_str(), edge.kind))
+            .collect();
+        assert_eq!(
+            taken,
+            vec![
+                (node("GetHtml").id.as_str(), EdgeKind::Start),
+                (node("Dunno").id.as_str(), EdgeKind::Catch)
+            ]
+        );
+
+        let mermaid = diagram.to_mermaid();
+        assert!(mermaid.contains(&format!("class {} visited", node("GetHtml").id)));
+        assert!(mermaid.contains(&format!("class {} failed", node("Dunno").id)));
+        assert!(mermaid.contains("linkStyle 0,"));
+    }
+}
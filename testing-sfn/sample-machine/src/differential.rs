@@ -0,0 +1,277 @@
+//! Differential testing: the same test cases on two Step Functions backends.
+//!
+//! Local emulators are reimplementations, so a case that passes on one backend may behave
+//! differently on another. The cases of [`TestCases`] run on both backends, typically the native
+//! [`Executor`] and step-functions-local (or any SFN-compatible endpoint), and every difference
+//! in status, output or visited states is reported as a [`Divergence`].
+
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::asl::Definition;
+use crate::cases::{CaseResult, CasesReport, TestCases};
+use crate::executor::Executor;
+use crate::fixture::StateMachineFixture;
+use crate::history::Execution;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub case: String,
+    /// `status`, `output`, `visited states` or `execution` when a backend could not run the case.
+    pub field: String,
+    pub left: Value,
+    pub right: Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DifferentialReport {
+    pub state_machine: String,
+    /// The names of the two backends, e.g. `native` and `step-functions-local`.
+    pub left: String,
+    pub right: String,
+    pub cases: Vec<String>,
+    pub divergences: Vec<Divergence>,
+}
+
+impl DifferentialReport {
+    /// Pairs the results of the same cases on two backends.
+    pub fn compare(
+        left_name: impl Into<String>,
+        left: &CasesReport,
+        right_name: impl Into<String>,
+        right: &CasesReport,
+    ) -> Self {
+        let mut cases = Vec::new();
+        let mut divergences = Vec::new();
+        for left_result in &left.results {
+            cases.push(left_result.name.clone());
+            let right_result = right
+                .results
+                .iter()
+                .find(|result| result.name == left_result.name);
+            divergences.extend(diverging(left_result, right_result));
+        }
+        for right_result in &right.results {
+            if !cases.contains(&right_result.name) {
+                cases.push(right_result.name.clone());
+                divergences.push(Divergence {
+                    case: right_result.name.clone(),
+                    field: "execution".to_string(),
+                    left: Value::from("not run"),
+                    right: execution_summary(right_result),
+                });
+            }
+        }
+
+        return Self {
+            state_machine: left.state_machine.clone(),
+            left: left_name.into(),
+            right: right_name.into(),
+            cases,
+            divergences,
+        };
+    }
+
+    pub fn diverged(&self, case: &str) -> Vec<&Divergence> {
+        return self
+            .divergences
+            .iter()
+            .filter(|divergence| divergence.case == case)
+            .collect();
+    }
+
+    /// Panics with the report if the backends disagreed on any case.
+    #[track_caller]
+    pub fn assert_no_divergence(&self) {
+        if !self.divergences.is_empty() {
+            panic!(
+                "{} and {} diverged on {} of {} test cases of {}\n\n{}",
+                self.left,
+                self.right,
+                self.cases
+                    .iter()
+                    .filter(|case| !self.diverged(case).is_empty())
+                    .count(),
+                self.cases.len(),
+                self.state_machine,
+                self
+            );
+        }
+    }
+}
+
+impl fmt::Display for DifferentialReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "comparing {} test cases of {} on {} and {}",
+            self.cases.len(),
+            self.state_machine,
+            self.left,
+            self.right
+        )?;
+        let mut diverged = 0;
+        for case in &self.cases {
+            let divergences = self.diverged(case);
+            if divergences.is_empty() {
+                writeln!(f, "case {}::{} ... same", self.state_machine, case)?;
+                continue;
+            }
+            diverged += 1;
+            writeln!(f, "case {}::{} ... DIVERGED", self.state_machine, case)?;
+            for divergence in divergences {
+                writeln!(
+                    f,
+                    "    {}: {} {}, {} {}",
+                    divergence.field, self.left, divergence.left, self.right, divergence.right
+                )?;
+            }
+        }
+
+        return write!(
+            f,
+            "\n{} same; {} diverged",
+            self.cases.len() - diverged,
+            diverged
+        );
+    }
+}
+
+/// Runs the cases on the native executor and on the machine of a fixture, e.g. on
+/// step-functions-local, and compares them. The executor needs the same mock config as the
+/// fixture's endpoint.
+pub async fn native_against_fixture(
+    cases: &TestCases,
+    executor: &Executor,
+    definition: &Definition,
+    fixture: &StateMachineFixture,
+    fixture_backend: &str,
+) -> DifferentialReport {
+    let (native, remote) = futures::join!(
+        cases.run_local(executor, definition),
+        cases.run_fixture(fixture)
+    );
+
+    return DifferentialReport::compare("native", &native, fixture_backend, &remote);
+}
+
+fn diverging(left: &CaseResult, right: Option<&CaseResult>) -> Vec<Divergence> {
+    let divergence = |field: &str, left_value: Value, right_value: Value| Divergence {
+        case: left.name.clone(),
+        field: field.to_string(),
+        left: left_value,
+        right: right_value,
+    };
+
+    let (left_execution, right_execution) = match (
+        &left.execution,
+        right.and_then(|right| right.execution.as_ref()),
+    ) {
+        (Some(left_execution), Some(right_execution)) => (left_execution, right_execution),
+        _ => {
+            return vec![Divergence {
+                case: left.name.clone(),
+                field: "execution".to_string(),
+                left: execution_summary(left),
+                right: right.map_or(Value::from("not run"), execution_summary),
+            }];
+        }
+    };
+
+    let mut divergences = Vec::new();
+    if left_execution.status != right_execution.status {
+        divergences.push(divergence(
+            "status",
+            Value::from(left_execution.status.as_str()),
+            Value::from(right_execution.status.as_str()),
+        ));
+    }
+    if left_execution.output != right_execution.output {
+        divergences.push(divergence(
+            "output",
+            left_execution.output.clone().unwrap_or(Value::Null),
+            right_execution.output.clone().unwrap_or(Value::Null),
+        ));
+    }
+    let (left_visited, right_visited) = (visited(left_execution), visited(right_execution));
+    if left_visited != right_visited {
+        divergences.push(divergence("visited states", left_visited, right_visited));
+    }
+
+    return divergences;
+}
+
+fn visited(execution: &Execution) -> Value {
+    return Value::from(execution.visited_states());
+}
+
+/// The status of a case's execution, or why it did not run.
+fn execution_summary(result: &CaseResult) -> Value {
+    return match &result.execution {
+        Some(execution) => Value::from(execution.status.as_str()),
+        None => Value::from(result.mismatches.join("; ")),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::cases::Expectations;
+    use crate::mock::{MockConfig, MockedResult};
+    use crate::remote;
+    use crate::server::{self, StepFunctions};
+    use crate::test_fixtures::simple_definition;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reports_where_two_backends_diverge() {
+        let config: MockConfig = serde_json::from_str(include_str!("sfn-local-mock.json")).unwrap();
+        let expectations: Expectations =
+            serde_json::from_str(include_str!("sfn-local-expectations.json")).unwrap();
+        // A backend that gets the big page wrong.
+        let mut other_config = config.clone();
+        other_config.mocked_responses.insert(
+            "MockedIsHtmlBigTrue".to_string(),
+            [("0".to_string(), MockedResult::Return(json!({ "size": 1 })))]
+                .into_iter()
+                .collect(),
+        );
+        let step_functions = StepFunctions::new(Executor::new().with_mock_config(other_config));
+        let address = server::spawn("127.0.0.1:0".parse().unwrap(), step_functions).unwrap();
+        let fixture = StateMachineFixture::create(
+            &remote::local_client(&format!("http://{}", address)).unwrap(),
+            "SimpleExample",
+            &simple_definition(),
+            "role",
+        )
+        .await
+        .unwrap();
+        let cases = TestCases::new("SimpleExample", Some(&config), Some(&expectations));
+
+        let report = native_against_fixture(
+            &cases,
+            &Executor::new().with_mock_config(config.clone()),
+            &simple_definition(),
+            &fixture,
+            "other",
+        )
+        .await;
+
+        assert!(report.diverged("GetHtmlError").is_empty());
+        assert!(report.diverged("IsNotBigPath").is_empty());
+        let fields: Vec<&str> = report
+            .diverged("IsBigPath")
+            .iter()
+            .map(|divergence| divergence.field.as_str())
+            .collect();
+        assert_eq!(fields, vec!["output", "visited states"]);
+        let rendered = report.to_string();
+        assert!(rendered.contains("case SimpleExample::IsBigPath ... DIVERGED"));
+        assert!(rendered.contains("    output: native true, other false"));
+        assert!(std::panic::catch_unwind(|| report.assert_no_divergence()).is_err());
+
+        fixture.cleanup().await.unwrap();
+    }
+}
@@ -0,0 +1,367 @@
+//! Drift between a local definition (e.g. `state_machines/simple.yml`) and the deployed one.
+//!
+//! Both documents are normalized first: numbers compare by value, `"End": false` is dropped and
+//! `Retry` defaults are filled in. The changes are reported per state, recursing into `Parallel`
+//! branches and `Map` processors, and skip the JSON pointers of resolved CloudFormation tags.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+use crate::asl::Definition;
+use crate::serverless::ServerlessStateMachine;
+
+/// Fields holding nested definitions, compared state by state.
+const NESTED_DEFINITIONS: &[&str] = &["Branches", "ItemProcessor", "Iterator"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// Only in the deployed definition.
+    Added,
+    /// Only in the local definition.
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DriftChange {
+    /// The state, e.g. `IsHtmlBig?` or `Both/Branches[1]/GetHtml`. `None` for top-level fields.
+    pub state: Option<String>,
+    /// The field within the state (or definition), e.g. `Choices[0].NumericGreaterThan`. Empty
+    /// when the whole state was added or removed.
+    pub field: String,
+    pub kind: ChangeKind,
+    pub local: Option<Value>,
+    pub deployed: Option<Value>,
+}
+
+impl fmt::Display for DriftChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let subject = match (&self.state, self.field.is_empty()) {
+            (Some(state), true) => format!("state {}", state),
+            (Some(state), false) => format!("{}: {}", state, self.field),
+            (None, _) => self.field.clone(),
+        };
+        let show = |value: &Option<Value>| value.clone().unwrap_or(Value::Null);
+
+        return match self.kind {
+            ChangeKind::Added => {
+                write!(f, "{} added in deployed: {}", subject, show(&self.deployed))
+            }
+            ChangeKind::Removed => {
+                write!(f, "{} missing in deployed: {}", subject, show(&self.local))
+            }
+            ChangeKind::Changed => write!(
+                f,
+                "{} changed from {} (local) to {} (deployed)",
+                subject,
+                show(&self.local),
+                show(&self.deployed)
+            ),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Drift {
+    pub changes: Vec<DriftChange>,
+}
+
+impl Drift {
+    pub fn has_drift(&self) -> bool {
+        return !self.changes.is_empty();
+    }
+
+    /// Panics with the changes if the definitions drifted.
+    #[track_caller]
+    pub fn assert_none(&self) {
+        if self.has_drift() {
+            panic!("the deployed state machine drifted from the local definition\n{}", self);
+        }
+    }
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.changes.is_empty() {
+            return write!(f, "No drift");
+        }
+        for (index, change) in self.changes.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "  {}", change)?;
+        }
+
+        return Ok(());
+    }
+}
+
+/// Compares two definitions. `ignored` holds JSON pointers (`/States/GetHtml/Resource`) whose
+/// values are not compared.
+pub fn compare(local: &Definition, deployed: &Definition, ignored: &BTreeSet<String>) -> Drift {
+    let mut comparison = Comparison {
+        ignored,
+        changes: Vec::new(),
+    };
+    comparison.definition(
+        &normalize(local.as_value()),
+        &normalize(deployed.as_value()),
+        None,
+        "",
+    );
+
+    return Drift {
+        changes: comparison.changes,
+    };
+}
+
+/// Compares a Serverless Framework state machine with its deployed definition, skipping the
+/// values of CloudFormation tags.
+pub fn compare_serverless(local: &ServerlessStateMachine, deployed: &Definition) -> Drift {
+    let ignored = local.resolved_tags.keys().cloned().collect();
+
+    return compare(&local.definition, deployed, &ignored);
+}
+
+struct Comparison<'a> {
+    ignored: &'a BTreeSet<String>,
+    changes: Vec<DriftChange>,
+}
+
+impl Comparison<'_> {
+    /// Compares two definitions state by state. `prefix` names the enclosing state of nested
+    /// definitions and `pointer` is their JSON pointer.
+    fn definition(
+        &mut self,
+        local: &Value,
+        deployed: &Value,
+        prefix: Option<&str>,
+        pointer: &str,
+    ) {
+        let empty = Map::new();
+        let local_states = local["States"].as_object().unwrap_or(&empty);
+        let deployed_states = deployed["States"].as_object().unwrap_or(&empty);
+
+        for key in keys(local, deployed) {
+            if key != "States" {
+                let field_pointer = format!("{}/{}", pointer, escape(&key));
+                self.value(&local[&key], &deployed[&key], prefix, &key, &field_pointer);
+            }
+        }
+
+        let names: BTreeSet<&String> = local_states.keys().chain(deployed_states.keys()).collect();
+        for name in names {
+            let state = match prefix {
+                Some(prefix) => format!("{}/{}", prefix, name),
+                None => name.clone(),
+            };
+            let state_pointer = format!("{}/States/{}", pointer, escape(name));
+            match (local_states.get(name), deployed_states.get(name)) {
+                (Some(local), Some(deployed)) => {
+                    self.state(local, deployed, &state, &state_pointer)
+                }
+                (local, deployed) => self.changes.push(DriftChange {
+                    state: Some(state),
+                    field: String::new(),
+                    kind: if local.is_some() {
+                        ChangeKind::Removed
+                    } else {
+                        ChangeKind::Added
+                    },
+                    local: local.cloned(),
+                    deployed: deployed.cloned(),
+                }),
+            }
+        }
+    }
+
+    fn state(&mut self, local: &Value, deployed: &Value, state: &str, pointer: &str) {
+        for key in keys(local, deployed) {
+            let field_pointer = format!("{}/{}", pointer, escape(&key));
+            if !NESTED_DEFINITIONS.contains(&key.as_str()) {
+                self.value(&local[&key], &deployed[&key], Some(state), &key, &field_pointer);
+                continue;
+            }
+
+            match (&local[&key], &deployed[&key]) {
+                (Value::Array(local_branches), Value::Array(deployed_branches))
+                    if local_branches.len() == deployed_branches.len() =>
+                {
+                    for (index, (local, deployed)) in
+                        local_branches.iter().zip(deployed_branches).enumerate()
+                    {
+                        self.definition(
+                            local,
+                            deployed,
+                            Some(&format!("{}/{}[{}]", state, key, index)),
+                            &format!("{}/{}", field_pointer, index),
+                        );
+                    }
+                }
+                (Value::Object(_), Value::Object(_)) => self.definition(
+                    &local[&key],
+                    &deployed[&key],
+                    Some(&format!("{}/{}", state, key)),
+                    &field_pointer,
+                ),
+                (local, deployed) => {
+                    self.value(local, deployed, Some(state), &key, &field_pointer)
+                }
+            }
+        }
+    }
+
+    /// Compares a field, reporting whole values that were added or removed and the leaves that
+    /// changed. `Value::Null` stands for a missing field.
+    fn value(
+        &mut self,
+        local: &Value,
+        deployed: &Value,
+        state: Option<&str>,
+        field: &str,
+        pointer: &str,
+    ) {
+        if self.ignored.contains(pointer) || same(local, deployed) {
+            return;
+        }
+
+        match (local, deployed) {
+            (Value::Object(_), Value::Object(_)) => {
+                for key in keys(local, deployed) {
+                    self.value(
+                        &local[&key],
+                        &deployed[&key],
+                        state,
+                        &format!("{}.{}", field, key),
+                        &format!("{}/{}", pointer, escape(&key)),
+                    );
+                }
+            }
+            (Value::Array(local_values), Value::Array(deployed_values)) => {
+                for index in 0..local_values.len().max(deployed_values.len()) {
+                    self.value(
+                        local_values.get(index).unwrap_or(&Value::Null),
+                        deployed_values.get(index).unwrap_or(&Value::Null),
+                        state,
+                        &format!("{}[{}]", field, index),
+                        &format!("{}/{}", pointer, index),
+                    );
+                }
+            }
+            _ => {
+                let present = |value: &Value| (!value.is_null()).then(|| value.clone());
+                self.changes.push(DriftChange {
+                    state: state.map(str::to_string),
+                    field: field.to_string(),
+                    kind: match (local.is_null(), deployed.is_null()) {
+                        (true, _) => ChangeKind::Added,
+                        (_, true) => ChangeKind::Removed,
+                        _ => ChangeKind::Changed,
+                    },
+                    local: present(local),
+                    deployed: present(deployed),
+                });
+            }
+        }
+    }
+}
+
+/// The keys of two objects, sorted and without duplicates.
+fn keys(local: &Value, deployed: &Value) -> BTreeSet<String> {
+    return [local, deployed]
+        .into_iter()
+        .filter_map(Value::as_object)
+        .flat_map(|object| object.keys().cloned())
+        .collect();
+}
+
+/// Equality with numbers compared by value, so `10240` equals `10240.0`.
+fn same(local: &Value, deployed: &Value) -> bool {
+    return match (local, deployed) {
+        (Value::Number(local), Value::Number(deployed)) => local.as_f64() == deployed.as_f64(),
+        (Value::Array(local), Value::Array(deployed)) => {
+            local.len() == deployed.len()
+                && local.iter().zip(deployed).all(|(local, deployed)| same(local, deployed))
+        }
+        (Value::Object(local), Value::Object(deployed)) => {
+            local.len() == deployed.len()
+                && local.iter().all(|(key, local)| {
+                    deployed
+                        .get(key)
+                        .is_some_and(|deployed| same(local, deployed))
+                })
+        }
+        _ => local == deployed,
+    };
+}
+
+fn normalize(value: &Value) -> Value {
+    return match value {
+        Value::Object(object) => {
+            let mut normalized = Map::new();
+            for (key, value) in object {
+                if key == "End" && value == &Value::Bool(false) {
+                    continue;
+                }
+                normalized.insert(key.clone(), normalize(value));
+            }
+            if let Some(Value::Array(retriers)) = normalized.get_mut("Retry") {
+                for retrier in retriers.iter_mut().filter_map(Value::as_object_mut) {
+                    retrier
+                        .entry("IntervalSeconds")
+                        .or_insert_with(|| Value::from(1));
+                    retrier.entry("MaxAttempts").or_insert_with(|| Value::from(3));
+                    retrier.entry("BackoffRate").or_insert_with(|| Value::from(2.0));
+                }
+            }
+            Value::Object(normalized)
+        }
+        Value::Array(values) => Value::Array(values.iter().map(normalize).collect()),
+        value => value.clone(),
+    };
+}
+
+/// Escapes a JSON pointer segment.
+fn escape(segment: &str) -> String {
+    return segment.replace('~', "~0").replace('/', "~1");
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn reports_semantic_changes_per_state() {
+        let local =
+            ServerlessStateMachine::from_yaml(include_str!("../state_machines/simple.yml")).unwrap();
+        let mut deployed = local.definition.clone().into_value();
+        deployed["States"]["GetHtml"]["Resource"] =
+            json!("arn:aws:lambda:eu-west-1:111122223333:function:tsa-chp04ln04-dev-get-html");
+        deployed["States"]["GetHtml"]["Retry"] = json!([{ "ErrorEquals": ["States.ALL"] }]);
+        deployed["States"]["IsHtmlBig?"]["Choices"][0]["NumericGreaterThan"] = json!(20480.0);
+        deployed["States"]["IsBig"]["End"] = json!(false);
+        deployed["States"]["IsBig"]["Next"] = json!("Done");
+        deployed["States"]["Done"] = json!({ "Type": "Succeed" });
+        let deployed = Definition::from_value(deployed).unwrap();
+
+        let drift = compare_serverless(&local, &deployed);
+        let changes: Vec<String> = drift.changes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            changes,
+            vec![
+                "state Done added in deployed: {\"Type\":\"Succeed\"}",
+                "GetHtml: Retry added in deployed: [{\"BackoffRate\":2.0,\"ErrorEquals\":[\"States.ALL\"],\"IntervalSeconds\":1,\"MaxAttempts\":3}]",
+                "IsBig: End missing in deployed: true",
+                "IsBig: Next added in deployed: \"Done\"",
+                "IsHtmlBig?: Choices[0].NumericGreaterThan changed from 10240 (local) to 20480.0 (deployed)",
+            ]
+        );
+        assert!(std::panic::catch_unwind(|| drift.assert_none()).is_err());
+
+        assert!(!compare_serverless(&local, &local.definition).has_drift());
+    }
+}
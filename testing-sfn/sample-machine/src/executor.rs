@@ -0,0 +1,1949 @@
+//! A native Amazon States Language executor.
+//!
+//! Task states are resolved, in order, from the mocked responses of the execution's test case
+//! (the step-functions-local mock config) and from the [`TaskHandler`]s registered for their
+//! `Resource`. Activity tasks wait for a worker instead (see [`crate::activity`]), and
+//! `states:startExecution` tasks run registered state machines (see [`crate::nested`]). Every
+//! execution records a history in the `GetExecutionHistory` event format.
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::future::{try_join_all, BoxFuture};
+use futures::{StreamExt, TryStreamExt};
+use serde_json::{json, Map, Value};
+use tokio::sync::{mpsc, watch};
+
+use crate::activity::{Activities, ActivityTask};
+use crate::asl::path::Path;
+use crate::asl::processing::{self, merge_result};
+use crate::asl::query::{self, Bindings};
+use crate::asl::{
+    choice, error_matches, to_document, Definition, QueryLanguage, State, StateKind, StatesError,
+    STATES_HEARTBEAT_TIMEOUT, STATES_QUERY_EVALUATION_ERROR, STATES_TASK_FAILED, STATES_TIMEOUT,
+};
+use crate::history::{now, to_rfc3339, EventDetails, Execution, ExecutionStatus, HistoryEvent};
+use crate::live::{ExecutionObserver, LiveEvent};
+use crate::mock::{MockConfig, MockedTestCase};
+use crate::nested::{self, NestedExecutions, StartRequest, StopOnDrop, START_EXECUTION};
+use crate::redrive::{self, RedrivePoint};
+
+pub const DEFAULT_REGION: &str = "us-east-1";
+pub const DEFAULT_ACCOUNT_ID: &str = "123456789012";
+/// Step Functions fails an execution whose history would grow past this many events, which also
+/// ends executions that loop forever.
+pub const MAX_HISTORY_EVENTS: usize = 25_000;
+
+/// Runs the work of a Task state `Resource`.
+#[async_trait]
+pub trait TaskHandler: Send + Sync {
+    async fn invoke(&self, request: TaskRequest) -> Result<Value, StatesError>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskRequest {
+    pub state_name: String,
+    pub resource: String,
+    /// The state input after `InputPath` and `Parameters`.
+    pub input: Value,
+    /// Set for `.waitForTaskToken` integrations. The handler result is ignored and the task
+    /// completes once the token is reported through `SendTask*`.
+    pub task_token: Option<String>,
+}
+
+pub struct HandlerFn<F> {
+    f: F,
+}
+
+/// Wraps an async closure into a [`TaskHandler`], in the spirit of `lambda_runtime::service_fn`.
+pub fn handler_fn<F, Fut>(f: F) -> HandlerFn<F>
+where
+    F: Fn(TaskRequest) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Value, StatesError>> + Send,
+{
+    return HandlerFn { f };
+}
+
+#[async_trait]
+impl<F, Fut> TaskHandler for HandlerFn<F>
+where
+    F: Fn(TaskRequest) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<Value, StatesError>> + Send,
+{
+    async fn invoke(&self, request: TaskRequest) -> Result<Value, StatesError> {
+        return (self.f)(request).await;
+    }
+}
+
+/// Why a `SendTaskSuccess`/`SendTaskFailure`/`SendTaskHeartbeat` call was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    InvalidToken,
+    TaskTimedOut,
+}
+
+impl TokenError {
+    pub fn code(&self) -> &'static str {
+        return match self {
+            TokenError::InvalidToken => "InvalidToken",
+            TokenError::TaskTimedOut => "TaskTimedOut",
+        };
+    }
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            TokenError::InvalidToken => write!(f, "Invalid task token"),
+            TokenError::TaskTimedOut => write!(f, "Task Timed Out"),
+        };
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+enum TokenSignal {
+    Success(Value),
+    Failure(StatesError),
+    Heartbeat,
+}
+
+#[derive(Default)]
+struct TokenTable {
+    pending: HashMap<String, mpsc::UnboundedSender<TokenSignal>>,
+    closed: HashSet<String>,
+}
+
+#[derive(Clone, Default)]
+struct TaskTokens {
+    table: Arc<Mutex<TokenTable>>,
+}
+
+impl TaskTokens {
+    fn register(&self, token: &str) -> mpsc::UnboundedReceiver<TokenSignal> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.table
+            .lock()
+            .unwrap()
+            .pending
+            .insert(token.to_string(), sender);
+
+        return receiver;
+    }
+
+    fn close(&self, token: &str) {
+        let mut table = self.table.lock().unwrap();
+        table.pending.remove(token);
+        table.closed.insert(token.to_string());
+    }
+
+    fn signal(&self, token: &str, signal: TokenSignal) -> Result<(), TokenError> {
+        let mut table = self.table.lock().unwrap();
+        let sent = match table.pending.get(token) {
+            Some(sender) => sender.send(signal).is_ok(),
+            None if table.closed.contains(token) => return Err(TokenError::TaskTimedOut),
+            None => return Err(TokenError::InvalidToken),
+        };
+        if !sent {
+            // The execution waiting for the token is gone, e.g. it was stopped.
+            table.pending.remove(token);
+            table.closed.insert(token.to_string());
+            return Err(TokenError::TaskTimedOut);
+        }
+
+        return Ok(());
+    }
+}
+
+/// Runs state machine definitions locally.
+///
+/// Cloning an executor is cheap; clones share registered handlers and outstanding task tokens.
+#[derive(Clone, Default)]
+pub struct Executor {
+    handlers: HashMap<String, Arc<dyn TaskHandler>>,
+    mock_config: Option<Arc<MockConfig>>,
+    tokens: TaskTokens,
+    activities: Activities,
+    nested: NestedExecutions,
+    observers: Vec<Arc<dyn ExecutionObserver>>,
+    skip_waits: bool,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Registers the handler for a Task `Resource`. A resource ending in `*` matches every
+    /// resource with that prefix.
+    pub fn with_handler(
+        mut self,
+        resource: impl Into<String>,
+        handler: impl TaskHandler + 'static,
+    ) -> Self {
+        self.handlers.insert(resource.into(), Arc::new(handler));
+        return self;
+    }
+
+    /// Adds an observer of the live events of every execution (see [`crate::live`]).
+    pub fn with_observer(mut self, observer: impl ExecutionObserver + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        return self;
+    }
+
+    pub fn with_mock_config(mut self, mock_config: MockConfig) -> Self {
+        self.mock_config = Some(Arc::new(mock_config));
+        return self;
+    }
+
+    /// Registers a state machine that `states:startExecution` tasks can start.
+    pub fn with_state_machine(self, state_machine_arn: &str, definition: Definition) -> Self {
+        self.register_state_machine(state_machine_arn, definition);
+        return self;
+    }
+
+    /// Like [`Self::with_state_machine`], for every clone of this executor.
+    pub fn register_state_machine(&self, state_machine_arn: &str, definition: Definition) {
+        self.nested.register(state_machine_arn, definition);
+    }
+
+    pub fn deregister_state_machine(&self, state_machine_arn: &str) {
+        self.nested.deregister(state_machine_arn);
+    }
+
+    /// An execution started by a `states:startExecution` task.
+    pub fn nested_execution(&self, execution_arn: &str) -> Option<ExecutionHandle> {
+        return self.nested.execution(execution_arn);
+    }
+
+    /// Fast-forwards `Wait` states and retry intervals instead of sleeping through them.
+    pub fn skip_waits(mut self, skip_waits: bool) -> Self {
+        self.skip_waits = skip_waits;
+        return self;
+    }
+
+    pub fn mock_config(&self) -> Option<&MockConfig> {
+        return self.mock_config.as_deref();
+    }
+
+    /// Starts an execution in the background.
+    pub fn start(&self, request: ExecutionRequest) -> ExecutionHandle {
+        let record = Arc::new(Mutex::new(Execution {
+            execution_arn: execution_arn(&request.state_machine_arn, &request.name),
+            state_machine_arn: request.state_machine_arn.clone(),
+            name: request.name.clone(),
+            status: ExecutionStatus::Running,
+            start_date: now(),
+            stop_date: None,
+            input: request.input.clone(),
+            output: None,
+            error: None,
+            cause: None,
+            events: Vec::new(),
+            redrive_count: 0,
+            redrive_date: None,
+        }));
+        let run = Run::new(self.clone(), &request, record.clone());
+        run.record(
+            "ExecutionStarted",
+            EventDetails {
+                input: Some(to_document(&request.input)),
+                role_arn: request.role_arn.clone(),
+                ..Default::default()
+            },
+        );
+        let start = RedrivePoint {
+            state: request.definition.start_at().to_string(),
+            input: request.input.clone(),
+            variables: Map::new(),
+        };
+
+        return self.spawn(run, Arc::new(request), record, start);
+    }
+
+    /// Restarts a failed, aborted or timed-out execution from its unsuccessful state, like
+    /// `RedriveExecution` (see [`crate::redrive`]). The execution keeps its ARN and history. It
+    /// runs on this executor, so it may have fixed handlers or mocks.
+    pub fn redrive(&self, handle: &ExecutionHandle) -> Result<ExecutionHandle, StatesError> {
+        let point = redrive::redrive_point(&handle.snapshot())?;
+        let redrive_count = {
+            let mut record = handle.record.lock().unwrap();
+            record.status = ExecutionStatus::Running;
+            record.stop_date = None;
+            record.output = None;
+            record.error = None;
+            record.cause = None;
+            record.redrive_count += 1;
+            record.redrive_date = Some(now());
+            record.redrive_count
+        };
+        let mut run = Run::new(self.clone(), &handle.request, handle.record.clone());
+        // The mocked tasks continue their response sequences instead of starting over.
+        run.mocks = handle.mocks.clone();
+        run.record(
+            "ExecutionRedriven",
+            EventDetails {
+                redrive_count: Some(redrive_count),
+                ..Default::default()
+            },
+        );
+
+        return Ok(self.spawn(run, handle.request.clone(), handle.record.clone(), point));
+    }
+
+    /// Runs an execution from `point` in the background.
+    fn spawn(
+        &self,
+        run: Run,
+        request: Arc<ExecutionRequest>,
+        record: Arc<Mutex<Execution>>,
+        point: RedrivePoint,
+    ) -> ExecutionHandle {
+        let (stop_sender, mut stop_receiver) = watch::channel(None);
+        let (finished_sender, finished_receiver) = watch::channel(false);
+        let definition = request.definition.clone();
+        let mocks = run.mocks.clone();
+
+        tokio::spawn(async move {
+            let timeout = definition.timeout_seconds();
+            let work = async {
+                if let Some(error) = run.setup_error.clone() {
+                    return Err(error);
+                }
+                return run
+                    .run_from(&definition, point.state, point.input, point.variables)
+                    .await;
+            };
+            let work = async {
+                return match timeout {
+                    Some(seconds) => tokio::time::timeout(Duration::from_secs(seconds), work)
+                        .await
+                        .map_err(|_| Outcome::TimedOut)?
+                        .map_err(Outcome::Failed),
+                    None => work.await.map_err(Outcome::Failed),
+                };
+            };
+            let aborted = async {
+                loop {
+                    if let Some(error) = stop_receiver.borrow().clone() {
+                        return error;
+                    }
+                    if stop_receiver.changed().await.is_err() {
+                        futures::future::pending::<()>().await;
+                    }
+                }
+            };
+
+            let outcome = tokio::select! {
+                result = work => match result {
+                    Ok(output) => Outcome::Succeeded(output),
+                    Err(outcome) => outcome,
+                },
+                error = aborted => Outcome::Aborted(error),
+            };
+            run.finish(outcome);
+            let _ = finished_sender.send(true);
+        });
+
+        return ExecutionHandle {
+            request,
+            record,
+            mocks,
+            stop: Arc::new(stop_sender),
+            finished: finished_receiver,
+        };
+    }
+
+    /// Runs a definition to completion and returns the finished execution.
+    pub async fn execute(&self, definition: &Definition, input: Value) -> Execution {
+        return self
+            .start(ExecutionRequest::new(definition.clone(), input))
+            .wait()
+            .await;
+    }
+
+    /// Waits up to `wait` for a task of the activity, like `GetActivityTask`. The task counts as
+    /// started by `worker_name`.
+    pub async fn get_activity_task(
+        &self,
+        activity_arn: &str,
+        worker_name: &str,
+        wait: Duration,
+    ) -> Option<ActivityTask> {
+        return self.activities.poll(activity_arn, worker_name, wait).await;
+    }
+
+    pub fn send_task_success(&self, token: &str, output: Value) -> Result<(), TokenError> {
+        return self.tokens.signal(token, TokenSignal::Success(output));
+    }
+
+    pub fn send_task_failure(&self, token: &str, error: StatesError) -> Result<(), TokenError> {
+        return self.tokens.signal(token, TokenSignal::Failure(error));
+    }
+
+    pub fn send_task_heartbeat(&self, token: &str) -> Result<(), TokenError> {
+        return self.tokens.signal(token, TokenSignal::Heartbeat);
+    }
+
+    pub(crate) fn handler(&self, resource: &str) -> Option<Arc<dyn TaskHandler>> {
+        if let Some(handler) = self.handlers.get(resource) {
+            return Some(handler.clone());
+        }
+
+        return self
+            .handlers
+            .iter()
+            .filter_map(|(pattern, handler)| {
+                let prefix = pattern.strip_suffix('*')?;
+                resource
+                    .starts_with(prefix)
+                    .then_some((prefix.len(), handler))
+            })
+            .max_by_key(|(length, _)| *length)
+            .map(|(_, handler)| handler.clone());
+    }
+
+    async fn sleep(&self, seconds: f64) {
+        if !self.skip_waits && seconds > 0.0 {
+            tokio::time::sleep(Duration::from_secs_f64(seconds)).await;
+        }
+    }
+}
+
+/// What to run and how to identify it.
+#[derive(Debug, Clone)]
+pub struct ExecutionRequest {
+    pub definition: Definition,
+    pub input: Value,
+    pub name: String,
+    pub state_machine_arn: String,
+    pub role_arn: Option<String>,
+    /// A test case of the executor's mock config, looked up under `mock_state_machine`.
+    pub test_case: Option<String>,
+    /// The state machine the test case is configured for in the mock config, when it is not the
+    /// executed one, e.g. for a uniquely named copy of it.
+    pub mock_state_machine: Option<String>,
+    /// Fields merged over the generated context object (`$$`).
+    pub context: Option<Value>,
+}
+
+impl ExecutionRequest {
+    pub fn new(definition: Definition, input: Value) -> Self {
+        return Self {
+            definition,
+            input,
+            name: uuid::Uuid::new_v4().to_string(),
+            state_machine_arn: state_machine_arn(DEFAULT_REGION, DEFAULT_ACCOUNT_ID, "Local"),
+            role_arn: None,
+            test_case: None,
+            mock_state_machine: None,
+            context: None,
+        };
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        return self;
+    }
+
+    pub fn state_machine_arn(mut self, arn: impl Into<String>) -> Self {
+        self.state_machine_arn = arn.into();
+        return self;
+    }
+
+    pub fn role_arn(mut self, role_arn: impl Into<String>) -> Self {
+        self.role_arn = Some(role_arn.into());
+        return self;
+    }
+
+    pub fn test_case(mut self, test_case: impl Into<String>) -> Self {
+        self.test_case = Some(test_case.into());
+        return self;
+    }
+
+    pub fn mock_state_machine(mut self, name: impl Into<String>) -> Self {
+        self.mock_state_machine = Some(name.into());
+        return self;
+    }
+
+    pub fn context(mut self, context: Value) -> Self {
+        self.context = Some(context);
+        return self;
+    }
+}
+
+/// A running (or finished) execution.
+#[derive(Clone)]
+pub struct ExecutionHandle {
+    request: Arc<ExecutionRequest>,
+    record: Arc<Mutex<Execution>>,
+    /// The mocked results of the execution's test case, with their invocation counts.
+    mocks: Arc<Mutex<Option<MockedTestCase>>>,
+    stop: Arc<watch::Sender<Option<StatesError>>>,
+    finished: watch::Receiver<bool>,
+}
+
+impl ExecutionHandle {
+    pub fn execution_arn(&self) -> String {
+        return self.record.lock().unwrap().execution_arn.clone();
+    }
+
+    /// The execution as it is right now, including the history recorded so far.
+    pub fn snapshot(&self) -> Execution {
+        return self.record.lock().unwrap().clone();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        return *self.finished.borrow();
+    }
+
+    /// Aborts the execution, like `StopExecution`.
+    pub fn stop(&self, error: Option<String>, cause: Option<String>) {
+        let _ = self.stop.send(Some(StatesError::new(
+            error.unwrap_or_default(),
+            cause.unwrap_or_default(),
+        )));
+    }
+
+    pub async fn wait(&self) -> Execution {
+        let mut finished = self.finished.clone();
+        while !*finished.borrow() {
+            if finished.changed().await.is_err() {
+                break;
+            }
+        }
+
+        return self.snapshot();
+    }
+}
+
+/// The output of a state, the state to run next (`None` to end) and the variables it assigns.
+pub(crate) type StateResult = Result<(Value, Option<String>, Map<String, Value>), StatesError>;
+
+enum Outcome {
+    Succeeded(Value),
+    Failed(StatesError),
+    TimedOut,
+    Aborted(StatesError),
+}
+
+/// How a Task `Resource` is invoked and reported in the history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Integration {
+    /// `LambdaFunction` for plain Lambda ARNs, `Activity` for activity ARNs, `Task` for service
+    /// integrations.
+    event_prefix: &'static str,
+    resource_type: String,
+    resource: String,
+    wait_for_task_token: bool,
+}
+
+impl Integration {
+    fn parse(resource: &str) -> Self {
+        if resource.starts_with("arn:aws:lambda:") {
+            return Self {
+                event_prefix: "LambdaFunction",
+                resource_type: "lambda".to_string(),
+                resource: resource.to_string(),
+                wait_for_task_token: false,
+            };
+        }
+        if resource.starts_with("arn:aws:states:") && resource.contains(":activity:") {
+            return Self {
+                event_prefix: "Activity",
+                resource_type: "activity".to_string(),
+                resource: resource.to_string(),
+                wait_for_task_token: false,
+            };
+        }
+
+        let service = resource
+            .strip_prefix("arn:aws:states:::")
+            .unwrap_or(resource);
+        let (base, pattern) = [".waitForTaskToken", ".sync:2", ".sync"]
+            .iter()
+            .find_map(|pattern| Some((service.strip_suffix(pattern)?, *pattern)))
+            .unwrap_or((service, ""));
+        let (resource_type, name) = base.rsplit_once(':').unwrap_or(("", base));
+
+        return Self {
+            event_prefix: "Task",
+            resource_type: resource_type.to_string(),
+            resource: format!("{}{}", name, pattern),
+            wait_for_task_token: pattern == ".waitForTaskToken",
+        };
+    }
+
+    fn details(&self) -> EventDetails {
+        if self.event_prefix != "Task" {
+            return EventDetails::default();
+        }
+
+        return EventDetails {
+            resource_type: Some(self.resource_type.clone()),
+            resource: Some(self.resource.clone()),
+            ..Default::default()
+        };
+    }
+}
+
+/// The state of one execution while it runs.
+struct Run {
+    executor: Executor,
+    record: Arc<Mutex<Execution>>,
+    mocks: Arc<Mutex<Option<MockedTestCase>>>,
+    setup_error: Option<StatesError>,
+    region: String,
+    /// The definition's `QueryLanguage`, which states without their own use.
+    query_language: QueryLanguage,
+    context: Value,
+    context_overrides: Option<Value>,
+}
+
+impl Run {
+    fn new(executor: Executor, request: &ExecutionRequest, record: Arc<Mutex<Execution>>) -> Self {
+        let machine_name = state_machine_name(&request.state_machine_arn);
+        let mock_name = request
+            .mock_state_machine
+            .as_deref()
+            .unwrap_or(machine_name);
+        let (mocks, setup_error) = match (&request.test_case, executor.mock_config()) {
+            (None, _) => (None, None),
+            (Some(test_case), config) => {
+                match config.and_then(|config| MockedTestCase::new(config, mock_name, test_case)) {
+                    Some(mocks) => (Some(mocks), None),
+                    None => (
+                        None,
+                        Some(StatesError::runtime(format!(
+                            "Test case '{}' is not configured for state machine '{}'",
+                            test_case, mock_name
+                        ))),
+                    ),
+                }
+            }
+        };
+        let execution = record.lock().unwrap().clone();
+        let mut context = json!({
+            "Execution": {
+                "Id": execution.execution_arn,
+                "Input": request.input,
+                "Name": request.name,
+                "RedriveCount": execution.redrive_count,
+                "RoleArn": request.role_arn,
+                "StartTime": to_rfc3339(execution.start_date),
+            },
+            "StateMachine": {
+                "Id": request.state_machine_arn,
+                "Name": machine_name,
+            },
+        });
+        if let Some(overrides) = &request.context {
+            merge_values(&mut context, overrides);
+        }
+
+        return Self {
+            region: arn_region(&request.state_machine_arn).to_string(),
+            query_language: request.definition.query_language(),
+            executor,
+            record,
+            mocks: Arc::new(Mutex::new(mocks)),
+            setup_error,
+            context,
+            context_overrides: request.context.clone(),
+        };
+    }
+
+    fn record(&self, event_type: impl Into<String>, details: EventDetails) {
+        self.push(event_type.into(), details);
+    }
+
+    fn push(&self, event_type: String, details: EventDetails) -> HistoryEvent {
+        let mut record = self.record.lock().unwrap();
+        let id = record.events.len() as i64 + 1;
+        let event = HistoryEvent {
+            id,
+            previous_event_id: id - 1,
+            timestamp: now(),
+            event_type,
+            details,
+        };
+        record.events.push(event.clone());
+
+        return event;
+    }
+
+    /// Records an event the executor's observers see, and waits for each of them.
+    async fn record_live(
+        &self,
+        event_type: impl Into<String>,
+        details: EventDetails,
+        task_token: Option<&str>,
+    ) {
+        let event = self.push(event_type.into(), details);
+        if self.executor.observers.is_empty() {
+            return;
+        }
+        let live = LiveEvent {
+            execution_arn: self.record.lock().unwrap().execution_arn.clone(),
+            event,
+            task_token: task_token.map(str::to_string),
+        };
+        for observer in &self.executor.observers {
+            observer.on_event(&live).await;
+        }
+    }
+
+    fn finish(&self, outcome: Outcome) {
+        let (status, event_type, details) = match outcome {
+            Outcome::Succeeded(output) => {
+                let details = EventDetails {
+                    output: Some(to_document(&output)),
+                    ..Default::default()
+                };
+                self.record.lock().unwrap().output = Some(output);
+                (ExecutionStatus::Succeeded, "ExecutionSucceeded", details)
+            }
+            Outcome::Failed(error) => (
+                ExecutionStatus::Failed,
+                "ExecutionFailed",
+                error_details(&error),
+            ),
+            Outcome::TimedOut => (
+                ExecutionStatus::TimedOut,
+                "ExecutionTimedOut",
+                error_details(&StatesError::new(
+                    STATES_TIMEOUT,
+                    "The execution exceeded its TimeoutSeconds",
+                )),
+            ),
+            Outcome::Aborted(error) => (
+                ExecutionStatus::Aborted,
+                "ExecutionAborted",
+                error_details(&error),
+            ),
+        };
+
+        self.record(event_type, details.clone());
+        let mut record = self.record.lock().unwrap();
+        record.status = status;
+        record.stop_date = Some(now());
+        if status != ExecutionStatus::Succeeded {
+            record.error = details.error.filter(|error| !error.is_empty());
+            record.cause = details.cause.filter(|cause| !cause.is_empty());
+        }
+    }
+
+    /// Runs a definition (or a Parallel branch / Map iteration) from its `StartAt` state.
+    ///
+    /// `variables` are those in scope when the definition starts. A branch or iteration gets a
+    /// copy of the enclosing scope, so what it assigns is not visible outside of it.
+    fn run_definition<'a>(
+        &'a self,
+        definition: &'a Definition,
+        input: Value,
+        variables: Map<String, Value>,
+    ) -> BoxFuture<'a, Result<Value, StatesError>> {
+        let start_at = definition.start_at().to_string();
+
+        return self.run_from(definition, start_at, input, variables);
+    }
+
+    /// Runs a definition from one of its states, e.g. the state a redriven execution restarts
+    /// at.
+    fn run_from<'a>(
+        &'a self,
+        definition: &'a Definition,
+        start: String,
+        input: Value,
+        variables: Map<String, Value>,
+    ) -> BoxFuture<'a, Result<Value, StatesError>> {
+        return Box::pin(async move {
+            let mut current = start;
+            let mut input = input;
+            let mut variables = variables;
+            loop {
+                let state = definition.state(&current).ok_or_else(|| {
+                    StatesError::runtime(format!("State '{}' does not exist", current))
+                })?;
+                let kind = state.kind().ok_or_else(|| {
+                    StatesError::runtime(format!("State '{}' has an unknown Type", current))
+                })?;
+                if self.record.lock().unwrap().events.len() >= MAX_HISTORY_EVENTS {
+                    return Err(StatesError::runtime(format!(
+                        "The execution reached the limit of {} history events",
+                        MAX_HISTORY_EVENTS
+                    )));
+                }
+
+                self.record_live(
+                    format!("{:?}StateEntered", kind),
+                    EventDetails {
+                        name: Some(current.clone()),
+                        input: Some(to_document(&input)),
+                        ..Default::default()
+                    },
+                    None,
+                )
+                .await;
+                let context = self.state_context(&current);
+                let (output, next, assigned) = self
+                    .run_state(state, kind, input, &context, &variables)
+                    .await?;
+                self.record_live(
+                    format!("{:?}StateExited", kind),
+                    EventDetails {
+                        name: Some(current.clone()),
+                        output: Some(to_document(&output)),
+                        assigned_variables: (!assigned.is_empty()).then(|| {
+                            assigned
+                                .iter()
+                                .map(|(name, value)| (name.clone(), to_document(value)))
+                                .collect()
+                        }),
+                        ..Default::default()
+                    },
+                    None,
+                )
+                .await;
+                variables.extend(assigned);
+
+                match next {
+                    Some(next) => {
+                        current = next;
+                        input = output;
+                    }
+                    None => return Ok(output),
+                }
+            }
+        });
+    }
+
+    fn state_context(&self, name: &str) -> Value {
+        let mut context = self.context.clone();
+        context["State"] = json!({
+            "Name": name,
+            "EnteredTime": to_rfc3339(now()),
+            "RetryCount": 0,
+        });
+        if let Some(overrides) = &self.context_overrides {
+            merge_values(&mut context, overrides);
+        }
+
+        return context;
+    }
+
+    async fn run_state(
+        &self,
+        state: State<'_>,
+        kind: StateKind,
+        input: Value,
+        context: &Value,
+        variables: &Map<String, Value>,
+    ) -> StateResult {
+        let language = self.query_language(&state);
+        let mut runner = self;
+
+        return evaluate_state(
+            &mut runner,
+            &state,
+            kind,
+            language,
+            input,
+            context,
+            variables,
+        )
+        .await;
+    }
+
+    fn query_language(&self, state: &State<'_>) -> QueryLanguage {
+        return state.query_language(self.query_language);
+    }
+
+    async fn invoke_task(
+        &self,
+        state: &State<'_>,
+        effective: &Value,
+        context: &Value,
+        variables: &Map<String, Value>,
+    ) -> Result<Value, StatesError> {
+        let resource = state.str_field("Resource").ok_or_else(|| {
+            StatesError::runtime(format!("Task '{}' has no Resource", state.name))
+        })?;
+        let integration = Integration::parse(resource);
+        let token = (integration.wait_for_task_token || integration.event_prefix == "Activity")
+            .then(|| uuid::Uuid::new_v4().to_string());
+        // Registered right away, so the token can be reported as soon as it is issued.
+        let signals = token
+            .as_deref()
+            .map(|token| self.executor.tokens.register(token));
+        let mut context = context.clone();
+        if let (Some(token), true) = (&token, integration.wait_for_task_token) {
+            context["Task"] = json!({ "Token": token });
+        }
+        let parameters = task_parameters(
+            state,
+            self.query_language(state),
+            effective,
+            &context,
+            variables,
+        )?;
+        let timeout = self.seconds_field(state, "TimeoutSeconds", effective, variables)?;
+        let heartbeat = self.seconds_field(state, "HeartbeatSeconds", effective, variables)?;
+        let prefix = integration.event_prefix;
+
+        self.record_live(
+            format!("{}Scheduled", prefix),
+            EventDetails {
+                input: (prefix != "Task").then(|| to_document(&parameters)),
+                parameters: (prefix == "Task").then(|| to_document(&parameters)),
+                resource: Some(integration.resource.clone()),
+                resource_type: integration.details().resource_type,
+                region: (prefix == "Task").then(|| self.region.clone()),
+                timeout_in_seconds: timeout,
+                heartbeat_in_seconds: heartbeat,
+                ..Default::default()
+            },
+            token.as_deref(),
+        )
+        .await;
+        if prefix != "Activity" {
+            // Activities start once a worker picks them up.
+            self.record(format!("{}Started", prefix), integration.details());
+        }
+
+        let result = self
+            .run_task(
+                state,
+                resource,
+                &integration,
+                parameters,
+                token.clone().zip(signals),
+                (timeout, heartbeat),
+            )
+            .await;
+        if let Some(token) = &token {
+            self.executor.tokens.close(token);
+        }
+
+        match &result {
+            Ok(output) => self.record(
+                format!("{}Succeeded", prefix),
+                EventDetails {
+                    output: Some(to_document(output)),
+                    ..integration.details()
+                },
+            ),
+            Err(error)
+                if error.error == STATES_TIMEOUT || error.error == STATES_HEARTBEAT_TIMEOUT =>
+            {
+                self.record(
+                    format!("{}TimedOut", prefix),
+                    merge_details(integration.details(), error_details(error)),
+                )
+            }
+            Err(error) => self.record(
+                format!("{}Failed", prefix),
+                merge_details(integration.details(), error_details(error)),
+            ),
+        }
+
+        return result;
+    }
+
+    async fn run_task(
+        &self,
+        state: &State<'_>,
+        resource: &str,
+        integration: &Integration,
+        parameters: Value,
+        token: Option<(String, mpsc::UnboundedReceiver<TokenSignal>)>,
+        (timeout, heartbeat): (Option<i64>, Option<i64>),
+    ) -> Result<Value, StatesError> {
+        let mocked = self
+            .mocks
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|mocks| mocks.next_result(state.name));
+        if let Some(result) = mocked {
+            if integration.event_prefix == "Activity" {
+                self.record("ActivityStarted", EventDetails::default());
+            }
+            return result;
+        }
+
+        let work = async {
+            if integration.event_prefix == "Activity" {
+                let (token, signals) = token.expect("activity tasks always have a task token");
+                return self
+                    .run_activity(resource, parameters, token, signals, heartbeat)
+                    .await;
+            }
+            if integration.resource_type == "states"
+                && integration.resource.starts_with(START_EXECUTION)
+                && self.executor.handler(resource).is_none()
+            {
+                return self.run_nested(integration, parameters).await;
+            }
+            let handler = self.executor.handler(resource).ok_or_else(|| {
+                StatesError::new(
+                    STATES_TASK_FAILED,
+                    format!("No handler is registered for resource '{}'", resource),
+                )
+            })?;
+            let request = TaskRequest {
+                state_name: state.name.to_string(),
+                resource: resource.to_string(),
+                input: parameters,
+                task_token: token.as_ref().map(|(token, _)| token.clone()),
+            };
+            let mut signals = match token {
+                Some((_, signals)) => signals,
+                None => return handler.invoke(request).await,
+            };
+
+            let submitted = handler.invoke(request).await?;
+            self.record(
+                "TaskSubmitted",
+                EventDetails {
+                    output: Some(to_document(&submitted)),
+                    ..integration.details()
+                },
+            );
+            return await_token(&mut signals, heartbeat).await;
+        };
+
+        return match timeout {
+            Some(seconds) => tokio::time::timeout(Duration::from_secs(seconds as u64), work)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(StatesError::new(
+                        STATES_TIMEOUT,
+                        format!("Task did not complete within {} seconds", seconds),
+                    ))
+                }),
+            None => work.await,
+        };
+    }
+
+    /// Queues an activity task and waits for a worker to pick it up and report its token.
+    async fn run_activity(
+        &self,
+        activity_arn: &str,
+        input: Value,
+        token: String,
+        mut signals: mpsc::UnboundedReceiver<TokenSignal>,
+        heartbeat: Option<i64>,
+    ) -> Result<Value, StatesError> {
+        let task = ActivityTask {
+            task_token: token,
+            input,
+        };
+        let worker_name = self
+            .executor
+            .activities
+            .schedule(activity_arn, task)
+            .await
+            .map_err(|_| StatesError::runtime("The activity task was dropped"))?;
+        self.record(
+            "ActivityStarted",
+            EventDetails {
+                worker_name: Some(worker_name),
+                ..Default::default()
+            },
+        );
+
+        return await_token(&mut signals, heartbeat).await;
+    }
+
+    /// Starts a registered state machine and, for `.sync` and `.sync:2`, waits for it.
+    async fn run_nested(
+        &self,
+        integration: &Integration,
+        parameters: Value,
+    ) -> Result<Value, StatesError> {
+        let request = StartRequest::from_parameters(&parameters)?;
+        let definition = self
+            .executor
+            .nested
+            .definition(&request.state_machine_arn)?;
+        let mut child = ExecutionRequest::new(definition, request.input)
+            .state_machine_arn(request.state_machine_arn);
+        if let Some(name) = request.name {
+            child = child.name(name);
+        }
+        let handle = self.executor.start(child);
+        self.executor.nested.track(&handle);
+        let started = nested::started_output(&handle.snapshot());
+
+        let version_2 = match &integration.resource[START_EXECUTION.len()..] {
+            "" => return Ok(started),
+            ".sync" => false,
+            _ => true,
+        };
+        self.record(
+            "TaskSubmitted",
+            EventDetails {
+                output: Some(to_document(&started)),
+                ..integration.details()
+            },
+        );
+        let handle = StopOnDrop(handle);
+        let execution = handle.0.wait().await;
+
+        return nested::sync_output(&execution, version_2);
+    }
+
+    /// Reads `<field>` or `<field>Path` (e.g. `TimeoutSeconds`/`TimeoutSecondsPath`). In
+    /// JSONata states `<field>` may be an expression instead.
+    fn seconds_field(
+        &self,
+        state: &State<'_>,
+        field: &str,
+        effective: &Value,
+        variables: &Map<String, Value>,
+    ) -> Result<Option<i64>, StatesError> {
+        if let Some(seconds) = state.field(field).and_then(Value::as_i64) {
+            return Ok(Some(seconds));
+        }
+        if let (QueryLanguage::Jsonata, Some(expression)) =
+            (self.query_language(state), state.field(field))
+        {
+            let bindings = Bindings::new(variables, effective, &self.context);
+            return query::evaluate(expression, &bindings)?
+                .as_i64()
+                .filter(|seconds| *seconds > 0)
+                .map(Some)
+                .ok_or_else(|| {
+                    StatesError::new(
+                        STATES_QUERY_EVALUATION_ERROR,
+                        format!("{} must evaluate to a positive integer", field),
+                    )
+                });
+        }
+        let path_field = format!("{}Path", field);
+
+        return match state.str_field(&path_field) {
+            Some(expression) => {
+                crate::asl::path::select(expression, effective, &self.context, &path_field)?
+                    .as_i64()
+                    .filter(|seconds| *seconds > 0)
+                    .map(Some)
+                    .ok_or_else(|| {
+                        StatesError::runtime(format!(
+                            "{} must resolve to a positive integer",
+                            path_field
+                        ))
+                    })
+            }
+            None => Ok(None),
+        };
+    }
+
+    async fn run_parallel(
+        &self,
+        state: &State<'_>,
+        effective: &Value,
+        context: &Value,
+        variables: &Map<String, Value>,
+    ) -> Result<Value, StatesError> {
+        let input = match self.query_language(state) {
+            QueryLanguage::JsonPath => processing::parameters(state, effective, context)?,
+            QueryLanguage::Jsonata => {
+                query::arguments(state, &Bindings::new(variables, effective, context))?
+            }
+        };
+        let branches = state.branches();
+        self.record("ParallelStateStarted", EventDetails::default());
+
+        let result = try_join_all(
+            branches
+                .iter()
+                .map(|branch| self.run_definition(branch, input.clone(), variables.clone())),
+        )
+        .await;
+        match &result {
+            Ok(_) => self.record("ParallelStateSucceeded", EventDetails::default()),
+            Err(_) => self.record("ParallelStateFailed", EventDetails::default()),
+        }
+
+        return result.map(Value::Array);
+    }
+
+    async fn run_map(
+        &self,
+        state: &State<'_>,
+        effective: &Value,
+        context: &Value,
+        variables: &Map<String, Value>,
+    ) -> Result<Value, StatesError> {
+        let language = self.query_language(state);
+        let items = match language {
+            QueryLanguage::JsonPath => {
+                let items_path = state.str_field("ItemsPath").unwrap_or("$");
+                match Path::parse(items_path)?.select(effective, context) {
+                    Some(Value::Array(items)) => items,
+                    _ => {
+                        return Err(StatesError::runtime(format!(
+                            "Map state '{}' ItemsPath '{}' did not resolve to an array",
+                            state.name, items_path
+                        )))
+                    }
+                }
+            }
+            QueryLanguage::Jsonata => {
+                let items = match state.field("Items") {
+                    Some(items) => {
+                        query::evaluate(items, &Bindings::new(variables, effective, context))?
+                    }
+                    None => effective.clone(),
+                };
+                match items {
+                    Value::Array(items) => items,
+                    other => {
+                        return Err(StatesError::new(
+                            STATES_QUERY_EVALUATION_ERROR,
+                            format!("Map state '{}' Items {} is not an array", state.name, other),
+                        ))
+                    }
+                }
+            }
+        };
+        let processor = state.branches().pop().ok_or_else(|| {
+            StatesError::runtime(format!("Map state '{}' has no ItemProcessor", state.name))
+        })?;
+        let selector = state
+            .field("ItemSelector")
+            .or_else(|| state.field("Parameters"));
+        let concurrency = match state.field("MaxConcurrency").and_then(Value::as_u64) {
+            Some(0) | None => items.len().max(1),
+            Some(concurrency) => concurrency as usize,
+        };
+
+        self.record(
+            "MapStateStarted",
+            EventDetails {
+                length: Some(items.len() as i64),
+                ..Default::default()
+            },
+        );
+        let processor = &processor;
+        let result: Result<Vec<Value>, StatesError> =
+            futures::stream::iter(items.into_iter().enumerate())
+                .map(|(index, item)| async move {
+                    let mut item_context = context.clone();
+                    item_context["Map"] = json!({ "Item": { "Index": index, "Value": item } });
+                    let item_input = match (selector, language) {
+                        (Some(selector), QueryLanguage::JsonPath) => {
+                            processing::apply_template(selector, effective, &item_context)?
+                        }
+                        (Some(selector), QueryLanguage::Jsonata) => {
+                            let bindings = Bindings::new(variables, effective, &item_context);
+                            query::evaluate(selector, &bindings)?
+                        }
+                        (None, _) => item,
+                    };
+                    let iteration = EventDetails {
+                        name: Some(state.name.to_string()),
+                        index: Some(index as i64),
+                        ..Default::default()
+                    };
+
+                    self.record("MapIterationStarted", iteration.clone());
+                    let result = self
+                        .run_definition(processor, item_input, variables.clone())
+                        .await;
+                    match &result {
+                        Ok(_) => self.record("MapIterationSucceeded", iteration),
+                        Err(_) => self.record("MapIterationFailed", iteration),
+                    }
+                    result
+                })
+                .buffered(concurrency)
+                .try_collect()
+                .await;
+        match &result {
+            Ok(_) => self.record("MapStateSucceeded", EventDetails::default()),
+            Err(_) => self.record("MapStateFailed", EventDetails::default()),
+        }
+
+        return result.map(Value::Array);
+    }
+}
+
+#[async_trait]
+impl StateRunner for &Run {
+    async fn attempt(
+        &mut self,
+        state: &State<'_>,
+        kind: StateKind,
+        effective: &Value,
+        context: &Value,
+        variables: &Map<String, Value>,
+    ) -> Result<Value, StatesError> {
+        return match kind {
+            StateKind::Task => self.invoke_task(state, effective, context, variables).await,
+            StateKind::Parallel => {
+                self.run_parallel(state, effective, context, variables)
+                    .await
+            }
+            _ => self.run_map(state, effective, context, variables).await,
+        };
+    }
+
+    async fn sleep(&mut self, seconds: f64) {
+        self.executor.sleep(seconds).await;
+    }
+}
+
+/// A processing stage of a state, after which [`StateRunner::record`] sees the document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stage {
+    InputPath,
+    Parameters,
+    Arguments,
+    Result,
+    ResultSelector,
+    ResultPath,
+    OutputPath,
+}
+
+/// The work of a state that depends on how it runs: an execution invokes tasks and sleeps, an
+/// inspection (see [`crate::inspect`]) uses a mocked result and records every stage.
+#[async_trait]
+pub(crate) trait StateRunner: Send {
+    /// Runs a Task, Parallel or Map state once on its effective input.
+    async fn attempt(
+        &mut self,
+        state: &State<'_>,
+        kind: StateKind,
+        effective: &Value,
+        context: &Value,
+        variables: &Map<String, Value>,
+    ) -> Result<Value, StatesError>;
+
+    /// Waits for a Wait state or before a retry.
+    async fn sleep(&mut self, seconds: f64);
+
+    /// Whether the retrier at `index` runs the state again, after `retry_count` retries of its
+    /// own. Returning `false` hands the error to the catchers.
+    fn retries(&mut self, _index: usize, _retry_count: u32, _delay_seconds: f64) -> bool {
+        return true;
+    }
+
+    /// Called with the document after each processing stage that runs.
+    fn record(&mut self, _stage: Stage, _document: &Value) {}
+
+    /// Whether the catcher at `index`, the first that matches `error`, handles it. Returning
+    /// `false` fails the state with the error.
+    fn catches(&mut self, _index: usize, _error: &StatesError) -> bool {
+        return true;
+    }
+}
+
+/// Runs one state on `input`: the input and output processing of its query language around the
+/// work of `runner`, with Retry and Catch. Executions and inspections share it.
+pub(crate) async fn evaluate_state<R: StateRunner>(
+    runner: &mut R,
+    state: &State<'_>,
+    kind: StateKind,
+    language: QueryLanguage,
+    input: Value,
+    context: &Value,
+    variables: &Map<String, Value>,
+) -> StateResult {
+    if language == QueryLanguage::Jsonata {
+        return evaluate_jsonata_state(runner, state, kind, input, context, variables).await;
+    }
+    let next = state.next().map(str::to_string);
+
+    if kind == StateKind::Fail {
+        return Err(fail_error(state, &input, context)?);
+    }
+    let effective = processing::input_path(state, &input, context)?;
+    runner.record(Stage::InputPath, &effective);
+
+    let (output, next, assigned) = match kind {
+        StateKind::Pass => {
+            let result = match state.field("Result") {
+                Some(result) => result.clone(),
+                None => processing::parameters(state, &effective, context)?,
+            };
+            runner.record(Stage::Parameters, &result);
+            let assigned = query::assign_json_path(state.value, &result, context)?;
+            let output = processing::result_path(state, &input, result)?;
+            runner.record(Stage::ResultPath, &output);
+            (output, next, assigned)
+        }
+        StateKind::Choice => {
+            let next = choice::next_state(state, &effective, context)?;
+            let assigned = query::assign_json_path(state.value, &effective, context)?;
+            (effective, Some(next), assigned)
+        }
+        StateKind::Wait => {
+            let seconds = wait_seconds(state, &effective, context)?;
+            runner.sleep(seconds).await;
+            let assigned = query::assign_json_path(state.value, &effective, context)?;
+            (effective, next, assigned)
+        }
+        StateKind::Succeed => {
+            let assigned = query::assign_json_path(state.value, &effective, context)?;
+            (effective, None, assigned)
+        }
+        _ => {
+            let attempted =
+                attempt_with_retries(runner, state, kind, &effective, context, variables).await;
+            let result = match attempted {
+                Ok(result) => result,
+                Err(error) => {
+                    return catch(runner, state, language, &input, context, variables, error)
+                }
+            };
+            runner.record(Stage::Result, &result);
+            let selected = processing::result_selector(state, &result, context)?;
+            runner.record(Stage::ResultSelector, &selected);
+            let assigned = query::assign_json_path(state.value, &selected, context)?;
+            let output = processing::result_path(state, &input, selected)?;
+            runner.record(Stage::ResultPath, &output);
+            (output, next, assigned)
+        }
+    };
+    let output = processing::output_path(state, &output, context)?;
+    runner.record(Stage::OutputPath, &output);
+
+    return Ok((output, next, assigned));
+}
+
+/// Runs a `QueryLanguage: JSONata` state, where `Arguments`, `Output` and `Assign` replace
+/// the JSONPath processing fields.
+async fn evaluate_jsonata_state<R: StateRunner>(
+    runner: &mut R,
+    state: &State<'_>,
+    kind: StateKind,
+    input: Value,
+    context: &Value,
+    variables: &Map<String, Value>,
+) -> StateResult {
+    let bindings = Bindings::new(variables, &input, context);
+    let next = state.next().map(str::to_string);
+
+    let (next, rule) = match kind {
+        StateKind::Pass => (next, None),
+        StateKind::Choice => {
+            let (next, rule) = query::choose(state, &bindings)?;
+            (Some(next), rule)
+        }
+        StateKind::Wait => {
+            let seconds = query::wait_seconds(state, &bindings)?;
+            runner.sleep(seconds).await;
+            (next, None)
+        }
+        StateKind::Succeed => (None, None),
+        StateKind::Fail => return Err(query::fail_error(state, &bindings)?),
+        StateKind::Task | StateKind::Parallel | StateKind::Map => {
+            let result =
+                attempt_with_retries(runner, state, kind, &input, context, variables).await;
+            // Errors in Output and Assign are caught like task errors, but not retried.
+            let processed = result.and_then(|result| {
+                runner.record(Stage::Result, &result);
+                let bindings = bindings.result(&result);
+                let assigned = query::assign(state.value, &bindings)?;
+                let output = query::output(state.value, &bindings, result.clone())?;
+                Ok((output, assigned))
+            });
+            return match processed {
+                Ok((output, assigned)) => Ok((output, next, assigned)),
+                Err(error) => catch(
+                    runner,
+                    state,
+                    QueryLanguage::Jsonata,
+                    &input,
+                    context,
+                    variables,
+                    error,
+                ),
+            };
+        }
+    };
+
+    let mut assigned = query::assign(state.value, &bindings)?;
+    let mut output = query::output(state.value, &bindings, input.clone())?;
+    if let Some(rule) = rule {
+        assigned.extend(query::assign(rule, &bindings)?);
+        output = query::output(rule, &bindings, output)?;
+    }
+
+    return Ok((output, next, assigned));
+}
+
+/// Attempts a Task, Parallel or Map state until it succeeds or no retrier runs it again. The
+/// retry count starts at the `State.RetryCount` of `context`.
+async fn attempt_with_retries<R: StateRunner>(
+    runner: &mut R,
+    state: &State<'_>,
+    kind: StateKind,
+    effective: &Value,
+    context: &Value,
+    variables: &Map<String, Value>,
+) -> Result<Value, StatesError> {
+    let retriers = state.retriers()?;
+    let retried = context["State"]["RetryCount"].as_u64().unwrap_or_default() as u32;
+    let mut attempts = vec![0; retriers.len()];
+    let mut context = context.clone();
+    loop {
+        let error = match runner
+            .attempt(state, kind, effective, &context, variables)
+            .await
+        {
+            Ok(result) => return Ok(result),
+            Err(error) => error,
+        };
+
+        let retrier = retriers
+            .iter()
+            .position(|retrier| error_matches(&retrier.error_equals, &error.error));
+        match retrier {
+            Some(index) if retried + attempts[index] < retriers[index].max_attempts => {
+                let retry_count = retried + attempts[index];
+                let delay_seconds = retriers[index].delay_seconds(retry_count);
+                if !runner.retries(index, retry_count, delay_seconds) {
+                    return Err(error);
+                }
+                runner.sleep(delay_seconds).await;
+                attempts[index] += 1;
+                context["State"]["RetryCount"] =
+                    Value::from(retried + attempts.iter().sum::<u32>());
+            }
+            _ => return Err(error),
+        }
+    }
+}
+
+fn catch<R: StateRunner>(
+    runner: &mut R,
+    state: &State<'_>,
+    language: QueryLanguage,
+    input: &Value,
+    context: &Value,
+    variables: &Map<String, Value>,
+    error: StatesError,
+) -> StateResult {
+    let catchers = state.catchers()?;
+    let index = match catchers
+        .iter()
+        .position(|catcher| error_matches(&catcher.error_equals, &error.error))
+    {
+        Some(index) => index,
+        None => return Err(error),
+    };
+    if !runner.catches(index, &error) {
+        return Err(error);
+    }
+    let catcher = &catchers[index];
+    let clause = &state.value["Catch"][index];
+    let error_output = error.to_value();
+
+    if language == QueryLanguage::Jsonata {
+        let bindings = Bindings::new(variables, input, context).error_output(&error_output);
+        let assigned = query::assign(clause, &bindings)?;
+        let output = query::output(clause, &bindings, error_output.clone())?;
+        return Ok((output, Some(catcher.next.clone()), assigned));
+    }
+
+    let assigned = query::assign_json_path(clause, &error_output, context)?;
+    let output = match &catcher.result_path {
+        Some(result_path) => merge_result(result_path, input, error_output)?,
+        None => input.clone(),
+    };
+
+    return Ok((output, Some(catcher.next.clone()), assigned));
+}
+
+/// What a Task state passes to its resource: `Parameters`, or `Arguments` in JSONata.
+pub(crate) fn task_parameters(
+    state: &State<'_>,
+    language: QueryLanguage,
+    effective: &Value,
+    context: &Value,
+    variables: &Map<String, Value>,
+) -> Result<Value, StatesError> {
+    return match language {
+        QueryLanguage::JsonPath => processing::parameters(state, effective, context),
+        QueryLanguage::Jsonata => {
+            query::arguments(state, &Bindings::new(variables, effective, context))
+        }
+    };
+}
+
+pub(crate) fn wait_seconds(
+    state: &State<'_>,
+    input: &Value,
+    context: &Value,
+) -> Result<f64, StatesError> {
+    if let Some(seconds) = state.field("Seconds").and_then(Value::as_f64) {
+        return Ok(seconds);
+    }
+    if let Some(expression) = state.str_field("SecondsPath") {
+        return crate::asl::path::select(expression, input, context, "SecondsPath")?
+            .as_f64()
+            .ok_or_else(|| StatesError::runtime("SecondsPath must resolve to a number"));
+    }
+
+    let timestamp = match (
+        state.str_field("Timestamp"),
+        state.str_field("TimestampPath"),
+    ) {
+        (Some(timestamp), _) => timestamp.to_string(),
+        (None, Some(expression)) => {
+            crate::asl::path::select(expression, input, context, "TimestampPath")?
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| StatesError::runtime("TimestampPath must resolve to a string"))?
+        }
+        (None, None) => {
+            return Err(StatesError::runtime(format!(
+                "Wait state '{}' has no Seconds, SecondsPath, Timestamp or TimestampPath",
+                state.name
+            )))
+        }
+    };
+    let target = choice::parse_timestamp(&timestamp)
+        .ok_or_else(|| StatesError::runtime(format!("'{}' is not a valid timestamp", timestamp)))?;
+
+    return Ok((target - now()).max(0.0));
+}
+
+pub(crate) fn fail_error(
+    state: &State<'_>,
+    input: &Value,
+    context: &Value,
+) -> Result<StatesError, StatesError> {
+    let field = |name: &str| -> Result<String, StatesError> {
+        if let Some(value) = state.str_field(name) {
+            return Ok(value.to_string());
+        }
+        let path_field = format!("{}Path", name);
+        return match state.str_field(&path_field) {
+            Some(expression) => {
+                let value = crate::asl::path::select(expression, input, context, &path_field)?;
+                Ok(value
+                    .as_str()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| value.to_string()))
+            }
+            None => Ok(String::new()),
+        };
+    };
+
+    return Ok(StatesError::new(field("Error")?, field("Cause")?));
+}
+
+/// Deep-merges `overrides` into `target`; objects are merged key by key, anything else replaces.
+pub(crate) fn merge_values(target: &mut Value, overrides: &Value) {
+    match (target, overrides) {
+        (Value::Object(target), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge_values(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (target, overrides) => *target = overrides.clone(),
+    }
+}
+
+/// Waits for a task token to be reported, failing with `States.HeartbeatTimeout` when
+/// `HeartbeatSeconds` pass without a report.
+async fn await_token(
+    signals: &mut mpsc::UnboundedReceiver<TokenSignal>,
+    heartbeat: Option<i64>,
+) -> Result<Value, StatesError> {
+    loop {
+        let signal = match heartbeat {
+            Some(seconds) => {
+                tokio::time::timeout(Duration::from_secs(seconds as u64), signals.recv())
+                    .await
+                    .map_err(|_| {
+                        StatesError::new(
+                            STATES_HEARTBEAT_TIMEOUT,
+                            format!("No heartbeat was received for {} seconds", seconds),
+                        )
+                    })?
+            }
+            None => signals.recv().await,
+        };
+        match signal {
+            Some(TokenSignal::Success(output)) => return Ok(output),
+            Some(TokenSignal::Failure(error)) => return Err(error),
+            Some(TokenSignal::Heartbeat) => continue,
+            None => return Err(StatesError::runtime("The task token was dropped")),
+        }
+    }
+}
+
+fn error_details(error: &StatesError) -> EventDetails {
+    return EventDetails {
+        error: Some(error.error.clone()),
+        cause: Some(error.cause.clone()),
+        ..Default::default()
+    };
+}
+
+fn merge_details(base: EventDetails, error: EventDetails) -> EventDetails {
+    return EventDetails {
+        error: error.error,
+        cause: error.cause,
+        ..base
+    };
+}
+
+pub fn state_machine_arn(region: &str, account_id: &str, name: &str) -> String {
+    return format!(
+        "arn:aws:states:{}:{}:stateMachine:{}",
+        region, account_id, name
+    );
+}
+
+/// `arn:aws:states:<region>:<account>:stateMachine:<name>` becomes
+/// `arn:aws:states:<region>:<account>:execution:<name>:<execution name>`.
+pub fn execution_arn(state_machine_arn: &str, name: &str) -> String {
+    let base = state_machine_arn
+        .split('#')
+        .next()
+        .unwrap_or(state_machine_arn);
+
+    return format!(
+        "{}:{}",
+        base.replacen(":stateMachine:", ":execution:", 1),
+        name
+    );
+}
+
+/// The name of the state machine, without any `#TestCase` suffix.
+pub fn state_machine_name(state_machine_arn: &str) -> &str {
+    let base = state_machine_arn
+        .split('#')
+        .next()
+        .unwrap_or(state_machine_arn);
+
+    return base.rsplit(':').next().unwrap_or(base);
+}
+
+fn arn_region(arn: &str) -> &str {
+    return arn
+        .split(':')
+        .nth(3)
+        .filter(|region| !region.is_empty())
+        .unwrap_or(DEFAULT_REGION);
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::test_fixtures::simple_definition;
+
+    #[tokio::test]
+    async fn runs_the_sample_machine_with_mocked_test_cases() {
+        let config: MockConfig = serde_json::from_str(include_str!("sfn-local-mock.json")).unwrap();
+        let executor = Executor::new().with_mock_config(config);
+        let arn = state_machine_arn(DEFAULT_REGION, DEFAULT_ACCOUNT_ID, "SimpleExample");
+        let run = |test_case: &str| {
+            executor.start(
+                ExecutionRequest::new(
+                    simple_definition(),
+                    json!({ "url": "https://www.rust-lang.org/" }),
+                )
+                .state_machine_arn(arn.clone())
+                .test_case(test_case),
+            )
+        };
+
+        let is_big = run("IsBigPath").wait().await;
+        assert_eq!(is_big.status, ExecutionStatus::Succeeded);
+        assert_eq!(is_big.output, Some(json!(true)));
+
+        let is_not_big = run("IsNotBigPath").wait().await;
+        assert_eq!(is_not_big.output, Some(json!(false)));
+
+        let error = run("GetHtmlError").wait().await;
+        assert_eq!(error.status, ExecutionStatus::Failed);
+        assert_eq!(error.visited_states(), vec!["GetHtml", "Dunno"]);
+    }
+
+    #[tokio::test]
+    async fn retries_then_completes_task_tokens() {
+        let definition = Definition::from_value(json!({
+            "StartAt": "Notify",
+            "States": {
+                "Notify": {
+                    "Type": "Task",
+                    "Resource": "arn:aws:states:::sns:publish.waitForTaskToken",
+                    "Parameters": { "taskToken.$": "$$.Task.Token" },
+                    "Retry": [{ "ErrorEquals": ["Flaky"], "MaxAttempts": 1 }],
+                    "ResultPath": "$.decision",
+                    "End": true
+                }
+            }
+        }))
+        .unwrap();
+        let attempts = Arc::new(Mutex::new(0));
+        let (tokens, mut published) = mpsc::unbounded_channel();
+        let handler_attempts = attempts.clone();
+        let executor = Executor::new().skip_waits(true).with_handler(
+            "arn:aws:states:::sns:*",
+            handler_fn(move |request: TaskRequest| {
+                let attempts = handler_attempts.clone();
+                let tokens = tokens.clone();
+                async move {
+                    *attempts.lock().unwrap() += 1;
+                    if *attempts.lock().unwrap() == 1 {
+                        return Err(StatesError::new("Flaky", "first attempt"));
+                    }
+                    tokens
+                        .send(request.input["taskToken"].as_str().unwrap().to_string())
+                        .unwrap();
+                    return Ok(json!({ "MessageId": "1" }));
+                }
+            }),
+        );
+
+        let handle = executor.start(ExecutionRequest::new(definition, json!({ "orderId": "1" })));
+        let token = published.recv().await.unwrap();
+        executor.send_task_heartbeat(&token).unwrap();
+        executor
+            .send_task_success(&token, json!({ "accepted": true }))
+            .unwrap();
+        let execution = handle.wait().await;
+
+        assert_eq!(*attempts.lock().unwrap(), 2);
+        assert_eq!(
+            execution.output,
+            Some(json!({ "orderId": "1", "decision": { "accepted": true } }))
+        );
+        assert_eq!(
+            executor.send_task_success(&token, json!({})),
+            Err(TokenError::TaskTimedOut)
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_parallel_branches_and_map_iterations() {
+        let definition = Definition::from_value(json!({
+            "StartAt": "Fan out",
+            "States": {
+                "Fan out": {
+                    "Type": "Parallel",
+                    "Branches": [
+                        { "StartAt": "Double", "States": { "Double": {
+                            "Type": "Map",
+                            "ItemsPath": "$.numbers",
+                            "ItemSelector": { "n.$": "$$.Map.Item.Value" },
+                            "ItemProcessor": { "StartAt": "Add", "States": { "Add": {
+                                "Type": "Pass",
+                                "Parameters": { "value.$": "States.MathAdd($.n, $.n)" },
+                                "OutputPath": "$.value",
+                                "End": true
+                            } } },
+                            "End": true
+                        } } },
+                        { "StartAt": "Name", "States": { "Name": { "Type": "Pass", "InputPath": "$.name", "End": true } } }
+                    ],
+                    "End": true
+                }
+            }
+        }))
+        .unwrap();
+
+        let execution = Executor::new()
+            .execute(
+                &definition,
+                json!({ "numbers": [1, 2, 3], "name": "order" }),
+            )
+            .await;
+
+        assert_eq!(execution.output, Some(json!([[2, 4, 6], "order"])));
+    }
+
+    #[tokio::test]
+    async fn evaluates_jsonata_and_scopes_variables() {
+        let definition = Definition::from_value(json!({
+            "QueryLanguage": "JSONata",
+            "StartAt": "Remember",
+            "States": {
+                "Remember": {
+                    "Type": "Pass",
+                    "Assign": { "threshold": 10240, "url": "{% $states.input.url %}" },
+                    "Next": "Measure"
+                },
+                "Measure": {
+                    "Type": "Map",
+                    "Items": "{% $states.input.sizes %}",
+                    "ItemSelector": { "size": "{% $states.context.Map.Item.Value %}" },
+                    "ItemProcessor": { "StartAt": "Compare", "States": { "Compare": {
+                        "Type": "Pass",
+                        "Assign": { "inner": true },
+                        "Output": "{% $states.input.size > $threshold %}",
+                        "End": true
+                    } } },
+                    "Assign": { "bigCount": "{% $count($states.result[$]) %}" },
+                    "Output": "{% $states.input %}",
+                    "Next": "Report"
+                },
+                "Report": {
+                    "Type": "Pass",
+                    "Output": { "url": "{% $url %}", "big": "{% $bigCount %}", "inner": "{% $exists($inner) %}" },
+                    "Next": "Broken"
+                },
+                "Broken": {
+                    "Type": "Task",
+                    "Resource": "arn:aws:states:::lambda:invoke",
+                    "Arguments": { "size": "{% $states.input.missing %}" },
+                    "Catch": [{
+                        "ErrorEquals": ["States.QueryEvaluationError"],
+                        "Output": "{% $states.errorOutput.Error & ' after ' & $states.input.url %}",
+                        "Next": "Done"
+                    }],
+                    "End": true
+                },
+                "Done": { "Type": "Succeed" }
+            }
+        }))
+        .unwrap();
+
+        let execution = Executor::new()
+            .execute(
+                &definition,
+                json!({ "url": "https://example.com", "sizes": [1, 20480, 10241] }),
+            )
+            .await;
+        let exited = |event_type: &str, name: &str| {
+            return execution
+                .events
+                .iter()
+                .find(|event| {
+                    event.event_type == event_type && event.details.name.as_deref() == Some(name)
+                })
+                .unwrap()
+                .details
+                .clone();
+        };
+
+        assert_eq!(
+            execution.output,
+            Some(json!(
+                "States.QueryEvaluationError after https://example.com"
+            ))
+        );
+        assert_eq!(
+            exited("MapStateExited", "Measure").assigned_variables,
+            Some([("bigCount".to_string(), "2".to_string())].into())
+        );
+        assert_eq!(
+            exited("PassStateExited", "Report").output.unwrap(),
+            r#"{"big":2,"inner":false,"url":"https://example.com"}"#
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Uniquely named state machines that clean up after themselves.
+//!
+//! A [`StateMachineFixture`] creates its machine as `<base name>-<unique suffix>`, so tests can
+//! share a long-lived endpoint and run concurrently. The machine is tagged with
+//! [`MOCK_CONFIG_NAME_TAG`], so the `sfn-local` binary runs it with the mock config test cases of
+//! `<base name>`. Dropping the fixture, including while a test panics, stops the running
+//! executions and deletes the machine.
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use serde_json::Value;
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+use crate::asl::Definition;
+use crate::mock::MockConfig;
+use crate::remote;
+use crate::server::MOCK_CONFIG_NAME_TAG;
+
+/// How long dropping a fixture waits for the cleanup when it cannot use the current runtime.
+const DROP_CLEANUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct StateMachineFixture {
+    client: aws_sdk_sfn::Client,
+    base_name: String,
+    name: String,
+    arn: String,
+    cleaned_up: bool,
+}
+
+impl StateMachineFixture {
+    pub async fn create(
+        client: &aws_sdk_sfn::Client,
+        base_name: &str,
+        definition: &Definition,
+        role_arn: &str,
+    ) -> Result<Self, crate::Error> {
+        let name = unique_name(base_name);
+        let tags = [(MOCK_CONFIG_NAME_TAG, base_name)];
+        let arn =
+            remote::create_tagged_state_machine(client, &name, definition, role_arn, &tags).await?;
+
+        return Ok(Self {
+            client: client.clone(),
+            base_name: base_name.to_string(),
+            name,
+            arn,
+            cleaned_up: false,
+        });
+    }
+
+    pub fn client(&self) -> &aws_sdk_sfn::Client {
+        return &self.client;
+    }
+
+    /// The name the mock config knows the machine by.
+    pub fn base_name(&self) -> &str {
+        return &self.base_name;
+    }
+
+    pub fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    pub fn arn(&self) -> &str {
+        return &self.arn;
+    }
+
+    /// The `arn#TestCase` ARN that starts an execution with the mocks of `test_case`.
+    pub fn test_case_arn(&self, test_case: &str) -> String {
+        return format!("{}#{}", self.arn, test_case);
+    }
+
+    /// The test case ARN of every test case configured for the base name, by test case name.
+    pub fn test_case_arns(&self, mock_config: &MockConfig) -> BTreeMap<String, String> {
+        return mock_config
+            .state_machine(&self.base_name)
+            .map(|state_machine| {
+                state_machine
+                    .test_cases
+                    .keys()
+                    .map(|test_case| (test_case.clone(), self.test_case_arn(test_case)))
+                    .collect()
+            })
+            .unwrap_or_default();
+    }
+
+    /// Starts an execution, with the mocks of `test_case` if given, and returns its ARN.
+    pub async fn start_execution(
+        &self,
+        test_case: Option<&str>,
+        input: &Value,
+    ) -> Result<String, crate::Error> {
+        let arn = match test_case {
+            Some(test_case) => self.test_case_arn(test_case),
+            None => self.arn.clone(),
+        };
+
+        return remote::start_execution(&self.client, &arn, input).await;
+    }
+
+    /// Cleans up now and reports errors, instead of logging them when dropped.
+    pub async fn cleanup(mut self) -> Result<(), crate::Error> {
+        self.cleaned_up = true;
+
+        return cleanup(&self.client, &self.arn).await;
+    }
+}
+
+impl Drop for StateMachineFixture {
+    /// `Drop` cannot be async, so this blocks until the cleanup is done. On a multi-threaded
+    /// runtime the cleanup runs on it, and an in-process server keeps answering on the other
+    /// workers. Otherwise it runs on a separate thread with its own runtime, for at most
+    /// [`DROP_CLEANUP_TIMEOUT`]: a single-threaded runtime is blocked meanwhile, so a server on it
+    /// cannot answer.
+    fn drop(&mut self) {
+        if self.cleaned_up {
+            return;
+        }
+
+        let result = match Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| handle.block_on(cleanup(&self.client, &self.arn)))
+            }
+            _ => cleanup_on_thread(self.client.clone(), self.arn.clone()),
+        };
+        if let Err(error) = result {
+            eprintln!("Failed to clean up {}: {}", self.arn, error);
+        }
+    }
+}
+
+async fn cleanup(client: &aws_sdk_sfn::Client, arn: &str) -> Result<(), crate::Error> {
+    remote::stop_running_executions(client, arn).await?;
+    remote::delete_state_machine(client, arn).await?;
+
+    return Ok(());
+}
+
+fn cleanup_on_thread(client: aws_sdk_sfn::Client, arn: String) -> Result<(), crate::Error> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let result = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(crate::Error::from)
+            .and_then(|runtime| runtime.block_on(cleanup(&client, &arn)));
+        let _ = sender.send(result);
+    });
+
+    return match receiver.recv_timeout(DROP_CLEANUP_TIMEOUT) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(format!(
+            "the cleanup did not finish within {:?}",
+            DROP_CLEANUP_TIMEOUT
+        )
+        .into()),
+        Err(RecvTimeoutError::Disconnected) => Err("the cleanup panicked".into()),
+    };
+}
+
+fn unique_name(base_name: &str) -> String {
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+
+    // State machine names are limited to 80 characters.
+    return format!("{}-{}", base_name, &suffix[..12]);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::AssertUnwindSafe;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::executor::Executor;
+    use crate::history::ExecutionStatus;
+    use crate::server::{self, StepFunctions};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn deletes_the_machine_and_stops_executions_when_dropped() {
+        let mock_config: MockConfig = serde_json::from_value(json!({
+            "StateMachines": { "Waiter": { "TestCases": { "Quick": {}, "Slow": {} } } }
+        }))
+        .unwrap();
+        let step_functions =
+            StepFunctions::new(Executor::new().with_mock_config(mock_config.clone()));
+        let address =
+            server::spawn("127.0.0.1:0".parse().unwrap(), step_functions.clone()).unwrap();
+        let client = remote::local_client(&format!("http://{}", address)).unwrap();
+        let definition = Definition::from_value(json!({
+            "StartAt": "Wait",
+            "States": { "Wait": { "Type": "Wait", "Seconds": 3600, "End": true } }
+        }))
+        .unwrap();
+
+        let first = StateMachineFixture::create(&client, "Waiter", &definition, "role")
+            .await
+            .unwrap();
+        let second = StateMachineFixture::create(&client, "Waiter", &definition, "role")
+            .await
+            .unwrap();
+        assert_ne!(first.name(), second.name());
+        assert_eq!(
+            first
+                .test_case_arns(&mock_config)
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["Quick", "Slow"]
+        );
+
+        let execution_arn = first
+            .start_execution(Some("Slow"), &json!({}))
+            .await
+            .unwrap();
+        let arn = first.arn().to_string();
+        let panicked = std::panic::catch_unwind(AssertUnwindSafe(move || {
+            let _first = first;
+            panic!("a failing test");
+        }));
+        assert!(panicked.is_err());
+
+        // StopExecution returns before the execution has recorded that it was aborted.
+        let mut status = Value::Null;
+        for _ in 0..50 {
+            let execution = step_functions
+                .dispatch(
+                    "DescribeExecution",
+                    json!({ "executionArn": execution_arn }),
+                )
+                .unwrap();
+            status = execution["status"].clone();
+            if status != ExecutionStatus::Running.as_str() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(status, ExecutionStatus::Aborted.as_str());
+        let deleted =
+            step_functions.dispatch("DescribeStateMachine", json!({ "stateMachineArn": arn }));
+        assert_eq!(deleted.unwrap_err().code, "StateMachineDoesNotExist");
+
+        second.cleanup().await.unwrap();
+    }
+}
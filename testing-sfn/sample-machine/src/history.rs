@@ -0,0 +1,293 @@
+//! Executions and their event histories, modelled after `DescribeExecution` and
+//! `GetExecutionHistory` so that local and remote executions can be inspected the same way.
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::asl::{from_document, StatesError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ExecutionStatus {
+    Running,
+    Succeeded,
+    Failed,
+    TimedOut,
+    Aborted,
+}
+
+impl ExecutionStatus {
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            ExecutionStatus::Running => "RUNNING",
+            ExecutionStatus::Succeeded => "SUCCEEDED",
+            ExecutionStatus::Failed => "FAILED",
+            ExecutionStatus::TimedOut => "TIMED_OUT",
+            ExecutionStatus::Aborted => "ABORTED",
+        };
+    }
+
+    pub fn is_finished(&self) -> bool {
+        return *self != ExecutionStatus::Running;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Execution {
+    pub execution_arn: String,
+    pub state_machine_arn: String,
+    pub name: String,
+    pub status: ExecutionStatus,
+    pub start_date: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_date: Option<f64>,
+    pub input: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cause: Option<String>,
+    #[serde(default)]
+    pub events: Vec<HistoryEvent>,
+    /// How often the execution was redriven.
+    #[serde(default)]
+    pub redrive_count: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redrive_date: Option<f64>,
+}
+
+impl Execution {
+    pub fn failure(&self) -> Option<StatesError> {
+        return self
+            .error
+            .as_ref()
+            .map(|error| StatesError::new(error.clone(), self.cause.clone().unwrap_or_default()));
+    }
+
+    /// The executions started by the execution's `states:startExecution` tasks, in order.
+    pub fn nested_execution_arns(&self) -> Vec<String> {
+        return self
+            .events
+            .iter()
+            .filter(|event| {
+                event.event_type == "TaskSubmitted"
+                    && event.details.resource_type.as_deref() == Some("states")
+            })
+            .filter_map(|event| from_document(event.details.output.as_deref()).ok())
+            .filter_map(|output| Some(output["ExecutionArn"].as_str()?.to_string()))
+            .collect();
+    }
+
+    /// The states entered during the execution, in order.
+    pub fn visited_states(&self) -> Vec<String> {
+        return self
+            .events
+            .iter()
+            .filter(|event| event.event_type.ends_with("StateEntered"))
+            .filter_map(|event| event.details.name.clone())
+            .collect();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEvent {
+    pub id: i64,
+    pub previous_event_id: i64,
+    /// Seconds since the Unix epoch, as in the AWS JSON protocol.
+    pub timestamp: f64,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(default)]
+    pub details: EventDetails,
+}
+
+/// The union of the `*EventDetails` structures the executor emits. Only the fields that apply
+/// to an event's type are set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cause: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role_arn: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_in_seconds: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heartbeat_in_seconds: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redrive_count: Option<u32>,
+    /// The variables a state set, as JSON documents, on `*StateExited` events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assigned_variables: Option<BTreeMap<String, String>>,
+}
+
+impl HistoryEvent {
+    /// The `*EventDetails` member this event's details live under in the AWS JSON protocol.
+    pub fn details_member(event_type: &str) -> Option<String> {
+        if event_type.ends_with("StateEntered") {
+            return Some("stateEnteredEventDetails".to_string());
+        }
+        if event_type.ends_with("StateExited") {
+            return Some("stateExitedEventDetails".to_string());
+        }
+        if matches!(
+            event_type,
+            "ParallelStateStarted"
+                | "ParallelStateSucceeded"
+                | "ParallelStateAborted"
+                | "ParallelStateFailed"
+                | "MapStateSucceeded"
+                | "MapStateAborted"
+                | "MapStateFailed"
+                | "WaitStateAborted"
+        ) {
+            return None;
+        }
+
+        let mut characters = event_type.chars();
+        let first = characters.next()?.to_ascii_lowercase();
+
+        return Some(format!("{}{}EventDetails", first, characters.as_str()));
+    }
+
+    /// Renders the event as a `GetExecutionHistory` `HistoryEvent` structure.
+    pub fn to_aws_json(&self, include_execution_data: bool) -> Value {
+        let mut event = Map::new();
+        event.insert("id".to_string(), Value::from(self.id));
+        if self.previous_event_id > 0 {
+            event.insert(
+                "previousEventId".to_string(),
+                Value::from(self.previous_event_id),
+            );
+        }
+        event.insert("timestamp".to_string(), Value::from(self.timestamp));
+        event.insert("type".to_string(), Value::from(self.event_type.clone()));
+
+        if let Some(member) = Self::details_member(&self.event_type) {
+            let mut details = self.details.clone();
+            if !include_execution_data {
+                details.input = None;
+                details.output = None;
+                details.parameters = None;
+            }
+            event.insert(
+                member,
+                serde_json::to_value(details).expect("event details are always serializable"),
+            );
+        }
+
+        return Value::Object(event);
+    }
+}
+
+pub fn now() -> f64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs_f64())
+        .unwrap_or_default();
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 timestamp with millisecond precision, the
+/// way timestamps appear in the context object.
+pub fn to_rfc3339(seconds: f64) -> String {
+    let millis = (seconds * 1000.0).round() as i64;
+    let (days, millis_of_day) = (millis.div_euclid(86_400_000), millis.rem_euclid(86_400_000));
+
+    // Howard Hinnant's civil_from_days.
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    return format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        millis_of_day / 3_600_000,
+        millis_of_day / 60_000 % 60,
+        millis_of_day / 1000 % 60,
+        millis_of_day % 1000
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn renders_events_with_their_details_member() {
+        let event = HistoryEvent {
+            id: 2,
+            previous_event_id: 1,
+            timestamp: 1.5,
+            event_type: "TaskStateEntered".to_string(),
+            details: EventDetails {
+                name: Some("GetHtml".to_string()),
+                input: Some("{}".to_string()),
+                ..Default::default()
+            },
+        };
+
+        assert_eq!(
+            event.to_aws_json(true),
+            json!({
+                "id": 2,
+                "previousEventId": 1,
+                "timestamp": 1.5,
+                "type": "TaskStateEntered",
+                "stateEnteredEventDetails": { "name": "GetHtml", "input": "{}" }
+            })
+        );
+        assert_eq!(
+            HistoryEvent::details_member("LambdaFunctionScheduled").unwrap(),
+            "lambdaFunctionScheduledEventDetails"
+        );
+    }
+
+    #[test]
+    fn formats_timestamps_as_rfc3339() {
+        assert_eq!(to_rfc3339(0.0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(to_rfc3339(1_672_574_400.25), "2023-01-01T12:00:00.250Z");
+    }
+}
@@ -0,0 +1,174 @@
+//! The html-getter Lambda, which fetches a page and returns its size.
+//!
+//! The fetch gets the time left until the invocation deadline, minus a safety margin, so a slow
+//! site fails with an [`HtmlTimeout`] the state machine can `Catch` before Lambda kills the
+//! function.
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lambda_runtime::{Diagnostic, LambdaEvent};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Kept back from the invocation deadline to report a timeout before Lambda kills the function.
+const DEADLINE_MARGIN: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Input {
+    pub url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Output {
+    pub url: String,
+    pub size: usize,
+}
+
+/// The fetch did not finish within the invocation's budget.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct HtmlTimeout {
+    pub url: String,
+    pub bytes_read: usize,
+    pub budget_millis: u64,
+}
+
+/// The errors of the handler, reported with the `errorType` `HtmlTimeout` or `HtmlRequestFailed`.
+/// The `errorMessage` of a timeout is the JSON of [`HtmlTimeout`], for `States.StringToJson`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HtmlError {
+    Timeout(HtmlTimeout),
+    Request(String),
+}
+
+impl fmt::Display for HtmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            HtmlError::Timeout(timeout) => {
+                write!(
+                    f,
+                    "{}",
+                    serde_json::to_string(timeout).map_err(|_| fmt::Error)?
+                )
+            }
+            HtmlError::Request(message) => write!(f, "{}", message),
+        };
+    }
+}
+
+/// The runtime reports the `errorType` of the diagnostic, which `ErrorEquals` of `Retry` and
+/// `Catch` match.
+impl From<HtmlError> for Diagnostic {
+    fn from(error: HtmlError) -> Self {
+        let error_type = match error {
+            HtmlError::Timeout(_) => "HtmlTimeout",
+            HtmlError::Request(_) => "HtmlRequestFailed",
+        };
+
+        return Diagnostic {
+            error_type: error_type.to_string(),
+            error_message: error.to_string(),
+        };
+    }
+}
+
+/// The html-getter Lambda: fetches the page at `url` and returns its size.
+pub async fn handler(event: LambdaEvent<Input>) -> Result<Output, HtmlError> {
+    let budget = fetch_budget(event.context.deadline, SystemTime::now());
+    let size = get_html(&event.payload.url, budget).await?;
+    let output = Output {
+        url: event.payload.url,
+        size,
+    };
+
+    return Ok(output);
+}
+
+/// The time left until `deadline` (epoch milliseconds), minus the safety margin.
+fn fetch_budget(deadline: u64, now: SystemTime) -> Duration {
+    let deadline = UNIX_EPOCH + Duration::from_millis(deadline);
+
+    return deadline
+        .duration_since(now)
+        .unwrap_or_default()
+        .saturating_sub(DEADLINE_MARGIN);
+}
+
+/// Reads the page at `url` and returns its size, giving up once `budget` has passed.
+async fn get_html(url: &str, budget: Duration) -> Result<usize, HtmlError> {
+    let mut bytes_read = 0;
+    let fetch = async {
+        let mut response = reqwest::get(url).await?;
+        while let Some(chunk) = response.chunk().await? {
+            bytes_read += chunk.len();
+        }
+        return Ok::<(), reqwest::Error>(());
+    };
+    let fetched = tokio::time::timeout(budget, fetch).await;
+
+    return match fetched {
+        Ok(Ok(())) => Ok(bytes_read),
+        Ok(Err(error)) => Err(HtmlError::Request(error.to_string())),
+        Err(_) => Err(HtmlError::Timeout(HtmlTimeout {
+            url: url.to_string(),
+            bytes_read,
+            budget_millis: budget.as_millis() as u64,
+        })),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn the_fetch_budget_ends_before_the_deadline() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+
+        assert_eq!(fetch_budget(1_003_000, now), Duration::from_millis(2_500));
+        assert_eq!(fetch_budget(1_000_200, now), Duration::ZERO);
+        assert_eq!(fetch_budget(999_000, now), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn slow_sites_time_out_with_the_bytes_read_so_far() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 1000000\r\n\r\n")
+                .await
+                .unwrap();
+            socket.write_all(&[b'a'; 1000]).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        });
+
+        let error = get_html(&url, Duration::from_millis(300))
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error,
+            HtmlError::Timeout(HtmlTimeout {
+                url,
+                bytes_read: 1000,
+                budget_millis: 300,
+            })
+        );
+        let diagnostic = serde_json::to_value(Diagnostic::from(error)).unwrap();
+        assert_eq!(diagnostic["errorType"], "HtmlTimeout");
+        assert_eq!(
+            serde_json::from_str::<Value>(diagnostic["errorMessage"].as_str().unwrap()).unwrap()
+                ["bytesRead"],
+            1000
+        );
+        let diagnostic = Diagnostic::from(HtmlError::Request("connection refused".to_string()));
+        assert_eq!(diagnostic.error_type, "HtmlRequestFailed");
+    }
+}
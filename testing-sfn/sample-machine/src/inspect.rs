@@ -0,0 +1,470 @@
+//! Evaluating a single state on its own, like the Step Functions `TestState` API.
+//!
+//! An inspection runs one state against an input and, for Task, Parallel and Map states, a
+//! mocked result. Nothing is invoked and `Wait` states do not wait. The result holds the document
+//! after every processing stage, the variables after the state's `Assign`, the next state and
+//! the Retry or Catch decision for errors.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::asl::{Definition, QueryLanguage, State, StateKind, StatesError};
+use crate::executor::{evaluate_state, merge_values, task_parameters, Stage, StateRunner};
+use crate::history::{now, to_rfc3339};
+use crate::mock::MockedResult;
+
+/// What to inspect. This is also the JSON accepted by the `sfn-inspect` command.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InspectionRequest {
+    /// A single state, or a whole state machine together with `state_name`.
+    pub definition: Value,
+    /// The state to inspect in a state machine definition; defaults to its `StartAt`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_name: Option<String>,
+    #[serde(default = "InspectionRequest::default_input")]
+    pub input: Value,
+    /// Fields merged over the context object (`$$`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Value>,
+    /// The workflow variables in scope when the state is entered, as an object.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variables: Option<Value>,
+    /// The task (or Parallel/Map) result, in the mock config `Return`/`Throw` format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mock: Option<MockedResult>,
+    /// How many times the state has been retried already.
+    #[serde(default)]
+    pub retry_count: u32,
+}
+
+impl InspectionRequest {
+    pub fn new(definition: Value, input: Value) -> Self {
+        return Self {
+            definition,
+            state_name: None,
+            input,
+            context: None,
+            variables: None,
+            mock: None,
+            retry_count: 0,
+        };
+    }
+
+    fn default_input() -> Value {
+        return Value::Object(Map::new());
+    }
+
+    pub fn state_name(mut self, state_name: impl Into<String>) -> Self {
+        self.state_name = Some(state_name.into());
+        return self;
+    }
+
+    pub fn context(mut self, context: Value) -> Self {
+        self.context = Some(context);
+        return self;
+    }
+
+    pub fn variables(mut self, variables: Value) -> Self {
+        self.variables = Some(variables);
+        return self;
+    }
+
+    pub fn mock(mut self, mock: MockedResult) -> Self {
+        self.mock = Some(mock);
+        return self;
+    }
+
+    pub fn retry_count(mut self, retry_count: u32) -> Self {
+        self.retry_count = retry_count;
+        return self;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum InspectionStatus {
+    Succeeded,
+    Failed,
+    /// The state failed and one of its retriers would run it again.
+    Retriable,
+    /// The state failed and one of its catchers handled the error.
+    CaughtError,
+}
+
+/// The document after each processing stage. Stages that did not run are left out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InspectionData {
+    pub input: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after_input_path: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after_parameters: Option<Value>,
+    /// What a JSONata state's `Arguments` evaluated to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after_arguments: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after_result_selector: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after_result_path: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after_output_path: Option<Value>,
+    /// The variables in scope after the state, when there are any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variables: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryDecision {
+    /// The position of the matching retrier in `Retry`.
+    pub retrier_index: usize,
+    pub retry_count: u32,
+    pub delay_seconds: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CatchDecision {
+    /// The position of the matching catcher in `Catch`.
+    pub catcher_index: usize,
+    pub next_state: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inspection {
+    pub status: InspectionStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_state: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cause: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryDecision>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub catch: Option<CatchDecision>,
+    pub inspection_data: InspectionData,
+}
+
+/// Inspects the state described by `request`.
+///
+/// Errors raised by the state end up in the returned [`Inspection`]. An `Err` means the request
+/// itself is unusable: an invalid definition, an unknown state or a missing mocked result.
+pub fn inspect(request: &InspectionRequest) -> Result<Inspection, StatesError> {
+    if request.definition.get("States").is_some() {
+        let definition = Definition::from_value(request.definition.clone())?;
+        let name = request
+            .state_name
+            .as_deref()
+            .unwrap_or_else(|| definition.start_at());
+        let state = definition.state(name).ok_or_else(|| {
+            StatesError::runtime(format!("State '{}' does not exist in the definition", name))
+        })?;
+
+        let language = state.query_language(definition.query_language());
+        return inspect_with(&state, language, request);
+    }
+
+    let state = State {
+        name: request.state_name.as_deref().unwrap_or("State"),
+        value: &request.definition,
+    };
+
+    return inspect_state(&state, request);
+}
+
+/// Inspects `state`; the `definition` and `state_name` of the request are ignored.
+pub fn inspect_state(
+    state: &State<'_>,
+    request: &InspectionRequest,
+) -> Result<Inspection, StatesError> {
+    return inspect_with(
+        state,
+        state.query_language(QueryLanguage::default()),
+        request,
+    );
+}
+
+fn inspect_with(
+    state: &State<'_>,
+    language: QueryLanguage,
+    request: &InspectionRequest,
+) -> Result<Inspection, StatesError> {
+    let kind = state.kind().ok_or_else(|| {
+        StatesError::new(
+            "InvalidDefinition",
+            format!("state '{}' has an unknown Type", state.name),
+        )
+    })?;
+    let needs_result = matches!(kind, StateKind::Task | StateKind::Parallel | StateKind::Map);
+    if needs_result && request.mock.is_none() {
+        return Err(StatesError::runtime(format!(
+            "Inspecting the {:?} state '{}' requires a mocked result",
+            kind, state.name
+        )));
+    }
+
+    let context = state_context(state, request);
+    let mut inspector = Inspector {
+        request,
+        language,
+        data: InspectionData {
+            input: request.input.clone(),
+            ..Default::default()
+        },
+        retry: None,
+        caught: None,
+    };
+    let evaluated = futures::executor::block_on(evaluate_state(
+        &mut inspector,
+        state,
+        kind,
+        language,
+        request.input.clone(),
+        &context,
+        &request_variables(request),
+    ));
+
+    let mut inspection = Inspection {
+        status: InspectionStatus::Succeeded,
+        output: None,
+        next_state: None,
+        error: None,
+        cause: None,
+        retry: None,
+        catch: None,
+        inspection_data: inspector.data,
+    };
+    match evaluated {
+        Ok((output, next_state, assigned)) => {
+            inspection.inspection_data.variables = variables_after(request, assigned);
+            inspection.output = Some(output);
+            if let Some((catcher_index, error)) = inspector.caught {
+                inspection.status = InspectionStatus::CaughtError;
+                inspection.catch = Some(CatchDecision {
+                    catcher_index,
+                    next_state: next_state.clone().unwrap_or_default(),
+                });
+                inspection.error = Some(error.error);
+                inspection.cause = Some(error.cause);
+            }
+            inspection.next_state = next_state;
+        }
+        Err(error) => {
+            inspection.status = match inspector.retry {
+                Some(_) => InspectionStatus::Retriable,
+                None => InspectionStatus::Failed,
+            };
+            inspection.retry = inspector.retry;
+            inspection.error = Some(error.error);
+            inspection.cause = Some(error.cause);
+        }
+    }
+
+    return Ok(inspection);
+}
+
+fn state_context(state: &State<'_>, request: &InspectionRequest) -> Value {
+    let mut context = json!({
+        "Execution": { "Input": request.input },
+        "State": {
+            "Name": state.name,
+            "EnteredTime": to_rfc3339(now()),
+            "RetryCount": request.retry_count,
+        },
+    });
+    let resource = state.str_field("Resource").unwrap_or_default();
+    if resource.ends_with(".waitForTaskToken") {
+        context["Task"] = json!({ "Token": uuid::Uuid::new_v4().to_string() });
+    }
+    if let Some(overrides) = &request.context {
+        merge_values(&mut context, overrides);
+    }
+
+    return context;
+}
+
+/// Runs a state with the mocked result of the request, recording the document after each
+/// stage. An error that a retrier would retry fails the state instead of being caught.
+struct Inspector<'a> {
+    request: &'a InspectionRequest,
+    language: QueryLanguage,
+    data: InspectionData,
+    retry: Option<RetryDecision>,
+    caught: Option<(usize, StatesError)>,
+}
+
+#[async_trait]
+impl<'a> StateRunner for Inspector<'a> {
+    async fn attempt(
+        &mut self,
+        state: &State<'_>,
+        kind: StateKind,
+        effective: &Value,
+        context: &Value,
+        variables: &Map<String, Value>,
+    ) -> Result<Value, StatesError> {
+        if kind != StateKind::Map {
+            let parameters = task_parameters(state, self.language, effective, context, variables)?;
+            let stage = match self.language {
+                QueryLanguage::JsonPath => Stage::Parameters,
+                QueryLanguage::Jsonata => Stage::Arguments,
+            };
+            self.record(stage, &parameters);
+        }
+        let mock = self.request.mock.clone().expect("checked by inspect_with");
+
+        return mock.into_result();
+    }
+
+    async fn sleep(&mut self, _seconds: f64) {}
+
+    fn retries(&mut self, index: usize, retry_count: u32, delay_seconds: f64) -> bool {
+        self.retry = Some(RetryDecision {
+            retrier_index: index,
+            retry_count,
+            delay_seconds,
+        });
+        return false;
+    }
+
+    fn record(&mut self, stage: Stage, document: &Value) {
+        let field = match stage {
+            Stage::InputPath => &mut self.data.after_input_path,
+            Stage::Parameters => &mut self.data.after_parameters,
+            Stage::Arguments => &mut self.data.after_arguments,
+            Stage::Result => &mut self.data.result,
+            Stage::ResultSelector => &mut self.data.after_result_selector,
+            Stage::ResultPath => &mut self.data.after_result_path,
+            Stage::OutputPath => &mut self.data.after_output_path,
+        };
+        *field = Some(document.clone());
+    }
+
+    fn catches(&mut self, index: usize, error: &StatesError) -> bool {
+        if self.retry.is_some() {
+            return false;
+        }
+        self.caught = Some((index, error.clone()));
+        return true;
+    }
+}
+
+fn request_variables(request: &InspectionRequest) -> Map<String, Value> {
+    return request
+        .variables
+        .as_ref()
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default();
+}
+
+/// The variables of the request with `assigned` applied, or `None` when there are none.
+fn variables_after(request: &InspectionRequest, assigned: Map<String, Value>) -> Option<Value> {
+    let mut variables = request_variables(request);
+    variables.extend(assigned);
+
+    return (!variables.is_empty()).then_some(Value::Object(variables));
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock::MockedError;
+
+    fn order_definition() -> Value {
+        return serde_json::from_str(include_str!(
+            "../../complex-machine/state-machine-definition.asl.json"
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn records_every_stage_of_a_task() {
+        let request = InspectionRequest::new(order_definition(), json!({ "orderId": "42" }))
+            .state_name("Notify restaurant about the new order")
+            .context(json!({ "Task": { "Token": "token" } }))
+            .mock(MockedResult::Return(json!({ "accepted": true })));
+        let inspection = inspect(&request).unwrap();
+
+        assert_eq!(inspection.status, InspectionStatus::Succeeded);
+        assert_eq!(inspection.next_state.as_deref(), Some("Is order accepted?"));
+        assert_eq!(
+            inspection.inspection_data.after_parameters.unwrap()["Message"],
+            json!({ "orderId": "42", "taskToken": "token" })
+        );
+        assert_eq!(inspection.output, Some(json!({ "accepted": true })));
+    }
+
+    #[test]
+    fn decides_between_retrying_catching_and_failing() {
+        let state = json!({
+            "Type": "Task",
+            "Resource": "arn:aws:lambda:us-east-1:123456789012:function:GetHtml",
+            "Retry": [{ "ErrorEquals": ["Lambda.TooManyRequestsException"], "MaxAttempts": 2 }],
+            "Catch": [{ "ErrorEquals": ["States.ALL"], "Next": "Fallback", "ResultPath": "$.error" }],
+            "Next": "Done"
+        });
+        let throttled = InspectionRequest::new(state.clone(), json!({ "url": "x" })).mock(
+            MockedResult::Throw(MockedError {
+                error: "Lambda.TooManyRequestsException".to_string(),
+                cause: "slow down".to_string(),
+            }),
+        );
+
+        let retried = inspect(&throttled.clone().retry_count(1)).unwrap();
+        assert_eq!(retried.status, InspectionStatus::Retriable);
+        assert_eq!(retried.retry.unwrap().delay_seconds, 2.0);
+
+        let caught = inspect(&throttled.retry_count(2)).unwrap();
+        assert_eq!(caught.status, InspectionStatus::CaughtError);
+        assert_eq!(caught.next_state.as_deref(), Some("Fallback"));
+        assert_eq!(
+            caught.output.unwrap()["error"]["Error"],
+            "Lambda.TooManyRequestsException"
+        );
+
+        let missing = InspectionRequest::new(
+            json!({ "Type": "Pass", "InputPath": "$.nope", "End": true }),
+            json!({}),
+        );
+        let failed = inspect(&missing).unwrap();
+        assert_eq!(failed.status, InspectionStatus::Failed);
+        assert_eq!(failed.error.as_deref(), Some("States.Runtime"));
+        assert!(inspect(&InspectionRequest::new(state, json!({}))).is_err());
+    }
+
+    #[test]
+    fn evaluates_jsonata_states_with_variables() {
+        let state = json!({
+            "Type": "Task",
+            "QueryLanguage": "JSONata",
+            "Resource": "arn:aws:lambda:us-east-1:123456789012:function:GetHtml",
+            "Arguments": { "url": "{% $url %}" },
+            "Assign": { "size": "{% $states.result.size %}" },
+            "Output": "{% $states.result.size > $threshold %}",
+            "End": true
+        });
+        let request = InspectionRequest::new(state, json!({}))
+            .variables(json!({ "url": "https://example.com", "threshold": 10240 }))
+            .mock(MockedResult::Return(json!({ "size": 20480 })));
+        let inspection = inspect(&request).unwrap();
+
+        assert_eq!(
+            inspection.inspection_data.after_arguments,
+            Some(json!({ "url": "https://example.com" }))
+        );
+        assert_eq!(inspection.output, Some(json!(true)));
+        assert_eq!(inspection.inspection_data.variables.unwrap()["size"], 20480);
+    }
+}
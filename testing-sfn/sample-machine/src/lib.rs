@@ -0,0 +1,42 @@
+//! Local tooling for testing AWS Step Functions state machines.
+//!
+//! The crate ships a native Amazon States Language executor and an HTTP server that speaks the
+//! Step Functions AWS JSON 1.0 protocol, so `aws_sdk_sfn::Client` can talk to it the same way it
+//! talks to step-functions-local.
+
+#![allow(clippy::needless_return)]
+
+pub mod activity;
+pub mod asl;
+pub mod builder;
+pub mod callback;
+pub mod cases;
+pub mod contract;
+pub mod cost;
+pub mod coverage;
+pub mod diagram;
+pub mod differential;
+pub mod drift;
+pub mod executor;
+pub mod fixture;
+pub mod history;
+pub mod html;
+pub mod inspect;
+pub mod live;
+pub mod mock;
+pub mod mutation;
+pub mod nested;
+pub mod patch;
+pub mod property;
+pub mod recording;
+pub mod redrive;
+pub mod remote;
+pub mod server;
+pub mod serverless;
+pub mod snapshot;
+pub mod sub_machine;
+#[cfg(test)]
+mod test_fixtures;
+pub mod trace;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
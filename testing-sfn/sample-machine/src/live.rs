@@ -0,0 +1,241 @@
+//! Live execution events, for assertions made while an execution runs.
+//!
+//! Observers registered with [`Executor::with_observer`](crate::executor::Executor::with_observer)
+//! see every state entered and exited and every task scheduled, as it happens. The execution
+//! waits for each observer before it goes on, so an observer can hold it at a state while it
+//! checks the world, e.g. that an item was written before `Notify restaurant` runs. The
+//! scheduled event of a `.waitForTaskToken` or activity task carries the issued task token.
+//!
+//! Remote executions are observed by polling their history with [`watch_execution`]; they are
+//! not paused, and their events carry no task tokens.
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+
+use crate::history::{Execution, HistoryEvent};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveEvent {
+    pub execution_arn: String,
+    pub event: HistoryEvent,
+    /// The token issued to a `.waitForTaskToken` or activity task, on its scheduled event.
+    pub task_token: Option<String>,
+}
+
+impl LiveEvent {
+    pub fn is_state_entered(&self, state: &str) -> bool {
+        return self.event.event_type.ends_with("StateEntered")
+            && self.event.details.name.as_deref() == Some(state);
+    }
+
+    pub fn is_state_exited(&self, state: &str) -> bool {
+        return self.event.event_type.ends_with("StateExited")
+            && self.event.details.name.as_deref() == Some(state);
+    }
+}
+
+/// Whether a history event is one observers see.
+pub fn is_live(event_type: &str) -> bool {
+    return event_type.ends_with("StateEntered")
+        || event_type.ends_with("StateExited")
+        || event_type.ends_with("Scheduled");
+}
+
+/// Sees the live events of executions.
+#[async_trait]
+pub trait ExecutionObserver: Send + Sync {
+    async fn on_event(&self, event: &LiveEvent);
+}
+
+pub struct ObserverFn<F> {
+    f: F,
+}
+
+/// Wraps an async closure into an [`ExecutionObserver`], like
+/// [`handler_fn`](crate::executor::handler_fn) does for task handlers.
+pub fn observer_fn<F, Fut>(f: F) -> ObserverFn<F>
+where
+    F: Fn(LiveEvent) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send,
+{
+    return ObserverFn { f };
+}
+
+#[async_trait]
+impl<F, Fut> ExecutionObserver for ObserverFn<F>
+where
+    F: Fn(LiveEvent) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send,
+{
+    async fn on_event(&self, event: &LiveEvent) {
+        (self.f)(event.clone()).await;
+    }
+}
+
+/// Forwards live events to the receiver returned by [`event_stream`], without pausing.
+pub struct StreamObserver {
+    sender: mpsc::UnboundedSender<LiveEvent>,
+}
+
+#[async_trait]
+impl ExecutionObserver for StreamObserver {
+    async fn on_event(&self, event: &LiveEvent) {
+        // Nobody listens any more once the receiver is dropped.
+        let _ = self.sender.unbounded_send(event.clone());
+    }
+}
+
+/// An observer and the stream of the events it sees.
+pub fn event_stream() -> (StreamObserver, mpsc::UnboundedReceiver<LiveEvent>) {
+    let (sender, receiver) = mpsc::unbounded();
+
+    return (StreamObserver { sender }, receiver);
+}
+
+/// Polls the history of a remote execution and passes its live events to `observer`, in order,
+/// until it finishes or `timeout` passes. Returns the execution as last fetched.
+pub async fn watch_execution(
+    client: &aws_sdk_sfn::Client,
+    execution_arn: &str,
+    observer: &dyn ExecutionObserver,
+    timeout: Duration,
+) -> Result<Execution, crate::Error> {
+    let deadline = Instant::now() + timeout;
+    let mut seen = HashSet::new();
+    loop {
+        let execution = crate::remote::describe_execution(client, execution_arn).await?;
+        for event in observe(&execution, &mut seen) {
+            observer.on_event(&event).await;
+        }
+        if execution.status.is_finished() || Instant::now() >= deadline {
+            return Ok(execution);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// The live events of `execution` whose ids are not in `seen` yet.
+fn observe(execution: &Execution, seen: &mut HashSet<i64>) -> Vec<LiveEvent> {
+    return execution
+        .events
+        .iter()
+        .filter(|event| is_live(&event.event_type) && seen.insert(event.id))
+        .map(|event| LiveEvent {
+            execution_arn: execution.execution_arn.clone(),
+            event: event.clone(),
+            task_token: None,
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use futures::StreamExt;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::asl::{Definition, StatesError};
+    use crate::executor::{handler_fn, ExecutionRequest, Executor, TaskRequest};
+
+    #[tokio::test]
+    async fn observers_pause_executions_while_they_assert() {
+        let definition = Definition::from_value(json!({
+            "StartAt": "Save order",
+            "States": {
+                "Save order": { "Type": "Task", "Resource": "arn:aws:states:::dynamodb:putItem", "Next": "Notify restaurant" },
+                "Notify restaurant": { "Type": "Task", "Resource": "arn:aws:states:::sns:publish", "End": true }
+            }
+        }))
+        .unwrap();
+        let table = Arc::new(Mutex::new(None));
+        let checks = Arc::new(Mutex::new(Vec::new()));
+
+        let (saved, checked, seen) = (table.clone(), checks.clone(), checks.clone());
+        let executor = Executor::new()
+            .with_handler(
+                "arn:aws:states:::dynamodb:putItem",
+                handler_fn(move |_: TaskRequest| {
+                    let saved = saved.clone();
+                    async move {
+                        *saved.lock().unwrap() = Some("ORDERED");
+                        return Ok::<Value, StatesError>(json!({}));
+                    }
+                }),
+            )
+            .with_handler(
+                "arn:aws:states:::sns:publish",
+                handler_fn(move |_: TaskRequest| {
+                    let seen = seen.clone();
+                    async move { return Ok(json!({ "checked": seen.lock().unwrap().len() })) }
+                }),
+            )
+            .with_observer(observer_fn(move |event: LiveEvent| {
+                let (table, checked) = (table.clone(), checked.clone());
+                async move {
+                    if event.is_state_entered("Notify restaurant") {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        checked.lock().unwrap().push(*table.lock().unwrap());
+                    }
+                }
+            }));
+
+        let execution = executor.execute(&definition, json!({})).await;
+
+        assert_eq!(*checks.lock().unwrap(), [Some("ORDERED")]);
+        assert_eq!(execution.output, Some(json!({ "checked": 1 })));
+    }
+
+    #[tokio::test]
+    async fn streams_events_with_their_task_tokens() {
+        let definition = Definition::from_value(json!({
+            "StartAt": "Approve",
+            "States": {
+                "Approve": {
+                    "Type": "Task",
+                    "Resource": "arn:aws:states:::sqs:sendMessage.waitForTaskToken",
+                    "Parameters": { "token.$": "$$.Task.Token" },
+                    "End": true
+                }
+            }
+        }))
+        .unwrap();
+        let (observer, mut events) = event_stream();
+        let executor = Executor::new()
+            .with_handler(
+                "arn:aws:states:::sqs:*",
+                handler_fn(|_: TaskRequest| async { return Ok(json!({})) }),
+            )
+            .with_observer(observer);
+
+        let handle = executor.start(ExecutionRequest::new(definition, json!({})));
+        let mut types = Vec::new();
+        while let Some(event) = events.next().await {
+            types.push(event.event.event_type.clone());
+            if let Some(token) = &event.task_token {
+                executor
+                    .send_task_success(token, json!({ "approved": true }))
+                    .unwrap();
+            }
+            if event.is_state_exited("Approve") {
+                break;
+            }
+        }
+
+        assert_eq!(
+            types,
+            ["TaskStateEntered", "TaskScheduled", "TaskStateExited"]
+        );
+        assert_eq!(
+            handle.wait().await.output,
+            Some(json!({ "approved": true }))
+        );
+    }
+}
@@ -1,49 +1,52 @@
-use lambda_runtime::{service_fn, LambdaEvent};
-use serde::{Deserialize, Serialize};
+#![allow(clippy::needless_return)]
+
+use lambda_runtime::service_fn;
+use sample_machine::html;
 
 #[tokio::main]
 async fn main() -> Result<(), lambda_runtime::Error> {
-    let func = service_fn(handler);
+    let func = service_fn(html::handler);
     lambda_runtime::run(func).await?;
 
     return Ok(());
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Input {
-    pub url: String,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct Output {
-    pub url: String,
-    pub size: usize,
-}
-
-async fn handler(event: LambdaEvent<Input>) -> Result<Output, lambda_runtime::Error> {
-    let bytes = reqwest::get(&event.payload.url).await?.bytes().await?;
-    let output = Output {
-        url: event.payload.url,
-        size: bytes.len(),
-    };
-
-    return Ok(output);
-}
-
 #[cfg(test)]
 mod tests {
     use std::env;
 
     use aws_sdk_sfn::Region;
-    use tokio_retry::{strategy, Retry};
+    use sample_machine::asl::Definition;
+    use sample_machine::cases::{CasesReport, Expectations, TestCases};
+    use sample_machine::contract::{self, Contract};
+    use sample_machine::differential::DifferentialReport;
+    use sample_machine::drift;
+    use sample_machine::executor::Executor;
+    use sample_machine::fixture::StateMachineFixture;
+    use sample_machine::html::{Input, Output};
+    use sample_machine::mock::MockConfig;
+    use sample_machine::serverless::ServerlessStateMachine;
+
+    #[test]
+    fn the_definition_reads_what_the_handler_returns() {
+        let state_machine = ServerlessStateMachine::from_file("state_machines/simple.yml").unwrap();
+
+        Contract::new(&state_machine.definition)
+            .input(contract::schema_of::<Input>())
+            .task_output("GetHtml", contract::schema_of::<Output>())
+            .check()
+            .assert_ok();
+    }
 
-    use crate::assert_machine_status;
+    /// The deployed machine, read from `.env-outputs`, and a client for sfn-local.
+    struct Deployed {
+        definition: Definition,
+        role_arn: String,
+        local_sfn_client: aws_sdk_sfn::Client,
+    }
 
-    #[tokio::test]
-    async fn tests_the_machine_via_sfn_local() {
-        dotenv::from_filename(".env-outputs")
-            .ok()
-            .expect("Failed to load .env-outputs");
+    async fn deployed() -> Deployed {
+        dotenv::from_filename(".env-outputs").expect("Failed to load .env-outputs");
 
         let config = aws_config::load_from_env().await;
         let sfn_config = aws_sdk_sfn::config::Builder::from(&config)
@@ -51,14 +54,12 @@ mod tests {
             .build();
         let sfn_client = aws_sdk_sfn::Client::from_conf(sfn_config);
 
-        let local_sfn_config = aws_sdk_sfn::config::Builder::from(&config)
-            .endpoint_resolver(
-                aws_sdk_sfn::Endpoint::immutable("http://localhost:8083")
-                    .expect("Invalid endpoint"),
-            )
-            .region(Region::new(env::var("AwsRegion").unwrap()))
-            .build();
-        let local_sfn_client = aws_sdk_sfn::Client::from_conf(local_sfn_config);
+        let local_sfn_client = sample_machine::remote::endpoint_client(
+            &config,
+            &env::var("AwsRegion").unwrap(),
+            "http://localhost:8083",
+        )
+        .expect("Invalid endpoint");
 
         let current_state_machine = sfn_client
             .describe_state_machine()
@@ -67,111 +68,77 @@ mod tests {
             .await
             .unwrap();
 
-        let create_local_state_machine_response = local_sfn_client
-            .create_state_machine()
-            .definition(current_state_machine.definition.unwrap())
-            .name("SimpleExample")
-            .role_arn(current_state_machine.role_arn.unwrap())
-            .send()
-            .await
-            .unwrap();
+        return Deployed {
+            definition: Definition::from_json(current_state_machine.definition().unwrap()).unwrap(),
+            role_arn: current_state_machine.role_arn().unwrap().to_string(),
+            local_sfn_client,
+        };
+    }
 
-        let is_big_html_arn = format!(
-            "{}#IsBigPath",
-            create_local_state_machine_response
-                .state_machine_arn()
-                .unwrap()
-        );
-        let start_is_big_html_response = local_sfn_client
-            .start_execution()
-            .state_machine_arn(is_big_html_arn)
-            .input(r#"{"url": "https://www.rust-lang.org/"}"#)
-            .send()
-            .await
-            .unwrap();
+    fn mock_config() -> MockConfig {
+        return MockConfig::from_file("src/sfn-local-mock.json").unwrap();
+    }
 
-        let is_big_html_result =
-            Retry::spawn(strategy::FixedInterval::from_millis(500).take(4), || {
-                assert_machine_status(
-                    &local_sfn_client,
-                    start_is_big_html_response.execution_arn().unwrap(),
-                    aws_sdk_sfn::model::ExecutionStatus::Succeeded,
-                )
-            })
-            .await;
+    fn cases() -> TestCases {
+        let expectations = Expectations::from_file("src/sfn-local-expectations.json").unwrap();
 
-        assert_eq!(is_big_html_result.unwrap(), "true".to_string());
+        return TestCases::new("SimpleExample", Some(&mock_config()), Some(&expectations));
+    }
 
-        let is_small_html_arn = format!(
-            "{}#IsNotBigPath",
-            create_local_state_machine_response
-                .state_machine_arn()
-                .unwrap()
+    async fn run_test_case(state_machine: &str, case: &str) -> CasesReport {
+        assert_eq!(
+            state_machine, "SimpleExample",
+            "only SimpleExample is deployed"
         );
-        let start_is_small_html_response = local_sfn_client
-            .start_execution()
-            .state_machine_arn(is_small_html_arn)
-            .input(r#"{"url": "https://www.rust-lang.org/"}"#)
-            .send()
-            .await
-            .unwrap();
+        let deployed = deployed().await;
+        let local_state_machine = StateMachineFixture::create(
+            &deployed.local_sfn_client,
+            state_machine,
+            &deployed.definition,
+            &deployed.role_arn,
+        )
+        .await
+        .unwrap();
+        let report = cases().only(case).run_fixture(&local_state_machine).await;
+        local_state_machine.cleanup().await.unwrap();
 
-        let is_small_html_result =
-            Retry::spawn(strategy::FixedInterval::from_millis(500).take(4), || {
-                assert_machine_status(
-                    &local_sfn_client,
-                    start_is_small_html_response.execution_arn().unwrap(),
-                    aws_sdk_sfn::model::ExecutionStatus::Succeeded,
-                )
-            })
-            .await;
-        assert_eq!(is_small_html_result.unwrap(), "false".to_string());
+        return report;
+    }
 
-        let is_error_html_arn = format!(
-            "{}#GetHtmlError",
-            create_local_state_machine_response
-                .state_machine_arn()
-                .unwrap()
-        );
-        let is_error_html_response = local_sfn_client
-            .start_execution()
-            .state_machine_arn(is_error_html_arn)
-            .input(r#"{"url": "https://www.rust-lang.org/"}"#)
-            .send()
-            .await
-            .unwrap();
+    include!(concat!(env!("OUT_DIR"), "/sfn_local_test_cases.rs"));
 
-        let is_error_html_result =
-            Retry::spawn(strategy::FixedInterval::from_millis(500).take(4), || {
-                assert_machine_status(
-                    &local_sfn_client,
-                    is_error_html_response.execution_arn().unwrap(),
-                    aws_sdk_sfn::model::ExecutionStatus::Failed,
-                )
-            })
-            .await;
+    #[tokio::test]
+    async fn the_deployed_machine_matches_the_configured_one() {
+        let deployed = deployed().await;
+        let configured_state_machine =
+            ServerlessStateMachine::from_file("state_machines/simple.yml").unwrap();
 
-        assert_eq!(is_error_html_result.is_err(), false);
-        assert_eq!(is_error_html_result.unwrap(), "".to_string())
+        drift::compare_serverless(&configured_state_machine, &deployed.definition).assert_none();
     }
-}
 
-async fn assert_machine_status(
-    client: &aws_sdk_sfn::Client,
-    arn: &str,
-    status: aws_sdk_sfn::model::ExecutionStatus,
-) -> Result<String, ()> {
-    let execution = client
-        .describe_execution()
-        .execution_arn(arn)
-        .send()
+    #[tokio::test]
+    async fn sfn_local_agrees_with_the_native_executor() {
+        let deployed = deployed().await;
+        let local_state_machine = StateMachineFixture::create(
+            &deployed.local_sfn_client,
+            "SimpleExample",
+            &deployed.definition,
+            &deployed.role_arn,
+        )
         .await
         .unwrap();
-
-    if execution.status.clone().unwrap() == status {
-        let execution_output = execution.output().unwrap_or("").to_string();
-        return Ok(execution_output);
+        let cases = cases();
+        let sfn_local_report = cases.run_fixture(&local_state_machine).await;
+
+        // The emulator behind localhost:8083 is a reimplementation too; cross-check it.
+        let native_report = cases
+            .run_local(
+                &Executor::new().with_mock_config(mock_config()),
+                &deployed.definition,
+            )
+            .await;
+        local_state_machine.cleanup().await.unwrap();
+        DifferentialReport::compare("native", &native_report, "sfn-local", &sfn_local_report)
+            .assert_no_divergence();
     }
-
-    return Err(());
 }
@@ -0,0 +1,220 @@
+//! The step-functions-local mock configuration format (`SFN_MOCK_CONFIG`).
+//!
+//! Test cases map state names to mocked responses. A mocked response maps invocation indexes
+//! (`"0"`, or ranges such as `"1-3"`) to either a `Return` value or a `Throw` error.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::asl::StatesError;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MockConfig {
+    #[serde(default)]
+    pub state_machines: BTreeMap<String, MockedStateMachine>,
+    #[serde(default)]
+    pub mocked_responses: BTreeMap<String, MockedResponse>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MockedStateMachine {
+    /// Test case name to a map of state name to mocked response name.
+    #[serde(default)]
+    pub test_cases: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Invocation index (or range) to the result of that invocation.
+pub type MockedResponse = BTreeMap<String, MockedResult>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MockedResult {
+    Return(Value),
+    Throw(MockedError),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MockedError {
+    pub error: String,
+    #[serde(default)]
+    pub cause: String,
+}
+
+impl MockedResult {
+    pub fn into_result(self) -> Result<Value, StatesError> {
+        return match self {
+            MockedResult::Return(value) => Ok(value),
+            MockedResult::Throw(error) => Err(StatesError::new(error.error, error.cause)),
+        };
+    }
+}
+
+impl MockConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let contents = std::fs::read_to_string(path)?;
+
+        return Ok(serde_json::from_str(&contents)?);
+    }
+
+    /// Writes the configuration as pretty-printed JSON.
+    pub fn to_file(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        std::fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+
+        return Ok(());
+    }
+
+    /// Adds a mocked response, replacing the one of the same name.
+    pub fn set_response(&mut self, name: impl Into<String>, response: MockedResponse) {
+        self.mocked_responses.insert(name.into(), response);
+    }
+
+    /// Mocks `state` with the response `response` in a test case, adding the state machine and
+    /// the test case if needed.
+    pub fn set_test_case_state(
+        &mut self,
+        state_machine: &str,
+        test_case: &str,
+        state: &str,
+        response: &str,
+    ) {
+        self.state_machines
+            .entry(state_machine.to_string())
+            .or_default()
+            .test_cases
+            .entry(test_case.to_string())
+            .or_default()
+            .insert(state.to_string(), response.to_string());
+    }
+
+    /// Reads the file named by the `SFN_MOCK_CONFIG` environment variable, like
+    /// step-functions-local does.
+    pub fn from_env() -> Result<Option<Self>, crate::Error> {
+        return match std::env::var("SFN_MOCK_CONFIG") {
+            Ok(path) => Ok(Some(Self::from_file(path)?)),
+            Err(_) => Ok(None),
+        };
+    }
+
+    /// The mocks of a state machine, by its exact name like step-functions-local.
+    pub fn state_machine(&self, name: &str) -> Option<&MockedStateMachine> {
+        return self.state_machines.get(name);
+    }
+
+    pub fn test_case(
+        &self,
+        state_machine: &str,
+        test_case: &str,
+    ) -> Option<&BTreeMap<String, String>> {
+        return self.state_machine(state_machine)?.test_cases.get(test_case);
+    }
+
+    /// The mocked result for the `invocation`-th (zero based) call of a mocked response.
+    pub fn response(&self, name: &str, invocation: usize) -> Result<MockedResult, StatesError> {
+        let response = self.mocked_responses.get(name).ok_or_else(|| {
+            StatesError::runtime(format!("Mocked response '{}' does not exist", name))
+        })?;
+
+        for (key, result) in response {
+            let (start, end) = match key.split_once('-') {
+                Some((start, end)) => (start.trim().parse(), end.trim().parse()),
+                None => (key.trim().parse(), key.trim().parse()),
+            };
+            if let (Ok(start), Ok(end)) = (start, end) {
+                let (start, end): (usize, usize) = (start, end);
+                if (start..=end).contains(&invocation) {
+                    return Ok(result.clone());
+                }
+            }
+        }
+
+        return Err(StatesError::runtime(format!(
+            "Mocked response '{}' has no result for invocation {}",
+            name, invocation
+        )));
+    }
+}
+
+/// The mocked state results for a single execution of a test case.
+#[derive(Debug, Clone, Default)]
+pub struct MockedTestCase {
+    config: MockConfig,
+    states: BTreeMap<String, String>,
+    invocations: HashMap<String, usize>,
+}
+
+impl MockedTestCase {
+    pub fn new(config: &MockConfig, state_machine: &str, test_case: &str) -> Option<Self> {
+        let states = config.test_case(state_machine, test_case)?.clone();
+
+        return Some(Self {
+            config: config.clone(),
+            states,
+            invocations: HashMap::new(),
+        });
+    }
+
+    pub fn mocks_state(&self, state: &str) -> bool {
+        return self.states.contains_key(state);
+    }
+
+    /// Returns the next mocked result for `state`, or `None` if the state is not mocked.
+    pub fn next_result(&mut self, state: &str) -> Option<Result<Value, StatesError>> {
+        let response = self.states.get(state)?;
+        let invocation = self.invocations.entry(state.to_string()).or_insert(0);
+        let result = self.config.response(response, *invocation);
+        *invocation += 1;
+
+        return Some(result.and_then(MockedResult::into_result));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn reads_the_sample_machine_mock_config() {
+        let config: MockConfig = serde_json::from_str(include_str!("sfn-local-mock.json")).unwrap();
+        let mut test_case = MockedTestCase::new(&config, "SimpleExample", "GetHtmlError").unwrap();
+
+        assert_eq!(
+            test_case.next_result("GetHtml").unwrap(),
+            Err(StatesError::new(
+                "Lambda.ResourceNotReadyException",
+                "Lambda is not ready"
+            ))
+        );
+        assert!(test_case.next_result("IsHtmlBig?").is_none());
+        assert!(MockedTestCase::new(&config, "SimpleExample", "Nope").is_none());
+    }
+
+    #[test]
+    fn picks_results_by_invocation_range() {
+        let config: MockConfig = serde_json::from_value(json!({
+            "StateMachines": { "M": { "TestCases": { "Retry": { "Task": "Flaky" } } } },
+            "MockedResponses": {
+                "Flaky": {
+                    "0-1": { "Throw": { "Error": "Lambda.TooManyRequestsException", "Cause": "" } },
+                    "2": { "Return": { "ok": true } }
+                }
+            }
+        }))
+        .unwrap();
+        let mut test_case = MockedTestCase::new(&config, "M", "Retry").unwrap();
+
+        assert!(test_case.next_result("Task").unwrap().is_err());
+        assert!(test_case.next_result("Task").unwrap().is_err());
+        assert_eq!(
+            test_case.next_result("Task").unwrap(),
+            Ok(json!({ "ok": true }))
+        );
+        assert!(test_case.next_result("Task").unwrap().is_err());
+    }
+}
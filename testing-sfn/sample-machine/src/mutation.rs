@@ -0,0 +1,493 @@
+//! Mutation testing of state machine definitions.
+//!
+//! Mutants are copies of a definition with one plausible mistake each: a flipped comparison
+//! (`NumericGreaterThan` to `NumericLessThan` or `NumericGreaterThanEquals`), a threshold off by
+//! one, a negated or recombined Choice rule, a `Next`, `Default` or `Catch` target pointing
+//! elsewhere, a swapped `Default`, or a deleted `Catch` or `Retry` clause. Every mutant runs the
+//! test cases on the native executor; a mutant that passes all of them survived, which means the
+//! cases would not catch that mistake.
+
+use std::fmt;
+
+use serde_json::{json, Value};
+
+use crate::asl::{choice, Definition};
+use crate::cases::TestCases;
+use crate::executor::Executor;
+
+/// Comparisons and what each one may be mistaken for, without the `String`/`Numeric`/`Timestamp`
+/// prefix.
+const FLIPPED_COMPARISONS: &[(&str, &str)] = &[
+    ("GreaterThan", "LessThan"),
+    ("GreaterThan", "GreaterThanEquals"),
+    ("LessThan", "GreaterThan"),
+    ("LessThan", "LessThanEquals"),
+    ("GreaterThanEquals", "LessThanEquals"),
+    ("GreaterThanEquals", "GreaterThan"),
+    ("LessThanEquals", "GreaterThanEquals"),
+    ("LessThanEquals", "LessThan"),
+];
+const COMPARISON_FAMILIES: &[&str] = &["String", "Numeric", "Timestamp"];
+/// Fields that hold nested definitions.
+const NESTED_DEFINITIONS: &[&str] = &["Branches", "ItemProcessor", "Iterator"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mutation {
+    /// The mutated state, e.g. `IsHtmlBig?` or `Both/Branches[1]/GetHtml`.
+    pub state: String,
+    /// What changed, e.g. `Choices[0].NumericGreaterThan: 10240 -> 10241`.
+    pub description: String,
+}
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}: {}", self.state, self.description);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mutant {
+    pub mutation: Mutation,
+    pub definition: Definition,
+}
+
+/// Every valid single-mistake mutant of a definition, including the states of `Parallel`
+/// branches and `Map` processors.
+pub fn mutants(definition: &Definition) -> Vec<Mutant> {
+    let mut mutants = Vec::new();
+    collect(
+        definition.as_value(),
+        definition.as_value(),
+        None,
+        "",
+        &mut mutants,
+    );
+
+    return mutants;
+}
+
+fn collect(
+    root: &Value,
+    scope: &Value,
+    prefix: Option<&str>,
+    pointer: &str,
+    mutants: &mut Vec<Mutant>,
+) {
+    let states = match scope["States"].as_object() {
+        Some(states) => states,
+        None => return,
+    };
+    let names: Vec<&str> = states.keys().map(String::as_str).collect();
+
+    for (name, state) in states {
+        let label = match prefix {
+            Some(prefix) => format!("{}/{}", prefix, name),
+            None => name.clone(),
+        };
+        let state_pointer = format!("{}/States/{}", pointer, escape(name));
+        for (description, mutated) in state_mutations(name, state, &names) {
+            let mut document = root.clone();
+            if let Some(target) = document.pointer_mut(&state_pointer) {
+                *target = mutated;
+            }
+            // Mutants that break the definition would be rejected before any test runs.
+            if let Ok(definition) = Definition::from_value(document) {
+                mutants.push(Mutant {
+                    mutation: Mutation {
+                        state: label.clone(),
+                        description,
+                    },
+                    definition,
+                });
+            }
+        }
+
+        for key in NESTED_DEFINITIONS {
+            match &state[*key] {
+                Value::Array(branches) => {
+                    for (index, branch) in branches.iter().enumerate() {
+                        collect(
+                            root,
+                            branch,
+                            Some(&format!("{}/{}[{}]", label, key, index)),
+                            &format!("{}/{}/{}", state_pointer, key, index),
+                            mutants,
+                        );
+                    }
+                }
+                Value::Object(_) => collect(
+                    root,
+                    &state[*key],
+                    Some(&format!("{}/{}", label, key)),
+                    &format!("{}/{}", state_pointer, key),
+                    mutants,
+                ),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn state_mutations(name: &str, state: &Value, names: &[&str]) -> Vec<(String, Value)> {
+    let mut mutations = Vec::new();
+
+    let rules = state["Choices"].as_array().cloned().unwrap_or_default();
+    for (index, rule) in rules.iter().enumerate() {
+        for (description, mutated) in rule_mutations(rule, &format!("Choices[{}]", index)) {
+            let mut mutant = state.clone();
+            mutant["Choices"][index] = mutated;
+            mutations.push((description, mutant));
+        }
+    }
+    if let Some(default) = state["Default"].as_str() {
+        for (index, rule) in rules.iter().enumerate() {
+            let next = rule["Next"].as_str().unwrap_or_default();
+            if next != default {
+                let mut mutant = state.clone();
+                mutant["Default"] = json!(next);
+                mutant["Choices"][index]["Next"] = json!(default);
+                mutations.push((format!("Default swapped with Choices[{}].Next", index), mutant));
+            }
+        }
+    }
+
+    let mut transitions = vec![("Next".to_string(), "/Next".to_string())];
+    transitions.push(("Default".to_string(), "/Default".to_string()));
+    for index in 0..rules.len() {
+        transitions.push((
+            format!("Choices[{}].Next", index),
+            format!("/Choices/{}/Next", index),
+        ));
+    }
+    for index in 0..state["Catch"].as_array().map_or(0, Vec::len) {
+        transitions.push((
+            format!("Catch[{}].Next", index),
+            format!("/Catch/{}/Next", index),
+        ));
+    }
+    for (field, pointer) in transitions {
+        let current = match state.pointer(&pointer).and_then(Value::as_str) {
+            Some(current) => current,
+            None => continue,
+        };
+        for target in names {
+            if *target == current || *target == name {
+                continue;
+            }
+            let mut mutant = state.clone();
+            if let Some(next) = mutant.pointer_mut(&pointer) {
+                *next = json!(target);
+            }
+            mutations.push((format!("{}: {} -> {}", field, current, target), mutant));
+        }
+    }
+
+    for field in ["Catch", "Retry"] {
+        for index in 0..state[field].as_array().map_or(0, Vec::len) {
+            let mut mutant = state.clone();
+            if let Some(clauses) = mutant[field].as_array_mut() {
+                clauses.remove(index);
+                if clauses.is_empty() {
+                    if let Some(object) = mutant.as_object_mut() {
+                        object.remove(field);
+                    }
+                }
+            }
+            mutations.push((format!("{}[{}] removed", field, index), mutant));
+        }
+    }
+
+    return mutations;
+}
+
+/// Mutations of a (possibly nested) Choice rule. `field` names the rule, e.g. `Choices[0].And[1]`.
+fn rule_mutations(rule: &Value, field: &str) -> Vec<(String, Value)> {
+    let mut mutations = Vec::new();
+
+    for (combinator, other) in [("And", "Or"), ("Or", "And")] {
+        let rules = match rule.get(combinator).and_then(Value::as_array) {
+            Some(rules) => rules,
+            None => continue,
+        };
+        let mut swapped = rule.clone();
+        if let Some(object) = swapped.as_object_mut() {
+            let rules = object.remove(combinator).unwrap_or_default();
+            object.insert(other.to_string(), rules);
+        }
+        mutations.push((format!("{}: {} -> {}", field, combinator, other), swapped));
+        for (index, nested) in rules.iter().enumerate() {
+            let nested_field = format!("{}.{}[{}]", field, combinator, index);
+            for (description, mutated) in rule_mutations(nested, &nested_field) {
+                let mut mutant = rule.clone();
+                mutant[combinator][index] = mutated;
+                mutations.push((description, mutant));
+            }
+        }
+        return mutations;
+    }
+
+    if let Some(nested) = rule.get("Not") {
+        let mut unwrapped = nested.clone();
+        if let Some(next) = rule.get("Next") {
+            unwrapped["Next"] = next.clone();
+        }
+        mutations.push((format!("{}: Not removed", field), unwrapped));
+        for (description, mutated) in rule_mutations(nested, &format!("{}.Not", field)) {
+            let mut mutant = rule.clone();
+            mutant["Not"] = mutated;
+            mutations.push((description, mutant));
+        }
+        return mutations;
+    }
+
+    let mut condition = rule.clone();
+    let next = condition
+        .as_object_mut()
+        .and_then(|object| object.remove("Next"));
+    let mut negated = json!({ "Not": condition });
+    if let Some(next) = next {
+        negated["Next"] = next;
+    }
+    mutations.push((format!("{}: negated", field), negated));
+
+    for operator in choice::COMPARISON_OPERATORS {
+        for key in [operator.to_string(), format!("{}Path", operator)] {
+            let expected = match rule.get(&key) {
+                Some(expected) => expected,
+                None => continue,
+            };
+
+            let family = COMPARISON_FAMILIES
+                .iter()
+                .find(|family| operator.starts_with(*family));
+            if let Some(family) = family {
+                let comparison = &operator[family.len()..];
+                for (from, to) in FLIPPED_COMPARISONS {
+                    if *from != comparison {
+                        continue;
+                    }
+                    let flipped = format!("{}{}{}", family, to, &key[operator.len()..]);
+                    let mut mutant = rule.clone();
+                    if let Some(object) = mutant.as_object_mut() {
+                        object.remove(&key);
+                        object.insert(flipped.clone(), expected.clone());
+                    }
+                    mutations.push((format!("{}: {} -> {}", field, key, flipped), mutant));
+                }
+            }
+
+            if key != *operator {
+                continue;
+            }
+            let values = match expected {
+                Value::Number(number) => match number.as_i64() {
+                    Some(number) => vec![
+                        json!(number.saturating_add(1)),
+                        json!(number.saturating_sub(1)),
+                    ],
+                    None => number
+                        .as_f64()
+                        .map(|number| vec![json!(number + 1.0), json!(number - 1.0)])
+                        .unwrap_or_default(),
+                },
+                Value::Bool(boolean) => vec![json!(!boolean)],
+                Value::String(string) if operator.starts_with("String") => {
+                    vec![json!(format!("{}-mutated", string))]
+                }
+                _ => Vec::new(),
+            };
+            for value in values {
+                let mut mutant = rule.clone();
+                mutant[&key] = value.clone();
+                mutations.push((
+                    format!("{}.{}: {} -> {}", field, key, expected, value),
+                    mutant,
+                ));
+            }
+        }
+    }
+
+    return mutations;
+}
+
+/// Escapes a JSON pointer segment.
+fn escape(segment: &str) -> String {
+    return segment.replace('~', "~0").replace('/', "~1");
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MutantResult {
+    pub mutation: Mutation,
+    /// The cases that failed on the mutant; empty when it survived.
+    pub killed_by: Vec<String>,
+}
+
+impl MutantResult {
+    pub fn survived(&self) -> bool {
+        return self.killed_by.is_empty();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MutationReport {
+    pub state_machine: String,
+    pub results: Vec<MutantResult>,
+}
+
+impl MutationReport {
+    pub fn survived(&self) -> Vec<&MutantResult> {
+        return self
+            .results
+            .iter()
+            .filter(|result| result.survived())
+            .collect();
+    }
+
+    /// The share of killed mutants, `1.0` when there are none.
+    pub fn score(&self) -> f64 {
+        if self.results.is_empty() {
+            return 1.0;
+        }
+
+        return (self.results.len() - self.survived().len()) as f64 / self.results.len() as f64;
+    }
+
+    /// Panics with the report if any mutant survived.
+    #[track_caller]
+    pub fn assert_all_killed(&self) {
+        let survived = self.survived().len();
+        if survived > 0 {
+            panic!(
+                "{} of {} mutants of {} survived the test cases\n\n{}",
+                survived,
+                self.results.len(),
+                self.state_machine,
+                self
+            );
+        }
+    }
+}
+
+impl fmt::Display for MutationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} of {} mutants killed ({:.0}%)",
+            self.state_machine,
+            self.results.len() - self.survived().len(),
+            self.results.len(),
+            self.score() * 100.0
+        )?;
+        for result in self.survived() {
+            write!(f, "\n  survived: {}", result.mutation)?;
+        }
+
+        return Ok(());
+    }
+}
+
+/// Runs the cases on every mutant of the definition. The executor needs the mock config of the
+/// cases. Fails if the cases do not pass on the definition itself.
+pub async fn run(
+    definition: &Definition,
+    cases: &TestCases,
+    executor: &Executor,
+) -> Result<MutationReport, crate::Error> {
+    let baseline = cases.run_local(executor, definition).await;
+    if !baseline.failed().is_empty() {
+        return Err(format!(
+            "The test cases must pass on the original definition before mutation testing\n{}",
+            baseline
+        )
+        .into());
+    }
+
+    let mut results = Vec::new();
+    for mutant in mutants(definition) {
+        let report = cases.run_local(executor, &mutant.definition).await;
+        results.push(MutantResult {
+            mutation: mutant.mutation,
+            killed_by: report
+                .failed()
+                .iter()
+                .map(|result| result.name.clone())
+                .collect(),
+        });
+    }
+
+    return Ok(MutationReport {
+        state_machine: cases.state_machine().to_string(),
+        results,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cases::Expectations;
+    use crate::coverage;
+    use crate::mock::MockConfig;
+    use crate::test_fixtures::simple_definition;
+
+    #[test]
+    fn mutates_choices_transitions_and_catchers() {
+        let mutations: Vec<String> = mutants(&simple_definition())
+            .iter()
+            .map(|mutant| mutant.mutation.to_string())
+            .collect();
+
+        for expected in [
+            "IsHtmlBig?: Choices[0]: negated",
+            "IsHtmlBig?: Choices[0]: NumericGreaterThan -> NumericLessThan",
+            "IsHtmlBig?: Choices[0]: NumericGreaterThan -> NumericGreaterThanEquals",
+            "IsHtmlBig?: Choices[0].NumericGreaterThan: 10240 -> 10241",
+            "IsHtmlBig?: Choices[0].NumericGreaterThan: 10240 -> 10239",
+            "IsHtmlBig?: Default swapped with Choices[0].Next",
+            "IsHtmlBig?: Default: IsNotBig -> Dunno",
+            "GetHtml: Next: IsHtmlBig? -> IsBig",
+            "GetHtml: Catch[0].Next: Dunno -> IsNotBig",
+            "GetHtml: Catch[0] removed",
+        ] {
+            assert!(mutations.contains(&expected.to_string()), "{}", expected);
+        }
+        assert!(!mutations
+            .iter()
+            .any(|mutation| mutation.starts_with("GetHtml") && mutation.contains("-> GetHtml")));
+    }
+
+    #[tokio::test]
+    async fn reports_the_mutants_the_cases_miss() {
+        let config: MockConfig = serde_json::from_str(include_str!("sfn-local-mock.json")).unwrap();
+        let expectations: Expectations =
+            serde_json::from_str(include_str!("sfn-local-expectations.json")).unwrap();
+        let cases = TestCases::new("SimpleExample", Some(&config), Some(&expectations));
+        let executor = Executor::new().skip_waits(true).with_mock_config(config);
+
+        let report = run(&simple_definition(), &cases, &executor).await.unwrap();
+        let survived: Vec<String> = report
+            .survived()
+            .iter()
+            .map(|result| result.mutation.to_string())
+            .collect();
+        assert!(survived.contains(
+            &"IsHtmlBig?: Choices[0].NumericGreaterThan: 10240 -> 10241".to_string()
+        ));
+        assert!(std::panic::catch_unwind(|| report.assert_all_killed()).is_err());
+
+        // The generated boundary cases catch the off-by-one thresholds.
+        let coverage = coverage::generate(&simple_definition(), "SimpleExample").await;
+        let cases = TestCases::new(
+            "SimpleExample",
+            Some(&coverage.mock_config),
+            Some(&coverage.expectations),
+        );
+        let executor = Executor::new()
+            .skip_waits(true)
+            .with_mock_config(coverage.mock_config.clone());
+        let report = run(&simple_definition(), &cases, &executor).await.unwrap();
+        assert!(report
+            .survived()
+            .iter()
+            .all(|result| !result.mutation.description.contains("NumericGreaterThan")));
+    }
+}
@@ -0,0 +1,265 @@
+//! Nested executions: Task states with the `arn:aws:states:::states:startExecution` resource.
+//!
+//! The child state machine must be registered with the executor under the `StateMachineArn`
+//! the task passes. Plain `startExecution` returns as soon as the child started; `.sync` and
+//! `.sync:2` wait for it and return its `DescribeExecution` result, with `Input` and `Output` as
+//! JSON strings for `.sync` and as JSON values for `.sync:2`. A child that does not succeed
+//! fails the task with `States.TaskFailed`, its description (including its `Error` and `Cause`)
+//! as the cause. The parent's `TaskSubmitted` event holds the child's `ExecutionArn`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value};
+
+use crate::asl::{from_document, to_document, Definition, StatesError, STATES_TASK_FAILED};
+use crate::executor::ExecutionHandle;
+use crate::history::{Execution, ExecutionStatus};
+
+/// The service integration action, before its `.sync`/`.sync:2` suffix.
+pub const START_EXECUTION: &str = "startExecution";
+pub const STATE_MACHINE_DOES_NOT_EXIST: &str = "StepFunctions.StateMachineDoesNotExistException";
+
+/// The state machines tasks can start and the executions they started.
+#[derive(Clone, Default)]
+pub(crate) struct NestedExecutions {
+    state_machines: Arc<Mutex<HashMap<String, Definition>>>,
+    executions: Arc<Mutex<HashMap<String, ExecutionHandle>>>,
+}
+
+impl NestedExecutions {
+    pub(crate) fn register(&self, state_machine_arn: &str, definition: Definition) {
+        self.state_machines
+            .lock()
+            .unwrap()
+            .insert(state_machine_arn.to_string(), definition);
+    }
+
+    pub(crate) fn deregister(&self, state_machine_arn: &str) {
+        self.state_machines
+            .lock()
+            .unwrap()
+            .remove(state_machine_arn);
+    }
+
+    pub(crate) fn definition(&self, state_machine_arn: &str) -> Result<Definition, StatesError> {
+        return self
+            .state_machines
+            .lock()
+            .unwrap()
+            .get(state_machine_arn)
+            .cloned()
+            .ok_or_else(|| {
+                StatesError::new(
+                    STATE_MACHINE_DOES_NOT_EXIST,
+                    format!("State Machine Does Not Exist: '{}'", state_machine_arn),
+                )
+            });
+    }
+
+    pub(crate) fn track(&self, handle: &ExecutionHandle) {
+        self.executions
+            .lock()
+            .unwrap()
+            .insert(handle.execution_arn(), handle.clone());
+    }
+
+    pub(crate) fn execution(&self, execution_arn: &str) -> Option<ExecutionHandle> {
+        return self.executions.lock().unwrap().get(execution_arn).cloned();
+    }
+}
+
+/// The `StartExecution` call a task's parameters describe.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StartRequest {
+    pub state_machine_arn: String,
+    pub input: Value,
+    pub name: Option<String>,
+}
+
+impl StartRequest {
+    /// `Input` may be a JSON value or a JSON document string, and defaults to `{}`.
+    pub(crate) fn from_parameters(parameters: &Value) -> Result<Self, StatesError> {
+        let state_machine_arn = parameters["StateMachineArn"].as_str().ok_or_else(|| {
+            StatesError::runtime("startExecution needs a StateMachineArn string parameter")
+        })?;
+        let input = match &parameters["Input"] {
+            Value::Null => json!({}),
+            Value::String(document) => from_document(Some(document.as_str()))?,
+            input => input.clone(),
+        };
+
+        return Ok(Self {
+            state_machine_arn: state_machine_arn.to_string(),
+            input,
+            name: parameters["Name"].as_str().map(str::to_string),
+        });
+    }
+}
+
+/// Stops the child of a `.sync` task when the parent stops waiting for it, e.g. because the
+/// parent was stopped or the task timed out.
+pub(crate) struct StopOnDrop(pub ExecutionHandle);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        if !self.0.is_finished() {
+            self.0
+                .stop(None, Some("The parent execution stopped".to_string()));
+        }
+    }
+}
+
+/// What a plain `startExecution` task returns.
+pub(crate) fn started_output(execution: &Execution) -> Value {
+    return json!({
+        "ExecutionArn": execution.execution_arn,
+        "StartDate": millis(execution.start_date),
+    });
+}
+
+/// What a `.sync` (`version_2 == false`) or `.sync:2` task returns for a finished child.
+pub(crate) fn sync_output(execution: &Execution, version_2: bool) -> Result<Value, StatesError> {
+    let document = |value: &Value| {
+        return match version_2 {
+            true => value.clone(),
+            false => Value::from(to_document(value)),
+        };
+    };
+    let mut output = json!({
+        "ExecutionArn": execution.execution_arn,
+        "Input": document(&execution.input),
+        "InputDetails": { "Included": true },
+        "Name": execution.name,
+        "StartDate": millis(execution.start_date),
+        "StateMachineArn": execution.state_machine_arn,
+        "Status": execution.status.as_str(),
+    });
+    if let Some(stop_date) = execution.stop_date {
+        output["StopDate"] = Value::from(millis(stop_date));
+    }
+    if let Some(value) = &execution.output {
+        output["Output"] = document(value);
+        output["OutputDetails"] = json!({ "Included": true });
+    }
+    if execution.status == ExecutionStatus::Succeeded {
+        return Ok(output);
+    }
+
+    if let Some(error) = &execution.error {
+        output["Error"] = Value::from(error.clone());
+    }
+    if let Some(cause) = &execution.cause {
+        output["Cause"] = Value::from(cause.clone());
+    }
+
+    return Err(StatesError::new(STATES_TASK_FAILED, to_document(&output)));
+}
+
+fn millis(seconds: f64) -> i64 {
+    return (seconds * 1000.0).round() as i64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{state_machine_arn, Executor, DEFAULT_ACCOUNT_ID, DEFAULT_REGION};
+
+    fn executor() -> Executor {
+        let child = Definition::from_value(json!({
+            "StartAt": "CheckSize",
+            "States": {
+                "CheckSize": {
+                    "Type": "Choice",
+                    "Choices": [{ "Variable": "$.size", "NumericGreaterThan": 10240, "Next": "TooBig" }],
+                    "Default": "Accept"
+                },
+                "Accept": { "Type": "Pass", "Parameters": { "accepted.$": "$.size" }, "End": true },
+                "TooBig": { "Type": "Fail", "Error": "Order.TooBig", "Cause": "The order is too big" }
+            }
+        }))
+        .unwrap();
+
+        return Executor::new().with_state_machine(&child_arn(), child);
+    }
+
+    fn child_arn() -> String {
+        return state_machine_arn(DEFAULT_REGION, DEFAULT_ACCOUNT_ID, "CheckOrder");
+    }
+
+    fn parent(resource: &str) -> Definition {
+        return Definition::from_value(json!({
+            "StartAt": "CheckOrder",
+            "States": {
+                "CheckOrder": {
+                    "Type": "Task",
+                    "Resource": resource,
+                    "Parameters": {
+                        "StateMachineArn": child_arn(),
+                        "Name": "child",
+                        "Input": { "size.$": "$.size" }
+                    },
+                    "Catch": [{ "ErrorEquals": ["States.TaskFailed"], "ResultPath": "$.error", "Next": "Rejected" }],
+                    "End": true
+                },
+                "Rejected": { "Type": "Pass", "End": true }
+            }
+        }))
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn sync_tasks_return_the_child_execution() {
+        let executor = executor();
+
+        let sync_2 = parent("arn:aws:states:::states:startExecution.sync:2");
+        let execution = executor.execute(&sync_2, json!({ "size": 512 })).await;
+        let output = execution.output.as_ref().unwrap();
+        assert_eq!(output["Status"], "SUCCEEDED");
+        assert_eq!(output["Output"], json!({ "accepted": 512 }));
+        assert_eq!(output["Input"], json!({ "size": 512 }));
+
+        let child_arn = output["ExecutionArn"].as_str().unwrap();
+        assert_eq!(execution.nested_execution_arns(), [child_arn]);
+        let child = executor.nested_execution(child_arn).unwrap().snapshot();
+        assert_eq!(child.name, "child");
+        assert_eq!(child.visited_states(), ["CheckSize", "Accept"]);
+
+        let sync = parent("arn:aws:states:::states:startExecution.sync");
+        let output = executor
+            .execute(&sync, json!({ "size": 512 }))
+            .await
+            .output
+            .unwrap();
+        assert_eq!(output["Output"], r#"{"accepted":512}"#);
+    }
+
+    #[tokio::test]
+    async fn child_failures_fail_the_task() {
+        let executor = executor();
+        let sync = parent("arn:aws:states:::states:startExecution.sync");
+
+        let output = executor
+            .execute(&sync, json!({ "size": 20480 }))
+            .await
+            .output
+            .unwrap();
+        assert_eq!(output["error"]["Error"], STATES_TASK_FAILED);
+        let cause: Value =
+            serde_json::from_str(output["error"]["Cause"].as_str().unwrap()).unwrap();
+        assert_eq!(cause["Status"], "FAILED");
+        assert_eq!(cause["Error"], "Order.TooBig");
+        assert_eq!(cause["Cause"], "The order is too big");
+
+        let started = parent("arn:aws:states:::states:startExecution");
+        let output = executor
+            .execute(&started, json!({ "size": 20480 }))
+            .await
+            .output
+            .unwrap();
+        assert!(output["ExecutionArn"]
+            .as_str()
+            .unwrap()
+            .ends_with(":CheckOrder:child"));
+    }
+}
@@ -0,0 +1,67 @@
+#![allow(clippy::needless_return)]
+
+use std::env;
+
+use lambda_runtime::{service_fn, LambdaEvent};
+use sample_machine::asl::StatesError;
+use sample_machine::callback::{decide_fn, CallbackWorker, Decision, TaskTokenMessage};
+use sample_machine::executor::DEFAULT_REGION;
+use sample_machine::remote;
+use serde_json::{json, Value};
+
+/**
+ * Answers the `.waitForTaskToken` tasks of the order workflow from the SNS or SQS messages
+ * carrying `{orderId, taskToken}`, returning the failed SQS messages as `batchItemFailures`.
+ * `ORDER_DECISION` is `accept` (the default), `reject` (succeeds with `"accepted": false`),
+ * `fail` (`SendTaskFailure` with `Order.Failed`) or `ignore`.
+ * Set `SFN_ENDPOINT`, e.g. `http://localhost:8083`, to answer a local SFN endpoint.
+ */
+
+#[tokio::main]
+async fn main() -> Result<(), lambda_runtime::Error> {
+    let decision = env::var("ORDER_DECISION").unwrap_or("accept".to_string());
+    if !["accept", "reject", "fail", "ignore"].contains(&decision.as_str()) {
+        return Err(format!("Unknown ORDER_DECISION '{}'", decision).into());
+    }
+
+    let config = aws_config::load_from_env().await;
+    let client = match env::var("SFN_ENDPOINT") {
+        Ok(endpoint) => {
+            let region = config
+                .region()
+                .map(|region| region.to_string())
+                .unwrap_or(DEFAULT_REGION.to_string());
+            remote::endpoint_client(&config, &region, &endpoint)?
+        }
+        Err(_) => aws_sdk_sfn::Client::new(&config),
+    };
+    let worker = CallbackWorker::new(
+        client,
+        decide_fn(move |message: TaskTokenMessage| {
+            let decision = decision.clone();
+            async move { return decide(&decision, &message) }
+        }),
+    );
+
+    let worker = &worker;
+    let func = service_fn(move |event: LambdaEvent<Value>| async move {
+        return worker.handle_event(&event.payload).await?.response();
+    });
+    lambda_runtime::run(func).await?;
+
+    return Ok(());
+}
+
+fn decide(decision: &str, message: &TaskTokenMessage) -> Decision {
+    let order_id = message.message["orderId"].clone();
+
+    return match decision {
+        "accept" => Decision::Accept(json!({ "orderId": order_id, "accepted": true })),
+        "reject" => Decision::Accept(json!({ "orderId": order_id, "accepted": false })),
+        "fail" => Decision::Reject(StatesError::new(
+            "Order.Failed",
+            format!("Order {} could not be handled", order_id),
+        )),
+        _ => Decision::Ignore,
+    };
+}
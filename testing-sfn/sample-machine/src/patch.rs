@@ -0,0 +1,378 @@
+//! Rewriting definitions for tests: shorter timeouts and waits, swapped Task resources, Retry
+//! overrides and a different state machine name.
+//!
+//! Every patch names the state it changes and fails if that state does not exist or cannot take
+//! the change, so a patch cannot silently stop applying when the definition evolves. The applied
+//! patches are kept on the [`PatchedDefinition`] and listed by its `Display` implementation.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::asl::{Definition, Retrier, StateKind, StatesError};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "patch", rename_all = "camelCase")]
+pub enum Patch {
+    TimeoutSeconds {
+        state: String,
+        seconds: u64,
+    },
+    HeartbeatSeconds {
+        state: String,
+        seconds: u64,
+    },
+    /// The `Seconds` of a Wait state; replaces `SecondsPath`, `Timestamp` and `TimestampPath`.
+    WaitSeconds {
+        state: String,
+        seconds: u64,
+    },
+    /// Replaces a Task `Resource` in every state that uses it.
+    SwapResource {
+        from: String,
+        to: String,
+    },
+    /// Replaces the `Resource` of a single Task state.
+    StateResource {
+        state: String,
+        resource: String,
+    },
+    Retry {
+        state: String,
+        retriers: Vec<Retrier>,
+    },
+    Rename {
+        name: String,
+    },
+}
+
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Patch::TimeoutSeconds { state, seconds } => {
+                write!(f, "'{}': TimeoutSeconds = {}", state, seconds)
+            }
+            Patch::HeartbeatSeconds { state, seconds } => {
+                write!(f, "'{}': HeartbeatSeconds = {}", state, seconds)
+            }
+            Patch::WaitSeconds { state, seconds } => {
+                write!(f, "'{}': Seconds = {}", state, seconds)
+            }
+            Patch::SwapResource { from, to } => write!(f, "Resource {} -> {}", from, to),
+            Patch::StateResource { state, resource } => {
+                write!(f, "'{}': Resource = {}", state, resource)
+            }
+            Patch::Retry { state, retriers } => write!(
+                f,
+                "'{}': Retry = {}",
+                state,
+                serde_json::to_string(retriers).unwrap_or_default()
+            ),
+            Patch::Rename { name } => write!(f, "name = {}", name),
+        };
+    }
+}
+
+/// Collects patches for a definition; nothing is changed until [`DefinitionPatcher::apply`].
+#[derive(Debug, Clone)]
+pub struct DefinitionPatcher {
+    definition: Definition,
+    patches: Vec<Patch>,
+}
+
+impl DefinitionPatcher {
+    pub fn new(definition: Definition) -> Self {
+        return Self {
+            definition,
+            patches: Vec::new(),
+        };
+    }
+
+    pub fn timeout_seconds(self, state: impl Into<String>, seconds: u64) -> Self {
+        return self.patch(Patch::TimeoutSeconds {
+            state: state.into(),
+            seconds,
+        });
+    }
+
+    pub fn heartbeat_seconds(self, state: impl Into<String>, seconds: u64) -> Self {
+        return self.patch(Patch::HeartbeatSeconds {
+            state: state.into(),
+            seconds,
+        });
+    }
+
+    pub fn wait_seconds(self, state: impl Into<String>, seconds: u64) -> Self {
+        return self.patch(Patch::WaitSeconds {
+            state: state.into(),
+            seconds,
+        });
+    }
+
+    pub fn swap_resource(self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        return self.patch(Patch::SwapResource {
+            from: from.into(),
+            to: to.into(),
+        });
+    }
+
+    pub fn state_resource(self, state: impl Into<String>, resource: impl Into<String>) -> Self {
+        return self.patch(Patch::StateResource {
+            state: state.into(),
+            resource: resource.into(),
+        });
+    }
+
+    pub fn retry(self, state: impl Into<String>, retriers: Vec<Retrier>) -> Self {
+        return self.patch(Patch::Retry {
+            state: state.into(),
+            retriers,
+        });
+    }
+
+    pub fn rename(self, name: impl Into<String>) -> Self {
+        return self.patch(Patch::Rename { name: name.into() });
+    }
+
+    pub fn patch(mut self, patch: Patch) -> Self {
+        self.patches.push(patch);
+        return self;
+    }
+
+    /// Applies the patches in order. Fails on the first patch that does not apply.
+    pub fn apply(self) -> Result<PatchedDefinition, StatesError> {
+        let mut value = self.definition.clone().into_value();
+        let mut name = None;
+        for patch in &self.patches {
+            apply_patch(&mut value, patch, &mut name).map_err(|cause| {
+                StatesError::new("InvalidPatch", format!("{}: {}", patch, cause))
+            })?;
+        }
+
+        return Ok(PatchedDefinition {
+            definition: Definition::from_value(value)?,
+            original: self.definition,
+            name,
+            patches: self.patches,
+        });
+    }
+}
+
+/// A patched definition together with the patches that produced it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatchedDefinition {
+    pub definition: Definition,
+    pub original: Definition,
+    /// The state machine name set by [`DefinitionPatcher::rename`].
+    pub name: Option<String>,
+    pub patches: Vec<Patch>,
+}
+
+impl PatchedDefinition {
+    /// The patched name, or `default` if the machine was not renamed.
+    pub fn name_or<'a>(&'a self, default: &'a str) -> &'a str {
+        return self.name.as_deref().unwrap_or(default);
+    }
+}
+
+impl fmt::Display for PatchedDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.patches.is_empty() {
+            return write!(f, "unpatched definition");
+        }
+        write!(f, "definition patched with:")?;
+        for patch in &self.patches {
+            write!(f, "\n  - {}", patch)?;
+        }
+
+        return Ok(());
+    }
+}
+
+fn apply_patch(value: &mut Value, patch: &Patch, name: &mut Option<String>) -> Result<(), String> {
+    match patch {
+        Patch::TimeoutSeconds { state, seconds } => {
+            let target = state_of_kind(value, state, &[StateKind::Task])?;
+            target["TimeoutSeconds"] = Value::from(*seconds);
+            remove_fields(target, &["TimeoutSecondsPath"]);
+        }
+        Patch::HeartbeatSeconds { state, seconds } => {
+            let target = state_of_kind(value, state, &[StateKind::Task])?;
+            target["HeartbeatSeconds"] = Value::from(*seconds);
+            remove_fields(target, &["HeartbeatSecondsPath"]);
+        }
+        Patch::WaitSeconds { state, seconds } => {
+            let target = state_of_kind(value, state, &[StateKind::Wait])?;
+            target["Seconds"] = Value::from(*seconds);
+            remove_fields(target, &["SecondsPath", "Timestamp", "TimestampPath"]);
+        }
+        Patch::SwapResource { from, to } => {
+            if swap_resources(value, from, to) == 0 {
+                return Err(format!("no Task state uses the resource {}", from));
+            }
+        }
+        Patch::StateResource { state, resource } => {
+            let target = state_of_kind(value, state, &[StateKind::Task])?;
+            target["Resource"] = Value::from(resource.as_str());
+        }
+        Patch::Retry { state, retriers } => {
+            let kinds = [StateKind::Task, StateKind::Parallel, StateKind::Map];
+            let target = state_of_kind(value, state, &kinds)?;
+            target["Retry"] = serde_json::to_value(retriers).map_err(|error| error.to_string())?;
+        }
+        Patch::Rename { name: new_name } => *name = Some(new_name.clone()),
+    }
+
+    return Ok(());
+}
+
+fn state_of_kind<'a>(
+    value: &'a mut Value,
+    name: &str,
+    kinds: &[StateKind],
+) -> Result<&'a mut Value, String> {
+    let state = find_state(value, name).ok_or_else(|| "the state does not exist".to_string())?;
+    let kind: Option<StateKind> = serde_json::from_value(state["Type"].clone()).ok();
+    if !kind.is_some_and(|kind| kinds.contains(&kind)) {
+        return Err(format!("the state is not a {:?} state", kinds));
+    }
+
+    return Ok(state);
+}
+
+/// Finds a state by name, including states nested in Parallel branches and Map processors.
+fn find_state<'a>(definition: &'a mut Value, name: &str) -> Option<&'a mut Value> {
+    let states = definition.get_mut("States")?.as_object_mut()?;
+    if states.contains_key(name) {
+        return states.get_mut(name);
+    }
+
+    return states
+        .values_mut()
+        .flat_map(nested_definitions)
+        .find_map(|nested| find_state(nested, name));
+}
+
+fn nested_definitions(state: &mut Value) -> Vec<&mut Value> {
+    let state = match state.as_object_mut() {
+        Some(state) => state,
+        None => return Vec::new(),
+    };
+
+    return state
+        .iter_mut()
+        .flat_map(|(key, value)| match (key.as_str(), value) {
+            ("Branches", Value::Array(branches)) => branches.iter_mut().collect(),
+            ("ItemProcessor" | "Iterator", processor) => vec![processor],
+            _ => Vec::new(),
+        })
+        .collect();
+}
+
+fn swap_resources(definition: &mut Value, from: &str, to: &str) -> usize {
+    let states = match definition.get_mut("States").and_then(Value::as_object_mut) {
+        Some(states) => states,
+        None => return 0,
+    };
+
+    let mut swapped = 0;
+    for state in states.values_mut() {
+        if state["Type"] == "Task" && state["Resource"] == from {
+            state["Resource"] = Value::from(to);
+            swapped += 1;
+        }
+        for nested in nested_definitions(state) {
+            swapped += swap_resources(nested, from, to);
+        }
+    }
+
+    return swapped;
+}
+
+fn remove_fields(state: &mut Value, fields: &[&str]) {
+    if let Some(state) = state.as_object_mut() {
+        for field in fields {
+            state.remove(*field);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn order_definition() -> Definition {
+        return Definition::from_json(include_str!(
+            "../../complex-machine/state-machine-definition.asl.json"
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn patches_nested_states_and_records_every_patch() {
+        let patched = DefinitionPatcher::new(order_definition())
+            .timeout_seconds("Notify restaurant about the new order", 1)
+            .timeout_seconds("Set order status to ACCEPTED", 2)
+            .swap_resource(
+                "arn:aws:states:::sns:publish.waitForTaskToken",
+                "arn:aws:states:::lambda:invoke.waitForTaskToken",
+            )
+            .retry(
+                "Add order",
+                vec![serde_json::from_value(json!({ "ErrorEquals": ["States.ALL"] })).unwrap()],
+            )
+            .rename("OrdersTest")
+            .apply()
+            .unwrap();
+
+        let notify = patched
+            .definition
+            .state("Notify restaurant about the new order")
+            .unwrap();
+        assert_eq!(notify.value["TimeoutSeconds"], 1);
+        assert_eq!(
+            notify.value["Resource"],
+            "arn:aws:states:::lambda:invoke.waitForTaskToken"
+        );
+        let branch = &patched
+            .definition
+            .state("Handle accepted order")
+            .unwrap()
+            .value["Branches"];
+        assert_eq!(
+            branch[0]["States"]["Set order status to ACCEPTED"]["TimeoutSeconds"],
+            2
+        );
+        assert_eq!(
+            branch[1]["States"]["Notify the user about the accepted order"]["Resource"],
+            "arn:aws:states:::lambda:invoke.waitForTaskToken"
+        );
+        assert_eq!(
+            patched.definition.state("Add order").unwrap().value["Retry"][0]["MaxAttempts"],
+            3
+        );
+        assert_eq!(patched.name_or("Orders"), "OrdersTest");
+        assert_eq!(patched.original, order_definition());
+        assert_eq!(patched.to_string().lines().count(), 6);
+    }
+
+    #[test]
+    fn rejects_patches_that_do_not_apply() {
+        let missing = DefinitionPatcher::new(order_definition())
+            .wait_seconds("Wait for the restaurant", 1)
+            .apply()
+            .unwrap_err();
+        assert_eq!(
+            missing.cause,
+            "'Wait for the restaurant': Seconds = 1: the state does not exist"
+        );
+
+        let wrong_kind = DefinitionPatcher::new(order_definition())
+            .heartbeat_seconds("Is order accepted?", 1)
+            .apply();
+        assert!(wrong_kind.is_err());
+    }
+}
@@ -0,0 +1,887 @@
+//! Property-based testing of state machine branching.
+//!
+//! A [`Property`] draws execution inputs and task results from [`Strategy`]s (built in code or
+//! from a JSON schema), runs each sample on the native executor with the results mocked, and
+//! checks every execution:
+//!
+//! ```ignore
+//! Property::new("big pages end in IsBig", "SimpleExample")
+//!     .task_result("GetHtml", Strategy::object([("size", Strategy::integer(0, 1 << 20))]))
+//!     .check(|sample, execution| match sample.result("GetHtml") {
+//!         Some(result) if result["size"].as_i64() > Some(10240) => property::ends_in(execution, "IsBig"),
+//!         _ => Ok(()),
+//!     })
+//!     .run(&definition)
+//!     .await
+//!     .assert_holds();
+//! ```
+//!
+//! A failing sample is shrunk to a minimal one, e.g. `size` `10241` for a threshold of `10240`,
+//! and printed as a mock config that can be pasted into `sfn-local-mock.json`. Samples are drawn
+//! from `SFN_PROPERTY_SEED` when it is set, so a failure can be replayed.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Map, Value};
+
+use crate::asl::{Definition, StateKind};
+use crate::executor::{
+    state_machine_arn, ExecutionRequest, Executor, DEFAULT_ACCOUNT_ID, DEFAULT_REGION,
+};
+use crate::history::{Execution, ExecutionStatus};
+use crate::mock::{MockConfig, MockedError, MockedResult, MockedStateMachine};
+
+const DEFAULT_CASES: usize = 100;
+const MAX_SHRINK_STEPS: usize = 1000;
+/// A task returns its sampled result for this many invocations, e.g. when it is retried.
+const INVOCATIONS: usize = 100;
+const DEFAULT_RANGE: i64 = 1_000_000;
+const DEFAULT_LENGTH: usize = 16;
+const COUNTEREXAMPLE: &str = "Counterexample";
+const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+/// How to draw a JSON value, and how to shrink one towards a simpler value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Strategy {
+    Just(Value),
+    Integer {
+        min: i64,
+        max: i64,
+    },
+    Number {
+        min: f64,
+        max: f64,
+    },
+    Boolean,
+    String {
+        max_length: usize,
+    },
+    OneOf(Vec<Strategy>),
+    Array {
+        items: Box<Strategy>,
+        max_length: usize,
+    },
+    Object {
+        required: BTreeMap<String, Strategy>,
+        optional: BTreeMap<String, Strategy>,
+    },
+}
+
+impl Strategy {
+    pub fn just(value: Value) -> Self {
+        return Strategy::Just(value);
+    }
+
+    pub fn integer(min: i64, max: i64) -> Self {
+        return Strategy::Integer { min, max };
+    }
+
+    /// An object with the given (always present) fields.
+    pub fn object<K: Into<String>>(fields: impl IntoIterator<Item = (K, Strategy)>) -> Self {
+        return Strategy::Object {
+            required: fields
+                .into_iter()
+                .map(|(name, strategy)| (name.into(), strategy))
+                .collect(),
+            optional: BTreeMap::new(),
+        };
+    }
+
+    /// Adds a field that is present in about half of the objects. Only applies to objects.
+    pub fn optional(mut self, name: impl Into<String>, strategy: Strategy) -> Self {
+        if let Strategy::Object { optional, .. } = &mut self {
+            optional.insert(name.into(), strategy);
+        }
+        return self;
+    }
+
+    /// A strategy for the values a JSON schema describes: `type` (or a list of types), `const`,
+    /// `enum`, `oneOf`/`anyOf`, `minimum`/`maximum`, `maxLength`, `items`/`maxItems`, and
+    /// `properties` with `required`.
+    pub fn from_schema(schema: &Value) -> Result<Self, crate::Error> {
+        if let Some(value) = schema.get("const") {
+            return Ok(Strategy::Just(value.clone()));
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            return Ok(Strategy::OneOf(
+                values.iter().cloned().map(Strategy::Just).collect(),
+            ));
+        }
+        for key in ["oneOf", "anyOf"] {
+            if let Some(schemas) = schema.get(key).and_then(Value::as_array) {
+                return Ok(Strategy::OneOf(
+                    schemas
+                        .iter()
+                        .map(Strategy::from_schema)
+                        .collect::<Result<_, _>>()?,
+                ));
+            }
+        }
+
+        let types: Vec<&str> = match &schema["type"] {
+            Value::String(type_name) => vec![type_name.as_str()],
+            Value::Array(type_names) => type_names.iter().filter_map(Value::as_str).collect(),
+            _ => return Err(format!("The schema {} has no type", schema).into()),
+        };
+        let mut strategies = types
+            .into_iter()
+            .map(|type_name| Strategy::from_typed_schema(schema, type_name))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        return Ok(match strategies.len() {
+            1 => strategies.remove(0),
+            _ => Strategy::OneOf(strategies),
+        });
+    }
+
+    fn from_typed_schema(schema: &Value, type_name: &str) -> Result<Self, crate::Error> {
+        let length = |key: &str| {
+            schema
+                .get(key)
+                .and_then(Value::as_u64)
+                .map_or(DEFAULT_LENGTH, |length| length as usize)
+        };
+
+        return Ok(match type_name {
+            "null" => Strategy::Just(Value::Null),
+            "boolean" => Strategy::Boolean,
+            "integer" => Strategy::Integer {
+                min: schema["minimum"].as_i64().unwrap_or(-DEFAULT_RANGE),
+                max: schema["maximum"].as_i64().unwrap_or(DEFAULT_RANGE),
+            },
+            "number" => Strategy::Number {
+                min: schema["minimum"]
+                    .as_f64()
+                    .unwrap_or(-DEFAULT_RANGE as f64),
+                max: schema["maximum"].as_f64().unwrap_or(DEFAULT_RANGE as f64),
+            },
+            "string" => Strategy::String {
+                max_length: length("maxLength"),
+            },
+            "array" => Strategy::Array {
+                items: Box::new(match schema.get("items") {
+                    Some(items) => Strategy::from_schema(items)?,
+                    None => Strategy::Just(Value::Null),
+                }),
+                max_length: length("maxItems"),
+            },
+            "object" => {
+                let required: Vec<&str> = schema["required"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .collect();
+                let mut strategy = Strategy::object(Vec::<(String, Strategy)>::new());
+                let properties = schema["properties"].as_object().into_iter().flatten();
+                for (name, property) in properties {
+                    let field = Strategy::from_schema(property)?;
+                    if let Strategy::Object { required: fields, .. } = &mut strategy {
+                        if required.contains(&name.as_str()) {
+                            fields.insert(name.clone(), field);
+                            continue;
+                        }
+                    }
+                    strategy = strategy.optional(name.clone(), field);
+                }
+                strategy
+            }
+            type_name => return Err(format!("Unsupported schema type '{}'", type_name).into()),
+        });
+    }
+
+    fn generate(&self, rng: &mut Rng) -> Value {
+        return match self {
+            Strategy::Just(value) => value.clone(),
+            Strategy::Integer { min, max } => {
+                // Edge values find off-by-one errors far more often than uniform ones.
+                if rng.below(8) == 0 {
+                    let edges = [*min, *max, 0.clamp(*min, *max)];
+                    return json!(edges[rng.below(edges.len() as u64) as usize]);
+                }
+                let span = (*max as i128 - *min as i128 + 1) as u128;
+                json!((*min as i128 + (rng.next_u64() as u128 % span) as i128) as i64)
+            }
+            Strategy::Number { min, max } => json!(min + rng.unit() * (max - min)),
+            Strategy::Boolean => json!(rng.below(2) == 1),
+            Strategy::String { max_length } => {
+                let length = rng.below(*max_length as u64 + 1);
+                let string: String = (0..length)
+                    .map(|_| ALPHABET[rng.below(ALPHABET.len() as u64) as usize] as char)
+                    .collect();
+                json!(string)
+            }
+            Strategy::OneOf(strategies) => match strategies.len() {
+                0 => Value::Null,
+                length => strategies[rng.below(length as u64) as usize].generate(rng),
+            },
+            Strategy::Array { items, max_length } => {
+                let length = rng.below(*max_length as u64 + 1);
+                Value::Array((0..length).map(|_| items.generate(rng)).collect())
+            }
+            Strategy::Object { required, optional } => {
+                let mut object = Map::new();
+                for (name, strategy) in required {
+                    object.insert(name.clone(), strategy.generate(rng));
+                }
+                for (name, strategy) in optional {
+                    if rng.below(2) == 1 {
+                        object.insert(name.clone(), strategy.generate(rng));
+                    }
+                }
+                Value::Object(object)
+            }
+        };
+    }
+
+    /// The simplest value of the strategy, which shrinking moves towards.
+    fn minimal(&self) -> Value {
+        return match self {
+            Strategy::Just(value) => value.clone(),
+            Strategy::Integer { min, max } => json!(0.clamp(*min, *max)),
+            Strategy::Number { min, max } => json!(0.0_f64.clamp(*min, *max)),
+            Strategy::Boolean => json!(false),
+            Strategy::String { .. } => json!(""),
+            Strategy::OneOf(strategies) => strategies
+                .first()
+                .map_or(Value::Null, Strategy::minimal),
+            Strategy::Array { .. } => json!([]),
+            Strategy::Object { required, .. } => Value::Object(
+                required
+                    .iter()
+                    .map(|(name, strategy)| (name.clone(), strategy.minimal()))
+                    .collect(),
+            ),
+        };
+    }
+
+    fn contains(&self, value: &Value) -> bool {
+        return match self {
+            Strategy::Just(expected) => expected == value,
+            Strategy::Integer { min, max } => value
+                .as_i64()
+                .is_some_and(|value| (*min..=*max).contains(&value)),
+            Strategy::Number { min, max } => value
+                .as_f64()
+                .is_some_and(|value| *min <= value && value <= *max),
+            Strategy::Boolean => value.is_boolean(),
+            Strategy::String { max_length } => value
+                .as_str()
+                .is_some_and(|value| value.chars().count() <= *max_length),
+            Strategy::OneOf(strategies) => strategies.iter().any(|strategy| strategy.contains(value)),
+            Strategy::Array { items, max_length } => value.as_array().is_some_and(|values| {
+                values.len() <= *max_length && values.iter().all(|value| items.contains(value))
+            }),
+            Strategy::Object { required, optional } => value.as_object().is_some_and(|object| {
+                required.iter().all(|(name, strategy)| {
+                    object.get(name).is_some_and(|value| strategy.contains(value))
+                }) && optional.iter().all(|(name, strategy)| {
+                    object.get(name).into_iter().all(|value| strategy.contains(value))
+                })
+            }),
+        };
+    }
+
+    /// Simpler values than `value`, most promising first.
+    fn shrink(&self, value: &Value) -> Vec<Value> {
+        let mut candidates = Vec::new();
+        match (self, value) {
+            (Strategy::Integer { min, max }, value) => {
+                if let Some(value) = value.as_i64() {
+                    let target = 0.clamp(*min, *max);
+                    let distance = value as i128 - target as i128;
+                    if distance != 0 {
+                        candidates.push(json!(target));
+                    }
+                    // Halving the distance to the target finds boundaries in a few steps.
+                    let mut step = distance / 2;
+                    while step != 0 {
+                        candidates.push(json!((value as i128 - step) as i64));
+                        step /= 2;
+                    }
+                }
+            }
+            (Strategy::Number { min, max }, value) => {
+                if let Some(value) = value.as_f64() {
+                    let target = 0.0_f64.clamp(*min, *max);
+                    candidates.push(json!(target));
+                    candidates.push(json!(value.trunc().clamp(*min, *max)));
+                    if (value - target).abs() >= 1.0 {
+                        candidates.push(json!(value - (value - target) / 2.0));
+                    }
+                }
+            }
+            (Strategy::Boolean, Value::Bool(true)) => candidates.push(json!(false)),
+            (Strategy::String { .. }, Value::String(string)) => {
+                let chars: Vec<char> = string.chars().collect();
+                if !chars.is_empty() {
+                    candidates.push(json!(""));
+                    candidates.push(json!(chars[..chars.len() / 2].iter().collect::<String>()));
+                    candidates.push(json!(chars[..chars.len() - 1].iter().collect::<String>()));
+                }
+                if let Some(index) = chars.iter().position(|char| *char != 'a') {
+                    let mut simpler = chars.clone();
+                    simpler[index] = 'a';
+                    candidates.push(json!(simpler.into_iter().collect::<String>()));
+                }
+            }
+            (Strategy::OneOf(strategies), value) => {
+                for (index, strategy) in strategies.iter().enumerate() {
+                    if strategy.contains(value) {
+                        candidates.extend(strategies[..index].iter().map(Strategy::minimal));
+                        candidates.extend(strategy.shrink(value));
+                    }
+                }
+            }
+            (Strategy::Array { items, .. }, Value::Array(values)) => {
+                if !values.is_empty() {
+                    candidates.push(json!([]));
+                }
+                for index in 0..values.len() {
+                    let mut fewer = values.clone();
+                    fewer.remove(index);
+                    candidates.push(Value::Array(fewer));
+                }
+                for (index, item) in values.iter().enumerate() {
+                    for simpler in items.shrink(item) {
+                        let mut values = values.clone();
+                        values[index] = simpler;
+                        candidates.push(Value::Array(values));
+                    }
+                }
+            }
+            (Strategy::Object { required, optional }, Value::Object(object)) => {
+                for name in optional.keys().filter(|name| object.contains_key(*name)) {
+                    let mut fewer = object.clone();
+                    fewer.remove(name);
+                    candidates.push(Value::Object(fewer));
+                }
+                for (name, strategy) in required.iter().chain(optional) {
+                    let field = match object.get(name) {
+                        Some(field) => field,
+                        None => continue,
+                    };
+                    for simpler in strategy.shrink(field) {
+                        let mut object = object.clone();
+                        object.insert(name.clone(), simpler);
+                        candidates.push(Value::Object(object));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let mut unique: Vec<Value> = Vec::new();
+        for candidate in candidates {
+            if &candidate != value && self.contains(&candidate) && !unique.contains(&candidate) {
+                unique.push(candidate);
+            }
+        }
+
+        return unique;
+    }
+}
+
+/// The input and mocked task results of one execution.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub input: Value,
+    /// The result of every Task state, for each of its invocations.
+    pub results: BTreeMap<String, MockedResult>,
+}
+
+impl Sample {
+    /// What a task returns, or `None` when it throws.
+    pub fn result(&self, state: &str) -> Option<&Value> {
+        return match self.results.get(state)? {
+            MockedResult::Return(value) => Some(value),
+            MockedResult::Throw(_) => None,
+        };
+    }
+
+    /// The sample as a mock config with a single test case.
+    pub fn mock_config(&self, state_machine: &str, test_case: &str) -> MockConfig {
+        let mut config = MockConfig::default();
+        let mut states = BTreeMap::new();
+        for (state, result) in &self.results {
+            let response = format!(
+                "{}{}",
+                test_case,
+                state
+                    .chars()
+                    .filter(char::is_ascii_alphanumeric)
+                    .collect::<String>()
+            );
+            states.insert(state.clone(), response.clone());
+            config.mocked_responses.insert(
+                response,
+                [(format!("0-{}", INVOCATIONS - 1), result.clone())]
+                    .into_iter()
+                    .collect(),
+            );
+        }
+        config.state_machines.insert(
+            state_machine.to_string(),
+            MockedStateMachine {
+                test_cases: [(test_case.to_string(), states)].into_iter().collect(),
+            },
+        );
+
+        return config;
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TaskStrategy {
+    result: Strategy,
+    /// Errors the task throws instead, in about a quarter of the samples.
+    errors: Vec<String>,
+}
+
+impl Default for TaskStrategy {
+    fn default() -> Self {
+        return Self {
+            result: Strategy::Just(json!({})),
+            errors: Vec::new(),
+        };
+    }
+}
+
+type Check = Box<dyn Fn(&Sample, &Execution) -> Result<(), String> + Send + Sync>;
+
+pub struct Property {
+    name: String,
+    state_machine: String,
+    input: Strategy,
+    tasks: BTreeMap<String, TaskStrategy>,
+    cases: usize,
+    seed: u64,
+    checks: Vec<Check>,
+}
+
+impl Property {
+    /// A property of a state machine, named after its key in the mock config. Tasks without a
+    /// strategy return `{}` and the input is `{}` unless set.
+    pub fn new(name: impl Into<String>, state_machine: impl Into<String>) -> Self {
+        let seed = std::env::var("SFN_PROPERTY_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+            });
+
+        return Self {
+            name: name.into(),
+            state_machine: state_machine.into(),
+            input: Strategy::Just(json!({})),
+            tasks: BTreeMap::new(),
+            cases: DEFAULT_CASES,
+            seed,
+            checks: Vec::new(),
+        };
+    }
+
+    pub fn input(mut self, strategy: Strategy) -> Self {
+        self.input = strategy;
+        return self;
+    }
+
+    pub fn task_result(mut self, state: impl Into<String>, strategy: Strategy) -> Self {
+        self.tasks.entry(state.into()).or_default().result = strategy;
+        return self;
+    }
+
+    /// Lets the task throw one of `errors` in some samples.
+    pub fn task_errors(mut self, state: impl Into<String>, errors: &[&str]) -> Self {
+        self.tasks.entry(state.into()).or_default().errors =
+            errors.iter().map(|error| error.to_string()).collect();
+        return self;
+    }
+
+    /// How many samples to run.
+    pub fn cases(mut self, cases: usize) -> Self {
+        self.cases = cases;
+        return self;
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        return self;
+    }
+
+    /// Adds a check that every execution must pass; the error explains the violation.
+    pub fn check(
+        mut self,
+        check: impl Fn(&Sample, &Execution) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.checks.push(Box::new(check));
+        return self;
+    }
+
+    /// Runs samples until one fails a check, then shrinks it.
+    pub async fn run(&self, definition: &Definition) -> PropertyReport {
+        let tasks: BTreeMap<String, TaskStrategy> = definition
+            .task_states()
+            .into_iter()
+            .map(|state| {
+                let strategy = self.tasks.get(&state).cloned().unwrap_or_default();
+                (state, strategy)
+            })
+            .collect();
+        let mut rng = Rng::new(self.seed);
+
+        for case in 1..=self.cases {
+            let sample = Sample {
+                input: self.input.generate(&mut rng),
+                results: tasks
+                    .iter()
+                    .map(|(state, strategy)| (state.clone(), self.draw(strategy, &mut rng)))
+                    .collect(),
+            };
+            if let Err(message) = self.test(definition, &sample).await {
+                let counterexample = self.shrink(definition, &tasks, sample, message).await;
+                return self.report(case, Some(counterexample));
+            }
+        }
+
+        return self.report(self.cases, None);
+    }
+
+    fn report(&self, cases: usize, counterexample: Option<Counterexample>) -> PropertyReport {
+        return PropertyReport {
+            name: self.name.clone(),
+            state_machine: self.state_machine.clone(),
+            cases,
+            seed: self.seed,
+            counterexample,
+        };
+    }
+
+    fn draw(&self, strategy: &TaskStrategy, rng: &mut Rng) -> MockedResult {
+        if !strategy.errors.is_empty() && rng.below(4) == 0 {
+            let error = &strategy.errors[rng.below(strategy.errors.len() as u64) as usize];
+            return MockedResult::Throw(MockedError {
+                error: error.clone(),
+                cause: format!("Thrown by property '{}'", self.name),
+            });
+        }
+
+        return MockedResult::Return(strategy.result.generate(rng));
+    }
+
+    async fn test(&self, definition: &Definition, sample: &Sample) -> Result<(), String> {
+        let executor = Executor::new()
+            .skip_waits(true)
+            .with_mock_config(sample.mock_config(&self.state_machine, COUNTEREXAMPLE));
+        let request = ExecutionRequest::new(definition.clone(), sample.input.clone())
+            .state_machine_arn(state_machine_arn(
+                DEFAULT_REGION,
+                DEFAULT_ACCOUNT_ID,
+                &self.state_machine,
+            ))
+            .test_case(COUNTEREXAMPLE);
+        let execution = executor.start(request).wait().await;
+
+        for check in &self.checks {
+            check(sample, &execution)?;
+        }
+
+        return Ok(());
+    }
+
+    /// Replaces the failing sample by simpler ones for as long as they still fail.
+    async fn shrink(
+        &self,
+        definition: &Definition,
+        tasks: &BTreeMap<String, TaskStrategy>,
+        original: Sample,
+        mut message: String,
+    ) -> Counterexample {
+        let mut sample = original.clone();
+        let mut steps = 0;
+        'shrinking: while steps < MAX_SHRINK_STEPS {
+            for candidate in simpler_samples(&sample, &self.input, tasks) {
+                if let Err(candidate_message) = self.test(definition, &candidate).await {
+                    sample = candidate;
+                    message = candidate_message;
+                    steps += 1;
+                    continue 'shrinking;
+                }
+            }
+            break;
+        }
+
+        return Counterexample {
+            mock_config: sample.mock_config(&self.state_machine, COUNTEREXAMPLE),
+            original,
+            sample,
+            message,
+            shrink_steps: steps,
+        };
+    }
+}
+
+fn simpler_samples(
+    sample: &Sample,
+    input: &Strategy,
+    tasks: &BTreeMap<String, TaskStrategy>,
+) -> Vec<Sample> {
+    let mut samples: Vec<Sample> = input
+        .shrink(&sample.input)
+        .into_iter()
+        .map(|input| Sample {
+            input,
+            results: sample.results.clone(),
+        })
+        .collect();
+    for (state, result) in &sample.results {
+        let strategy = match tasks.get(state) {
+            Some(strategy) => strategy,
+            None => continue,
+        };
+        let simpler = match result {
+            MockedResult::Return(value) => strategy.result.shrink(value),
+            MockedResult::Throw(_) => vec![strategy.result.minimal()],
+        };
+        for value in simpler {
+            let mut results = sample.results.clone();
+            results.insert(state.clone(), MockedResult::Return(value));
+            samples.push(Sample {
+                input: sample.input.clone(),
+                results,
+            });
+        }
+    }
+
+    return samples;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Counterexample {
+    /// The shrunk sample.
+    pub sample: Sample,
+    /// The sample that failed first.
+    pub original: Sample,
+    pub message: String,
+    pub shrink_steps: usize,
+    /// The shrunk sample as a test case named `Counterexample`.
+    pub mock_config: MockConfig,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyReport {
+    pub name: String,
+    pub state_machine: String,
+    /// How many samples ran, including the failing one.
+    pub cases: usize,
+    pub seed: u64,
+    pub counterexample: Option<Counterexample>,
+}
+
+impl PropertyReport {
+    pub fn holds(&self) -> bool {
+        return self.counterexample.is_none();
+    }
+
+    /// Panics with the report and its counterexample if the property does not hold.
+    #[track_caller]
+    pub fn assert_holds(&self) {
+        if !self.holds() {
+            panic!(
+                "property '{}' of {} does not hold\n\n{}",
+                self.name, self.state_machine, self
+            );
+        }
+    }
+}
+
+impl fmt::Display for PropertyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counterexample = match &self.counterexample {
+            Some(counterexample) => counterexample,
+            None => {
+                return write!(
+                    f,
+                    "property '{}' held for {} samples (seed {})",
+                    self.name, self.cases, self.seed
+                );
+            }
+        };
+        let pretty = |value: &Value| {
+            serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+        };
+
+        writeln!(
+            f,
+            "property '{}' failed after {} samples (seed {}), shrunk in {} steps: {}",
+            self.name, self.cases, self.seed, counterexample.shrink_steps, counterexample.message
+        )?;
+        writeln!(f, "input:\n{}", pretty(&counterexample.sample.input))?;
+        let mock_config = serde_json::to_value(&counterexample.mock_config).unwrap_or_default();
+
+        return write!(f, "mock config:\n{}", pretty(&mock_config));
+    }
+}
+
+/// Fails unless the execution ended in `state`.
+pub fn ends_in(execution: &Execution, state: &str) -> Result<(), String> {
+    let visited = execution.visited_states();
+
+    return match visited.last() {
+        Some(last) if last == state => Ok(()),
+        _ => Err(format!("expected to end in {}, visited {:?}", state, visited)),
+    };
+}
+
+/// Fails if the execution failed anywhere but in a top-level `Fail` state, i.e. on an error that
+/// no `Catch` clause handled.
+pub fn no_uncaught_failure(definition: &Definition, execution: &Execution) -> Result<(), String> {
+    if execution.status != ExecutionStatus::Failed {
+        return Ok(());
+    }
+    let visited = execution.visited_states();
+    let failed_in_fail_state = visited
+        .last()
+        .and_then(|last| definition.state(last))
+        .and_then(|state| state.kind())
+        == Some(StateKind::Fail);
+    if failed_in_fail_state {
+        return Ok(());
+    }
+
+    return Err(format!(
+        "uncaught {} ({}) after {:?}",
+        execution.error.as_deref().unwrap_or("error"),
+        execution.cause.as_deref().unwrap_or_default(),
+        visited
+    ));
+}
+
+/// SplitMix64: small, fast and good enough to draw test data from a seed.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        return Self { state: seed };
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+        return z ^ (z >> 31);
+    }
+
+    /// A number in `0..bound`.
+    fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return 0;
+        }
+
+        return self.next_u64() % bound;
+    }
+
+    /// A number in `0.0..1.0`.
+    fn unit(&mut self) -> f64 {
+        return (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::simple_value;
+
+    fn simple_definition(threshold: u64, catch: bool) -> Definition {
+        let mut definition = simple_value();
+        definition["States"]["IsHtmlBig?"]["Choices"][0]["NumericGreaterThan"] = json!(threshold);
+        if !catch {
+            definition["States"]["GetHtml"]
+                .as_object_mut()
+                .unwrap()
+                .remove("Catch");
+        }
+        return Definition::from_value(definition).unwrap();
+    }
+
+    fn big_pages_end_in_is_big() -> Property {
+        return Property::new("big pages end in IsBig", "SimpleExample")
+            .task_result(
+                "GetHtml",
+                Strategy::object([("size", Strategy::integer(0, 32768))]),
+            )
+            .seed(7)
+            .check(|sample, execution| {
+                let size = sample.result("GetHtml").and_then(|result| result["size"].as_i64());
+                if size.unwrap_or(0) <= 10240 {
+                    return Ok(());
+                }
+                ends_in(execution, "IsBig")?;
+                return match &execution.output {
+                    Some(Value::Bool(true)) => Ok(()),
+                    output => Err(format!("expected output true, got {:?}", output)),
+                };
+            });
+    }
+
+    #[tokio::test]
+    async fn shrinks_a_counterexample_to_the_boundary() {
+        let report = big_pages_end_in_is_big()
+            .run(&simple_definition(20480, true))
+            .await;
+
+        let counterexample = report.counterexample.as_ref().unwrap();
+        assert_eq!(
+            counterexample.sample.result("GetHtml"),
+            Some(&json!({ "size": 10241 }))
+        );
+        let rendered = report.to_string();
+        assert!(rendered.contains("expected to end in IsBig"));
+        assert!(rendered.contains("\"CounterexampleGetHtml\": {\n      \"0-99\": {\n        \"Return\": {\n          \"size\": 10241"));
+        assert!(std::panic::catch_unwind(|| report.assert_holds()).is_err());
+
+        big_pages_end_in_is_big()
+            .run(&simple_definition(10240, true))
+            .await
+            .assert_holds();
+    }
+
+    #[tokio::test]
+    async fn finds_uncaught_failures() {
+        let schema = json!({
+            "type": "object",
+            "properties": { "size": { "type": "integer", "minimum": 0 } },
+            "required": ["size"]
+        });
+        let property = |definition: &Definition| {
+            let definition = definition.clone();
+            return Property::new("no uncaught failure", "SimpleExample")
+                .task_result("GetHtml", Strategy::from_schema(&schema).unwrap())
+                .task_errors("GetHtml", &["Lambda.ServiceException"])
+                .seed(11)
+                .check(move |_, execution| no_uncaught_failure(&definition, execution));
+        };
+
+        let caught = simple_definition(10240, true);
+        property(&caught).run(&caught).await.assert_holds();
+
+        let uncaught = simple_definition(10240, false);
+        let report = property(&uncaught).run(&uncaught).await;
+        let counterexample = report.counterexample.unwrap();
+        assert!(matches!(
+            counterexample.sample.results["GetHtml"],
+            MockedResult::Throw(_)
+        ));
+        assert!(counterexample
+            .message
+            .starts_with("uncaught Lambda.ServiceException"));
+    }
+}
@@ -0,0 +1,187 @@
+//! Recording real task results as mocked responses.
+//!
+//! Instead of guessing payloads like `{"size": 1024000}`, a handler registered with an
+//! [`Executor`] (e.g. one calling the real html-getter, or a local stub server) is invoked with
+//! real inputs, and what it returns or throws becomes a [`MockedResponse`]: the result for the
+//! n-th input is the result of the n-th invocation. Saved with [`MockConfig::set_response`],
+//! recording again under the same name replaces the previous recording, and the test cases
+//! referencing it keep doing so.
+
+use serde_json::Value;
+
+use crate::asl::StatesError;
+use crate::executor::{Executor, TaskRequest};
+use crate::mock::{MockConfig, MockedError, MockedResponse, MockedResult};
+
+/// Records the results of the handler a state's resource runs.
+pub struct Recorder {
+    executor: Executor,
+    state_machine: String,
+    state: String,
+    resource: String,
+    test_cases: Vec<String>,
+}
+
+impl Recorder {
+    pub fn new(
+        executor: &Executor,
+        state_machine: impl Into<String>,
+        state: impl Into<String>,
+        resource: impl Into<String>,
+    ) -> Self {
+        return Self {
+            executor: executor.clone(),
+            state_machine: state_machine.into(),
+            state: state.into(),
+            resource: resource.into(),
+            test_cases: Vec::new(),
+        };
+    }
+
+    /// A test case of the state machine that mocks the state with the recording.
+    pub fn test_case(mut self, test_case: impl Into<String>) -> Self {
+        self.test_cases.push(test_case.into());
+        return self;
+    }
+
+    /// Invokes the handler of the resource with each input in turn.
+    pub async fn record(
+        &self,
+        inputs: impl IntoIterator<Item = Value>,
+    ) -> Result<MockedResponse, StatesError> {
+        let handler = self.executor.handler(&self.resource).ok_or_else(|| {
+            StatesError::runtime(format!("No handler is registered for '{}'", self.resource))
+        })?;
+
+        let mut response = MockedResponse::new();
+        for (invocation, input) in inputs.into_iter().enumerate() {
+            let request = TaskRequest {
+                state_name: self.state.clone(),
+                resource: self.resource.clone(),
+                input,
+                task_token: None,
+            };
+            let result = match handler.invoke(request).await {
+                Ok(output) => MockedResult::Return(output),
+                Err(error) => MockedResult::Throw(MockedError {
+                    error: error.error,
+                    cause: error.cause,
+                }),
+            };
+            response.insert(invocation.to_string(), result);
+        }
+
+        return Ok(response);
+    }
+
+    /// Records the mocked response `name` into `config`, replacing an earlier recording, and
+    /// mocks the state with it in the test cases.
+    pub async fn record_into(
+        &self,
+        config: &mut MockConfig,
+        name: &str,
+        inputs: impl IntoIterator<Item = Value>,
+    ) -> Result<(), StatesError> {
+        config.set_response(name, self.record(inputs).await?);
+        for test_case in &self.test_cases {
+            config.set_test_case_state(&self.state_machine, test_case, &self.state, name);
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::executor::handler_fn;
+    use crate::mock::MockedTestCase;
+
+    const GET_HTML: &str = "arn:aws:lambda:us-east-1:123456789012:function:get-html";
+
+    fn recorder(executor: &Executor) -> Recorder {
+        return Recorder::new(executor, "SimpleExample", "GetHtml", GET_HTML);
+    }
+
+    fn executor() -> Executor {
+        return Executor::new().with_handler(
+            "arn:aws:lambda:*",
+            handler_fn(|request: TaskRequest| async move {
+                let url = request.input["url"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string();
+                if url.is_empty() {
+                    return Err(StatesError::new("Html.InvalidUrl", "no url"));
+                }
+                return Ok(json!({ "url": url, "size": url.len() * 1000 }));
+            }),
+        );
+    }
+
+    #[tokio::test]
+    async fn records_outputs_and_errors_per_invocation() {
+        let response = recorder(&executor())
+            .record([json!({ "url": "https://example.com" }), json!({})])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response["0"],
+            MockedResult::Return(json!({ "url": "https://example.com", "size": 19000 }))
+        );
+        assert_eq!(
+            response["1"],
+            MockedResult::Throw(MockedError {
+                error: "Html.InvalidUrl".to_string(),
+                cause: "no url".to_string(),
+            })
+        );
+
+        let error = recorder(&Executor::new())
+            .record([json!({})])
+            .await
+            .unwrap_err();
+        assert!(error.cause.contains("No handler"));
+    }
+
+    #[tokio::test]
+    async fn re_recording_updates_entries_in_place() {
+        let mut config: MockConfig =
+            serde_json::from_str(include_str!("sfn-local-mock.json")).unwrap();
+        let executor = executor();
+
+        recorder(&executor)
+            .test_case("IsNotBigPath")
+            .record_into(
+                &mut config,
+                "MockedIsHtmlBigFalse",
+                [json!({ "url": "https://a.io" })],
+            )
+            .await
+            .unwrap();
+        recorder(&executor)
+            .test_case("ExampleCom")
+            .record_into(
+                &mut config,
+                "RecordedExampleCom",
+                [json!({ "url": "https://example.com" })],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(config.mocked_responses.len(), 4);
+        let mut test_case = MockedTestCase::new(&config, "SimpleExample", "IsNotBigPath").unwrap();
+        assert_eq!(
+            test_case.next_result("GetHtml").unwrap(),
+            Ok(json!({ "url": "https://a.io", "size": 12000 }))
+        );
+        let mut test_case = MockedTestCase::new(&config, "SimpleExample", "ExampleCom").unwrap();
+        assert_eq!(
+            test_case.next_result("GetHtml").unwrap().unwrap()["size"],
+            19000
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! Where a redriven execution picks up, like `RedriveExecution`.
+//!
+//! A failed, aborted or timed-out execution restarts at its unsuccessful top-level state, with
+//! the input that state had and the variables the states before it assigned; the states that
+//! succeeded are not run again. When the unsuccessful state is a `Fail` state reached through a
+//! `Catch`, the execution restarts at the state whose error was caught instead, so that e.g. a
+//! fixed mock or handler gets another chance. A `Parallel` or `Map` state restarts as a whole.
+//! The redriven execution keeps its ARN and history, which gets an `ExecutionRedriven` event.
+
+use serde_json::{Map, Value};
+
+use crate::asl::{from_document, StatesError};
+use crate::history::{Execution, ExecutionStatus};
+
+pub const EXECUTION_NOT_REDRIVABLE: &str = "ExecutionNotRedrivable";
+
+/// The state a redriven execution starts at, and what it starts with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedrivePoint {
+    pub state: String,
+    pub input: Value,
+    pub variables: Map<String, Value>,
+}
+
+/// A top-level state the execution entered.
+struct Entered {
+    name: String,
+    input: Value,
+    is_fail: bool,
+    /// Whether a task or nested state of it failed, e.g. before a Catch.
+    failed: bool,
+    /// The variables in scope when it was entered.
+    variables: Map<String, Value>,
+}
+
+/// Finds where a finished, unsuccessful execution restarts.
+pub fn redrive_point(execution: &Execution) -> Result<RedrivePoint, StatesError> {
+    if matches!(
+        execution.status,
+        ExecutionStatus::Running | ExecutionStatus::Succeeded
+    ) {
+        return Err(StatesError::new(
+            EXECUTION_NOT_REDRIVABLE,
+            format!(
+                "Execution '{}' is {}; only failed, aborted or timed out executions can be redriven",
+                execution.execution_arn,
+                execution.status.as_str()
+            ),
+        ));
+    }
+
+    let mut depth = 0usize;
+    let mut variables = Map::new();
+    let mut previous: Option<Entered> = None;
+    let mut pending: Option<Entered> = None;
+    for event in &execution.events {
+        let event_type = event.event_type.as_str();
+        if matches!(event_type, "ParallelStateStarted" | "MapStateStarted") {
+            depth += 1;
+            continue;
+        }
+        let nested_state =
+            event_type.starts_with("ParallelState") || event_type.starts_with("MapState");
+        if nested_state && !event_type.ends_with("Entered") && !event_type.ends_with("Exited") {
+            depth = depth.saturating_sub(1);
+        }
+        if event_type.ends_with("Failed") || event_type.ends_with("TimedOut") {
+            if let Some(pending) = &mut pending {
+                pending.failed = true;
+            }
+        }
+        if depth > 0 {
+            continue;
+        }
+
+        if event_type.ends_with("StateEntered") {
+            pending = Some(Entered {
+                name: event.details.name.clone().unwrap_or_default(),
+                input: from_document(event.details.input.as_deref())?,
+                is_fail: event_type == "FailStateEntered",
+                failed: false,
+                variables: variables.clone(),
+            });
+        }
+        if event_type.ends_with("StateExited") {
+            for (name, value) in event.details.assigned_variables.iter().flatten() {
+                variables.insert(name.clone(), from_document(Some(value))?);
+            }
+            previous = pending.take();
+        }
+    }
+
+    let unsuccessful = pending.ok_or_else(|| {
+        StatesError::new(
+            EXECUTION_NOT_REDRIVABLE,
+            format!(
+                "Execution '{}' has no unsuccessful state to redrive from",
+                execution.execution_arn
+            ),
+        )
+    })?;
+    let restart = match previous {
+        Some(caught) if unsuccessful.is_fail && caught.failed => caught,
+        _ => unsuccessful,
+    };
+
+    return Ok(RedrivePoint {
+        state: restart.name,
+        input: restart.input,
+        variables: restart.variables,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::asl::Definition;
+    use crate::executor::{handler_fn, ExecutionRequest, Executor, TaskRequest};
+    use crate::mock::MockConfig;
+
+    fn definition() -> Definition {
+        return Definition::from_value(json!({
+            "StartAt": "Prepare",
+            "States": {
+                "Prepare": { "Type": "Pass", "Result": "https://example.com", "ResultPath": "$.url", "Next": "GetHtml" },
+                "GetHtml": {
+                    "Type": "Task",
+                    "Resource": "arn:aws:lambda:us-east-1:123456789012:function:get-html",
+                    "Catch": [{ "ErrorEquals": ["States.ALL"], "Next": "Dunno" }],
+                    "End": true
+                },
+                "Dunno": { "Type": "Fail", "Error": "Dunno" }
+            }
+        }))
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn caught_failures_redrive_from_the_failed_task() {
+        let broken = Executor::new().with_handler(
+            "arn:aws:lambda:*",
+            handler_fn(|_: TaskRequest| async {
+                return Err(StatesError::new("Html.Unreachable", "connection reset"));
+            }),
+        );
+        let handle = broken.start(ExecutionRequest::new(definition(), json!({ "id": 1 })));
+        let failed = handle.wait().await;
+        assert_eq!(failed.error.as_deref(), Some("Dunno"));
+        assert_eq!(
+            redrive_point(&failed).unwrap(),
+            RedrivePoint {
+                state: "GetHtml".to_string(),
+                input: json!({ "id": 1, "url": "https://example.com" }),
+                variables: Map::new(),
+            }
+        );
+
+        let fixed = Executor::new().with_handler(
+            "arn:aws:lambda:*",
+            handler_fn(|request: TaskRequest| async move {
+                return Ok(json!({ "size": 20480, "url": request.input["url"] }));
+            }),
+        );
+        let redriven = fixed.redrive(&handle).unwrap().wait().await;
+
+        assert_eq!(redriven.status, ExecutionStatus::Succeeded);
+        assert_eq!(redriven.execution_arn, failed.execution_arn);
+        assert_eq!(redriven.redrive_count, 1);
+        assert_eq!(
+            redriven.output,
+            Some(json!({ "size": 20480, "url": "https://example.com" }))
+        );
+        assert_eq!(
+            redriven.visited_states(),
+            ["Prepare", "GetHtml", "Dunno", "GetHtml"]
+        );
+        let redriven_event = redriven
+            .events
+            .iter()
+            .find(|event| event.event_type == "ExecutionRedriven")
+            .unwrap();
+        assert_eq!(redriven_event.details.redrive_count, Some(1));
+    }
+
+    #[tokio::test]
+    async fn redriven_mocked_tasks_continue_their_response_sequence() {
+        let mock_config: MockConfig = serde_json::from_value(json!({
+            "StateMachines": { "Local": { "TestCases": { "Flaky": { "GetHtml": "Flaky" } } } },
+            "MockedResponses": {
+                "Flaky": {
+                    "0": { "Throw": { "Error": "Html.Unreachable", "Cause": "connection reset" } },
+                    "1": { "Return": { "size": 1024 } }
+                }
+            }
+        }))
+        .unwrap();
+        let executor = Executor::new().with_mock_config(mock_config);
+        let handle =
+            executor.start(ExecutionRequest::new(definition(), json!({})).test_case("Flaky"));
+        assert_eq!(handle.wait().await.error.as_deref(), Some("Dunno"));
+
+        let redriven = executor.redrive(&handle).unwrap().wait().await;
+
+        assert_eq!(redriven.status, ExecutionStatus::Succeeded);
+        assert_eq!(redriven.output, Some(json!({ "size": 1024 })));
+    }
+
+    #[tokio::test]
+    async fn only_unsuccessful_executions_are_redrivable() {
+        let executor = Executor::new().with_handler(
+            "arn:aws:lambda:*",
+            handler_fn(|_: TaskRequest| async { return Ok(json!({})) }),
+        );
+        let handle = executor.start(ExecutionRequest::new(definition(), json!({})));
+        handle.wait().await;
+
+        let error = match executor.redrive(&handle) {
+            Ok(_) => panic!("A succeeded execution was redriven"),
+            Err(error) => error,
+        };
+        assert_eq!(error.error, EXECUTION_NOT_REDRIVABLE);
+    }
+}
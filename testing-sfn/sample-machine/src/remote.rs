@@ -0,0 +1,372 @@
+//! Running state machines on Step Functions, step-functions-local or the `sfn-local` binary
+//! through `aws_sdk_sfn`.
+
+use std::time::{Duration, Instant};
+
+use aws_sdk_sfn::Region;
+use serde_json::Value;
+
+use crate::asl::{from_document, to_document, Definition};
+use crate::history::{EventDetails, Execution, HistoryEvent};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A client for an SFN-compatible endpoint such as `http://localhost:8083`.
+pub fn endpoint_client(
+    config: &aws_config::SdkConfig,
+    region: &str,
+    endpoint: &str,
+) -> Result<aws_sdk_sfn::Client, crate::Error> {
+    let sfn_config = aws_sdk_sfn::config::Builder::from(config)
+        .endpoint_resolver(aws_sdk_sfn::Endpoint::immutable(endpoint)?)
+        .region(Region::new(region.to_string()))
+        .build();
+
+    return Ok(aws_sdk_sfn::Client::from_conf(sfn_config));
+}
+
+/// Creates a state machine and returns its ARN.
+pub async fn create_state_machine(
+    client: &aws_sdk_sfn::Client,
+    name: &str,
+    definition: &Definition,
+    role_arn: &str,
+) -> Result<String, crate::Error> {
+    return create_tagged_state_machine(client, name, definition, role_arn, &[]).await;
+}
+
+/// Creates a state machine with `(key, value)` tags and returns its ARN.
+pub async fn create_tagged_state_machine(
+    client: &aws_sdk_sfn::Client,
+    name: &str,
+    definition: &Definition,
+    role_arn: &str,
+    tags: &[(&str, &str)],
+) -> Result<String, crate::Error> {
+    let tags = tags
+        .iter()
+        .map(|(key, value)| {
+            aws_sdk_sfn::model::Tag::builder()
+                .key(*key)
+                .value(*value)
+                .build()
+        })
+        .collect();
+    let response = client
+        .create_state_machine()
+        .name(name)
+        .definition(definition.to_json())
+        .role_arn(role_arn)
+        .set_tags(Some(tags))
+        .send()
+        .await?;
+
+    return Ok(response.state_machine_arn().unwrap_or_default().to_string());
+}
+
+/// The definition of a state machine, as `DescribeStateMachine` returns it.
+pub async fn describe_definition(
+    client: &aws_sdk_sfn::Client,
+    state_machine_arn: &str,
+) -> Result<Definition, crate::Error> {
+    let response = client
+        .describe_state_machine()
+        .state_machine_arn(state_machine_arn)
+        .send()
+        .await?;
+
+    return Ok(Definition::from_json(response.definition().unwrap_or_default())?);
+}
+
+/// Starts an execution and returns its ARN. `state_machine_arn` may carry a `#TestCase` suffix.
+pub async fn start_execution(
+    client: &aws_sdk_sfn::Client,
+    state_machine_arn: &str,
+    input: &Value,
+) -> Result<String, crate::Error> {
+    let response = client
+        .start_execution()
+        .state_machine_arn(state_machine_arn)
+        .input(to_document(input))
+        .send()
+        .await?;
+
+    return Ok(response.execution_arn().unwrap_or_default().to_string());
+}
+
+/// A client for a local endpoint with static test credentials, as local endpoints do not check
+/// credentials.
+pub fn local_client(endpoint: &str) -> Result<aws_sdk_sfn::Client, crate::Error> {
+    let sfn_config = aws_sdk_sfn::Config::builder()
+        .endpoint_resolver(aws_sdk_sfn::Endpoint::immutable(endpoint)?)
+        .region(Region::new(crate::executor::DEFAULT_REGION))
+        .credentials_provider(aws_sdk_sfn::Credentials::new(
+            "local", "local", None, None, "static",
+        ))
+        .build();
+
+    return Ok(aws_sdk_sfn::Client::from_conf(sfn_config));
+}
+
+/// Stops every running execution of a state machine and returns how many were stopped.
+pub async fn stop_running_executions(
+    client: &aws_sdk_sfn::Client,
+    state_machine_arn: &str,
+) -> Result<usize, crate::Error> {
+    let mut running = Vec::new();
+    let mut next_token = None;
+    loop {
+        let response = client
+            .list_executions()
+            .state_machine_arn(state_machine_arn)
+            .status_filter(aws_sdk_sfn::model::ExecutionStatus::Running)
+            .set_next_token(next_token)
+            .send()
+            .await?;
+        running.extend(
+            response
+                .executions()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|execution| execution.execution_arn().map(str::to_string)),
+        );
+        next_token = response.next_token().map(str::to_string);
+        if next_token.is_none() {
+            break;
+        }
+    }
+
+    for execution_arn in &running {
+        client
+            .stop_execution()
+            .execution_arn(execution_arn)
+            .cause("The test fixture was cleaned up")
+            .send()
+            .await?;
+    }
+
+    return Ok(running.len());
+}
+
+pub async fn delete_state_machine(
+    client: &aws_sdk_sfn::Client,
+    state_machine_arn: &str,
+) -> Result<(), crate::Error> {
+    client
+        .delete_state_machine()
+        .state_machine_arn(state_machine_arn)
+        .send()
+        .await?;
+
+    return Ok(());
+}
+
+/// Fetches an execution with its full history (`DescribeExecution` and every page of
+/// `GetExecutionHistory`), in the same shape as local executions.
+pub async fn describe_execution(
+    client: &aws_sdk_sfn::Client,
+    execution_arn: &str,
+) -> Result<Execution, crate::Error> {
+    let described = client
+        .describe_execution()
+        .execution_arn(execution_arn)
+        .send()
+        .await?;
+    let status = described
+        .status()
+        .map(|status| status.as_str())
+        .unwrap_or("RUNNING");
+
+    let mut events = Vec::new();
+    let mut next_token = None;
+    loop {
+        let response = client
+            .get_execution_history()
+            .execution_arn(execution_arn)
+            .set_next_token(next_token)
+            .send()
+            .await?;
+        events.extend(
+            response
+                .events()
+                .unwrap_or_default()
+                .iter()
+                .map(history_event),
+        );
+        next_token = response.next_token().map(str::to_string);
+        if next_token.is_none() {
+            break;
+        }
+    }
+    // `DescribeExecution` does not return the error and cause; the final event carries them.
+    let failure = events.iter().rev().find(|event| {
+        matches!(
+            event.event_type.as_str(),
+            "ExecutionFailed" | "ExecutionAborted" | "ExecutionTimedOut"
+        )
+    });
+
+    return Ok(Execution {
+        execution_arn: execution_arn.to_string(),
+        state_machine_arn: described
+            .state_machine_arn()
+            .unwrap_or_default()
+            .to_string(),
+        name: described.name().unwrap_or_default().to_string(),
+        status: serde_json::from_value(Value::from(status))?,
+        start_date: described
+            .start_date()
+            .map_or(0.0, |date| date.as_secs_f64()),
+        stop_date: described.stop_date().map(|date| date.as_secs_f64()),
+        input: from_document(described.input())?,
+        output: described
+            .output()
+            .map(|output| from_document(Some(output)))
+            .transpose()?,
+        error: failure.and_then(|event| event.details.error.clone()),
+        cause: failure.and_then(|event| event.details.cause.clone()),
+        events,
+        redrive_count: 0,
+        redrive_date: None,
+    });
+}
+
+/// Polls an execution until it finishes or `timeout` passes, then fetches it with its history.
+/// An execution that is still running after `timeout` is returned as is.
+pub async fn wait_for_execution(
+    client: &aws_sdk_sfn::Client,
+    execution_arn: &str,
+    timeout: Duration,
+) -> Result<Execution, crate::Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let described = client
+            .describe_execution()
+            .execution_arn(execution_arn)
+            .send()
+            .await?;
+        let running = matches!(
+            described.status(),
+            None | Some(aws_sdk_sfn::model::ExecutionStatus::Running)
+        );
+        if !running || Instant::now() >= deadline {
+            break;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    return describe_execution(client, execution_arn).await;
+}
+
+/// Converts the details the traces and assertions look at: state names, documents and errors.
+fn history_event(event: &aws_sdk_sfn::model::HistoryEvent) -> HistoryEvent {
+    let owned = |value: Option<&str>| value.map(str::to_string);
+    let mut details = EventDetails::default();
+
+    if let Some(entered) = event.state_entered_event_details() {
+        details.name = owned(entered.name());
+        details.input = owned(entered.input());
+    }
+    if let Some(exited) = event.state_exited_event_details() {
+        details.name = owned(exited.name());
+        details.output = owned(exited.output());
+    }
+    if let Some(started) = event.execution_started_event_details() {
+        details.input = owned(started.input());
+    }
+    let output = event
+        .task_succeeded_event_details()
+        .and_then(|details| details.output())
+        .or_else(|| {
+            event
+                .lambda_function_succeeded_event_details()
+                .and_then(|details| details.output())
+        })
+        .or_else(|| {
+            event
+                .execution_succeeded_event_details()
+                .and_then(|details| details.output())
+        });
+    if output.is_some() {
+        details.output = owned(output);
+    }
+
+    let failure = event
+        .task_failed_event_details()
+        .map(|d| (d.error(), d.cause()))
+        .or_else(|| {
+            event
+                .task_timed_out_event_details()
+                .map(|d| (d.error(), d.cause()))
+        })
+        .or_else(|| {
+            event
+                .task_start_failed_event_details()
+                .map(|d| (d.error(), d.cause()))
+        })
+        .or_else(|| {
+            event
+                .task_submit_failed_event_details()
+                .map(|d| (d.error(), d.cause()))
+        })
+        .or_else(|| {
+            event
+                .lambda_function_failed_event_details()
+                .map(|d| (d.error(), d.cause()))
+        })
+        .or_else(|| {
+            event
+                .lambda_function_timed_out_event_details()
+                .map(|d| (d.error(), d.cause()))
+        })
+        .or_else(|| {
+            event
+                .lambda_function_schedule_failed_event_details()
+                .map(|d| (d.error(), d.cause()))
+        })
+        .or_else(|| {
+            event
+                .lambda_function_start_failed_event_details()
+                .map(|d| (d.error(), d.cause()))
+        })
+        .or_else(|| {
+            event
+                .activity_failed_event_details()
+                .map(|d| (d.error(), d.cause()))
+        })
+        .or_else(|| {
+            event
+                .activity_timed_out_event_details()
+                .map(|d| (d.error(), d.cause()))
+        })
+        .or_else(|| {
+            event
+                .execution_failed_event_details()
+                .map(|d| (d.error(), d.cause()))
+        })
+        .or_else(|| {
+            event
+                .execution_aborted_event_details()
+                .map(|d| (d.error(), d.cause()))
+        })
+        .or_else(|| {
+            event
+                .execution_timed_out_event_details()
+                .map(|d| (d.error(), d.cause()))
+        });
+    if let Some((error, cause)) = failure {
+        details.error = owned(error);
+        details.cause = owned(cause);
+    }
+
+    return HistoryEvent {
+        id: event.id(),
+        previous_event_id: event.previous_event_id(),
+        timestamp: event.timestamp().map_or(0.0, |date| date.as_secs_f64()),
+        event_type: event
+            .r#type()
+            .map(|event_type| event_type.as_str().to_string())
+            .unwrap_or_default(),
+        details,
+    };
+}
@@ -0,0 +1,698 @@
+//! An HTTP server speaking the Step Functions AWS JSON 1.0 protocol, backed by the native
+//! [`Executor`]. It is a drop-in replacement for step-functions-local for the operations the
+//! tests use, including the `arn#TestCase` suffix of `StartExecution`.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+
+use crate::activity::ACTIVITY_POLL_SECONDS;
+use crate::asl::{from_document, to_document, Definition, StatesError};
+use crate::executor::{
+    state_machine_arn, ExecutionHandle, ExecutionRequest, Executor, DEFAULT_ACCOUNT_ID,
+    DEFAULT_REGION,
+};
+use crate::history::{now, Execution};
+
+const TARGET_PREFIX: &str = "AWSStepFunctions.";
+const DEFAULT_MAX_RESULTS: usize = 100;
+
+/// The tag naming the state machine of the mock config whose test cases apply to a machine, for
+/// machines created under another name such as the uniquely named test fixtures.
+pub const MOCK_CONFIG_NAME_TAG: &str = "sfn-local:mock-config-name";
+
+/// An error returned to the client as `{"__type": ..., "message": ...}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    pub code: String,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+        return Self {
+            code: code.into(),
+            message: message.into(),
+        };
+    }
+
+    fn validation(message: impl Into<String>) -> Self {
+        return Self::new("ValidationException", message);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct StateMachineRecord {
+    arn: String,
+    name: String,
+    /// The state machine name the mock config test cases are looked up under.
+    mock_name: String,
+    definition: Definition,
+    role_arn: String,
+    machine_type: String,
+    creation_date: f64,
+}
+
+/// The state shared by every request: registered state machines and their executions.
+#[derive(Clone)]
+pub struct StepFunctions {
+    executor: Executor,
+    region: String,
+    account_id: String,
+    state_machines: Arc<Mutex<HashMap<String, StateMachineRecord>>>,
+    executions: Arc<Mutex<HashMap<String, ExecutionHandle>>>,
+}
+
+impl StepFunctions {
+    pub fn new(executor: Executor) -> Self {
+        return Self {
+            executor,
+            region: DEFAULT_REGION.to_string(),
+            account_id: DEFAULT_ACCOUNT_ID.to_string(),
+            state_machines: Arc::new(Mutex::new(HashMap::new())),
+            executions: Arc::new(Mutex::new(HashMap::new())),
+        };
+    }
+
+    pub fn with_region(mut self, region: impl Into<String>) -> Self {
+        self.region = region.into();
+        return self;
+    }
+
+    pub fn with_account_id(mut self, account_id: impl Into<String>) -> Self {
+        self.account_id = account_id.into();
+        return self;
+    }
+
+    pub fn executor(&self) -> &Executor {
+        return &self.executor;
+    }
+
+    /// Dispatches one AWS JSON 1.0 operation, e.g. `StartExecution`.
+    pub fn dispatch(&self, operation: &str, request: Value) -> Result<Value, ApiError> {
+        return match operation {
+            "CreateStateMachine" => self.create_state_machine(&request),
+            "DescribeStateMachine" => self.describe_state_machine(&request),
+            "DeleteStateMachine" => self.delete_state_machine(&request),
+            "StartExecution" => self.start_execution(&request),
+            "DescribeExecution" => self.describe_execution(&request),
+            "ListExecutions" => self.list_executions(&request),
+            "GetExecutionHistory" => self.get_execution_history(&request),
+            "StopExecution" => self.stop_execution(&request),
+            "RedriveExecution" => self.redrive_execution(&request),
+            "CreateActivity" => {
+                let arn = format!(
+                    "arn:aws:states:{}:{}:activity:{}",
+                    self.region,
+                    self.account_id,
+                    required(&request, "name")?
+                );
+                Ok(json!({ "activityArn": arn, "creationDate": now() }))
+            }
+            "SendTaskSuccess" => {
+                let output = from_document(Some(required(&request, "output")?))
+                    .map_err(|error| ApiError::new("InvalidOutput", error.cause))?;
+                self.executor
+                    .send_task_success(required(&request, "taskToken")?, output)
+                    .map_err(|error| ApiError::new(error.code(), error.to_string()))?;
+                Ok(json!({}))
+            }
+            "SendTaskFailure" => {
+                let error = StatesError::new(
+                    optional(&request, "error").unwrap_or_default(),
+                    optional(&request, "cause").unwrap_or_default(),
+                );
+                self.executor
+                    .send_task_failure(required(&request, "taskToken")?, error)
+                    .map_err(|error| ApiError::new(error.code(), error.to_string()))?;
+                Ok(json!({}))
+            }
+            "SendTaskHeartbeat" => {
+                self.executor
+                    .send_task_heartbeat(required(&request, "taskToken")?)
+                    .map_err(|error| ApiError::new(error.code(), error.to_string()))?;
+                Ok(json!({}))
+            }
+            _ => Err(ApiError::new(
+                "UnknownOperationException",
+                format!("The operation {} is not supported", operation),
+            )),
+        };
+    }
+
+    /// `GetActivityTask`, which long-polls and is therefore not part of [`Self::dispatch`].
+    pub async fn get_activity_task(&self, request: &Value) -> Result<Value, ApiError> {
+        let task = self
+            .executor
+            .get_activity_task(
+                required(request, "activityArn")?,
+                optional(request, "workerName").unwrap_or_default(),
+                Duration::from_secs(ACTIVITY_POLL_SECONDS),
+            )
+            .await;
+
+        return Ok(match task {
+            Some(task) => {
+                json!({ "taskToken": task.task_token, "input": to_document(&task.input) })
+            }
+            None => json!({}),
+        });
+    }
+
+    fn create_state_machine(&self, request: &Value) -> Result<Value, ApiError> {
+        let name = required(request, "name")?;
+        let definition = Definition::from_json(required(request, "definition")?)
+            .map_err(|error| ApiError::new("InvalidDefinition", error.cause))?;
+        let arn = state_machine_arn(&self.region, &self.account_id, name);
+
+        let mut state_machines = self.state_machines.lock().unwrap();
+        if let Some(existing) = state_machines.get(&arn) {
+            if existing.definition != definition {
+                return Err(ApiError::new(
+                    "StateMachineAlreadyExists",
+                    format!("State Machine Already Exists: '{}'", arn),
+                ));
+            }
+            return Ok(json!({
+                "stateMachineArn": existing.arn,
+                "creationDate": existing.creation_date,
+            }));
+        }
+
+        let mock_name = request["tags"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|tag| tag["key"] == MOCK_CONFIG_NAME_TAG)
+            .and_then(|tag| tag["value"].as_str())
+            .unwrap_or(name);
+        let record = StateMachineRecord {
+            arn: arn.clone(),
+            name: name.to_string(),
+            mock_name: mock_name.to_string(),
+            definition,
+            role_arn: optional(request, "roleArn").unwrap_or_default().to_string(),
+            machine_type: optional(request, "type").unwrap_or("STANDARD").to_string(),
+            creation_date: now(),
+        };
+        let response = json!({
+            "stateMachineArn": record.arn,
+            "creationDate": record.creation_date,
+        });
+        self.executor
+            .register_state_machine(&arn, record.definition.clone());
+        state_machines.insert(arn, record);
+
+        return Ok(response);
+    }
+
+    fn describe_state_machine(&self, request: &Value) -> Result<Value, ApiError> {
+        let record = self.state_machine(required(request, "stateMachineArn")?)?;
+
+        return Ok(json!({
+            "stateMachineArn": record.arn,
+            "name": record.name,
+            "status": "ACTIVE",
+            "definition": record.definition.to_json(),
+            "roleArn": record.role_arn,
+            "type": record.machine_type,
+            "creationDate": record.creation_date,
+        }));
+    }
+
+    /// Deletes a state machine and stops its running executions. Deleting an unknown state
+    /// machine succeeds, like it does on Step Functions.
+    fn delete_state_machine(&self, request: &Value) -> Result<Value, ApiError> {
+        let arn = required(request, "stateMachineArn")?;
+        if self.state_machines.lock().unwrap().remove(arn).is_none() {
+            return Ok(json!({}));
+        }
+        self.executor.deregister_state_machine(arn);
+
+        for handle in self.executions.lock().unwrap().values() {
+            let execution = handle.snapshot();
+            if execution.state_machine_arn == arn && !execution.status.is_finished() {
+                handle.stop(None, Some("The state machine was deleted".to_string()));
+            }
+        }
+
+        return Ok(json!({}));
+    }
+
+    fn start_execution(&self, request: &Value) -> Result<Value, ApiError> {
+        let requested_arn = required(request, "stateMachineArn")?;
+        let (arn, test_case) = match requested_arn.split_once('#') {
+            Some((arn, test_case)) => (arn, Some(test_case)),
+            None => (requested_arn, None),
+        };
+        let record = self.state_machine(arn)?;
+        if let Some(test_case) = test_case {
+            let configured = self
+                .executor
+                .mock_config()
+                .and_then(|config| config.test_case(&record.mock_name, test_case))
+                .is_some();
+            if !configured {
+                return Err(ApiError::validation(format!(
+                    "Test case '{}' is not configured for state machine '{}'",
+                    test_case, record.mock_name
+                )));
+            }
+        }
+
+        let input = from_document(optional(request, "input"))
+            .map_err(|error| ApiError::new("InvalidExecutionInput", error.cause))?;
+        let mut execution = ExecutionRequest::new(record.definition.clone(), input)
+            .state_machine_arn(record.arn.clone())
+            .role_arn(record.role_arn.clone())
+            .mock_state_machine(record.mock_name.clone());
+        if let Some(name) = optional(request, "name") {
+            execution = execution.name(name);
+        }
+        if let Some(test_case) = test_case {
+            execution = execution.test_case(test_case);
+        }
+
+        let execution_arn = crate::executor::execution_arn(&record.arn, &execution.name);
+        let mut executions = self.executions.lock().unwrap();
+        if executions.contains_key(&execution_arn) {
+            return Err(ApiError::new(
+                "ExecutionAlreadyExists",
+                format!("Execution Already Exists: '{}'", execution_arn),
+            ));
+        }
+        let handle = self.executor.start(execution);
+        let start_date = handle.snapshot().start_date;
+        executions.insert(execution_arn.clone(), handle);
+
+        return Ok(json!({ "executionArn": execution_arn, "startDate": start_date }));
+    }
+
+    fn describe_execution(&self, request: &Value) -> Result<Value, ApiError> {
+        let execution = self
+            .execution(required(request, "executionArn")?)?
+            .snapshot();
+
+        return Ok(describe(&execution));
+    }
+
+    fn list_executions(&self, request: &Value) -> Result<Value, ApiError> {
+        let arn = required(request, "stateMachineArn")?;
+        self.state_machine(arn)?;
+        let status_filter = optional(request, "statusFilter");
+        let (offset, max_results) = page(request)?;
+
+        let mut executions: Vec<Execution> = self
+            .executions
+            .lock()
+            .unwrap()
+            .values()
+            .map(ExecutionHandle::snapshot)
+            .filter(|execution| execution.state_machine_arn == arn)
+            .filter(|execution| {
+                status_filter.is_none() || status_filter == Some(execution.status.as_str())
+            })
+            .collect();
+        executions.sort_by(|a, b| b.start_date.total_cmp(&a.start_date));
+
+        let items: Vec<Value> = executions
+            .iter()
+            .skip(offset)
+            .take(max_results)
+            .map(|execution| {
+                let mut item = describe(execution);
+                if let Value::Object(item) = &mut item {
+                    item.retain(|key, _| {
+                        !matches!(key.as_str(), "input" | "output" | "error" | "cause")
+                    });
+                }
+                item
+            })
+            .collect();
+        let mut response = json!({ "executions": items });
+        if offset + max_results < executions.len() {
+            response["nextToken"] = Value::from((offset + max_results).to_string());
+        }
+
+        return Ok(response);
+    }
+
+    fn get_execution_history(&self, request: &Value) -> Result<Value, ApiError> {
+        let execution = self
+            .execution(required(request, "executionArn")?)?
+            .snapshot();
+        let include_data = request["includeExecutionData"].as_bool().unwrap_or(true);
+        let (offset, max_results) = page(request)?;
+
+        let mut events = execution.events;
+        if request["reverseOrder"].as_bool().unwrap_or(false) {
+            events.reverse();
+        }
+        let page: Vec<Value> = events
+            .iter()
+            .skip(offset)
+            .take(max_results)
+            .map(|event| event.to_aws_json(include_data))
+            .collect();
+        let mut response = json!({ "events": page });
+        if offset + max_results < events.len() {
+            response["nextToken"] = Value::from((offset + max_results).to_string());
+        }
+
+        return Ok(response);
+    }
+
+    fn stop_execution(&self, request: &Value) -> Result<Value, ApiError> {
+        let handle = self.execution(required(request, "executionArn")?)?;
+        handle.stop(
+            optional(request, "error").map(str::to_string),
+            optional(request, "cause").map(str::to_string),
+        );
+
+        return Ok(json!({ "stopDate": now() }));
+    }
+
+    fn redrive_execution(&self, request: &Value) -> Result<Value, ApiError> {
+        let arn = required(request, "executionArn")?;
+        let handle = self
+            .executor
+            .redrive(&self.execution(arn)?)
+            .map_err(|error| ApiError::new(error.error, error.cause))?;
+        let redrive_date = handle.snapshot().redrive_date;
+        self.executions
+            .lock()
+            .unwrap()
+            .insert(arn.to_string(), handle);
+
+        return Ok(json!({ "redriveDate": redrive_date }));
+    }
+
+    fn state_machine(&self, arn: &str) -> Result<StateMachineRecord, ApiError> {
+        return self
+            .state_machines
+            .lock()
+            .unwrap()
+            .get(arn)
+            .cloned()
+            .ok_or_else(|| {
+                ApiError::new(
+                    "StateMachineDoesNotExist",
+                    format!("State Machine Does Not Exist: '{}'", arn),
+                )
+            });
+    }
+
+    /// An execution started through the API or by a `states:startExecution` task.
+    fn execution(&self, arn: &str) -> Result<ExecutionHandle, ApiError> {
+        return self
+            .executions
+            .lock()
+            .unwrap()
+            .get(arn)
+            .cloned()
+            .or_else(|| self.executor.nested_execution(arn))
+            .ok_or_else(|| {
+                ApiError::new(
+                    "ExecutionDoesNotExist",
+                    format!("Execution Does Not Exist: '{}'", arn),
+                )
+            });
+    }
+}
+
+/// Renders an execution as a `DescribeExecution` response.
+pub fn describe(execution: &Execution) -> Value {
+    let mut response = json!({
+        "executionArn": execution.execution_arn,
+        "stateMachineArn": execution.state_machine_arn,
+        "name": execution.name,
+        "status": execution.status.as_str(),
+        "startDate": execution.start_date,
+        "input": to_document(&execution.input),
+    });
+    if let Some(stop_date) = execution.stop_date {
+        response["stopDate"] = Value::from(stop_date);
+    }
+    if let Some(output) = &execution.output {
+        response["output"] = Value::from(to_document(output));
+    }
+    if let Some(error) = &execution.error {
+        response["error"] = Value::from(error.clone());
+    }
+    if let Some(cause) = &execution.cause {
+        response["cause"] = Value::from(cause.clone());
+    }
+    if let Some(redrive_date) = execution.redrive_date {
+        response["redriveCount"] = Value::from(execution.redrive_count);
+        response["redriveDate"] = Value::from(redrive_date);
+    }
+
+    return response;
+}
+
+/// The `nextToken` offset and the `maxResults` page size of a paginated request.
+fn page(request: &Value) -> Result<(usize, usize), ApiError> {
+    let max_results = match request["maxResults"].as_u64() {
+        Some(0) | None => DEFAULT_MAX_RESULTS,
+        Some(max_results) => max_results as usize,
+    };
+    let offset = match optional(request, "nextToken") {
+        Some(token) => token
+            .parse::<usize>()
+            .map_err(|_| ApiError::new("InvalidToken", "Invalid nextToken"))?,
+        None => 0,
+    };
+
+    return Ok((offset, max_results));
+}
+
+fn required<'a>(request: &'a Value, field: &str) -> Result<&'a str, ApiError> {
+    return optional(request, field)
+        .ok_or_else(|| ApiError::validation(format!("Missing required field '{}'", field)));
+}
+
+fn optional<'a>(request: &'a Value, field: &str) -> Option<&'a str> {
+    return request.get(field).and_then(Value::as_str);
+}
+
+async fn handle(
+    step_functions: StepFunctions,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let operation = request
+        .headers()
+        .get("x-amz-target")
+        .and_then(|target| target.to_str().ok())
+        .and_then(|target| target.strip_prefix(TARGET_PREFIX))
+        .map(str::to_string);
+
+    let result = match (request.method(), operation) {
+        (&Method::POST, Some(operation)) => {
+            match hyper::body::to_bytes(request.into_body()).await {
+                Ok(body) => match serde_json::from_slice::<Value>(&body) {
+                    Ok(body) if operation == "GetActivityTask" => {
+                        step_functions.get_activity_task(&body).await
+                    }
+                    Ok(body) => step_functions.dispatch(&operation, body),
+                    Err(error) => Err(ApiError::new("SerializationException", error.to_string())),
+                },
+                Err(error) => Err(ApiError::new("SerializationException", error.to_string())),
+            }
+        }
+        _ => Err(ApiError::new(
+            "UnknownOperationException",
+            "Expected a POST with an X-Amz-Target header",
+        )),
+    };
+
+    let (status, body) = match result {
+        Ok(body) => (StatusCode::OK, body),
+        Err(error) => (
+            StatusCode::BAD_REQUEST,
+            json!({ "__type": error.code, "message": error.message }),
+        ),
+    };
+
+    return Ok(Response::builder()
+        .status(status)
+        .header("content-type", "application/x-amz-json-1.0")
+        .body(Body::from(body.to_string()))
+        .expect("the response is always valid"));
+}
+
+/// Binds to `address` (use port `0` for an ephemeral port) and returns the bound address with
+/// the future that serves requests.
+pub fn bind(
+    address: SocketAddr,
+    step_functions: StepFunctions,
+) -> Result<(SocketAddr, impl Future<Output = Result<(), hyper::Error>>), crate::Error> {
+    let make_service = make_service_fn(move |_| {
+        let step_functions = step_functions.clone();
+        async move {
+            return Ok::<_, Infallible>(service_fn(move |request| {
+                handle(step_functions.clone(), request)
+            }));
+        }
+    });
+    let server = hyper::Server::try_bind(&address)?.serve(make_service);
+
+    return Ok((server.local_addr(), server));
+}
+
+/// Serves the API on `address` until the returned future is dropped.
+pub async fn serve(address: SocketAddr, step_functions: StepFunctions) -> Result<(), crate::Error> {
+    let (_, server) = bind(address, step_functions)?;
+    server.await?;
+
+    return Ok(());
+}
+
+/// Serves the API in the background, e.g. from a test.
+pub fn spawn(
+    address: SocketAddr,
+    step_functions: StepFunctions,
+) -> Result<SocketAddr, crate::Error> {
+    let (address, server) = bind(address, step_functions)?;
+    tokio::spawn(server);
+
+    return Ok(address);
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::history::ExecutionStatus;
+    use crate::mock::MockConfig;
+    use crate::test_fixtures::simple_definition;
+
+    #[tokio::test]
+    async fn runs_test_cases_through_the_json_protocol() {
+        let config: MockConfig = serde_json::from_str(include_str!("sfn-local-mock.json")).unwrap();
+        let step_functions = StepFunctions::new(Executor::new().with_mock_config(config));
+
+        let created = step_functions
+            .dispatch(
+                "CreateStateMachine",
+                json!({ "name": "SimpleExample", "definition": simple_definition().to_json(), "roleArn": "role" }),
+            )
+            .unwrap();
+        let arn = created["stateMachineArn"].as_str().unwrap();
+        let started = step_functions
+            .dispatch(
+                "StartExecution",
+                json!({ "stateMachineArn": format!("{}#IsBigPath", arn), "input": "{\"url\": \"https://www.rust-lang.org/\"}" }),
+            )
+            .unwrap();
+        let execution_arn = started["executionArn"].as_str().unwrap();
+        step_functions
+            .execution(execution_arn)
+            .unwrap()
+            .wait()
+            .await;
+
+        let described = step_functions
+            .dispatch(
+                "DescribeExecution",
+                json!({ "executionArn": execution_arn }),
+            )
+            .unwrap();
+        assert_eq!(described["status"], ExecutionStatus::Succeeded.as_str());
+        assert_eq!(described["output"], "true");
+
+        let history = step_functions
+            .dispatch(
+                "GetExecutionHistory",
+                json!({ "executionArn": execution_arn, "maxResults": 2 }),
+            )
+            .unwrap();
+        assert_eq!(history["events"][0]["type"], "ExecutionStarted");
+        assert_eq!(history["nextToken"], "2");
+
+        let missing = step_functions
+            .dispatch(
+                "StartExecution",
+                json!({ "stateMachineArn": format!("{}#Nope", arn) }),
+            )
+            .unwrap_err();
+        assert_eq!(missing.code, "ValidationException");
+    }
+
+    #[tokio::test]
+    async fn deleting_a_state_machine_stops_its_executions() {
+        let step_functions = StepFunctions::new(Executor::new());
+        let created = step_functions
+            .dispatch(
+                "CreateStateMachine",
+                json!({
+                    "name": "SimpleExample-1",
+                    "definition": r#"{"StartAt": "Wait", "States": {"Wait": {"Type": "Wait", "Seconds": 3600, "End": true}}}"#,
+                }),
+            )
+            .unwrap();
+        let arn = created["stateMachineArn"].as_str().unwrap();
+        let started = step_functions
+            .dispatch("StartExecution", json!({ "stateMachineArn": arn }))
+            .unwrap();
+
+        let running = step_functions
+            .dispatch(
+                "ListExecutions",
+                json!({ "stateMachineArn": arn, "statusFilter": "RUNNING" }),
+            )
+            .unwrap();
+        assert_eq!(
+            running["executions"][0]["executionArn"],
+            started["executionArn"]
+        );
+
+        step_functions
+            .dispatch("DeleteStateMachine", json!({ "stateMachineArn": arn }))
+            .unwrap();
+        let execution = step_functions
+            .execution(started["executionArn"].as_str().unwrap())
+            .unwrap()
+            .wait()
+            .await;
+        assert_eq!(execution.status, ExecutionStatus::Aborted);
+        assert_eq!(
+            step_functions
+                .dispatch("ListExecutions", json!({ "stateMachineArn": arn }))
+                .unwrap_err()
+                .code,
+            "StateMachineDoesNotExist"
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_over_http() {
+        let address = spawn(
+            "127.0.0.1:0".parse().unwrap(),
+            StepFunctions::new(Executor::new()),
+        )
+        .unwrap();
+
+        let response = reqwest::Client::new()
+            .post(format!("http://{}/", address))
+            .header("x-amz-target", "AWSStepFunctions.DescribeStateMachine")
+            .header("content-type", "application/x-amz-json-1.0")
+            .body(
+                r#"{"stateMachineArn": "arn:aws:states:us-east-1:123456789012:stateMachine:Nope"}"#,
+            )
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        let body: Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(body["__type"], "StateMachineDoesNotExist");
+    }
+}
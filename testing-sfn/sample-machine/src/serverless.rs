@@ -0,0 +1,153 @@
+//! Reading the state machines of the Serverless Framework configuration (`state_machines/*.yml`).
+//!
+//! CloudFormation tags are resolved to local placeholders: `!GetAtt get-html.Arn` becomes the ARN
+//! of a `get-html` Lambda function in the default region and account, `!Ref` and `!Sub` become
+//! their argument. The JSON pointer and expression of every resolved tag are kept, so a comparison
+//! with a deployed definition can skip them.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use crate::asl::Definition;
+use crate::executor::{DEFAULT_ACCOUNT_ID, DEFAULT_REGION};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerlessStateMachine {
+    /// The deployed state machine name (`name`).
+    pub name: Option<String>,
+    /// The CloudFormation logical id (`id`).
+    pub id: Option<String>,
+    pub definition: Definition,
+    /// JSON pointers into the definition to the tag expression they were resolved from, e.g.
+    /// `/States/GetHtml/Resource` to `!GetAtt get-html.Arn`.
+    pub resolved_tags: BTreeMap<String, String>,
+}
+
+impl ServerlessStateMachine {
+    pub fn from_yaml(yaml: &str) -> Result<Self, crate::Error> {
+        let document: serde_yaml::Value = serde_yaml::from_str(yaml)?;
+        let mut resolved_tags = BTreeMap::new();
+        let document = to_json(&document, "", &mut resolved_tags);
+        let definition = document
+            .get("definition")
+            .cloned()
+            .ok_or("The state machine has no definition")?;
+        resolved_tags = resolved_tags
+            .into_iter()
+            .filter_map(|(pointer, tag)| {
+                Some((pointer.strip_prefix("/definition")?.to_string(), tag))
+            })
+            .collect();
+
+        return Ok(Self {
+            name: document["name"].as_str().map(str::to_string),
+            id: document["id"].as_str().map(str::to_string),
+            definition: Definition::from_value(definition)?,
+            resolved_tags,
+        });
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        return Self::from_yaml(&std::fs::read_to_string(path)?);
+    }
+}
+
+pub(crate) fn to_json(
+    value: &serde_yaml::Value,
+    pointer: &str,
+    resolved_tags: &mut BTreeMap<String, String>,
+) -> Value {
+    return match value {
+        serde_yaml::Value::Null => Value::Null,
+        serde_yaml::Value::Bool(value) => Value::Bool(*value),
+        serde_yaml::Value::Number(number) => {
+            serde_json::to_value(number).unwrap_or(Value::Null)
+        }
+        serde_yaml::Value::String(value) => Value::String(value.clone()),
+        serde_yaml::Value::Sequence(values) => Value::Array(
+            values
+                .iter()
+                .enumerate()
+                .map(|(index, value)| {
+                    to_json(value, &format!("{}/{}", pointer, index), resolved_tags)
+                })
+                .collect(),
+        ),
+        serde_yaml::Value::Mapping(mapping) => {
+            let mut object = Map::new();
+            for (key, value) in mapping {
+                let key = match key {
+                    serde_yaml::Value::String(key) => key.clone(),
+                    other => serde_yaml::to_string(other)
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string(),
+                };
+                let child = format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+                object.insert(key, to_json(value, &child, resolved_tags));
+            }
+            Value::Object(object)
+        }
+        serde_yaml::Value::Tagged(tagged) => {
+            let argument = to_json(&tagged.value, pointer, resolved_tags);
+            let tag = tagged.tag.to_string();
+            let expression = match &argument {
+                Value::String(argument) => format!("{} {}", tag, argument),
+                other => format!("{} {}", tag, other),
+            };
+            resolved_tags.insert(pointer.to_string(), expression);
+            resolve_tag(tag.trim_start_matches('!'), argument)
+        }
+    };
+}
+
+fn resolve_tag(tag: &str, argument: Value) -> Value {
+    if tag == "GetAtt" {
+        let target = match &argument {
+            Value::String(target) => target.clone(),
+            Value::Array(parts) => parts
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join("."),
+            _ => String::new(),
+        };
+        if let Some(function) = target.strip_suffix(".Arn") {
+            return Value::from(format!(
+                "arn:aws:lambda:{}:{}:function:{}",
+                DEFAULT_REGION, DEFAULT_ACCOUNT_ID, function
+            ));
+        }
+        return Value::from(target);
+    }
+
+    return argument;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_simple_state_machine() {
+        let state_machine =
+            ServerlessStateMachine::from_yaml(include_str!("../state_machines/simple.yml")).unwrap();
+
+        assert_eq!(state_machine.id.as_deref(), Some("SimpleExampleStateMachine"));
+        assert_eq!(
+            state_machine.definition.state("GetHtml").unwrap().value["Resource"],
+            "arn:aws:lambda:us-east-1:123456789012:function:get-html"
+        );
+        assert_eq!(
+            state_machine.definition.state("IsHtmlBig?").unwrap().value["Choices"][0]
+                ["NumericGreaterThan"],
+            10240
+        );
+        assert_eq!(
+            state_machine.resolved_tags["/States/GetHtml/Resource"],
+            "!GetAtt get-html.Arn"
+        );
+    }
+}
@@ -0,0 +1,72 @@
+#![allow(clippy::needless_return)]
+
+use std::env;
+use std::path::Path;
+use std::process::ExitCode;
+
+use sample_machine::asl::Definition;
+use sample_machine::coverage;
+use sample_machine::serverless::ServerlessStateMachine;
+
+const USAGE: &str = "Usage: sfn-coverage <definition.asl.json | state_machines/simple.yml> \
+                     [--name <state machine>] [--mock-config <file>] [--expectations <file>]";
+
+/**
+ * Generates the test cases that cover every Choice rule, Catch clause and Retry policy of a
+ * definition, and writes them as a mock config (`sfn-coverage-mock.json` by default) and an
+ * expectations file (`sfn-coverage-expectations.json`).
+ * The cases are keyed by `--name`, by default the file name without its extension. Exits with 1
+ * when some branches could not be covered.
+ */
+#[tokio::main]
+async fn main() -> Result<ExitCode, sample_machine::Error> {
+    let mut definition_path = None;
+    let mut name = None;
+    let mut mock_config_path = "sfn-coverage-mock.json".to_string();
+    let mut expectations_path = "sfn-coverage-expectations.json".to_string();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--name" => name = Some(args.next().ok_or(USAGE)?),
+            "--mock-config" => mock_config_path = args.next().ok_or(USAGE)?,
+            "--expectations" => expectations_path = args.next().ok_or(USAGE)?,
+            "--help" => {
+                println!("{}", USAGE);
+                return Ok(ExitCode::SUCCESS);
+            }
+            _ => definition_path = Some(arg),
+        }
+    }
+    let definition_path = definition_path.ok_or(USAGE)?;
+
+    let path = Path::new(&definition_path);
+    let is_yaml = matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("yml" | "yaml")
+    );
+    let definition = if is_yaml {
+        ServerlessStateMachine::from_file(path)?.definition
+    } else {
+        Definition::from_json(&std::fs::read_to_string(path)?)?
+    };
+    let name = match name {
+        Some(name) => name,
+        None => path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| file_name.split('.').next())
+            .unwrap_or("StateMachine")
+            .to_string(),
+    };
+
+    let coverage = coverage::generate(&definition, &name).await;
+    coverage.write(&mock_config_path, &expectations_path)?;
+    println!("{}", coverage);
+    println!("wrote {} and {}", mock_config_path, expectations_path);
+
+    return Ok(if coverage.uncovered().is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    });
+}
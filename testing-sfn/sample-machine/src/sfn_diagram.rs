@@ -0,0 +1,57 @@
+#![allow(clippy::needless_return)]
+
+use std::path::Path;
+
+use sample_machine::asl::Definition;
+use sample_machine::diagram::Diagram;
+use sample_machine::history::Execution;
+use sample_machine::serverless::ServerlessStateMachine;
+
+const USAGE: &str = "Usage: sfn-diagram <definition.asl.json | state_machines/simple.yml> [--dot] \
+                     [--execution <execution.json>]";
+
+/**
+ * Renders an ASL definition (a JSON file, or the state machine of a serverless YAML file) as a
+ * Mermaid flowchart, or as Graphviz DOT with `--dot`.
+ * With `--execution <file>`, a `DescribeExecution`-like JSON document with its `events`, the
+ * executed path is highlighted and the failing states are marked.
+ */
+fn main() -> Result<(), sample_machine::Error> {
+    let mut definition_path = None;
+    let mut execution_path = None;
+    let mut dot = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dot" => dot = true,
+            "--execution" => execution_path = args.next(),
+            _ => definition_path = Some(arg),
+        }
+    }
+    let definition_path = definition_path.ok_or(USAGE)?;
+
+    let path = Path::new(&definition_path);
+    let is_yaml = matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("yml" | "yaml")
+    );
+    let definition = if is_yaml {
+        ServerlessStateMachine::from_file(path)?.definition
+    } else {
+        Definition::from_json(&std::fs::read_to_string(path)?)?
+    };
+    let mut diagram = Diagram::new(&definition);
+    if let Some(execution_path) = execution_path {
+        let execution: Execution =
+            serde_json::from_str(&std::fs::read_to_string(execution_path)?)?;
+        diagram = diagram.with_execution(&execution);
+    }
+
+    if dot {
+        print!("{}", diagram.to_dot());
+    } else {
+        print!("{}", diagram.to_mermaid());
+    }
+
+    return Ok(());
+}
@@ -0,0 +1,78 @@
+#![allow(clippy::needless_return)]
+
+use std::env;
+use std::process::ExitCode;
+
+use sample_machine::asl::Definition;
+use sample_machine::drift;
+use sample_machine::remote;
+use sample_machine::serverless::ServerlessStateMachine;
+
+const USAGE: &str = "Usage: sfn-drift [state_machines/simple.yml] [--arn <state machine arn>] \
+                     [--endpoint <url>] [--deployed <definition.asl.json>]";
+
+/**
+ * Compares a state machine of the Serverless Framework configuration with its deployed definition
+ * and exits with 1 on drift.
+ * The deployed definition is read from `--deployed <file>`, or fetched with `DescribeStateMachine`
+ * for `--arn` (by default `StateMachineArn` of `.env-outputs`), optionally from a local stand-in
+ * such as `sfn-local` with `--endpoint http://localhost:8083`.
+ */
+#[tokio::main]
+async fn main() -> Result<ExitCode, sample_machine::Error> {
+    let mut local_path = "state_machines/simple.yml".to_string();
+    let mut arn = None;
+    let mut endpoint = None;
+    let mut deployed_path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--arn" => arn = Some(args.next().ok_or(USAGE)?),
+            "--endpoint" => endpoint = Some(args.next().ok_or(USAGE)?),
+            "--deployed" => deployed_path = Some(args.next().ok_or(USAGE)?),
+            "--help" => {
+                println!("{}", USAGE);
+                return Ok(ExitCode::SUCCESS);
+            }
+            _ => local_path = arg,
+        }
+    }
+
+    let local = ServerlessStateMachine::from_file(&local_path)?;
+    let deployed = match deployed_path {
+        Some(path) => Definition::from_json(&std::fs::read_to_string(path)?)?,
+        None => {
+            dotenv::from_filename(".env-outputs").ok();
+            let arn = match arn {
+                Some(arn) => arn,
+                None => env::var("StateMachineArn")
+                    .map_err(|_| "Pass --arn or deploy to create .env-outputs")?,
+            };
+            let config = aws_config::load_from_env().await;
+            let region = env::var("AwsRegion").unwrap_or_else(|_| {
+                config
+                    .region()
+                    .map(|region| region.to_string())
+                    .unwrap_or_default()
+            });
+            let client = match endpoint {
+                Some(endpoint) => remote::endpoint_client(&config, &region, &endpoint)?,
+                None => aws_sdk_sfn::Client::from_conf(
+                    aws_sdk_sfn::config::Builder::from(&config)
+                        .region(aws_sdk_sfn::Region::new(region))
+                        .build(),
+                ),
+            };
+            remote::describe_definition(&client, &arn).await?
+        }
+    };
+
+    let drift = drift::compare_serverless(&local, &deployed);
+    if drift.has_drift() {
+        println!("{} drifted from the deployed definition:\n{}", local_path, drift);
+        return Ok(ExitCode::FAILURE);
+    }
+    println!("{} matches the deployed definition", local_path);
+
+    return Ok(ExitCode::SUCCESS);
+}
@@ -0,0 +1,27 @@
+#![allow(clippy::needless_return)]
+
+use std::io::Read;
+
+use sample_machine::inspect::{self, InspectionRequest};
+
+/**
+ * Inspects a single state, like the Step Functions `TestState` API.
+ * Reads an `InspectionRequest` as JSON from the file given as the first argument (or from stdin)
+ * and prints the `Inspection` as JSON.
+ */
+fn main() -> Result<(), sample_machine::Error> {
+    let request = match std::env::args().nth(1) {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => {
+            let mut request = String::new();
+            std::io::stdin().read_to_string(&mut request)?;
+            request
+        }
+    };
+    let request: InspectionRequest = serde_json::from_str(&request)?;
+    let inspection = inspect::inspect(&request)?;
+
+    println!("{}", serde_json::to_string_pretty(&inspection)?);
+
+    return Ok(());
+}
@@ -0,0 +1,37 @@
+#![allow(clippy::needless_return)]
+
+use std::env;
+use std::net::SocketAddr;
+
+use sample_machine::executor::{Executor, DEFAULT_ACCOUNT_ID, DEFAULT_REGION};
+use sample_machine::mock::MockConfig;
+use sample_machine::server::{self, StepFunctions};
+
+/**
+ * A stand-in for step-functions-local. It honors the same environment variables:
+ * `SFN_MOCK_CONFIG`, `AWS_DEFAULT_REGION` and `AWS_ACCOUNT_ID`.
+ * Set `SFN_SKIP_WAITS=true` to fast-forward `Wait` states and retry intervals.
+ */
+
+#[tokio::main]
+async fn main() -> Result<(), sample_machine::Error> {
+    let mut executor = Executor::new().skip_waits(
+        env::var("SFN_SKIP_WAITS")
+            .map(|value| value == "true")
+            .unwrap_or(false),
+    );
+    if let Some(mock_config) = MockConfig::from_env()? {
+        executor = executor.with_mock_config(mock_config);
+    }
+
+    let step_functions = StepFunctions::new(executor)
+        .with_region(env::var("AWS_DEFAULT_REGION").unwrap_or(DEFAULT_REGION.to_string()))
+        .with_account_id(env::var("AWS_ACCOUNT_ID").unwrap_or(DEFAULT_ACCOUNT_ID.to_string()));
+    let port = env::var("PORT").unwrap_or("8083".to_string());
+    let address: SocketAddr = format!("0.0.0.0:{}", port).parse()?;
+
+    println!("Step Functions API listening on {}", address);
+    server::serve(address, step_functions).await?;
+
+    return Ok(());
+}
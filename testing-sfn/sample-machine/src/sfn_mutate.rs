@@ -0,0 +1,77 @@
+#![allow(clippy::needless_return)]
+
+use std::env;
+use std::path::Path;
+use std::process::ExitCode;
+
+use sample_machine::asl::Definition;
+use sample_machine::cases::{Expectations, TestCases};
+use sample_machine::executor::Executor;
+use sample_machine::mock::MockConfig;
+use sample_machine::mutation;
+use sample_machine::serverless::ServerlessStateMachine;
+
+const USAGE: &str = "Usage: sfn-mutate <definition.asl.json | state_machines/simple.yml> \
+                     [--name <state machine>] [--mock-config <file>] [--expectations <file>]";
+
+/**
+ * Applies single mistakes to a definition (flipped comparisons, changed thresholds, retargeted
+ * transitions, removed Catch and Retry clauses) and runs the test cases of the mock config
+ * (`src/sfn-local-mock.json`) and expectations file (`src/sfn-local-expectations.json`) on each
+ * mutant. Lists the mutants that no case caught and exits with 1 if there are any.
+ */
+#[tokio::main]
+async fn main() -> Result<ExitCode, sample_machine::Error> {
+    let mut definition_path = None;
+    let mut name = None;
+    let mut mock_config_path = "src/sfn-local-mock.json".to_string();
+    let mut expectations_path = "src/sfn-local-expectations.json".to_string();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--name" => name = Some(args.next().ok_or(USAGE)?),
+            "--mock-config" => mock_config_path = args.next().ok_or(USAGE)?,
+            "--expectations" => expectations_path = args.next().ok_or(USAGE)?,
+            "--help" => {
+                println!("{}", USAGE);
+                return Ok(ExitCode::SUCCESS);
+            }
+            _ => definition_path = Some(arg),
+        }
+    }
+    let definition_path = definition_path.ok_or(USAGE)?;
+
+    let path = Path::new(&definition_path);
+    let is_yaml = matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("yml" | "yaml")
+    );
+    let definition = if is_yaml {
+        ServerlessStateMachine::from_file(path)?.definition
+    } else {
+        Definition::from_json(&std::fs::read_to_string(path)?)?
+    };
+    let name = match name {
+        Some(name) => name,
+        None => path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| file_name.split('.').next())
+            .unwrap_or("StateMachine")
+            .to_string(),
+    };
+
+    let config = MockConfig::from_file(&mock_config_path)?;
+    let expectations = Expectations::from_file(&expectations_path)?;
+    let cases = TestCases::new(&name, Some(&config), Some(&expectations));
+    let executor = Executor::new().skip_waits(true).with_mock_config(config);
+
+    let report = mutation::run(&definition, &cases, &executor).await?;
+    println!("{}", report);
+
+    return Ok(if report.survived().is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    });
+}
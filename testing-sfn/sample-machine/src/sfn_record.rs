@@ -0,0 +1,94 @@
+#![allow(clippy::needless_return)]
+
+use std::env;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lambda_runtime::{Context, Diagnostic, LambdaEvent};
+use sample_machine::asl::StatesError;
+use sample_machine::executor::{handler_fn, Executor, TaskRequest};
+use sample_machine::html::{self, Input};
+use sample_machine::mock::MockConfig;
+use sample_machine::recording::Recorder;
+use serde_json::json;
+
+/**
+ * The deadline of recorded invocations, the default Lambda timeout of the Serverless Framework.
+ */
+const RECORD_TIMEOUT: Duration = Duration::from_secs(6);
+
+const USAGE: &str = "Usage: sfn-record <mock-config.json> <response-name> <url>... \
+                     [--test-case <name>]...";
+
+/**
+ * Runs the html-getter handler on real URLs and records its results as the mocked response
+ * `<response-name>` of the `GetHtml` state of `SimpleExample`, replacing an earlier recording.
+ * Each `--test-case` mocks `GetHtml` with the recording.
+ */
+#[tokio::main]
+async fn main() -> Result<(), sample_machine::Error> {
+    let mut positional = Vec::new();
+    let mut test_cases = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--test-case" => test_cases.push(args.next().ok_or(USAGE)?),
+            "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() < 3 {
+        return Err(USAGE.into());
+    }
+    let (path, name, urls) = (&positional[0], &positional[1], &positional[2..]);
+
+    let mut config = match Path::new(path).exists() {
+        true => MockConfig::from_file(path)?,
+        false => MockConfig::default(),
+    };
+    let executor = Executor::new().with_handler(
+        "arn:aws:lambda:*",
+        handler_fn(|request: TaskRequest| async move {
+            let input: Input = serde_json::from_value(request.input)
+                .map_err(|error| StatesError::runtime(error.to_string()))?;
+            let mut context = Context::default();
+            let deadline = SystemTime::now() + RECORD_TIMEOUT;
+            context.deadline = deadline.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+            let output = html::handler(LambdaEvent::new(input, context))
+                .await
+                .map_err(|error| {
+                    let diagnostic = Diagnostic::from(error);
+                    return StatesError::new(diagnostic.error_type, diagnostic.error_message);
+                })?;
+            return Ok(json!(output));
+        }),
+    );
+    let mut recorder = Recorder::new(
+        &executor,
+        "SimpleExample",
+        "GetHtml",
+        "arn:aws:lambda:us-east-1:123456789012:function:get-html",
+    );
+    for test_case in test_cases {
+        recorder = recorder.test_case(test_case);
+    }
+    recorder
+        .record_into(
+            &mut config,
+            name,
+            urls.iter().map(|url| json!({ "url": url })),
+        )
+        .await?;
+    config.to_file(path)?;
+    eprintln!(
+        "recorded {} invocations as {} in {}",
+        urls.len(),
+        name,
+        path
+    );
+
+    return Ok(());
+}
@@ -0,0 +1,468 @@
+//! Snapshot assertions for executions.
+//!
+//! An execution is normalized before it is compared: the JSON documents of the history are
+//! parsed, and timestamps (by key, or RFC 3339 strings anywhere), execution and state machine
+//! ARNs, the execution name and task tokens are replaced with placeholders, so snapshots of local
+//! and remote runs stay stable.
+//!
+//! Snapshots live in a `snapshots` directory next to the file of the calling test, as
+//! `<name>.snap.json`. A missing or mismatching snapshot fails the assertion; set
+//! `SFN_UPDATE_SNAPSHOTS=1` to write the missing snapshots and rewrite the ones that no longer
+//! match, so a CI run never records a snapshot that nobody reviewed.
+
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use crate::history::Execution;
+
+const UPDATE_VARIABLE: &str = "SFN_UPDATE_SNAPSHOTS";
+/// Unchanged lines shown around every changed line of a diff.
+const DIFF_CONTEXT: usize = 3;
+
+const TIMESTAMP: &str = "[timestamp]";
+const EXECUTION_ARN: &str = "[execution arn]";
+const STATE_MACHINE_ARN: &str = "[state machine arn]";
+const EXECUTION_NAME: &str = "[execution name]";
+const TASK_TOKEN: &str = "[task token]";
+
+/// Keys whose values are timestamps, in executions, histories and the context object.
+const TIMESTAMP_KEYS: &[&str] = &[
+    "timestamp",
+    "startDate",
+    "stopDate",
+    "StartTime",
+    "EnteredTime",
+];
+
+/// History details that carry JSON documents as strings.
+const DOCUMENT_KEYS: &[&str] = &["input", "output", "parameters"];
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    directory: Option<PathBuf>,
+    history: bool,
+    update: bool,
+    redactions: Vec<(String, String)>,
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Snapshot {
+    /// A snapshot of the execution result and its history, updated when `SFN_UPDATE_SNAPSHOTS`
+    /// is set.
+    pub fn new() -> Self {
+        let update = std::env::var(UPDATE_VARIABLE)
+            .map(|value| !value.is_empty() && value != "0" && value != "false")
+            .unwrap_or(false);
+
+        return Self {
+            directory: None,
+            history: true,
+            update,
+            redactions: Vec::new(),
+        };
+    }
+
+    /// Stores the snapshots in `directory` instead of next to the calling test.
+    pub fn directory(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.directory = Some(directory.into());
+        return self;
+    }
+
+    /// Whether to include the normalized history, or only the status, input, output and error.
+    pub fn history(mut self, history: bool) -> Self {
+        self.history = history;
+        return self;
+    }
+
+    pub fn update(mut self, update: bool) -> Self {
+        self.update = update;
+        return self;
+    }
+
+    /// Replaces every occurrence of `value` in strings with `placeholder`.
+    pub fn redact(mut self, value: impl Into<String>, placeholder: impl Into<String>) -> Self {
+        self.redactions.push((value.into(), placeholder.into()));
+        return self;
+    }
+
+    /// The execution as it is stored in the snapshot.
+    pub fn normalize(&self, execution: &Execution) -> Value {
+        let mut value =
+            serde_json::to_value(execution).expect("executions are always serializable");
+        if let Value::Object(object) = &mut value {
+            object.remove("name");
+            if !self.history {
+                object.remove("events");
+            }
+        }
+        if let Some(Value::Array(events)) = value.get_mut("events") {
+            for event in events {
+                if let Some(Value::Object(details)) = event.get_mut("details") {
+                    parse_documents(details);
+                }
+            }
+        }
+
+        let mut redactions = vec![
+            (execution.execution_arn.clone(), EXECUTION_ARN.to_string()),
+            (
+                execution.state_machine_arn.clone(),
+                STATE_MACHINE_ARN.to_string(),
+            ),
+        ];
+        collect_task_tokens(&value, &mut redactions);
+        redactions.extend(self.redactions.iter().cloned());
+        redactions.retain(|(value, _)| !value.is_empty());
+        // Longer values first, so an ARN is not partially replaced by a value it contains.
+        redactions.sort_by_key(|(value, _)| std::cmp::Reverse(value.len()));
+        redact(&mut value, None, &execution.name, &redactions);
+
+        return value;
+    }
+
+    pub fn render(&self, execution: &Execution) -> String {
+        let rendered = serde_json::to_string_pretty(&self.normalize(execution))
+            .expect("snapshots are always serializable");
+
+        return format!("{}\n", rendered);
+    }
+
+    /// Compares the execution with the `name` snapshot and panics with a diff if it does not
+    /// match, or if it is missing. With updates enabled, the snapshot is written instead.
+    #[track_caller]
+    pub fn assert_matches(&self, name: &str, execution: &Execution) {
+        let directory = self
+            .directory
+            .clone()
+            .unwrap_or_else(|| snapshot_directory(std::panic::Location::caller().file()));
+        let path = directory.join(format!("{}.snap.json", name));
+        let actual = self.render(execution);
+
+        let expected = match std::fs::read_to_string(&path) {
+            Ok(expected) => expected,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound && self.update => {
+                write_snapshot(&path, &actual);
+                eprintln!("Created snapshot {}", path.display());
+                return;
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => panic!(
+                "snapshot {} is missing\n{}Run with {}=1 to create it.",
+                path.display(),
+                actual,
+                UPDATE_VARIABLE
+            ),
+            Err(error) => panic!("Failed to read snapshot {}: {}", path.display(), error),
+        };
+        if expected == actual {
+            return;
+        }
+        if self.update {
+            write_snapshot(&path, &actual);
+            eprintln!("Updated snapshot {}", path.display());
+            return;
+        }
+
+        panic!(
+            "snapshot {} does not match (- snapshot, + execution)\n{}\nRun with {}=1 to update it.",
+            path.display(),
+            diff(&expected, &actual),
+            UPDATE_VARIABLE
+        );
+    }
+}
+
+/// Compares an execution and its history with the `name` snapshot next to the calling test.
+#[track_caller]
+pub fn assert_snapshot(name: &str, execution: &Execution) {
+    Snapshot::new().assert_matches(name, execution);
+}
+
+/// A line diff of two texts, with `-` for lines only in `expected` and `+` for lines only in
+/// `actual`. Long runs of unchanged lines are collapsed.
+pub fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    // Longest common subsequence lengths of the suffixes.
+    let mut lengths = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lengths[i][j] = if expected[i] == actual[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines: Vec<(char, &str)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            lines.push((' ', expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < expected.len()
+            && (j == actual.len() || lengths[i + 1][j] >= lengths[i][j + 1])
+        {
+            lines.push(('-', expected[i]));
+            i += 1;
+        } else {
+            lines.push(('+', actual[j]));
+            j += 1;
+        }
+    }
+
+    let changed: Vec<usize> = (0..lines.len())
+        .filter(|index| lines[*index].0 != ' ')
+        .collect();
+    let near_change = |index: usize| {
+        changed
+            .iter()
+            .any(|changed| changed.abs_diff(index) <= DIFF_CONTEXT)
+    };
+
+    let mut rendered = String::new();
+    let mut skipping = false;
+    for (index, (marker, line)) in lines.iter().enumerate() {
+        if *marker == ' ' && !near_change(index) {
+            if !skipping {
+                rendered.push_str("  ...\n");
+                skipping = true;
+            }
+            continue;
+        }
+        skipping = false;
+        rendered.push_str(&format!("{} {}\n", marker, line));
+    }
+
+    return rendered;
+}
+
+/// `snapshots/` next to a source file, as `file!()` reports it: relative to the package or, in
+/// a workspace, to the workspace root.
+fn snapshot_directory(file: &str) -> PathBuf {
+    let file = Path::new(file);
+    let root = std::env::var("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .or_else(|_| std::env::current_dir())
+        .unwrap_or_default();
+    let source = if file.is_absolute() {
+        file.to_path_buf()
+    } else {
+        root.ancestors()
+            .map(|ancestor| ancestor.join(file))
+            .find(|candidate| candidate.exists())
+            .unwrap_or_else(|| root.join(file))
+    };
+
+    return source
+        .parent()
+        .map(|parent| parent.join("snapshots"))
+        .unwrap_or_else(|| PathBuf::from("snapshots"));
+}
+
+fn write_snapshot(path: &Path, contents: &str) {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .unwrap_or_else(|error| panic!("Failed to create {}: {}", parent.display(), error));
+    }
+    std::fs::write(path, contents)
+        .unwrap_or_else(|error| panic!("Failed to write snapshot {}: {}", path.display(), error));
+}
+
+fn parse_documents(details: &mut Map<String, Value>) {
+    for key in DOCUMENT_KEYS {
+        if let Some(Value::String(document)) = details.get(*key) {
+            if let Ok(parsed) = serde_json::from_str::<Value>(document) {
+                details.insert(key.to_string(), parsed);
+            }
+        }
+    }
+}
+
+/// Whether a string starts like an RFC 3339 timestamp, e.g. `2023-01-02T10:20:30.000Z`.
+fn is_timestamp(string: &str) -> bool {
+    let bytes = string.as_bytes();
+    if bytes.len() < 19 {
+        return false;
+    }
+
+    return bytes[..19].iter().enumerate().all(|(index, byte)| match index {
+        4 | 7 => *byte == b'-',
+        10 => *byte == b'T',
+        13 | 16 => *byte == b':',
+        _ => byte.is_ascii_digit(),
+    });
+}
+
+/// Task tokens are passed under keys such as `taskToken` or `TaskToken`, and the context object
+/// keeps them as `Task.Token`.
+fn is_task_token_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+
+    return key == "token" || key.ends_with("tasktoken");
+}
+
+fn collect_task_tokens(value: &Value, redactions: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                match value {
+                    Value::String(token) if is_task_token_key(key) => {
+                        redactions.push((token.clone(), TASK_TOKEN.to_string()));
+                    }
+                    value => collect_task_tokens(value, redactions),
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                collect_task_tokens(value, redactions);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn redact(value: &mut Value, key: Option<&str>, name: &str, redactions: &[(String, String)]) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                redact(value, Some(key), name, redactions);
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                redact(value, key, name, redactions);
+            }
+        }
+        _ if key.is_some_and(|key| TIMESTAMP_KEYS.contains(&key)) => {
+            *value = Value::from(TIMESTAMP);
+        }
+        Value::String(string) if is_timestamp(string) => {
+            *string = TIMESTAMP.to_string();
+        }
+        Value::String(string) if string == name => {
+            *string = EXECUTION_NAME.to_string();
+        }
+        Value::String(string) => {
+            for (redacted, placeholder) in redactions {
+                if string.contains(redacted.as_str()) {
+                    *string = string.replace(redacted.as_str(), placeholder);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::asl::Definition;
+    use crate::executor::Executor;
+
+    fn definition() -> Definition {
+        return Definition::from_value(json!({
+            "StartAt": "Describe",
+            "States": {
+                "Describe": {
+                    "Type": "Pass",
+                    "Parameters": {
+                        "url.$": "$.url",
+                        "execution.$": "$$.Execution.Id",
+                        "name.$": "$$.Execution.Name",
+                        "startedAt.$": "$$.Execution.StartTime"
+                    },
+                    "End": true
+                }
+            }
+        }))
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn redacts_what_changes_between_runs() {
+        let executor = Executor::new();
+        let input = json!({ "url": "https://www.rust-lang.org/" });
+        let first = executor.execute(&definition(), input.clone()).await;
+        let second = executor.execute(&definition(), input).await;
+        let snapshot = Snapshot::new().update(false);
+
+        assert_ne!(first.execution_arn, second.execution_arn);
+        assert_eq!(snapshot.render(&first), snapshot.render(&second));
+        let normalized = snapshot.normalize(&first);
+        assert_eq!(
+            normalized["output"],
+            json!({
+                "url": "https://www.rust-lang.org/",
+                "execution": EXECUTION_ARN,
+                "name": EXECUTION_NAME,
+                "startedAt": TIMESTAMP
+            })
+        );
+        assert_eq!(normalized["events"][0]["timestamp"], TIMESTAMP);
+        assert_eq!(
+            normalized["events"][1]["details"]["input"]["url"],
+            "https://www.rust-lang.org/"
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_compares_and_updates_snapshot_files() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let executor = Executor::new();
+        let execution = executor
+            .execute(&definition(), json!({ "url": "https://www.rust-lang.org/" }))
+            .await;
+        let changed = executor
+            .execute(&definition(), json!({ "url": "https://crates.io/" }))
+            .await;
+        let snapshot = Snapshot::new().directory(&directory).update(false);
+
+        let missing = std::panic::catch_unwind(|| snapshot.assert_matches("describe", &execution));
+        let message = *missing.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains("describe.snap.json is missing"));
+        assert!(!directory.join("describe.snap.json").exists());
+
+        snapshot
+            .clone()
+            .update(true)
+            .assert_matches("describe", &execution);
+        assert!(directory.join("describe.snap.json").exists());
+        snapshot.assert_matches("describe", &execution);
+
+        let mismatch = std::panic::catch_unwind(|| snapshot.assert_matches("describe", &changed));
+        let message = *mismatch.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains("-     \"url\": \"https://www.rust-lang.org/\""));
+        assert!(message.contains("+     \"url\": \"https://crates.io/\""));
+
+        snapshot
+            .clone()
+            .update(true)
+            .assert_matches("describe", &changed);
+        snapshot.assert_matches("describe", &changed);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn collapses_unchanged_lines_in_diffs() {
+        let expected = (1..=20).map(|n| n.to_string()).collect::<Vec<_>>().join("\n");
+        let actual = expected.replace("10", "ten");
+
+        assert_eq!(
+            diff(&expected, &actual),
+            "  ...\n  7\n  8\n  9\n- 10\n+ ten\n  11\n  12\n  13\n  ...\n"
+        );
+    }
+}
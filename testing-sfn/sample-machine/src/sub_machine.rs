@@ -0,0 +1,400 @@
+//! Starting a state machine at an arbitrary state.
+//!
+//! A [`SubMachine`] is the part of a definition reachable from one state, with that state as
+//! `StartAt`. Catch and Retry wiring is kept as-is, so the sub-machine behaves like the original
+//! from that point on, and it is a regular definition that runs locally or on any
+//! SFN-compatible endpoint.
+//!
+//! A state nested in a Parallel branch or a Map processor runs inside its enclosing state, which
+//! becomes a single-branch Parallel around the part of the branch reachable from the state. The
+//! wrapper keeps the enclosing state's name, Retry, Catch, result processing and transition, so
+//! errors of the branch are retried and caught, and the states after it run, as in the original.
+
+use std::collections::BTreeSet;
+
+use serde_json::{json, Map, Value};
+
+use crate::asl::path::Path;
+use crate::asl::{Definition, StatesError};
+use crate::executor::{ExecutionRequest, Executor};
+use crate::history::Execution;
+
+/// Payload template fields whose `$$` references can be pinned to a given context object.
+const TEMPLATE_FIELDS: &[&str] = &["Parameters", "ResultSelector", "ItemSelector"];
+
+/// The fields of an enclosing state that its wrapper around an extracted branch keeps. Input
+/// processing and the Map fields are dropped: the wrapper hands its input to the branch as is.
+const WRAPPER_FIELDS: &[&str] = &[
+    "Comment",
+    "QueryLanguage",
+    "Retry",
+    "Catch",
+    "ResultSelector",
+    "ResultPath",
+    "OutputPath",
+    "Output",
+    "Assign",
+    "Next",
+    "End",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubMachine {
+    pub definition: Definition,
+    pub input: Value,
+    pub context: Option<Value>,
+}
+
+impl SubMachine {
+    /// Builds the sub-machine starting at `state`. States nested in a Parallel branch or a Map
+    /// processor start inside their enclosing states, which keep their Retry and Catch.
+    pub fn new(definition: &Definition, state: &str, input: Value) -> Result<Self, StatesError> {
+        let (start, states) = extract(definition, state).ok_or_else(|| {
+            StatesError::runtime(format!(
+                "State '{}' does not exist in the definition",
+                state
+            ))
+        })?;
+
+        let mut sub_machine = Map::new();
+        if let Value::Object(root) = definition.as_value() {
+            for (key, value) in root {
+                if key != "StartAt" && key != "States" {
+                    sub_machine.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        sub_machine.insert("StartAt".to_string(), Value::from(start));
+        sub_machine.insert("States".to_string(), Value::Object(states));
+
+        return Ok(Self {
+            definition: Definition::from_value(Value::Object(sub_machine))?,
+            input,
+            context: None,
+        });
+    }
+
+    /// Uses `context` for the `$$` context object.
+    ///
+    /// Local executions see the merged context everywhere. Because an execution's context cannot
+    /// be set through the Step Functions API, payload template entries that read `$$` paths
+    /// present in `context` are also replaced with their values, so remote executions see them
+    /// too.
+    pub fn with_context(mut self, context: Value) -> Self {
+        let mut definition = self.definition.into_value();
+        if let Some(states) = definition.get_mut("States").and_then(Value::as_object_mut) {
+            for state in states.values_mut() {
+                pin_context(state, &context);
+            }
+        }
+        self.definition = Definition::from_value(definition)
+            .expect("pinning the context keeps the definition valid");
+        self.context = Some(context);
+
+        return self;
+    }
+
+    /// The request for running the sub-machine with [`Executor::start`].
+    pub fn request(&self) -> ExecutionRequest {
+        let request = ExecutionRequest::new(self.definition.clone(), self.input.clone());
+
+        return match &self.context {
+            Some(context) => request.context(context.clone()),
+            None => request,
+        };
+    }
+
+    pub async fn execute(&self, executor: &Executor) -> Execution {
+        return executor.start(self.request()).wait().await;
+    }
+
+    /// Creates the sub-machine as `name` on a Step Functions endpoint (for example
+    /// step-functions-local) and starts it. Returns the state machine and execution ARNs.
+    pub async fn start_remote(
+        &self,
+        client: &aws_sdk_sfn::Client,
+        name: &str,
+        role_arn: &str,
+    ) -> Result<(String, String), crate::Error> {
+        let state_machine_arn =
+            crate::remote::create_state_machine(client, name, &self.definition, role_arn).await?;
+        let execution_arn =
+            crate::remote::start_execution(client, &state_machine_arn, &self.input).await?;
+
+        return Ok((state_machine_arn, execution_arn));
+    }
+}
+
+/// The start and the states of `scope` reachable from it. The start is `state` itself, or the
+/// state of `scope` whose branch contains `state`, wrapped around the part of that branch.
+fn extract(scope: &Definition, state: &str) -> Option<(String, Map<String, Value>)> {
+    if scope.state(state).is_some() {
+        return Some((state.to_string(), reachable_states(scope, state)));
+    }
+
+    for enclosing in scope.states() {
+        for branch in enclosing.branches() {
+            if let Some((start, states)) = extract(&branch, state) {
+                let mut states_of_scope = reachable_states(scope, enclosing.name);
+                states_of_scope.insert(
+                    enclosing.name.to_string(),
+                    wrap(enclosing.value, start, states),
+                );
+                return Some((enclosing.name.to_string(), states_of_scope));
+            }
+        }
+    }
+
+    return None;
+}
+
+/// A single-branch Parallel with the Retry, Catch, result processing and transition of
+/// `enclosing`, running `states` from `start`.
+fn wrap(enclosing: &Value, start: String, states: Map<String, Value>) -> Value {
+    let mut wrapper = Map::new();
+    wrapper.insert("Type".to_string(), Value::from("Parallel"));
+    wrapper.insert(
+        "Branches".to_string(),
+        json!([{ "StartAt": start, "States": states }]),
+    );
+    for field in WRAPPER_FIELDS {
+        if let Some(value) = enclosing.get(*field) {
+            wrapper.insert(field.to_string(), value.clone());
+        }
+    }
+
+    return Value::Object(wrapper);
+}
+
+/// The states of `scope` reachable from `start`, by name.
+fn reachable_states(scope: &Definition, start: &str) -> Map<String, Value> {
+    let mut reachable = BTreeSet::new();
+    let mut pending = vec![start.to_string()];
+    while let Some(name) = pending.pop() {
+        if !reachable.insert(name.clone()) {
+            continue;
+        }
+        if let Some(state) = scope.state(&name) {
+            pending.extend(state.transitions().into_iter().map(str::to_string));
+        }
+    }
+
+    return scope
+        .states()
+        .filter(|state| reachable.contains(state.name))
+        .map(|state| (state.name.to_string(), state.value.clone()))
+        .collect();
+}
+
+/// Replaces `"key.$": "$$..."` template entries that resolve in `context` with `"key": value`,
+/// descending into Parallel branches and Map processors.
+fn pin_context(state: &mut Value, context: &Value) {
+    for field in TEMPLATE_FIELDS {
+        if let Some(template) = state.get_mut(*field) {
+            pin_template(template, context);
+        }
+    }
+
+    let nested = ["Branches", "ItemProcessor", "Iterator"];
+    for key in nested {
+        let branches: Vec<&mut Value> = match state.get_mut(key) {
+            Some(Value::Array(branches)) => branches.iter_mut().collect(),
+            Some(branch @ Value::Object(_)) => vec![branch],
+            _ => continue,
+        };
+        for branch in branches {
+            if let Some(states) = branch.get_mut("States").and_then(Value::as_object_mut) {
+                for state in states.values_mut() {
+                    pin_context(state, context);
+                }
+            }
+        }
+    }
+}
+
+fn pin_template(template: &mut Value, context: &Value) {
+    match template {
+        Value::Object(object) => {
+            let keys: Vec<String> = object.keys().cloned().collect();
+            for key in keys {
+                let pinned = key.strip_suffix(".$").and_then(|plain| {
+                    let expression = object[&key].as_str()?;
+                    let path = Path::parse(expression).ok().filter(|path| path.context)?;
+                    Some((plain.to_string(), path.select(&Value::Null, context)?))
+                });
+                match pinned {
+                    Some((plain, value)) => {
+                        object.remove(&key);
+                        object.insert(plain, value);
+                    }
+                    None => pin_template(object.get_mut(&key).unwrap(), context),
+                }
+            }
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| pin_template(value, context)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{handler_fn, TaskRequest};
+    use crate::history::ExecutionStatus;
+
+    fn order_definition() -> Definition {
+        return Definition::from_json(include_str!(
+            "../../complex-machine/state-machine-definition.asl.json"
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn keeps_only_the_states_reachable_from_the_start() {
+        let sub_machine = SubMachine::new(
+            &order_definition(),
+            "Set order status to NO_RESPONSE",
+            json!({}),
+        )
+        .unwrap();
+        let states: Vec<&str> = sub_machine
+            .definition
+            .states()
+            .map(|state| state.name)
+            .collect();
+
+        assert_eq!(
+            states,
+            vec![
+                "Notify the user about the rejected order",
+                "Set order status to NO_RESPONSE"
+            ]
+        );
+
+        let notify = SubMachine::new(
+            &order_definition(),
+            "Notify restaurant about the new order",
+            json!({}),
+        )
+        .unwrap();
+        assert!(notify
+            .definition
+            .state("Set order status to NO_RESPONSE")
+            .is_some());
+        assert!(notify.definition.state("Add order").is_none());
+    }
+
+    #[tokio::test]
+    async fn runs_from_a_deep_state_with_a_pinned_context() {
+        let definition = Definition::from_value(json!({
+            "StartAt": "Add order",
+            "States": {
+                "Add order": { "Type": "Pass", "Next": "Set status" },
+                "Set status": {
+                    "Type": "Task",
+                    "Resource": "arn:aws:states:::dynamodb:updateItem",
+                    "Parameters": {
+                        "orderId.$": "$.orderId",
+                        "execution.$": "$$.Execution.Name",
+                        "state.$": "$$.State.Name"
+                    },
+                    "End": true
+                }
+            }
+        }))
+        .unwrap();
+        let sub_machine = SubMachine::new(&definition, "Set status", json!({ "orderId": "42" }))
+            .unwrap()
+            .with_context(json!({ "Execution": { "Name": "order-42" } }));
+
+        assert_eq!(
+            sub_machine.definition.state("Set status").unwrap().value["Parameters"],
+            json!({ "orderId.$": "$.orderId", "execution": "order-42", "state.$": "$$.State.Name" })
+        );
+
+        let executor = Executor::new().with_handler(
+            "arn:aws:states:::dynamodb:updateItem",
+            handler_fn(|request: TaskRequest| async move { Ok(request.input) }),
+        );
+        let execution = sub_machine.execute(&executor).await;
+
+        assert_eq!(execution.status, ExecutionStatus::Succeeded);
+        assert_eq!(execution.visited_states(), vec!["Set status"]);
+        assert_eq!(execution.output.unwrap()["execution"], "order-42");
+    }
+
+    #[tokio::test]
+    async fn keeps_the_retry_and_catch_of_the_enclosing_state_of_a_nested_state() {
+        let definition = Definition::from_value(json!({
+            "StartAt": "Prepare",
+            "States": {
+                "Prepare": { "Type": "Pass", "Next": "Fan out" },
+                "Fan out": {
+                    "Type": "Parallel",
+                    "InputPath": "$.order",
+                    "Branches": [
+                        {
+                            "StartAt": "Validate",
+                            "States": {
+                                "Validate": { "Type": "Pass", "Next": "Charge" },
+                                "Charge": {
+                                    "Type": "Task",
+                                    "Resource": "arn:aws:lambda:us-east-1:123456789012:function:charge",
+                                    "End": true
+                                }
+                            }
+                        },
+                        {
+                            "StartAt": "Audit",
+                            "States": { "Audit": { "Type": "Pass", "End": true } }
+                        }
+                    ],
+                    "Retry": [{ "ErrorEquals": ["Payment.Declined"], "MaxAttempts": 1 }],
+                    "Catch": [{ "ErrorEquals": ["States.ALL"], "Next": "Refund" }],
+                    "Next": "Done"
+                },
+                "Refund": { "Type": "Pass", "End": true },
+                "Done": { "Type": "Succeed" }
+            }
+        }))
+        .unwrap();
+
+        let sub_machine = SubMachine::new(&definition, "Charge", json!({ "amount": 3 })).unwrap();
+
+        assert_eq!(sub_machine.definition.start_at(), "Fan out");
+        let states: Vec<&str> = sub_machine
+            .definition
+            .states()
+            .map(|state| state.name)
+            .collect();
+        assert_eq!(states, vec!["Done", "Fan out", "Refund"]);
+        let (original, fan_out) = (
+            definition.state("Fan out").unwrap(),
+            sub_machine.definition.state("Fan out").unwrap(),
+        );
+        assert_eq!(fan_out.value["Type"], "Parallel");
+        assert_eq!(fan_out.value.get("InputPath"), None);
+        assert_eq!(fan_out.value["Retry"], original.value["Retry"]);
+        assert_eq!(fan_out.value["Catch"], original.value["Catch"]);
+        let branches = fan_out.branches();
+        assert_eq!(branches.len(), 1);
+        assert_eq!(branches[0].start_at(), "Charge");
+        assert!(branches[0].state("Validate").is_none());
+
+        let executor = Executor::new().skip_waits(true).with_handler(
+            "arn:aws:lambda:us-east-1:123456789012:function:charge",
+            handler_fn(|_: TaskRequest| async move {
+                Err(StatesError::new("Payment.Declined", "insufficient funds"))
+            }),
+        );
+        let execution = sub_machine.execute(&executor).await;
+
+        assert_eq!(execution.status, ExecutionStatus::Succeeded);
+        assert_eq!(
+            execution.visited_states(),
+            vec!["Fan out", "Charge", "Charge", "Refund"]
+        );
+    }
+}
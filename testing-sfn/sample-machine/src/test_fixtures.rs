@@ -0,0 +1,18 @@
+//! Definitions shared by the tests of several modules.
+
+use serde_json::Value;
+
+use crate::asl::Definition;
+use crate::serverless::ServerlessStateMachine;
+
+/// The sample machine, as configured in `state_machines/simple.yml`.
+pub fn simple_definition() -> Definition {
+    return ServerlessStateMachine::from_yaml(include_str!("../state_machines/simple.yml"))
+        .unwrap()
+        .definition;
+}
+
+/// The sample machine as a JSON value, for tests that vary it.
+pub fn simple_value() -> Value {
+    return simple_definition().into_value();
+}
@@ -0,0 +1,393 @@
+//! Readable traces of executions, for diagnosing failed state machine tests.
+//!
+//! A trace is derived from the event history alone, so local executions and executions fetched
+//! with `GetExecutionHistory` (see [`crate::remote::describe_execution`]) read the same. Set
+//! `SFN_TRACE_DIR` to also write the trace of every failed assertion there as JSON.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::asl::StatesError;
+use crate::history::{Execution, ExecutionStatus};
+
+/// Event types that report a failed attempt of the innermost running state.
+const FAILURE_EVENTS: &[&str] = &[
+    "TaskFailed",
+    "TaskTimedOut",
+    "TaskStartFailed",
+    "TaskSubmitFailed",
+    "LambdaFunctionFailed",
+    "LambdaFunctionTimedOut",
+    "LambdaFunctionScheduleFailed",
+    "LambdaFunctionStartFailed",
+    "ActivityFailed",
+    "ActivityTimedOut",
+    "ActivityScheduleFailed",
+    "ParallelStateFailed",
+    "MapStateFailed",
+];
+
+/// Event types that report a successful attempt of the innermost running state.
+const SUCCESS_EVENTS: &[&str] = &[
+    "TaskSucceeded",
+    "LambdaFunctionSucceeded",
+    "ActivitySucceeded",
+    "ParallelStateSucceeded",
+    "MapStateSucceeded",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum StepOutcome {
+    Succeeded,
+    /// The state failed and a `Catch` clause moved on to `caught_by`.
+    Caught,
+    /// The state failed the execution.
+    Failed,
+    /// The state had not finished when the history ended.
+    Running,
+}
+
+/// One visit of a state.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceStep {
+    pub name: String,
+    /// `Task`, `Choice`, ... as in the `<Type>StateEntered` event.
+    pub state_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+    /// Every error the state raised, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<StatesError>,
+    /// How many of the errors were retried.
+    pub retries: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caught_by: Option<String>,
+    pub outcome: StepOutcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecutionTrace {
+    pub execution_arn: String,
+    pub status: ExecutionStatus,
+    pub input: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cause: Option<String>,
+    pub steps: Vec<TraceStep>,
+}
+
+impl ExecutionTrace {
+    pub fn from_execution(execution: &Execution) -> Self {
+        let mut steps: Vec<TraceStep> = Vec::new();
+        // Unfinished steps, innermost last; Parallel branches and Map iterations nest.
+        let mut open: Vec<usize> = Vec::new();
+        // A step that exited after a failure, waiting for the state its catcher moves on to.
+        let mut caught: Option<usize> = None;
+
+        for event in &execution.events {
+            let event_type = event.event_type.as_str();
+            let details = &event.details;
+
+            if let Some(state_type) = event_type.strip_suffix("StateEntered") {
+                if let Some(index) = caught.take() {
+                    steps[index].caught_by = details.name.clone();
+                }
+                open.push(steps.len());
+                steps.push(TraceStep {
+                    name: details.name.clone().unwrap_or_default(),
+                    state_type: state_type.to_string(),
+                    input: details.input.as_deref().map(parse_document),
+                    output: None,
+                    errors: Vec::new(),
+                    retries: 0,
+                    caught_by: None,
+                    outcome: StepOutcome::Running,
+                });
+            } else if event_type.ends_with("StateExited") {
+                let position = open
+                    .iter()
+                    .rposition(|index| Some(&steps[*index].name) == details.name.as_ref());
+                if let Some(position) = position {
+                    let index = open.remove(position);
+                    let step = &mut steps[index];
+                    step.output = details.output.as_deref().map(parse_document);
+                    step.outcome = StepOutcome::Succeeded;
+                    if step.errors.len() > step.retries {
+                        step.outcome = StepOutcome::Caught;
+                        caught = Some(index);
+                    }
+                }
+            } else if FAILURE_EVENTS.contains(&event_type) {
+                let position = match attempted_step(&steps, &open, event_type) {
+                    Some(position) => position,
+                    None => continue,
+                };
+                // States nested in a failed Parallel or Map state failed with it, and the
+                // Parallel and Map failure events carry the error of the nested state.
+                let mut error = StatesError::new(
+                    details.error.clone().unwrap_or_default(),
+                    details.cause.clone().unwrap_or_default(),
+                );
+                for index in open.drain(position + 1..) {
+                    steps[index].outcome = StepOutcome::Failed;
+                    if let Some(nested) = steps[index].errors.last() {
+                        error = nested.clone();
+                    }
+                }
+                let step = &mut steps[open[position]];
+                // An earlier error followed by another attempt was retried.
+                step.retries = step.errors.len();
+                step.errors.push(error);
+            } else if SUCCESS_EVENTS.contains(&event_type) {
+                if let Some(position) = attempted_step(&steps, &open, event_type) {
+                    let step = &mut steps[open[position]];
+                    step.retries = step.errors.len();
+                }
+            }
+        }
+
+        if execution.status.is_finished() {
+            for index in open {
+                let step = &mut steps[index];
+                step.outcome = StepOutcome::Failed;
+                if step.errors.len() == step.retries {
+                    if let Some(failure) = execution.failure() {
+                        step.errors.push(failure);
+                    }
+                }
+            }
+        }
+
+        return Self {
+            execution_arn: execution.execution_arn.clone(),
+            status: execution.status,
+            input: execution.input.clone(),
+            output: execution.output.clone(),
+            error: execution.error.clone(),
+            cause: execution.cause.clone(),
+            steps,
+        };
+    }
+
+    /// The steps that failed the execution, innermost last.
+    pub fn failed_steps(&self) -> Vec<&TraceStep> {
+        return self
+            .steps
+            .iter()
+            .filter(|step| step.outcome == StepOutcome::Failed)
+            .collect();
+    }
+
+    pub fn to_json(&self) -> String {
+        return serde_json::to_string_pretty(self).expect("traces are always serializable");
+    }
+
+    /// Writes the trace to `<directory>/<execution name>.trace.json`.
+    pub fn write_json(&self, directory: impl AsRef<Path>) -> std::io::Result<PathBuf> {
+        let name = self
+            .execution_arn
+            .rsplit(':')
+            .next()
+            .unwrap_or(&self.execution_arn);
+        std::fs::create_dir_all(&directory)?;
+        let path = directory.as_ref().join(format!("{}.trace.json", name));
+        std::fs::write(&path, self.to_json())?;
+
+        return Ok(path);
+    }
+
+    /// Writes the trace to `SFN_TRACE_DIR` when it is set.
+    pub fn dump_if_configured(&self) -> Option<PathBuf> {
+        let directory = std::env::var("SFN_TRACE_DIR").ok()?;
+
+        return match self.write_json(directory) {
+            Ok(path) => Some(path),
+            Err(error) => {
+                eprintln!("Failed to write the execution trace: {}", error);
+                None
+            }
+        };
+    }
+}
+
+impl fmt::Display for ExecutionTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Execution {} {}",
+            self.execution_arn,
+            self.status.as_str()
+        )?;
+        if let Some(error) = &self.error {
+            write!(f, " with {}", error)?;
+            if let Some(cause) = self.cause.as_ref().filter(|cause| !cause.is_empty()) {
+                write!(f, ": {}", cause)?;
+            }
+        }
+        writeln!(f)?;
+        writeln!(f, "  input: {}", self.input)?;
+
+        for (number, step) in self.steps.iter().enumerate() {
+            writeln!(
+                f,
+                "  {}. {} ({}) {:?}",
+                number + 1,
+                step.name,
+                step.state_type,
+                step.outcome
+            )?;
+            if let Some(input) = &step.input {
+                writeln!(f, "       input:  {}", input)?;
+            }
+            for (attempt, error) in step.errors.iter().enumerate() {
+                let decision = if attempt < step.retries {
+                    "retried".to_string()
+                } else {
+                    match &step.caught_by {
+                        Some(next) => format!("caught, next: {}", next),
+                        None => "not handled".to_string(),
+                    }
+                };
+                writeln!(f, "       error:  {} ({})", error, decision)?;
+            }
+            if let Some(output) = &step.output {
+                writeln!(f, "       output: {}", output)?;
+            }
+        }
+        if let Some(output) = &self.output {
+            write!(f, "  output: {}", output)?;
+        }
+
+        return Ok(());
+    }
+}
+
+/// Panics with the execution trace unless the execution finished with `status`.
+#[track_caller]
+pub fn assert_status(execution: &Execution, status: ExecutionStatus) {
+    if execution.status != status {
+        fail(
+            execution,
+            format!(
+                "expected status {}, got {}",
+                status.as_str(),
+                execution.status.as_str()
+            ),
+        );
+    }
+}
+
+/// Panics with the execution trace unless the execution succeeded with `output`.
+#[track_caller]
+pub fn assert_output(execution: &Execution, output: &Value) {
+    assert_status(execution, ExecutionStatus::Succeeded);
+    if execution.output.as_ref() != Some(output) {
+        fail(
+            execution,
+            format!(
+                "expected output {}, got {}",
+                output,
+                execution.output.clone().unwrap_or(Value::Null)
+            ),
+        );
+    }
+}
+
+#[track_caller]
+fn fail(execution: &Execution, message: String) -> ! {
+    let trace = ExecutionTrace::from_execution(execution);
+    let dumped = trace
+        .dump_if_configured()
+        .map(|path| format!("\n(trace written to {})", path.display()))
+        .unwrap_or_default();
+
+    panic!("{}\n{}{}", message, trace, dumped);
+}
+
+/// The position in `open` of the state an attempt event belongs to: the innermost Parallel or
+/// Map state for their own events, and the innermost Task state for everything else.
+fn attempted_step(steps: &[TraceStep], open: &[usize], event_type: &str) -> Option<usize> {
+    let state_type = ["Parallel", "Map"]
+        .into_iter()
+        .find(|state_type| event_type.starts_with(state_type))
+        .unwrap_or("Task");
+
+    return open
+        .iter()
+        .rposition(|index| steps[*index].state_type == state_type);
+}
+
+/// Histories carry documents as JSON strings; anything that is not JSON is kept as a string.
+fn parse_document(document: &str) -> Value {
+    return serde_json::from_str(document).unwrap_or_else(|_| Value::from(document));
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::asl::Definition;
+    use crate::executor::{handler_fn, Executor, TaskRequest};
+
+    #[tokio::test]
+    async fn traces_retries_catches_and_the_failing_state() {
+        let definition = Definition::from_value(json!({
+            "StartAt": "GetHtml",
+            "States": {
+                "GetHtml": {
+                    "Type": "Task",
+                    "Resource": "arn:aws:lambda:us-east-1:123456789012:function:get-html",
+                    "Retry": [{ "ErrorEquals": ["Lambda.TooManyRequestsException"], "MaxAttempts": 1 }],
+                    "Catch": [{ "ErrorEquals": ["States.ALL"], "Next": "Dunno" }],
+                    "End": true
+                },
+                "Dunno": { "Type": "Fail", "Error": "Dunno", "Cause": "The page is unreachable" }
+            }
+        }))
+        .unwrap();
+        let executor = Executor::new().skip_waits(true).with_handler(
+            "arn:aws:lambda:*",
+            handler_fn(|_: TaskRequest| async {
+                Err(StatesError::new(
+                    "Lambda.TooManyRequestsException",
+                    "slow down",
+                ))
+            }),
+        );
+        let execution = executor
+            .execute(&definition, json!({ "url": "https://www.rust-lang.org/" }))
+            .await;
+        let trace = ExecutionTrace::from_execution(&execution);
+
+        let get_html = &trace.steps[0];
+        assert_eq!(get_html.errors.len(), 2);
+        assert_eq!(get_html.retries, 1);
+        assert_eq!(get_html.outcome, StepOutcome::Caught);
+        assert_eq!(get_html.caught_by.as_deref(), Some("Dunno"));
+        assert_eq!(trace.failed_steps()[0].name, "Dunno");
+        assert_eq!(
+            trace.failed_steps()[0].errors,
+            vec![StatesError::new("Dunno", "The page is unreachable")]
+        );
+
+        let rendered = trace.to_string();
+        assert!(rendered.contains("Lambda.TooManyRequestsException: slow down (retried)"));
+        assert!(rendered.contains("(caught, next: Dunno)"));
+
+        let panic =
+            std::panic::catch_unwind(|| assert_status(&execution, ExecutionStatus::Succeeded));
+        assert!(panic.is_err());
+    }
+}